@@ -0,0 +1,217 @@
+use crate::{
+    clock::Clock,
+    config::Config,
+    db::{
+        account_deletions::get_due_account_deletions,
+        audit_log::{insert_audit_event, AuditOutcome, NewAuditEvent},
+        email_changes::get_email_changes_of_user,
+        invitations::delete_invitations_of_address,
+        user::{delete_user, get_user_by_id},
+        webhooks::scrub_address_from_deliveries,
+        DbPool,
+    },
+    error::ApiResult,
+    webhooks::{emit_event, USER_DELETED},
+};
+use actix_web::web::Data;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+
+/// actor of the audit events the job writes
+const SYSTEM_ACTOR: &str = "system";
+
+/// removes an account for good. rows that point at the user id go with it through their
+/// foreign keys, rows that only name the address are deleted here and webhook payloads lose
+/// every address the user had. the audit log is hash chained and append only, so the events
+/// the user already has there keep their address, ip and user agent. the purge itself is only
+/// recorded with the user id
+async fn purge_user(pool: &DbPool, user_id: i64, now: DateTime<Utc>) -> ApiResult<()> {
+    let Some(user) = get_user_by_id(pool, user_id).await? else {
+        return Ok(());
+    };
+    let mut addresses = vec![user.email_address.clone()];
+    for change in get_email_changes_of_user(pool, user.id).await? {
+        addresses.push(change.old_email_address);
+        addresses.push(change.new_email_address);
+    }
+    delete_invitations_of_address(pool, &user.tenant_id, &user.email_address).await?;
+    delete_user(pool, user.id).await?;
+    for address in &addresses {
+        scrub_address_from_deliveries(pool, address).await?;
+    }
+
+    insert_audit_event(
+        pool,
+        &NewAuditEvent {
+            tenant_id: &user.tenant_id,
+            actor: SYSTEM_ACTOR,
+            action: "user.purge",
+            target: &user.id.to_string(),
+            outcome: AuditOutcome::Success,
+            ip: None,
+            user_agent: None,
+        },
+        now,
+    )
+    .await?;
+    emit_event(
+        pool,
+        USER_DELETED,
+        json!({ "tenant_id": user.tenant_id, "user_id": user.id }),
+    )
+    .await
+}
+
+/// purges every account whose grace period is over, returns how many were purged
+pub async fn delete_due_accounts(pool: &DbPool, now: DateTime<Utc>) -> ApiResult<usize> {
+    let user_ids = get_due_account_deletions(pool, now).await?;
+    for user_id in &user_ids {
+        purge_user(pool, *user_id, now).await?;
+    }
+    Ok(user_ids.len())
+}
+
+/// purges due accounts forever, meant to be spawned next to the http server
+pub async fn run_account_deletion_worker(
+    pool: DbPool,
+    config: Config,
+    clock: Data<dyn Clock + Send + Sync>,
+) {
+    let interval = config
+        .account_deletion_poll_interval
+        .to_std()
+        .unwrap_or_default();
+    loop {
+        if let Err(e) = delete_due_accounts(&pool, clock.now()).await {
+            tracing::error!("deleting accounts failed: {e}");
+        }
+        actix_web::rt::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::EmailCodePurpose,
+        db::{
+            account_deletions::schedule_account_deletion,
+            audit_log::{get_audit_events, AuditFilter},
+            email_codes::{get_last_sent_email_code, insert_or_update_email_code},
+            invitations::{get_invitations, insert_invitation, NewInvitation},
+            sessions::get_session,
+            tenants::DEFAULT_TENANT_ID,
+            webhooks::{get_deliveries_of_webhook, insert_webhook, NewWebhook},
+        },
+        test::helper::{create_test_db, create_test_user_with_session, test_user_id},
+        utils::hash::sha256_hash,
+        webhooks::USER_REGISTERED,
+    };
+    use chrono::{Duration, Utc};
+
+    #[actix_web::test]
+    async fn only_due_accounts_are_purged() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "due@gmail.com").await;
+        let due_id = test_user_id(&db, "due@gmail.com").await;
+        create_test_user_with_session(&db, "later@gmail.com").await;
+        let later_id = test_user_id(&db, "later@gmail.com").await;
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "due@gmail.com",
+            EmailCodePurpose::Registration,
+            123456,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        insert_invitation(
+            &db,
+            &NewInvitation {
+                tenant_id: DEFAULT_TENANT_ID,
+                email_address: "due@gmail.com",
+                role_name: None,
+                invited_by: "admin@gmail.com",
+                token_id: "token",
+                expire_date: Utc::now() + Duration::days(1),
+            },
+        )
+        .await
+        .unwrap();
+        schedule_account_deletion(&db, due_id, "due", Utc::now() - Duration::seconds(1))
+            .await
+            .unwrap();
+        schedule_account_deletion(&db, later_id, "later", Utc::now() + Duration::days(1))
+            .await
+            .unwrap();
+
+        let webhook_id = insert_webhook(
+            &db,
+            &NewWebhook {
+                url: "https://hooks.example.com",
+                secret: "secret",
+                events: &[USER_REGISTERED.to_string(), USER_DELETED.to_string()],
+                created_by: "admin@gmail.com",
+            },
+        )
+        .await
+        .unwrap();
+        for email_address in ["due@gmail.com", "later@gmail.com"] {
+            emit_event(
+                &db,
+                USER_REGISTERED,
+                json!({ "tenant_id": DEFAULT_TENANT_ID, "email_address": email_address }),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(delete_due_accounts(&db, Utc::now()).await.unwrap(), 1);
+        assert!(get_user_by_id(&db, due_id).await.unwrap().is_none());
+        assert!(get_user_by_id(&db, later_id).await.unwrap().is_some());
+        assert!(get_session(&db, &sha256_hash(&token))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(get_last_sent_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "due@gmail.com",
+            EmailCodePurpose::Registration
+        )
+        .await
+        .unwrap()
+        .is_none());
+        assert!(get_invitations(&db, DEFAULT_TENANT_ID)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // the address isn't written anywhere again by the purge
+        let filter = AuditFilter {
+            action: Some("user.purge".to_string()),
+            ..AuditFilter::default()
+        };
+        let events = get_audit_events(&db, &filter, 10).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].target, due_id.to_string());
+        // and it's gone from the payloads sent before, the other user's stay as they were
+        let deliveries = get_deliveries_of_webhook(&db, webhook_id, 10)
+            .await
+            .unwrap();
+        assert_eq!(deliveries.len(), 3);
+        assert!(deliveries
+            .iter()
+            .all(|delivery| !delivery.payload.contains("due@gmail.com")));
+        assert_eq!(deliveries[0].event_type, USER_DELETED);
+        assert!(deliveries[0]
+            .payload
+            .contains(&format!(r#""user_id":{due_id}"#)));
+        assert!(deliveries[1].payload.contains("later@gmail.com"));
+        assert!(deliveries[2].payload.contains(r#""email_address":null"#));
+
+        // the purged account is gone from the queue
+        assert_eq!(delete_due_accounts(&db, Utc::now()).await.unwrap(), 0);
+    }
+}
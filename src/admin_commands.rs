@@ -0,0 +1,370 @@
+//! what the commands of `auth_admin` do to the database, the binary parses the arguments and
+//! prints the results
+
+use crate::{
+    audit::RequestInfo,
+    breached_passwords::BreachedPasswords,
+    db::{
+        audit_log::AuditOutcome,
+        oauth::delete_refresh_tokens_of_user,
+        roles::assign_role,
+        sessions::delete_sessions_of_user,
+        tenants::get_tenant,
+        user::{
+            get_user, insert_user, transition_user_status, update_user_password, AccountStatus,
+            User,
+        },
+        DbPool,
+    },
+    error::ApiError,
+    utils::{hash::sha256_hash, validators::*},
+};
+use anyhow::{anyhow, bail, Result};
+use std::io;
+
+/// actor of the audit events the commands record
+pub const AUDIT_ACTOR: &str = "auth_admin";
+
+/// asks for a password showing the given prompt, `rpassword::prompt_password` in the binary
+pub type ReadPassword<'a> = &'a dyn Fn(&str) -> io::Result<String>;
+
+/// what `create_admin` had to do
+#[derive(Debug, PartialEq)]
+pub struct CreatedAdmin {
+    pub created_user: bool,
+    pub assigned_role: bool,
+}
+
+/// checks the password against the password policy of the tenant, `personal_info` is what it
+/// shouldn't contain
+async fn new_password(
+    pool: &DbPool,
+    info: &RequestInfo,
+    breached_passwords: &BreachedPasswords,
+    read_password: ReadPassword<'_>,
+    personal_info: &[&str],
+) -> Result<String> {
+    let Some(tenant) = get_tenant(pool, &info.tenant_id).await? else {
+        bail!("tenant {} doesn't exist", info.tenant_id);
+    };
+    let password = read_password("new password: ")?;
+    validate_password(&password)?;
+    if let Err(ApiError::WeakPassword { violations }) =
+        tenant
+            .settings
+            .check_password(&password, personal_info, breached_passwords)
+    {
+        bail!("password doesn't meet the password policy: {violations:?}");
+    }
+    if read_password("repeat password: ")? != password {
+        bail!("passwords don't match");
+    }
+    Ok(password)
+}
+
+async fn find_user(pool: &DbPool, info: &RequestInfo, email_address: &str) -> Result<User> {
+    match get_user(pool, &info.tenant_id, email_address).await? {
+        Some(user) => Ok(user),
+        None => bail!("user {email_address} doesn't exist"),
+    }
+}
+
+/// creates a user with the admin role, or gives an existing user the admin role. the password
+/// is only asked for when the user has to be created
+pub async fn create_admin(
+    pool: &DbPool,
+    info: &RequestInfo,
+    breached_passwords: &BreachedPasswords,
+    read_password: ReadPassword<'_>,
+    email_address: &str,
+    name: &str,
+) -> Result<CreatedAdmin> {
+    validate_email_address(email_address)?;
+    let (user_id, created_user) = match get_user(pool, &info.tenant_id, email_address).await? {
+        Some(user) => (user.id, false),
+        None => {
+            validate_name(name)?;
+            let password = new_password(
+                pool,
+                info,
+                breached_passwords,
+                read_password,
+                &[name, email_address],
+            )
+            .await?;
+            let user_id = insert_user(
+                pool,
+                &info.tenant_id,
+                name,
+                &sha256_hash(&password),
+                email_address,
+            )
+            .await?;
+            info.audit(
+                pool,
+                AUDIT_ACTOR,
+                "user.create",
+                email_address,
+                AuditOutcome::Success,
+            )
+            .await?;
+            (user_id, true)
+        }
+    };
+
+    let assigned_role = assign_role(pool, user_id, "admin").await?;
+    if assigned_role {
+        info.audit(
+            pool,
+            AUDIT_ACTOR,
+            "user.assign_role:admin",
+            email_address,
+            AuditOutcome::Success,
+        )
+        .await?;
+    }
+    Ok(CreatedAdmin {
+        created_user,
+        assigned_role,
+    })
+}
+
+/// sets a new password and logs the user out everywhere
+pub async fn reset_password(
+    pool: &DbPool,
+    info: &RequestInfo,
+    breached_passwords: &BreachedPasswords,
+    read_password: ReadPassword<'_>,
+    email_address: &str,
+) -> Result<()> {
+    let user = find_user(pool, info, email_address).await?;
+    let password = new_password(
+        pool,
+        info,
+        breached_passwords,
+        read_password,
+        &[&user.name, email_address],
+    )
+    .await?;
+    update_user_password(pool, user.id, &sha256_hash(&password)).await?;
+    delete_sessions_of_user(pool, user.id).await?;
+    delete_refresh_tokens_of_user(pool, user.id).await?;
+    info.audit(
+        pool,
+        AUDIT_ACTOR,
+        "user.reset_password",
+        email_address,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok(())
+}
+
+pub fn parse_status(status: &str) -> Result<AccountStatus> {
+    match AccountStatus::parse(status) {
+        Some(status) => Ok(status),
+        None => bail!("unknown status {status}"),
+    }
+}
+
+/// moves the account to another status, only active accounts can be used
+pub async fn set_status(
+    pool: &DbPool,
+    info: &RequestInfo,
+    email_address: &str,
+    status: AccountStatus,
+    reason: Option<&str>,
+) -> Result<()> {
+    let user = find_user(pool, info, email_address).await?;
+    if let Some(reason) = reason {
+        validate_status_reason(reason)?;
+    }
+    transition_user_status(pool, &user, status, reason)
+        .await
+        .map_err(|e| match e {
+            ApiError::BadArgument { .. } => anyhow!(
+                "{email_address} can't go from {} to {}",
+                user.status.as_str(),
+                status.as_str()
+            ),
+            e => e.into(),
+        })?;
+    info.audit(
+        pool,
+        AUDIT_ACTOR,
+        &format!("user.set_status:{}", status.as_str()),
+        email_address,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{
+            audit_log::get_audit_events_of_address, roles::get_user_roles, sessions::get_session,
+            tenants::DEFAULT_TENANT_ID, user::does_user_exists,
+        },
+        test::helper::{create_test_db, create_test_user_with_session, test_user_id},
+    };
+    use chrono::Utc;
+
+    fn cli_info() -> RequestInfo {
+        RequestInfo {
+            tenant_id: DEFAULT_TENANT_ID.to_string(),
+            ip: None,
+            user_agent: None,
+            now: Utc::now(),
+        }
+    }
+
+    fn typed_password(_: &str) -> io::Result<String> {
+        Ok("another_hard_password".to_string())
+    }
+
+    async fn audited_actions(pool: &DbPool, email_address: &str) -> Vec<String> {
+        get_audit_events_of_address(pool, DEFAULT_TENANT_ID, email_address)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|event| event.actor == AUDIT_ACTOR)
+            .map(|event| event.action)
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn create_admin_creates_the_user_once() {
+        let db = create_test_db().await;
+        let breached_passwords = BreachedPasswords::default();
+        let info = cli_info();
+        let create = || {
+            create_admin(
+                &db,
+                &info,
+                &breached_passwords,
+                &typed_password,
+                "root@gmail.com",
+                "root",
+            )
+        };
+
+        assert_eq!(
+            create().await.unwrap(),
+            CreatedAdmin {
+                created_user: true,
+                assigned_role: true,
+            }
+        );
+        assert!(does_user_exists(
+            &db,
+            DEFAULT_TENANT_ID,
+            "root@gmail.com",
+            &sha256_hash("another_hard_password")
+        )
+        .await
+        .unwrap());
+        let user_id = test_user_id(&db, "root@gmail.com").await;
+        assert_eq!(get_user_roles(&db, user_id).await.unwrap(), ["admin"]);
+        let mut actions = audited_actions(&db, "root@gmail.com").await;
+        actions.sort();
+        assert_eq!(actions, ["user.assign_role:admin", "user.create"]);
+
+        assert_eq!(
+            create().await.unwrap(),
+            CreatedAdmin {
+                created_user: false,
+                assigned_role: false,
+            }
+        );
+        assert_eq!(audited_actions(&db, "root@gmail.com").await.len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn create_admin_needs_the_password_twice() {
+        let db = create_test_db().await;
+        let mismatching = |prompt: &str| Ok(format!("{prompt}another_hard_password"));
+
+        assert!(create_admin(
+            &db,
+            &cli_info(),
+            &BreachedPasswords::default(),
+            &mismatching,
+            "root@gmail.com",
+            "root",
+        )
+        .await
+        .is_err());
+        assert!(get_user(&db, DEFAULT_TENANT_ID, "root@gmail.com")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[actix_web::test]
+    async fn reset_password_ends_sessions() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "arian@gmail.com").await;
+
+        reset_password(
+            &db,
+            &cli_info(),
+            &BreachedPasswords::default(),
+            &typed_password,
+            "arian@gmail.com",
+        )
+        .await
+        .unwrap();
+        assert!(does_user_exists(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            &sha256_hash("another_hard_password")
+        )
+        .await
+        .unwrap());
+        assert!(get_session(&db, &sha256_hash(&token))
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            audited_actions(&db, "arian@gmail.com").await,
+            ["user.reset_password"]
+        );
+    }
+
+    #[actix_web::test]
+    async fn set_status_refuses_invalid_transitions() {
+        let db = create_test_db().await;
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+
+        let err = set_status(
+            &db,
+            &cli_info(),
+            "arian@gmail.com",
+            AccountStatus::Active,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "arian@gmail.com can't go from active to active"
+        );
+        set_status(
+            &db,
+            &cli_info(),
+            "arian@gmail.com",
+            parse_status("suspended").unwrap(),
+            Some("spam"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            audited_actions(&db, "arian@gmail.com").await,
+            ["user.set_status:suspended"]
+        );
+    }
+}
@@ -0,0 +1,453 @@
+use crate::{
+    audit::RequestInfo,
+    auth::AuthenticatedUser,
+    config::Config,
+    db::{
+        account_deletions::{
+            cancel_account_deletion, get_account_deletion_by_cancel_token,
+            schedule_account_deletion, AccountDeletion,
+        },
+        oauth::delete_refresh_tokens_of_user,
+        sessions::delete_sessions_of_user,
+        tenants::Tenant,
+        user::{does_user_exists, get_user_by_id, set_user_status, AccountStatus, User},
+        DbPool,
+    },
+    email_sender::{EmailSender, Message},
+    error::{ApiError, ApiResult},
+    utils::{hash::sha256_hash, random::generate_random_token},
+};
+use actix_web::{
+    get, post,
+    web::{Data, Json, Query},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct DeleteAccountArgs {
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeleteAccountResponse {
+    /// the account is purged after this, unless the emailed link cancels it
+    pub delete_date: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CancelAccountDeletionArgs {
+    pub token: String,
+}
+
+/// what the user sees before cancelling the deletion
+#[derive(Serialize, Deserialize)]
+pub struct AccountDeletionCancelPage {
+    pub email_address: String,
+    pub delete_date: DateTime<Utc>,
+}
+
+async fn schedule_deletion(
+    pool: &DbPool,
+    config: &Config,
+    email_sender: &(dyn EmailSender + Send + Sync),
+    user: &AuthenticatedUser,
+    password: &str,
+) -> ApiResult<DateTime<Utc>> {
+    let hashed_password = sha256_hash(password);
+    if !does_user_exists(pool, &user.tenant_id, &user.email_address, &hashed_password).await? {
+        return Err(ApiError::WrongCredentials);
+    }
+
+    let cancel_token = generate_random_token();
+    let delete_date = Utc::now() + config.account_deletion_grace_period;
+    // the cancel link has to arrive before the user is locked out
+    email_sender
+        .send_email(Message {
+            to: user
+                .email_address
+                .parse()
+                .map_err(|_| ApiError::InvalidEmailAddress)?,
+            subject: "Your account will be deleted".to_string(),
+            body: format!(
+                "your account will be deleted on {}. to keep it, cancel the deletion at \
+                {}?token={cancel_token}",
+                delete_date.to_rfc2822(),
+                config.account_deletion_cancel_uri
+            ),
+        })
+        .await?;
+
+    schedule_account_deletion(pool, user.id, &sha256_hash(&cancel_token), delete_date).await?;
+    // the account can't be used during the grace period
+    set_user_status(
+        pool,
+        user.id,
+        AccountStatus::Deleted,
+        Some("deletion requested by the user"),
+    )
+    .await?;
+    delete_sessions_of_user(pool, user.id).await?;
+    delete_refresh_tokens_of_user(pool, user.id).await?;
+    Ok(delete_date)
+}
+
+/// blocks the account right away and purges it once the grace period is over
+#[post("/me/delete")]
+pub async fn delete_account(
+    args: Json<DeleteAccountArgs>,
+    user: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    email_sender: Data<dyn EmailSender + Send + Sync>,
+) -> ApiResult<Json<DeleteAccountResponse>> {
+    user.require_session()?;
+    let result =
+        schedule_deletion(&pool, &config, email_sender.as_ref(), &user, &args.password).await;
+    let delete_date = info
+        .audit_result(
+            &pool,
+            &user.email_address,
+            "user.request_deletion",
+            &user.email_address,
+            result,
+        )
+        .await?;
+    Ok(Json(DeleteAccountResponse { delete_date }))
+}
+
+/// the deletion the cancel token belongs to, if it can still be cancelled
+async fn pending_deletion(
+    pool: &DbPool,
+    tenant: &Tenant,
+    token: &str,
+) -> ApiResult<(AccountDeletion, User)> {
+    let deletion = get_account_deletion_by_cancel_token(pool, &sha256_hash(token))
+        .await?
+        .ok_or(ApiError::InvalidAccountDeletion)?;
+    let user = get_user_by_id(pool, deletion.user_id)
+        .await?
+        .ok_or(ApiError::InvalidAccountDeletion)?;
+    if user.tenant_id != tenant.id || deletion.delete_date <= Utc::now() {
+        return Err(ApiError::InvalidAccountDeletion);
+    }
+    Ok((deletion, user))
+}
+
+/// returns the email address of the account that is kept
+async fn cancel_deletion(pool: &DbPool, tenant: &Tenant, token: &str) -> ApiResult<String> {
+    let (_, user) = pending_deletion(pool, tenant, token).await?;
+    if !cancel_account_deletion(pool, user.id).await? {
+        return Err(ApiError::InvalidAccountDeletion);
+    }
+    set_user_status(
+        pool,
+        user.id,
+        AccountStatus::Active,
+        Some("deletion cancelled by the user"),
+    )
+    .await?;
+    Ok(user.email_address)
+}
+
+async fn cancel_and_audit(
+    pool: &DbPool,
+    tenant: &Tenant,
+    info: &RequestInfo,
+    token: &str,
+) -> ApiResult<()> {
+    let result = cancel_deletion(pool, tenant, token).await;
+    let target = match &result {
+        Ok(email_address) => email_address.clone(),
+        Err(_) => "unknown".to_string(),
+    };
+    info.audit_result(pool, &target, "user.cancel_deletion", &target, result)
+        .await?;
+    Ok(())
+}
+
+/// keeps the account, the user logs in again afterwards
+#[post("/account_deletion/cancel")]
+pub async fn cancel_account_deletion_by_token(
+    args: Json<CancelAccountDeletionArgs>,
+    tenant: Tenant,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    cancel_and_audit(&pool, &tenant, &info, &args.token).await?;
+    Ok("")
+}
+
+/// the cancel link mailed by `delete_account`. it only shows the deletion, mail scanners open
+/// links on their own, so cancelling takes a `cancel_account_deletion_by_token` with the same
+/// token
+#[get("/account_deletion/cancel")]
+pub async fn account_deletion_cancel_page(
+    args: Query<CancelAccountDeletionArgs>,
+    tenant: Tenant,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<AccountDeletionCancelPage>> {
+    let (deletion, user) = pending_deletion(&pool, &tenant, &args.token).await?;
+    Ok(Json(AccountDeletionCancelPage {
+        email_address: user.email_address,
+        delete_date: deletion.delete_date,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account_deletion::delete_due_accounts,
+        api::{api_keys::list_api_keys, login::login},
+        db::{
+            account_deletions::get_account_deletion,
+            api_keys::{insert_api_key, NewApiKey},
+            sessions::get_session,
+        },
+        email_sender::MockEmailSender,
+        test::helper::{
+            create_test_clock, create_test_db, create_test_user_with_session, test_user_id,
+        },
+    };
+    use actix_web::{
+        http::{
+            header::{ContentType, AUTHORIZATION},
+            StatusCode,
+        },
+        test::{self, TestRequest},
+        App,
+    };
+    use std::{
+        future::ready,
+        sync::{Arc, Mutex},
+    };
+
+    #[actix_web::test]
+    async fn delete_and_cancel() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let user_id = test_user_id(&db, "arian@gmail.com").await;
+        let api_key = "ak_test_key";
+        insert_api_key(
+            &db,
+            &NewApiKey {
+                user_id,
+                name: "key",
+                prefix: "ak_test",
+                key_hash: &sha256_hash(api_key),
+                scopes: &[],
+                expire_date: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let sent = bodies.clone();
+        let mut email_mock = MockEmailSender::new();
+        email_mock.expect_send_email().returning(move |message| {
+            sent.lock().unwrap().push(message.body);
+            Box::pin(ready(Ok(())))
+        });
+        let email_sender: Arc<dyn EmailSender + Send + Sync> = Arc::new(email_mock);
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .app_data(Data::from(email_sender))
+                .service(delete_account)
+                .service(cancel_account_deletion_by_token)
+                .service(login)
+                .service(list_api_keys),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/me/delete")
+            .set_json(DeleteAccountArgs {
+                password: "wrong".to_string(),
+            })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::post()
+            .uri("/me/delete")
+            .set_json(DeleteAccountArgs {
+                password: "some_hard_password".to_string(),
+            })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp: DeleteAccountResponse = test::call_and_read_body_json(&app, req).await;
+        assert!(resp.delete_date > Utc::now() + chrono::Duration::days(29));
+
+        // the account can't be used during the grace period
+        assert!(get_session(&db, &sha256_hash(&token))
+            .await
+            .unwrap()
+            .is_none());
+        let req = TestRequest::get()
+            .uri("/api_keys")
+            .insert_header((AUTHORIZATION, format!("Bearer {api_key}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let login_payload =
+            r#"{"email_address": "arian@gmail.com", "password": "some_hard_password"}"#;
+        let req = TestRequest::post()
+            .uri("/login")
+            .set_payload(login_payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // nothing is purged before the grace period is over
+        assert_eq!(delete_due_accounts(&db, Utc::now()).await.unwrap(), 0);
+
+        let cancel_token = {
+            let bodies = bodies.lock().unwrap();
+            bodies[0].split("token=").nth(1).unwrap().to_string()
+        };
+        let req = TestRequest::post()
+            .uri("/account_deletion/cancel")
+            .set_json(CancelAccountDeletionArgs {
+                token: cancel_token.clone(),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(get_account_deletion(&db, user_id).await.unwrap().is_none());
+        let user = get_user_by_id(&db, user_id).await.unwrap().unwrap();
+        assert_eq!(user.status, AccountStatus::Active);
+
+        let req = TestRequest::post()
+            .uri("/login")
+            .set_payload(login_payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = TestRequest::post()
+            .uri("/account_deletion/cancel")
+            .set_json(CancelAccountDeletionArgs {
+                token: cancel_token,
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn nothing_changes_when_the_email_fails() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let user_id = test_user_id(&db, "arian@gmail.com").await;
+        let mut email_mock = MockEmailSender::new();
+        email_mock.expect_send_email().returning(|_| {
+            Box::pin(ready(Err(ApiError::EmailError {
+                reason: "unreachable".to_string(),
+            })))
+        });
+        let email_sender: Arc<dyn EmailSender + Send + Sync> = Arc::new(email_mock);
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(Config::default()))
+                .app_data(Data::from(email_sender))
+                .service(delete_account),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/me/delete")
+            .set_json(DeleteAccountArgs {
+                password: "some_hard_password".to_string(),
+            })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(!resp.status().is_success());
+        assert!(get_account_deletion(&db, user_id).await.unwrap().is_none());
+        let user = get_user_by_id(&db, user_id).await.unwrap().unwrap();
+        assert_eq!(user.status, AccountStatus::Active);
+        assert!(get_session(&db, &sha256_hash(&token))
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[actix_web::test]
+    async fn emailed_cancel_link_only_shows_the_deletion() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let user_id = test_user_id(&db, "arian@gmail.com").await;
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let sent = bodies.clone();
+        let mut email_mock = MockEmailSender::new();
+        email_mock.expect_send_email().returning(move |message| {
+            sent.lock().unwrap().push(message.body);
+            Box::pin(ready(Ok(())))
+        });
+        let email_sender: Arc<dyn EmailSender + Send + Sync> = Arc::new(email_mock);
+        let config = Config::default();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(config.clone()))
+                .app_data(Data::from(email_sender))
+                .service(delete_account)
+                .service(cancel_account_deletion_by_token)
+                .service(account_deletion_cancel_page),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/me/delete")
+            .set_json(DeleteAccountArgs {
+                password: "some_hard_password".to_string(),
+            })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let cancel_link = bodies.lock().unwrap()[0]
+            .split_whitespace()
+            .last()
+            .unwrap()
+            .to_string();
+        // scanners open the link more than once, none of that cancels the deletion
+        let uri = cancel_link.strip_prefix(&config.issuer).unwrap();
+        for _ in 0..2 {
+            let req = TestRequest::get().uri(uri).to_request();
+            let page: AccountDeletionCancelPage = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(page.email_address, "arian@gmail.com");
+        }
+        assert!(get_account_deletion(&db, user_id).await.unwrap().is_some());
+        let user = get_user_by_id(&db, user_id).await.unwrap().unwrap();
+        assert_eq!(user.status, AccountStatus::Deleted);
+
+        let cancel_token = uri.split_once("token=").unwrap().1.to_string();
+        let req = TestRequest::post()
+            .uri("/account_deletion/cancel")
+            .set_json(CancelAccountDeletionArgs {
+                token: cancel_token,
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(get_account_deletion(&db, user_id).await.unwrap().is_none());
+        let user = get_user_by_id(&db, user_id).await.unwrap().unwrap();
+        assert_eq!(user.status, AccountStatus::Active);
+
+        let req = TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,317 @@
+use crate::{
+    auth::AuthenticatedUser,
+    db::{
+        audit_log::{get_audit_events, verify_audit_chain, AuditEvent, AuditFilter, AuditOutcome},
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+};
+use actix_web::{
+    get,
+    web::{Data, Json, Query},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+const MAX_LIMIT: i64 = 10_000;
+
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Jsonl,
+    Csv,
+}
+
+#[derive(Deserialize)]
+pub struct AuditLogArgs {
+    actor: Option<String>,
+    action: Option<String>,
+    target: Option<String>,
+    outcome: Option<AuditOutcome>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    before_id: Option<i64>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditEventInfo {
+    pub id: i64,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub outcome: AuditOutcome,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_date: DateTime<Utc>,
+    pub hash: Option<String>,
+}
+
+impl From<AuditEvent> for AuditEventInfo {
+    fn from(event: AuditEvent) -> Self {
+        AuditEventInfo {
+            id: event.id,
+            actor: event.actor,
+            action: event.action,
+            target: event.target,
+            outcome: event.outcome,
+            ip: event.ip,
+            user_agent: event.user_agent,
+            created_date: event.created_date,
+            hash: event.hash,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VerifyResponse {
+    pub valid: bool,
+    pub checked_events: i64,
+    pub first_invalid_id: Option<i64>,
+    pub last_hash: Option<String>,
+}
+
+/// quotes fields when needed, and defuses values that spreadsheets would run as formulas
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{value}")
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+fn to_csv(events: &[AuditEventInfo]) -> String {
+    let mut csv =
+        String::from("id,created_date,actor,action,target,outcome,ip,user_agent,hash\r\n");
+    for event in events {
+        let fields = [
+            event.id.to_string(),
+            event.created_date.to_rfc3339(),
+            event.actor.clone(),
+            event.action.clone(),
+            event.target.clone(),
+            event.outcome.as_str().to_string(),
+            event.ip.clone().unwrap_or_default(),
+            event.user_agent.clone().unwrap_or_default(),
+            event.hash.clone().unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// newest events first, older pages can be fetched with `before_id`
+#[get("/admin/audit_log")]
+pub async fn query_audit_log(
+    args: Query<AuditLogArgs>,
+    admin: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<HttpResponse> {
+    admin.require_permission(&pool, "audit_log:read").await?;
+    if !(1..=MAX_LIMIT).contains(&args.limit) {
+        return Err(ApiError::BadArgument {
+            argument_name: "limit",
+        });
+    }
+
+    let args = args.into_inner();
+    let filter = AuditFilter {
+        tenant_id: Some(admin.tenant_id),
+        actor: args.actor,
+        action: args.action,
+        target: args.target,
+        outcome: args.outcome,
+        since: args.since,
+        until: args.until,
+        before_id: args.before_id,
+    };
+    let events: Vec<AuditEventInfo> = get_audit_events(&pool, &filter, args.limit)
+        .await?
+        .into_iter()
+        .map(AuditEventInfo::from)
+        .collect();
+
+    Ok(match args.format {
+        ExportFormat::Json => HttpResponse::Ok().json(events),
+        ExportFormat::Jsonl => {
+            let mut body = String::new();
+            for event in &events {
+                body.push_str(&serde_json::to_string(event).unwrap());
+                body.push('\n');
+            }
+            HttpResponse::Ok()
+                .content_type("application/x-ndjson")
+                .body(body)
+        }
+        ExportFormat::Csv => HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"audit_log.csv\"",
+            ))
+            .body(to_csv(&events)),
+    })
+}
+
+/// the chain runs through the events of every tenant
+#[get("/admin/audit_log/verify")]
+pub async fn verify_audit_log(
+    admin: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<VerifyResponse>> {
+    admin
+        .require_global_permission(&pool, "audit_log:read")
+        .await?;
+    let verification = verify_audit_chain(&pool).await?;
+    Ok(Json(VerifyResponse {
+        valid: verification.first_invalid_id.is_none(),
+        checked_events: verification.checked_events,
+        first_invalid_id: verification.first_invalid_id,
+        last_hash: verification.last_hash,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audit::RequestInfo,
+        db::{roles::assign_role, tenants::DEFAULT_TENANT_ID},
+        test::helper::{create_test_db, create_test_user_with_session, test_user_id},
+    };
+    use actix_web::{
+        http::{header::AUTHORIZATION, StatusCode},
+        test::{self, TestRequest},
+        App,
+    };
+
+    #[test]
+    fn csv_field_should_escape() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("=cmd()"), "'=cmd()");
+    }
+
+    #[actix_web::test]
+    async fn query_and_export_audit_log() {
+        let db = create_test_db().await;
+        let admin_token = create_test_user_with_session(&db, "admin@gmail.com").await;
+        let user_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let admin_id = test_user_id(&db, "admin@gmail.com").await;
+        assign_role(&db, admin_id, "admin").await.unwrap();
+
+        // events of other tenants are part of the chain but never listed
+        let shop_info = RequestInfo {
+            tenant_id: "shop".to_string(),
+            ip: None,
+            user_agent: None,
+            now: Utc::now(),
+        };
+        shop_info
+            .audit(
+                &db,
+                "pouya@gmail.com",
+                "user.login",
+                "pouya@gmail.com",
+                AuditOutcome::Failure,
+            )
+            .await
+            .unwrap();
+        let info = RequestInfo {
+            tenant_id: DEFAULT_TENANT_ID.to_string(),
+            ip: Some("10.0.0.1".to_string()),
+            user_agent: Some("curl, probably".to_string()),
+            now: Utc::now(),
+        };
+        info.audit(
+            &db,
+            "arian@gmail.com",
+            "user.login",
+            "arian@gmail.com",
+            AuditOutcome::Failure,
+        )
+        .await
+        .unwrap();
+        info.audit(
+            &db,
+            "arian@gmail.com",
+            "user.login",
+            "arian@gmail.com",
+            AuditOutcome::Success,
+        )
+        .await
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .service(query_audit_log)
+                .service(verify_audit_log),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/admin/audit_log")
+            .insert_header((AUTHORIZATION, format!("Bearer {user_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let req = TestRequest::get()
+            .uri("/admin/audit_log?outcome=failure")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let events: Vec<AuditEventInfo> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].ip.as_deref(), Some("10.0.0.1"));
+
+        let req = TestRequest::get()
+            .uri("/admin/audit_log?format=jsonl")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let lines: Vec<AuditEventInfo> = String::from_utf8(body.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].outcome, AuditOutcome::Success);
+
+        let req = TestRequest::get()
+            .uri("/admin/audit_log?format=csv&since=2000-01-01T00:00:00Z")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let csv = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv.contains("\"curl, probably\""));
+
+        let req = TestRequest::get()
+            .uri("/admin/audit_log/verify")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let verification: VerifyResponse = test::call_and_read_body_json(&app, req).await;
+        assert!(verification.valid);
+        assert_eq!(verification.checked_events, 3);
+        assert_eq!(verification.last_hash, lines[0].hash);
+    }
+}
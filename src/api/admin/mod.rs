@@ -0,0 +1,3 @@
+pub mod audit_log;
+pub mod tenants;
+pub mod users;
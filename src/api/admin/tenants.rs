@@ -0,0 +1,466 @@
+use crate::{
+    audit::RequestInfo,
+    auth::AuthenticatedUser,
+    db::{
+        audit_log::AuditOutcome,
+        tenants::{get_tenant, get_tenants, insert_tenant, update_tenant, Tenant, TenantSettings},
+        user::{update_canonical_email_addresses, MAX_PASSWORD_HISTORY},
+        DbPool,
+    },
+    email_domains::normalize_pattern,
+    error::{ApiError, ApiResult},
+    utils::{password_policy::CharacterClass, validators::MAX_PASSWORD_LENGTH},
+};
+use actix_web::{
+    get, post, put,
+    web::{Data, Json, Path},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct CreateTenantArgs {
+    id: String,
+    name: String,
+    host: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateTenantArgs {
+    pub name: String,
+    pub host: Option<String>,
+    /// `*.example.com` stands for the subdomains of example.com
+    #[serde(default)]
+    pub allowed_email_domains: Vec<String>,
+    #[serde(default)]
+    pub denied_email_domains: Vec<String>,
+    #[serde(default = "default_true")]
+    pub block_disposable_email_domains: bool,
+    #[serde(default = "default_true")]
+    pub lowercase_email_local_part: bool,
+    /// ignore the dots and `+tags` of providers like gmail
+    #[serde(default)]
+    pub canonicalize_email_providers: bool,
+    pub min_password_length: i64,
+    #[serde(default = "default_max_password_length")]
+    pub max_password_length: i64,
+    /// lowercase, uppercase, digit or symbol
+    #[serde(default)]
+    pub password_character_classes: Vec<String>,
+    #[serde(default = "default_min_password_score")]
+    pub min_password_score: i64,
+    /// 0 allows reusing any password
+    #[serde(default)]
+    pub password_history_size: i64,
+    /// 0 means passwords never expire
+    #[serde(default)]
+    pub password_max_age_days: i64,
+    #[serde(default)]
+    pub mfa_required: bool,
+    pub email_code_subject: String,
+    /// `{code}` is replaced with the code
+    pub email_code_template: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TenantInfo {
+    pub id: String,
+    pub name: String,
+    pub host: Option<String>,
+    pub allowed_email_domains: Vec<String>,
+    pub denied_email_domains: Vec<String>,
+    pub block_disposable_email_domains: bool,
+    pub lowercase_email_local_part: bool,
+    pub canonicalize_email_providers: bool,
+    pub min_password_length: i64,
+    pub max_password_length: i64,
+    pub password_character_classes: Vec<CharacterClass>,
+    pub min_password_score: i64,
+    pub password_history_size: i64,
+    pub password_max_age_days: i64,
+    pub mfa_required: bool,
+    pub email_code_subject: String,
+    pub email_code_template: String,
+    pub created_date: DateTime<Utc>,
+}
+
+impl From<Tenant> for TenantInfo {
+    fn from(tenant: Tenant) -> Self {
+        TenantInfo {
+            id: tenant.id,
+            name: tenant.name,
+            host: tenant.host,
+            allowed_email_domains: tenant.settings.allowed_email_domains,
+            denied_email_domains: tenant.settings.denied_email_domains,
+            block_disposable_email_domains: tenant.settings.block_disposable_email_domains,
+            lowercase_email_local_part: tenant.settings.lowercase_email_local_part,
+            canonicalize_email_providers: tenant.settings.canonicalize_email_providers,
+            min_password_length: tenant.settings.min_password_length,
+            max_password_length: tenant.settings.max_password_length,
+            password_character_classes: tenant.settings.password_character_classes,
+            min_password_score: tenant.settings.min_password_score,
+            password_history_size: tenant.settings.password_history_size,
+            password_max_age_days: tenant.settings.password_max_age_days,
+            mfa_required: tenant.settings.mfa_required,
+            email_code_subject: tenant.settings.email_code_subject,
+            email_code_template: tenant.settings.email_code_template,
+            created_date: tenant.created_date,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_max_password_length() -> i64 {
+    64
+}
+
+fn default_min_password_score() -> i64 {
+    2
+}
+
+/// ids end up in paths and headers, so they are kept to a safe set of characters
+fn validate_tenant_id(id: &str) -> ApiResult<()> {
+    let is_valid = (1..=64).contains(&id.len())
+        && id
+            .chars()
+            .all(|char| char.is_ascii_lowercase() || char.is_ascii_digit() || char == '-');
+    is_valid.then_some(()).ok_or(ApiError::BadArgument {
+        argument_name: "id",
+    })
+}
+
+fn validate_tenant_name(name: &str) -> ApiResult<()> {
+    (1..=64)
+        .contains(&name.len())
+        .then_some(())
+        .ok_or(ApiError::BadArgument {
+            argument_name: "name",
+        })
+}
+
+fn parse_character_classes(classes: &[String]) -> ApiResult<Vec<CharacterClass>> {
+    classes
+        .iter()
+        .map(|class| {
+            CharacterClass::parse(class).ok_or(ApiError::BadArgument {
+                argument_name: "password_character_classes",
+            })
+        })
+        .collect()
+}
+
+/// the patterns in the form they're matched in
+fn normalize_domain_patterns(
+    patterns: &[String],
+    argument_name: &'static str,
+) -> ApiResult<Vec<String>> {
+    patterns
+        .iter()
+        .map(|pattern| normalize_pattern(pattern).ok_or(ApiError::BadArgument { argument_name }))
+        .collect()
+}
+
+fn validate_settings(args: &UpdateTenantArgs) -> ApiResult<()> {
+    if !(1..=64).contains(&args.min_password_length) {
+        return Err(ApiError::BadArgument {
+            argument_name: "min_password_length",
+        });
+    }
+    if !(args.min_password_length..=MAX_PASSWORD_LENGTH as i64).contains(&args.max_password_length)
+    {
+        return Err(ApiError::BadArgument {
+            argument_name: "max_password_length",
+        });
+    }
+    if !(0..=4).contains(&args.min_password_score) {
+        return Err(ApiError::BadArgument {
+            argument_name: "min_password_score",
+        });
+    }
+    if !(0..=MAX_PASSWORD_HISTORY as i64).contains(&args.password_history_size) {
+        return Err(ApiError::BadArgument {
+            argument_name: "password_history_size",
+        });
+    }
+    if !(0..=3650).contains(&args.password_max_age_days) {
+        return Err(ApiError::BadArgument {
+            argument_name: "password_max_age_days",
+        });
+    }
+    if args.email_code_subject.is_empty() {
+        return Err(ApiError::BadArgument {
+            argument_name: "email_code_subject",
+        });
+    }
+    if !args.email_code_template.contains("{code}") {
+        return Err(ApiError::BadArgument {
+            argument_name: "email_code_template",
+        });
+    }
+    Ok(())
+}
+
+#[post("/admin/tenants")]
+pub async fn create_tenant(
+    args: Json<CreateTenantArgs>,
+    admin: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<TenantInfo>> {
+    admin
+        .require_global_permission(&pool, "tenants:write")
+        .await?;
+    validate_tenant_id(&args.id)?;
+    validate_tenant_name(&args.name)?;
+
+    insert_tenant(&pool, &args.id, &args.name, args.host.as_deref()).await?;
+    info.audit(
+        &pool,
+        &admin.email_address,
+        "tenant.create",
+        &args.id,
+        AuditOutcome::Success,
+    )
+    .await?;
+    let tenant = get_tenant(&pool, &args.id)
+        .await?
+        .ok_or(ApiError::UnknownTenant)?;
+    Ok(Json(tenant.into()))
+}
+
+#[get("/admin/tenants")]
+pub async fn list_tenants(
+    admin: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<Vec<TenantInfo>>> {
+    admin
+        .require_global_permission(&pool, "tenants:read")
+        .await?;
+    let tenants = get_tenants(&pool)
+        .await?
+        .into_iter()
+        .map(TenantInfo::from)
+        .collect();
+    Ok(Json(tenants))
+}
+
+#[put("/admin/tenants/{id}")]
+pub async fn update_tenant_settings(
+    id: Path<String>,
+    args: Json<UpdateTenantArgs>,
+    admin: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    admin
+        .require_global_permission(&pool, "tenants:write")
+        .await?;
+    validate_tenant_name(&args.name)?;
+    validate_settings(&args)?;
+
+    let args = args.into_inner();
+    let settings = TenantSettings {
+        allowed_email_domains: normalize_domain_patterns(
+            &args.allowed_email_domains,
+            "allowed_email_domains",
+        )?,
+        denied_email_domains: normalize_domain_patterns(
+            &args.denied_email_domains,
+            "denied_email_domains",
+        )?,
+        block_disposable_email_domains: args.block_disposable_email_domains,
+        lowercase_email_local_part: args.lowercase_email_local_part,
+        canonicalize_email_providers: args.canonicalize_email_providers,
+        min_password_length: args.min_password_length,
+        max_password_length: args.max_password_length,
+        password_character_classes: parse_character_classes(&args.password_character_classes)?,
+        min_password_score: args.min_password_score,
+        password_history_size: args.password_history_size,
+        password_max_age_days: args.password_max_age_days,
+        mfa_required: args.mfa_required,
+        email_code_subject: args.email_code_subject,
+        email_code_template: args.email_code_template,
+    };
+    let old_settings = get_tenant(&pool, &id)
+        .await?
+        .ok_or(ApiError::UnknownTenant)?
+        .settings;
+    if !update_tenant(&pool, &id, &args.name, args.host.as_deref(), &settings).await? {
+        return Err(ApiError::UnknownTenant);
+    }
+    if settings.email_canonicalization() != old_settings.email_canonicalization() {
+        update_canonical_email_addresses(&pool, &id).await?;
+    }
+    info.audit(
+        &pool,
+        &admin.email_address,
+        "tenant.update",
+        &id,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{
+            roles::assign_role, sessions::insert_session, tenants::DEFAULT_TENANT_ID,
+            user::insert_user,
+        },
+        tenant::TENANT_HEADER,
+        test::helper::{create_test_db, create_test_user_with_session, test_user_id},
+        utils::{hash::sha256_hash, random::generate_random_token},
+    };
+    use actix_web::{
+        http::{
+            header::{ContentType, AUTHORIZATION},
+            StatusCode,
+        },
+        test::{self, TestRequest},
+        App,
+    };
+    use chrono::Duration;
+
+    #[actix_web::test]
+    async fn manage_tenants() {
+        let db = create_test_db().await;
+        let admin_token = create_test_user_with_session(&db, "admin@gmail.com").await;
+        let admin_id = test_user_id(&db, "admin@gmail.com").await;
+        assign_role(&db, admin_id, "admin").await.unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .service(create_tenant)
+                .service(list_tenants)
+                .service(update_tenant_settings),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/admin/tenants")
+            .set_payload(r#"{"id": "Not Valid", "name": "Shop"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::post()
+            .uri("/admin/tenants")
+            .set_payload(r#"{"id": "shop", "name": "Shop", "host": "shop.example.com"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let tenant: TenantInfo = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(tenant.host.as_deref(), Some("shop.example.com"));
+        assert_eq!(tenant.min_password_length, 5);
+
+        let settings = UpdateTenantArgs {
+            name: "Shop".to_string(),
+            host: None,
+            allowed_email_domains: vec!["shop.com".to_string(), "*.Shop.com".to_string()],
+            denied_email_domains: vec!["not a domain".to_string()],
+            block_disposable_email_domains: true,
+            lowercase_email_local_part: true,
+            canonicalize_email_providers: true,
+            min_password_length: 12,
+            max_password_length: 64,
+            password_character_classes: vec!["digit".to_string()],
+            min_password_score: 3,
+            password_history_size: 5,
+            password_max_age_days: 90,
+            mfa_required: true,
+            email_code_subject: "Welcome to the shop".to_string(),
+            email_code_template: "no code here".to_string(),
+        };
+        let req = TestRequest::put()
+            .uri("/admin/tenants/shop")
+            .set_json(&settings)
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let settings = UpdateTenantArgs {
+            denied_email_domains: vec!["spam.shop.com".to_string()],
+            ..settings
+        };
+        let req = TestRequest::put()
+            .uri("/admin/tenants/shop")
+            .set_json(&settings)
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let settings = UpdateTenantArgs {
+            email_code_template: "your shop code: {code}".to_string(),
+            ..settings
+        };
+        let req = TestRequest::put()
+            .uri("/admin/tenants/shop")
+            .set_json(&settings)
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = TestRequest::put()
+            .uri("/admin/tenants/nope")
+            .set_json(&settings)
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let req = TestRequest::get()
+            .uri("/admin/tenants")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let tenants: Vec<TenantInfo> = test::call_and_read_body_json(&app, req).await;
+        let ids: Vec<&str> = tenants.iter().map(|tenant| tenant.id.as_str()).collect();
+        assert_eq!(ids, vec![DEFAULT_TENANT_ID, "shop"]);
+        assert_eq!(tenants[1].min_password_length, 12);
+        assert_eq!(
+            tenants[1].password_character_classes,
+            vec![CharacterClass::Digit]
+        );
+        assert!(tenants[1].mfa_required);
+        assert_eq!(
+            tenants[1].allowed_email_domains,
+            vec!["shop.com", "*.shop.com"]
+        );
+        assert_eq!(tenants[1].denied_email_domains, vec!["spam.shop.com"]);
+        assert_eq!(tenants[1].password_history_size, 5);
+        assert_eq!(tenants[1].password_max_age_days, 90);
+
+        // admins of other tenants can't manage tenants
+        let shop_admin_id = insert_user(&db, "shop", "admin", "password", "admin@shop.com")
+            .await
+            .unwrap();
+        assign_role(&db, shop_admin_id, "admin").await.unwrap();
+        let shop_admin_token = generate_random_token();
+        insert_session(
+            &db,
+            &sha256_hash(&shop_admin_token),
+            shop_admin_id,
+            Utc::now() + Duration::days(1),
+        )
+        .await
+        .unwrap();
+        let req = TestRequest::get()
+            .uri("/admin/tenants")
+            .insert_header((AUTHORIZATION, format!("Bearer {shop_admin_token}")))
+            .insert_header((TENANT_HEADER, "shop"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+}
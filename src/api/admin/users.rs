@@ -0,0 +1,748 @@
+use super::audit_log::AuditEventInfo;
+use crate::{
+    audit::RequestInfo,
+    auth::AuthenticatedUser,
+    breached_passwords::BreachedPasswords,
+    clock::Clock,
+    config::EmailCodePurpose,
+    db::{
+        audit_log::{get_audit_events, AuditFilter, AuditOutcome},
+        email_codes::insert_or_update_email_code,
+        oauth::delete_refresh_tokens_of_user,
+        roles::get_user_roles,
+        sessions::delete_sessions_of_user,
+        tenants::Tenant,
+        user::{
+            change_user_email_address, count_users, delete_user as delete_user_row, get_user,
+            insert_user, search_users, set_password_reset_required, set_user_status,
+            transition_user_status, AccountStatus, User, UserFilter,
+        },
+        DbPool,
+    },
+    email_sender::{EmailSender, Message},
+    error::{ApiError, ApiResult},
+    rng::RngProvider,
+    utils::{hash::sha256_hash, random::generate_six_digit_code, validators::*},
+};
+use actix_web::{
+    delete, get, post, put,
+    web::{Data, Json, Path, Query},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+const MAX_PER_PAGE: i64 = 100;
+const HISTORY_LENGTH: i64 = 50;
+
+#[derive(Deserialize)]
+pub struct ListUsersArgs {
+    search: Option<String>,
+    status: Option<String>,
+    role: Option<String>,
+    #[serde(default = "default_page")]
+    page: i64,
+    #[serde(default = "default_per_page")]
+    per_page: i64,
+}
+
+fn default_page() -> i64 {
+    1
+}
+
+fn default_per_page() -> i64 {
+    20
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UserInfo {
+    pub email_address: String,
+    pub name: String,
+    pub status: String,
+    pub status_reason: Option<String>,
+    pub status_date: Option<DateTime<Utc>>,
+    pub password_reset_required: bool,
+    pub roles: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UserPage {
+    pub users: Vec<UserInfo>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UserDetails {
+    #[serde(flatten)]
+    pub user: UserInfo,
+    /// latest audit events about this user, newest first
+    pub history: Vec<AuditEventInfo>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateUserArgs {
+    email_address: String,
+    name: String,
+    password: String,
+    /// `pending` creates an account that has to be activated before it can be used
+    #[serde(default)]
+    pending: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SetStatusArgs {
+    pub status: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeEmailAddressArgs {
+    email_address: String,
+}
+
+async fn user_info(pool: &DbPool, user: User) -> ApiResult<UserInfo> {
+    Ok(UserInfo {
+        roles: get_user_roles(pool, user.id).await?,
+        email_address: user.email_address,
+        name: user.name,
+        status: user.status.as_str().to_string(),
+        status_reason: user.status_reason,
+        status_date: user.status_date,
+        password_reset_required: user.password_reset_required,
+    })
+}
+
+fn unknown_user() -> ApiError {
+    ApiError::BadArgument {
+        argument_name: "email_address",
+    }
+}
+
+/// admins only see the users of their own tenant
+async fn get_tenant_user(
+    pool: &DbPool,
+    admin: &AuthenticatedUser,
+    email_address: &str,
+) -> ApiResult<User> {
+    get_user(pool, &admin.tenant_id, email_address)
+        .await?
+        .ok_or(unknown_user())
+}
+
+fn parse_status(status: &str) -> ApiResult<AccountStatus> {
+    AccountStatus::parse(status).ok_or(ApiError::BadArgument {
+        argument_name: "status",
+    })
+}
+
+/// admins shouldn't lock themselves out by accident
+fn check_not_self(admin: &AuthenticatedUser, user: &User) -> ApiResult<()> {
+    (admin.id != user.id).then_some(()).ok_or(unknown_user())
+}
+
+#[get("/admin/users")]
+pub async fn list_users(
+    args: Query<ListUsersArgs>,
+    admin: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<UserPage>> {
+    admin.require_permission(&pool, "users:read").await?;
+    if args.page < 1 {
+        return Err(ApiError::BadArgument {
+            argument_name: "page",
+        });
+    }
+    if !(1..=MAX_PER_PAGE).contains(&args.per_page) {
+        return Err(ApiError::BadArgument {
+            argument_name: "per_page",
+        });
+    }
+
+    let filter = UserFilter {
+        search: args.search.clone(),
+        status: args.status.as_deref().map(parse_status).transpose()?,
+        role: args.role.clone(),
+    };
+    let offset = (args.page - 1) * args.per_page;
+    let mut users = vec![];
+    for user in search_users(&pool, &admin.tenant_id, &filter, args.per_page, offset).await? {
+        users.push(user_info(&pool, user).await?);
+    }
+
+    Ok(Json(UserPage {
+        users,
+        total: count_users(&pool, &admin.tenant_id, &filter).await?,
+        page: args.page,
+        per_page: args.per_page,
+    }))
+}
+
+#[get("/admin/users/{email_address}")]
+pub async fn get_user_details(
+    email_address: Path<String>,
+    admin: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<UserDetails>> {
+    admin.require_permission(&pool, "users:read").await?;
+    let user = get_tenant_user(&pool, &admin, &email_address).await?;
+    let filter = AuditFilter {
+        tenant_id: Some(admin.tenant_id.clone()),
+        target: Some(email_address.clone()),
+        ..Default::default()
+    };
+    let history = get_audit_events(&pool, &filter, HISTORY_LENGTH)
+        .await?
+        .into_iter()
+        .map(AuditEventInfo::from)
+        .collect();
+
+    Ok(Json(UserDetails {
+        user: user_info(&pool, user).await?,
+        history,
+    }))
+}
+
+/// the admin vouches for the email address, so no email code is needed
+#[post("/admin/users")]
+pub async fn create_user(
+    args: Json<CreateUserArgs>,
+    admin: AuthenticatedUser,
+    tenant: Tenant,
+    breached_passwords: Data<BreachedPasswords>,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    admin.require_permission(&pool, "users:write").await?;
+    validate_email_address(&args.email_address)?;
+    validate_name(&args.name)?;
+    validate_password(&args.password)?;
+    tenant.settings.check_password(
+        &args.password,
+        &[&args.name, &args.email_address],
+        &breached_passwords,
+    )?;
+
+    let hashed_password = sha256_hash(&args.password);
+    let user_id = insert_user(
+        &pool,
+        &admin.tenant_id,
+        &args.name,
+        &hashed_password,
+        &args.email_address,
+    )
+    .await?;
+    if args.pending {
+        set_user_status(&pool, user_id, AccountStatus::Pending, None).await?;
+    }
+    info.audit(
+        &pool,
+        &admin.email_address,
+        "user.create",
+        &args.email_address,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+/// logs the user out everywhere and emails a code for `reset_password`, login is refused until then
+#[post("/admin/users/{email_address}/password_reset")]
+pub async fn force_password_reset(
+    email_address: Path<String>,
+    admin: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+    email_sender: Data<dyn EmailSender + Send + Sync>,
+    clock: Data<dyn Clock + Send + Sync>,
+    rng: Data<dyn RngProvider + Send + Sync>,
+) -> ApiResult<&'static str> {
+    admin.require_permission(&pool, "users:write").await?;
+    let user = get_tenant_user(&pool, &admin, &email_address).await?;
+    set_password_reset_required(&pool, user.id).await?;
+    delete_sessions_of_user(&pool, user.id).await?;
+    delete_refresh_tokens_of_user(&pool, user.id).await?;
+
+    let random_code = generate_six_digit_code(&mut *rng.rng());
+    let message = Message {
+        to: email_address
+            .parse()
+            .map_err(|_| ApiError::InvalidEmailAddress)?,
+        subject: "Reset your password".to_string(),
+        body: format!("your password has to be reset, your reset code is: {random_code}"),
+    };
+    email_sender.send_email(message).await?;
+    insert_or_update_email_code(
+        &pool,
+        &admin.tenant_id,
+        &email_address,
+        EmailCodePurpose::PasswordReset,
+        random_code,
+        clock.now(),
+    )
+    .await?;
+
+    info.audit(
+        &pool,
+        &admin.email_address,
+        "user.force_password_reset",
+        &email_address,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+/// moves the account to another status, only active accounts can be used
+#[put("/admin/users/{email_address}/status")]
+pub async fn set_status(
+    email_address: Path<String>,
+    args: Json<SetStatusArgs>,
+    admin: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    admin.require_permission(&pool, "users:write").await?;
+    let status = parse_status(&args.status)?;
+    if let Some(reason) = &args.reason {
+        validate_status_reason(reason)?;
+    }
+    let user = get_tenant_user(&pool, &admin, &email_address).await?;
+    check_not_self(&admin, &user)?;
+    transition_user_status(&pool, &user, status, args.reason.as_deref()).await?;
+    info.audit(
+        &pool,
+        &admin.email_address,
+        &format!("user.set_status:{}", status.as_str()),
+        &email_address,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+#[put("/admin/users/{email_address}/email_address")]
+pub async fn change_email_address(
+    email_address: Path<String>,
+    args: Json<ChangeEmailAddressArgs>,
+    admin: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    admin.require_permission(&pool, "users:write").await?;
+    validate_email_address(&args.email_address)?;
+    let user = get_tenant_user(&pool, &admin, &email_address).await?;
+
+    change_user_email_address(&pool, user.id, &args.email_address).await?;
+    info.audit(
+        &pool,
+        &admin.email_address,
+        "user.change_email_address",
+        &args.email_address,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+#[delete("/admin/users/{email_address}")]
+pub async fn delete_user(
+    email_address: Path<String>,
+    admin: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    admin.require_permission(&pool, "users:write").await?;
+    let user = get_tenant_user(&pool, &admin, &email_address).await?;
+    check_not_self(&admin, &user)?;
+    delete_user_row(&pool, user.id).await?;
+    info.audit(
+        &pool,
+        &admin.email_address,
+        "user.delete",
+        &email_address,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::login::login,
+        config::Config,
+        db::{
+            roles::assign_role,
+            tenants::{insert_tenant, DEFAULT_TENANT_ID},
+        },
+        email_sender::MockEmailSender,
+        tenant::TENANT_HEADER,
+        test::helper::{
+            create_test_clock, create_test_db, create_test_rng, create_test_user_with_session,
+            test_user_id,
+        },
+    };
+    use actix_web::{
+        http::{
+            header::{ContentType, AUTHORIZATION},
+            StatusCode,
+        },
+        test::{self, TestRequest},
+        App,
+    };
+    use std::{future::ready, sync::Arc};
+
+    async fn create_admin(db: &DbPool) -> String {
+        let admin_token = create_test_user_with_session(db, "admin@gmail.com").await;
+        let admin_id = test_user_id(db, "admin@gmail.com").await;
+        assign_role(db, admin_id, "admin").await.unwrap();
+        admin_token
+    }
+
+    #[actix_web::test]
+    async fn list_and_view_users() {
+        let db = create_test_db().await;
+        let admin_token = create_admin(&db).await;
+        let user_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        create_test_user_with_session(&db, "pouya@yahoo.com").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .service(list_users)
+                .service(get_user_details),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/admin/users")
+            .insert_header((AUTHORIZATION, format!("Bearer {user_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let req = TestRequest::get()
+            .uri("/admin/users?search=gmail&per_page=1&page=2")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let page: UserPage = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(page.total, 2);
+        assert_eq!(page.users.len(), 1);
+        assert_eq!(page.users[0].email_address, "arian@gmail.com");
+
+        let req = TestRequest::get()
+            .uri("/admin/users?role=admin")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let page: UserPage = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(page.total, 1);
+        assert_eq!(page.users[0].roles, vec!["admin"]);
+
+        let req = TestRequest::get()
+            .uri("/admin/users?per_page=1000")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::get()
+            .uri("/admin/users/pouya@yahoo.com")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let details: UserDetails = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(details.user.name, "arian");
+        assert!(details.history.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn create_suspend_and_delete_user() {
+        let db = create_test_db().await;
+        let admin_token = create_admin(&db).await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(Config::default()))
+                .service(login)
+                .service(create_user)
+                .service(set_status)
+                .service(delete_user),
+        )
+        .await;
+        let login_request = || {
+            TestRequest::post()
+                .uri("/login")
+                .set_payload(
+                    r#"{"email_address": "arian@gmail.com", "password": "some_hard_password"}"#,
+                )
+                .insert_header(ContentType::json())
+                .to_request()
+        };
+
+        let req = TestRequest::post()
+            .uri("/admin/users")
+            .set_payload(r#"{"email_address": "arian@gmail.com", "name": "arian", "password": "some_hard_password"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(&app, login_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let status_request = |status: &str| {
+            TestRequest::put()
+                .uri("/admin/users/arian@gmail.com/status")
+                .set_json(SetStatusArgs {
+                    status: status.to_string(),
+                    reason: Some("chargeback".to_string()),
+                })
+                .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+                .to_request()
+        };
+        let resp = test::call_service(&app, status_request("suspended")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(&app, login_request()).await;
+        let body = test::read_body(resp).await;
+        assert_eq!(body, "account is suspended");
+        let user = get_user(&db, DEFAULT_TENANT_ID, "arian@gmail.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.status_reason.as_deref(), Some("chargeback"));
+
+        // suspended accounts have to be activated before they can be locked
+        let resp = test::call_service(&app, status_request("locked")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = test::call_service(&app, status_request("unknown")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = test::call_service(&app, status_request("active")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(&app, login_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // admins can't delete themselves
+        let req = TestRequest::delete()
+            .uri("/admin/users/admin@gmail.com")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::delete()
+            .uri("/admin/users/arian@gmail.com")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(get_user(&db, DEFAULT_TENANT_ID, "arian@gmail.com")
+            .await
+            .unwrap()
+            .is_none());
+
+        let filter = AuditFilter {
+            actor: Some("admin@gmail.com".to_string()),
+            target: Some("arian@gmail.com".to_string()),
+            ..Default::default()
+        };
+        let actions: Vec<String> = get_audit_events(&db, &filter, 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| event.action)
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                "user.delete",
+                "user.set_status:active",
+                "user.set_status:suspended",
+                "user.create"
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn pending_users_have_to_be_activated() {
+        let db = create_test_db().await;
+        let admin_token = create_admin(&db).await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(Config::default()))
+                .service(login)
+                .service(create_user)
+                .service(list_users)
+                .service(set_status),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/admin/users")
+            .set_payload(r#"{"email_address": "arian@gmail.com", "name": "arian", "password": "some_hard_password", "pending": true}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = TestRequest::get()
+            .uri("/admin/users?status=pending")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let page: UserPage = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(page.total, 1);
+        assert_eq!(page.users[0].email_address, "arian@gmail.com");
+        assert_eq!(page.users[0].status, "pending");
+
+        let login_request = || {
+            TestRequest::post()
+                .uri("/login")
+                .set_payload(
+                    r#"{"email_address": "arian@gmail.com", "password": "some_hard_password"}"#,
+                )
+                .insert_header(ContentType::json())
+                .to_request()
+        };
+        let resp = test::call_service(&app, login_request()).await;
+        assert_eq!(test::read_body(resp).await, "account isn't activated yet");
+
+        // admins can't suspend themselves
+        let req = TestRequest::put()
+            .uri("/admin/users/admin@gmail.com/status")
+            .set_json(SetStatusArgs {
+                status: "suspended".to_string(),
+                reason: None,
+            })
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::put()
+            .uri("/admin/users/arian@gmail.com/status")
+            .set_json(SetStatusArgs {
+                status: "active".to_string(),
+                reason: None,
+            })
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(&app, login_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn force_password_reset_should_work() {
+        let mut email_mock = MockEmailSender::new();
+        email_mock
+            .expect_send_email()
+            .withf(|message| message.to.to_string() == "arian@gmail.com")
+            .once()
+            .returning(|_| Box::pin(ready(Ok(()))));
+        let email_provider: Arc<dyn EmailSender + Send + Sync> = Arc::new(email_mock);
+
+        let db = create_test_db().await;
+        let admin_token = create_admin(&db).await;
+        let user_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(create_test_rng())
+                .app_data(Data::from(email_provider))
+                .service(force_password_reset)
+                .service(change_email_address),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/admin/users/arian@gmail.com/password_reset")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let user = get_user(&db, DEFAULT_TENANT_ID, "arian@gmail.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(user.password_reset_required);
+
+        // the session of the user doesn't work anymore
+        let req = TestRequest::put()
+            .uri("/admin/users/arian@gmail.com/email_address")
+            .set_payload(r#"{"email_address": "new@gmail.com"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {user_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let req = TestRequest::put()
+            .uri("/admin/users/arian@gmail.com/email_address")
+            .set_payload(r#"{"email_address": "new@gmail.com"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(get_user(&db, DEFAULT_TENANT_ID, "new@gmail.com")
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[actix_web::test]
+    async fn admins_only_see_their_tenant() {
+        let db = create_test_db().await;
+        let admin_token = create_admin(&db).await;
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+        insert_tenant(&db, "shop", "Shop", None).await.unwrap();
+        insert_user(&db, "shop", "pouya", "password", "pouya@gmail.com")
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .service(list_users)
+                .service(get_user_details),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/admin/users")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let page: UserPage = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(page.total, 2);
+        assert!(page
+            .users
+            .iter()
+            .all(|user| user.email_address != "pouya@gmail.com"));
+
+        let req = TestRequest::get()
+            .uri("/admin/users/pouya@gmail.com")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // being an admin of the default tenant doesn't make you one of the others
+        let req = TestRequest::get()
+            .uri("/admin/users")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .insert_header((TENANT_HEADER, "shop"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
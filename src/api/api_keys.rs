@@ -0,0 +1,254 @@
+use crate::{
+    auth::{AuthenticatedUser, API_KEY_PREFIX},
+    db::{
+        api_keys::{delete_api_key, get_api_keys_of_user, insert_api_key, ApiKey, NewApiKey},
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    utils::{
+        hash::sha256_hash,
+        random::{generate_random_alphanumeric, generate_random_token},
+        validators::validate_scope,
+    },
+};
+use actix_web::{
+    delete, get, post,
+    web::{Data, Json, Path},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyArgs {
+    name: String,
+    #[serde(default)]
+    scopes: Vec<String>,
+    expire_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateApiKeyResponse {
+    pub id: i64,
+    pub prefix: String,
+    /// only shown once, just its hash is stored
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiKeyInfo {
+    pub id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_date: DateTime<Utc>,
+    pub expire_date: Option<DateTime<Utc>>,
+    pub last_used_date: Option<DateTime<Utc>>,
+    pub last_used_ip: Option<String>,
+}
+
+impl From<ApiKey> for ApiKeyInfo {
+    fn from(key: ApiKey) -> Self {
+        ApiKeyInfo {
+            id: key.id,
+            name: key.name,
+            prefix: key.prefix,
+            scopes: key.scopes,
+            created_date: key.created_date,
+            expire_date: key.expire_date,
+            last_used_date: key.last_used_date,
+            last_used_ip: key.last_used_ip,
+        }
+    }
+}
+
+#[post("/api_keys")]
+pub async fn create_api_key(
+    args: Json<CreateApiKeyArgs>,
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<CreateApiKeyResponse>> {
+    user.require_session()?;
+    if args.name.is_empty() || args.name.len() > 64 {
+        return Err(ApiError::BadArgument {
+            argument_name: "name",
+        });
+    }
+    args.scopes
+        .iter()
+        .try_for_each(|scope| validate_scope(scope))?;
+    if args.expire_date.is_some_and(|date| date <= Utc::now()) {
+        return Err(ApiError::BadArgument {
+            argument_name: "expire_date",
+        });
+    }
+
+    let prefix = generate_random_alphanumeric(8);
+    let key = format!("{API_KEY_PREFIX}{prefix}_{}", generate_random_token());
+    let id = insert_api_key(
+        &pool,
+        &NewApiKey {
+            user_id: user.id,
+            name: &args.name,
+            prefix: &prefix,
+            key_hash: &sha256_hash(&key),
+            scopes: &args.scopes,
+            expire_date: args.expire_date,
+        },
+    )
+    .await?;
+
+    Ok(Json(CreateApiKeyResponse { id, prefix, key }))
+}
+
+#[get("/api_keys")]
+pub async fn list_api_keys(
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<Vec<ApiKeyInfo>>> {
+    user.require_scope("api_keys:read")?;
+    let keys = get_api_keys_of_user(&pool, user.id)
+        .await?
+        .into_iter()
+        .map(ApiKeyInfo::from)
+        .collect();
+    Ok(Json(keys))
+}
+
+#[delete("/api_keys/{id}")]
+pub async fn revoke_api_key(
+    id: Path<i64>,
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    user.require_scope("api_keys:write")?;
+    delete_api_key(&pool, user.id, *id)
+        .await?
+        .then_some("")
+        .ok_or(ApiError::BadArgument {
+            argument_name: "id",
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::helper::{create_test_db, create_test_user_with_session, test_user_id};
+    use actix_web::{
+        http::{
+            header::{ContentType, AUTHORIZATION},
+            StatusCode,
+        },
+        test::{self, TestRequest},
+        App,
+    };
+
+    #[actix_web::test]
+    async fn create_use_and_revoke_api_key() {
+        let db = create_test_db().await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .service(create_api_key)
+                .service(list_api_keys)
+                .service(revoke_api_key),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/api_keys")
+            .set_payload(r#"{"name": "ci", "scopes": ["api_keys:read"]}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+        let created: CreateApiKeyResponse = test::call_and_read_body_json(&app, req).await;
+        assert!(created.key.starts_with(&format!("ak_{}_", created.prefix)));
+
+        // the key itself works like a session, but its secret is never listed
+        let req = TestRequest::get()
+            .uri("/api_keys")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", created.key)))
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert!(!String::from_utf8_lossy(&body).contains(&created.key));
+        let keys: Vec<ApiKeyInfo> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].prefix, created.prefix);
+        assert_eq!(keys[0].last_used_ip.as_deref(), Some("10.0.0.1"));
+
+        // an api key can't create more api keys
+        let req = TestRequest::post()
+            .uri("/api_keys")
+            .set_payload(r#"{"name": "another"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {}", created.key)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // and it can only do what its scopes allow
+        let req = TestRequest::delete()
+            .uri(&format!("/api_keys/{}", created.id))
+            .insert_header((AUTHORIZATION, format!("Bearer {}", created.key)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let req = TestRequest::delete()
+            .uri(&format!("/api_keys/{}", created.id))
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = TestRequest::get()
+            .uri("/api_keys")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", created.key)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn expired_api_key_is_rejected() {
+        let db = create_test_db().await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .service(create_api_key)
+                .service(list_api_keys),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/api_keys")
+            .set_payload(r#"{"name": "ci", "expire_date": "2020-01-01T00:00:00Z"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        insert_api_key(
+            &db,
+            &NewApiKey {
+                user_id: test_user_id(&db, "arian@gmail.com").await,
+                name: "old",
+                prefix: "abcd1234",
+                key_hash: &sha256_hash("ak_abcd1234_secret"),
+                scopes: &[],
+                expire_date: Some(Utc::now() - chrono::Duration::seconds(1)),
+            },
+        )
+        .await
+        .unwrap();
+        let req = TestRequest::get()
+            .uri("/api_keys")
+            .insert_header((AUTHORIZATION, "Bearer ak_abcd1234_secret"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
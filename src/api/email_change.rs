@@ -0,0 +1,832 @@
+use crate::{
+    audit::RequestInfo,
+    auth::AuthenticatedUser,
+    clock::Clock,
+    config::Config,
+    db::{
+        email_changes::{
+            get_email_change_by_undo_token, get_pending_email_change, insert_email_change,
+            record_wrong_email_change_code, update_email_change_status, EmailChange,
+            EmailChangeStatus, NewEmailChange,
+        },
+        oauth::delete_refresh_tokens_of_user,
+        sessions::delete_sessions_of_user,
+        tenants::Tenant,
+        user::{change_user_email_address, does_user_exists, get_user, get_user_by_id},
+        DbPool,
+    },
+    email_domains::EmailDomainLists,
+    email_sender::{EmailSender, Message},
+    error::{ApiError, ApiResult},
+    rng::RngProvider,
+    utils::{
+        hash::sha256_hash,
+        random::{generate_random_token, generate_six_digit_code},
+        validators::*,
+    },
+    webhooks::{emit_event, EMAIL_CHANGED},
+};
+use actix_web::{
+    get, post,
+    web::{Data, Json, Query},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// a pending change is cancelled after this many wrong codes
+const MAX_CODE_ATTEMPTS: i64 = 5;
+
+#[derive(Serialize, Deserialize)]
+pub struct RequestEmailChangeArgs {
+    pub new_email_address: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ConfirmEmailChangeArgs {
+    pub code: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UndoEmailChangeArgs {
+    pub token: String,
+}
+
+/// what the old address sees before undoing the change
+#[derive(Serialize, Deserialize)]
+pub struct EmailChangeUndoPage {
+    pub old_email_address: String,
+    pub new_email_address: String,
+    /// `pending` or `confirmed`, undoing a confirmed change reverts the address
+    pub status: String,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn request_change(
+    pool: &DbPool,
+    config: &Config,
+    email_sender: &(dyn EmailSender + Send + Sync),
+    email_domain_lists: &EmailDomainLists,
+    clock: &(dyn Clock + Send + Sync),
+    rng: &(dyn RngProvider + Send + Sync),
+    tenant: &Tenant,
+    user: &AuthenticatedUser,
+    args: &RequestEmailChangeArgs,
+) -> ApiResult<()> {
+    let hashed_password = sha256_hash(&args.password);
+    if !does_user_exists(pool, &user.tenant_id, &user.email_address, &hashed_password).await? {
+        return Err(ApiError::WrongCredentials);
+    }
+    validate_email_address(&args.new_email_address)?;
+    tenant
+        .settings
+        .check_email_address(&args.new_email_address, email_domain_lists)?;
+    if args.new_email_address == user.email_address {
+        return Err(ApiError::BadArgument {
+            argument_name: "new_email_address",
+        });
+    }
+    if get_user(pool, &tenant.id, &args.new_email_address)
+        .await?
+        .is_some()
+    {
+        return Err(ApiError::RegisterDuplicate);
+    }
+
+    let code = generate_six_digit_code(&mut *rng.rng());
+    let undo_token = generate_random_token();
+    let now = clock.now();
+    insert_email_change(
+        pool,
+        &NewEmailChange {
+            user_id: user.id,
+            old_email_address: &user.email_address,
+            new_email_address: &args.new_email_address,
+            code,
+            undo_token_hash: &sha256_hash(&undo_token),
+            code_expire_date: now + config.email_change_code_lifetime,
+            undo_expire_date: now + config.email_change_undo_lifetime,
+        },
+    )
+    .await?;
+
+    email_sender
+        .send_email(Message {
+            to: args
+                .new_email_address
+                .parse()
+                .map_err(|_| ApiError::InvalidEmailAddress)?,
+            subject: "Confirm your new email address".to_string(),
+            body: format!("your confirmation code is: {code}"),
+        })
+        .await?;
+    email_sender
+        .send_email(Message {
+            to: user
+                .email_address
+                .parse()
+                .map_err(|_| ApiError::InvalidEmailAddress)?,
+            subject: "Your email address is being changed".to_string(),
+            body: format!(
+                "a change of your email address to {} was requested. \
+                if this wasn't you, undo it at {}?token={undo_token}",
+                args.new_email_address, config.email_change_undo_uri
+            ),
+        })
+        .await
+}
+
+/// sends a code to the new address and a notice with an undo link to the current one, the
+/// address only changes once the code is confirmed
+#[post("/me/email")]
+#[allow(clippy::too_many_arguments)]
+pub async fn request_email_change(
+    args: Json<RequestEmailChangeArgs>,
+    user: AuthenticatedUser,
+    tenant: Tenant,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    email_sender: Data<dyn EmailSender + Send + Sync>,
+    email_domain_lists: Data<EmailDomainLists>,
+    clock: Data<dyn Clock + Send + Sync>,
+    rng: Data<dyn RngProvider + Send + Sync>,
+) -> ApiResult<&'static str> {
+    user.require_session()?;
+    let result = request_change(
+        &pool,
+        &config,
+        email_sender.as_ref(),
+        &email_domain_lists,
+        clock.as_ref(),
+        rng.as_ref(),
+        &tenant,
+        &user,
+        &args,
+    )
+    .await;
+    info.audit_result(
+        &pool,
+        &user.email_address,
+        "user.request_email_change",
+        &args.new_email_address,
+        result,
+    )
+    .await?;
+    Ok("")
+}
+
+async fn confirm_change(
+    pool: &DbPool,
+    clock: &(dyn Clock + Send + Sync),
+    user: &AuthenticatedUser,
+    code: u32,
+) -> ApiResult<EmailChange> {
+    let change = get_pending_email_change(pool, user.id)
+        .await?
+        .ok_or(ApiError::ExpiredEmailCode)?;
+    if change.code_expire_date <= clock.now() || change.attempts >= MAX_CODE_ATTEMPTS {
+        return Err(ApiError::ExpiredEmailCode);
+    }
+    if change.code != code {
+        record_wrong_email_change_code(pool, change.id).await?;
+        return Err(ApiError::WrongEmailCode);
+    }
+
+    if !update_email_change_status(
+        pool,
+        change.id,
+        EmailChangeStatus::Pending,
+        EmailChangeStatus::Confirmed,
+    )
+    .await?
+    {
+        return Err(ApiError::ExpiredEmailCode);
+    }
+    // sessions point at the user id and keep working, email codes of the old address are dropped
+    let changed = change_user_email_address(pool, user.id, &change.new_email_address).await;
+    if changed.is_err() {
+        update_email_change_status(
+            pool,
+            change.id,
+            EmailChangeStatus::Confirmed,
+            EmailChangeStatus::Cancelled,
+        )
+        .await?;
+    }
+    changed.map(|_| change)
+}
+
+#[post("/me/email/confirm")]
+pub async fn confirm_email_change(
+    args: Json<ConfirmEmailChangeArgs>,
+    user: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+    clock: Data<dyn Clock + Send + Sync>,
+) -> ApiResult<&'static str> {
+    user.require_session()?;
+    let result = confirm_change(&pool, clock.as_ref(), &user, args.code).await;
+    let change = info
+        .audit_result(
+            &pool,
+            &user.email_address,
+            "user.change_email",
+            &user.email_address,
+            result,
+        )
+        .await?;
+    emit_event(
+        &pool,
+        EMAIL_CHANGED,
+        json!({
+            "tenant_id": user.tenant_id,
+            "old_email_address": change.old_email_address,
+            "email_address": change.new_email_address,
+        }),
+    )
+    .await?;
+    Ok("")
+}
+
+/// the change the undo token belongs to, if it can still be undone
+async fn undoable_change(
+    pool: &DbPool,
+    clock: &(dyn Clock + Send + Sync),
+    tenant: &Tenant,
+    token: &str,
+) -> ApiResult<EmailChange> {
+    let change = get_email_change_by_undo_token(pool, &sha256_hash(token))
+        .await?
+        .ok_or(ApiError::InvalidEmailChange)?;
+    let user = get_user_by_id(pool, change.user_id)
+        .await?
+        .ok_or(ApiError::InvalidEmailChange)?;
+    if user.tenant_id != tenant.id
+        || change.undo_expire_date <= clock.now()
+        || !matches!(
+            change.status,
+            EmailChangeStatus::Pending | EmailChangeStatus::Confirmed
+        )
+    {
+        return Err(ApiError::InvalidEmailChange);
+    }
+    Ok(change)
+}
+
+async fn undo_change(
+    pool: &DbPool,
+    clock: &(dyn Clock + Send + Sync),
+    tenant: &Tenant,
+    token: &str,
+) -> ApiResult<EmailChange> {
+    let change = undoable_change(pool, clock, tenant, token).await?;
+    match change.status {
+        EmailChangeStatus::Pending => {
+            update_email_change_status(
+                pool,
+                change.id,
+                EmailChangeStatus::Pending,
+                EmailChangeStatus::Cancelled,
+            )
+            .await?
+            .then_some(())
+            .ok_or(ApiError::InvalidEmailChange)?;
+        }
+        EmailChangeStatus::Confirmed => {
+            // reverting first, a revert that fails (the old address was taken in the meantime)
+            // leaves the change confirmed and the token usable
+            change_user_email_address(pool, change.user_id, &change.old_email_address).await?;
+            if !update_email_change_status(
+                pool,
+                change.id,
+                EmailChangeStatus::Confirmed,
+                EmailChangeStatus::Undone,
+            )
+            .await?
+            {
+                return Err(ApiError::InvalidEmailChange);
+            }
+            // whoever changed the address may still be logged in
+            delete_sessions_of_user(pool, change.user_id).await?;
+            delete_refresh_tokens_of_user(pool, change.user_id).await?;
+        }
+        EmailChangeStatus::Cancelled | EmailChangeStatus::Undone => {
+            return Err(ApiError::InvalidEmailChange)
+        }
+    }
+    Ok(change)
+}
+
+async fn undo_and_audit(
+    pool: &DbPool,
+    clock: &(dyn Clock + Send + Sync),
+    tenant: &Tenant,
+    info: &RequestInfo,
+    token: &str,
+) -> ApiResult<()> {
+    let result = undo_change(pool, clock, tenant, token).await;
+    let target = match &result {
+        Ok(change) => change.old_email_address.clone(),
+        Err(_) => "unknown".to_string(),
+    };
+    let change = info
+        .audit_result(pool, &target, "user.undo_email_change", &target, result)
+        .await?;
+    // the status from before the undo, a confirmed change was reverted
+    if change.status == EmailChangeStatus::Confirmed {
+        emit_event(
+            pool,
+            EMAIL_CHANGED,
+            json!({
+                "tenant_id": tenant.id,
+                "old_email_address": change.new_email_address,
+                "email_address": change.old_email_address,
+            }),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// cancels a pending change, or reverts a confirmed one and logs the account out everywhere
+#[post("/email_change/undo")]
+pub async fn undo_email_change(
+    args: Json<UndoEmailChangeArgs>,
+    tenant: Tenant,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+    clock: Data<dyn Clock + Send + Sync>,
+) -> ApiResult<&'static str> {
+    undo_and_audit(&pool, clock.as_ref(), &tenant, &info, &args.token).await?;
+    Ok("")
+}
+
+/// the undo link mailed to the old address. it only shows the change, mail scanners open
+/// links on their own, so undoing takes a `undo_email_change` with the same token
+#[get("/email_change/undo")]
+pub async fn email_change_undo_page(
+    args: Query<UndoEmailChangeArgs>,
+    tenant: Tenant,
+    pool: Data<DbPool>,
+    clock: Data<dyn Clock + Send + Sync>,
+) -> ApiResult<Json<EmailChangeUndoPage>> {
+    let change = undoable_change(&pool, clock.as_ref(), &tenant, &args.token).await?;
+    Ok(Json(EmailChangeUndoPage {
+        old_email_address: change.old_email_address,
+        new_email_address: change.new_email_address,
+        status: change.status.as_str().to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::FakeClock,
+        config::EmailCodePurpose,
+        db::{
+            email_codes::{get_last_sent_email_code, insert_or_update_email_code},
+            sessions::get_session,
+            tenants::DEFAULT_TENANT_ID,
+            user::{delete_user, insert_user},
+        },
+        email_sender::MockEmailSender,
+        rng::SeededRngProvider,
+        test::helper::{
+            create_test_clock, create_test_db, create_test_rng, create_test_user_with_session,
+            test_user_id,
+        },
+    };
+    use actix_web::{
+        http::{header::AUTHORIZATION, StatusCode},
+        test::{self, TestRequest},
+        App,
+    };
+    use chrono::{Duration, Utc};
+    use std::{
+        future::ready,
+        sync::{Arc, Mutex},
+    };
+
+    /// an email sender that keeps every message it sends
+    fn capturing_email_sender() -> (Arc<dyn EmailSender + Send + Sync>, Arc<Mutex<Vec<Message>>>) {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let sent = messages.clone();
+        let mut email_mock = MockEmailSender::new();
+        email_mock.expect_send_email().returning(move |message| {
+            sent.lock().unwrap().push(message);
+            Box::pin(ready(Ok(())))
+        });
+        (Arc::new(email_mock), messages)
+    }
+
+    fn last_word(text: &str) -> String {
+        text.split_whitespace().last().unwrap().to_string()
+    }
+
+    fn request_change_request(token: &str, new_email_address: &str) -> TestRequest {
+        TestRequest::post()
+            .uri("/me/email")
+            .set_json(RequestEmailChangeArgs {
+                new_email_address: new_email_address.to_string(),
+                password: "some_hard_password".to_string(),
+            })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+    }
+
+    #[actix_web::test]
+    async fn change_email_address_with_confirmation() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "old@gmail.com").await;
+        let user_id = test_user_id(&db, "old@gmail.com").await;
+        insert_user(
+            &db,
+            DEFAULT_TENANT_ID,
+            "taken",
+            "password",
+            "taken@gmail.com",
+        )
+        .await
+        .unwrap();
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "old@gmail.com",
+            EmailCodePurpose::Registration,
+            123456,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        let (email_sender, messages) = capturing_email_sender();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(create_test_rng())
+                .app_data(Data::new(Config::default()))
+                .app_data(Data::from(email_sender))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(request_email_change)
+                .service(confirm_email_change),
+        )
+        .await;
+
+        let req = request_change_request(&token, "taken@gmail.com").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = request_change_request(&token, "new@gmail.com").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let (code, notice) = {
+            let messages = messages.lock().unwrap();
+            assert_eq!(messages.len(), 2);
+            assert_eq!(messages[0].to.to_string(), "new@gmail.com");
+            assert_eq!(messages[1].to.to_string(), "old@gmail.com");
+            assert!(messages[1].body.contains("?token="));
+            (
+                last_word(&messages[0].body).parse::<u32>().unwrap(),
+                messages[1].body.clone(),
+            )
+        };
+        assert!(notice.contains("new@gmail.com"));
+
+        // nothing changes before the confirmation
+        assert!(get_user(&db, DEFAULT_TENANT_ID, "old@gmail.com")
+            .await
+            .unwrap()
+            .is_some());
+
+        let req = TestRequest::post()
+            .uri("/me/email/confirm")
+            .set_json(ConfirmEmailChangeArgs { code: code + 1 })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::post()
+            .uri("/me/email/confirm")
+            .set_json(ConfirmEmailChangeArgs { code })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let user = get_user_by_id(&db, user_id).await.unwrap().unwrap();
+        assert_eq!(user.email_address, "new@gmail.com");
+        assert!(get_session(&db, &sha256_hash(&token))
+            .await
+            .unwrap()
+            .is_some());
+        assert!(get_last_sent_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "old@gmail.com",
+            EmailCodePurpose::Registration
+        )
+        .await
+        .unwrap()
+        .is_none());
+
+        // the code is used up
+        let req = TestRequest::post()
+            .uri("/me/email/confirm")
+            .set_json(ConfirmEmailChangeArgs { code })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn old_address_can_undo_the_change() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "old@gmail.com").await;
+        let user_id = test_user_id(&db, "old@gmail.com").await;
+        let (email_sender, messages) = capturing_email_sender();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(create_test_rng())
+                .app_data(Data::new(Config::default()))
+                .app_data(Data::from(email_sender))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(request_email_change)
+                .service(confirm_email_change)
+                .service(undo_email_change),
+        )
+        .await;
+
+        // undoing a pending change cancels it
+        let req = request_change_request(&token, "first@gmail.com").to_request();
+        test::call_service(&app, req).await;
+        let undo_token = last_word(&messages.lock().unwrap()[1].body)
+            .split("token=")
+            .nth(1)
+            .unwrap()
+            .to_string();
+        let req = TestRequest::post()
+            .uri("/email_change/undo")
+            .set_json(UndoEmailChangeArgs { token: undo_token })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(get_pending_email_change(&db, user_id)
+            .await
+            .unwrap()
+            .is_none());
+
+        // undoing a confirmed change reverts it and ends every session
+        let req = request_change_request(&token, "second@gmail.com").to_request();
+        test::call_service(&app, req).await;
+        let (code, undo_token) = {
+            let messages = messages.lock().unwrap();
+            let undo_link = last_word(&messages[3].body);
+            (
+                last_word(&messages[2].body).parse::<u32>().unwrap(),
+                undo_link.split("token=").nth(1).unwrap().to_string(),
+            )
+        };
+        let req = TestRequest::post()
+            .uri("/me/email/confirm")
+            .set_json(ConfirmEmailChangeArgs { code })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = TestRequest::post()
+            .uri("/email_change/undo")
+            .set_json(UndoEmailChangeArgs {
+                token: undo_token.clone(),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let user = get_user_by_id(&db, user_id).await.unwrap().unwrap();
+        assert_eq!(user.email_address, "old@gmail.com");
+        assert!(get_session(&db, &sha256_hash(&token))
+            .await
+            .unwrap()
+            .is_none());
+
+        let req = TestRequest::post()
+            .uri("/email_change/undo")
+            .set_json(UndoEmailChangeArgs { token: undo_token })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn failed_revert_keeps_the_undo_token() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "old@gmail.com").await;
+        let user_id = test_user_id(&db, "old@gmail.com").await;
+        let (email_sender, messages) = capturing_email_sender();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(create_test_rng())
+                .app_data(Data::new(Config::default()))
+                .app_data(Data::from(email_sender))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(request_email_change)
+                .service(confirm_email_change)
+                .service(undo_email_change),
+        )
+        .await;
+
+        let req = request_change_request(&token, "new@gmail.com").to_request();
+        test::call_service(&app, req).await;
+        let (code, undo_token) = {
+            let messages = messages.lock().unwrap();
+            let undo_link = last_word(&messages[1].body);
+            (
+                last_word(&messages[0].body).parse::<u32>().unwrap(),
+                undo_link.split("token=").nth(1).unwrap().to_string(),
+            )
+        };
+        let req = TestRequest::post()
+            .uri("/me/email/confirm")
+            .set_json(ConfirmEmailChangeArgs { code })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        test::call_service(&app, req).await;
+
+        // someone registers the old address before the undo
+        create_test_user_with_session(&db, "old@gmail.com").await;
+        let other_id = test_user_id(&db, "old@gmail.com").await;
+        let undo = || {
+            TestRequest::post()
+                .uri("/email_change/undo")
+                .set_json(UndoEmailChangeArgs {
+                    token: undo_token.clone(),
+                })
+                .to_request()
+        };
+        let resp = test::call_service(&app, undo()).await;
+        assert!(!resp.status().is_success());
+        let user = get_user_by_id(&db, user_id).await.unwrap().unwrap();
+        assert_eq!(user.email_address, "new@gmail.com");
+
+        delete_user(&db, other_id).await.unwrap();
+        let resp = test::call_service(&app, undo()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let user = get_user_by_id(&db, user_id).await.unwrap().unwrap();
+        assert_eq!(user.email_address, "old@gmail.com");
+    }
+
+    #[actix_web::test]
+    async fn emailed_undo_link_only_shows_the_change() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "old@gmail.com").await;
+        let user_id = test_user_id(&db, "old@gmail.com").await;
+        let (email_sender, messages) = capturing_email_sender();
+        let config = Config::default();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(create_test_rng())
+                .app_data(Data::new(config.clone()))
+                .app_data(Data::from(email_sender))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(request_email_change)
+                .service(confirm_email_change)
+                .service(undo_email_change)
+                .service(email_change_undo_page),
+        )
+        .await;
+
+        let req = request_change_request(&token, "new@gmail.com").to_request();
+        test::call_service(&app, req).await;
+        let (code, undo_link) = {
+            let messages = messages.lock().unwrap();
+            (
+                last_word(&messages[0].body).parse::<u32>().unwrap(),
+                last_word(&messages[1].body),
+            )
+        };
+        let req = TestRequest::post()
+            .uri("/me/email/confirm")
+            .set_json(ConfirmEmailChangeArgs { code })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        test::call_service(&app, req).await;
+        let user = get_user_by_id(&db, user_id).await.unwrap().unwrap();
+        assert_eq!(user.email_address, "new@gmail.com");
+
+        // scanners open the link more than once, none of that undoes the change
+        let uri = undo_link.strip_prefix(&config.issuer).unwrap();
+        for _ in 0..2 {
+            let req = TestRequest::get().uri(uri).to_request();
+            let page: EmailChangeUndoPage = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(page.old_email_address, "old@gmail.com");
+            assert_eq!(page.new_email_address, "new@gmail.com");
+            assert_eq!(page.status, "confirmed");
+        }
+        let user = get_user_by_id(&db, user_id).await.unwrap().unwrap();
+        assert_eq!(user.email_address, "new@gmail.com");
+
+        let undo_token = uri.split_once("token=").unwrap().1.to_string();
+        let req = TestRequest::post()
+            .uri("/email_change/undo")
+            .set_json(UndoEmailChangeArgs { token: undo_token })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let user = get_user_by_id(&db, user_id).await.unwrap().unwrap();
+        assert_eq!(user.email_address, "old@gmail.com");
+
+        let req = TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn codes_lock_after_too_many_attempts_and_expire() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "old@gmail.com").await;
+        let (email_sender, messages) = capturing_email_sender();
+        let clock = Arc::new(FakeClock::new(Utc::now()));
+        let app_clock: Arc<dyn Clock + Send + Sync> = clock.clone();
+        let rng: Arc<dyn RngProvider + Send + Sync> = Arc::new(SeededRngProvider::new(3));
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(Config::default()))
+                .app_data(Data::from(app_clock))
+                .app_data(Data::from(rng))
+                .app_data(Data::from(email_sender))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(request_email_change)
+                .service(confirm_email_change)
+                .service(undo_email_change),
+        )
+        .await;
+        let confirm_request = |code: u32| {
+            TestRequest::post()
+                .uri("/me/email/confirm")
+                .set_json(ConfirmEmailChangeArgs { code })
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+                .to_request()
+        };
+
+        let req = request_change_request(&token, "first@gmail.com").to_request();
+        test::call_service(&app, req).await;
+        let code = last_word(&messages.lock().unwrap()[0].body)
+            .parse::<u32>()
+            .unwrap();
+        let expected = SeededRngProvider::new(3);
+        assert_eq!(code, generate_six_digit_code(&mut *expected.rng()));
+
+        for _ in 0..MAX_CODE_ATTEMPTS {
+            let resp = test::call_service(&app, confirm_request(code + 1)).await;
+            assert_eq!(
+                test::read_body(resp).await,
+                ApiError::WrongEmailCode.to_string()
+            );
+        }
+        // the right code doesn't help anymore
+        let resp = test::call_service(&app, confirm_request(code)).await;
+        assert_eq!(
+            test::read_body(resp).await,
+            ApiError::ExpiredEmailCode.to_string()
+        );
+
+        let req = request_change_request(&token, "second@gmail.com").to_request();
+        test::call_service(&app, req).await;
+        let (code, undo_token) = {
+            let messages = messages.lock().unwrap();
+            let undo_link = last_word(&messages[3].body);
+            (
+                last_word(&messages[2].body).parse::<u32>().unwrap(),
+                undo_link.split("token=").nth(1).unwrap().to_string(),
+            )
+        };
+        clock.advance(Config::default().email_change_code_lifetime);
+        let resp = test::call_service(&app, confirm_request(code)).await;
+        assert_eq!(
+            test::read_body(resp).await,
+            ApiError::ExpiredEmailCode.to_string()
+        );
+
+        // the undo link outlives the code, but not forever
+        clock.advance(Config::default().email_change_undo_lifetime - Duration::hours(1));
+        let req = TestRequest::post()
+            .uri("/email_change/undo")
+            .set_json(UndoEmailChangeArgs { token: undo_token })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            test::read_body(resp).await,
+            ApiError::InvalidEmailChange.to_string()
+        );
+    }
+}
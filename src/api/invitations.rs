@@ -0,0 +1,713 @@
+use crate::{
+    audit::RequestInfo,
+    auth::AuthenticatedUser,
+    breached_passwords::BreachedPasswords,
+    config::Config,
+    db::{
+        audit_log::AuditOutcome,
+        invitations::{
+            accept_invitation, get_invitation, get_invitations, insert_invitation,
+            renew_invitation, revoke_invitation, Invitation, InvitationStatus, NewInvitation,
+        },
+        roles::{assign_role, does_role_exist},
+        tenants::Tenant,
+        user::{get_user, insert_user},
+        DbPool,
+    },
+    email_domains::EmailDomainLists,
+    email_sender::{EmailSender, Message},
+    error::{ApiError, ApiResult},
+    jwt::{decode_token, encode_token, InvitationClaims, INVITATION_AUDIENCE},
+    utils::{hash::sha256_hash, random::generate_random_token, validators::*},
+    webhooks::{emit_event, USER_REGISTERED},
+};
+use actix_web::{
+    delete, get, post,
+    web::{Data, Json, Path, Query},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Deserialize)]
+pub struct CreateInvitationArgs {
+    email_address: String,
+    /// role the user gets on acceptance
+    role: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AcceptInvitationArgs {
+    pub token: String,
+    /// only needed when the address has no account yet
+    pub name: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct InvitationPageArgs {
+    token: String,
+}
+
+/// what the invited user sees before accepting with `accept_invitation_token`
+#[derive(Serialize, Deserialize)]
+pub struct InvitationPage {
+    pub email_address: String,
+    pub tenant_name: String,
+    pub role: Option<String>,
+    pub expire_date: DateTime<Utc>,
+    /// accepting only asks for a name and password when there's no account yet
+    pub account_exists: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AcceptInvitationResponse {
+    pub email_address: String,
+    /// false when an existing account was attached
+    pub created: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InvitationInfo {
+    pub id: i64,
+    pub email_address: String,
+    pub role: Option<String>,
+    pub invited_by: String,
+    /// pending, expired, accepted or revoked
+    pub status: String,
+    pub created_date: DateTime<Utc>,
+    pub expire_date: DateTime<Utc>,
+    pub accepted_date: Option<DateTime<Utc>>,
+}
+
+impl From<Invitation> for InvitationInfo {
+    fn from(invitation: Invitation) -> Self {
+        let status = match invitation.status {
+            InvitationStatus::Pending if invitation.expire_date <= Utc::now() => "expired",
+            status => status.as_str(),
+        };
+        InvitationInfo {
+            id: invitation.id,
+            email_address: invitation.email_address,
+            role: invitation.role_name,
+            invited_by: invitation.invited_by,
+            status: status.to_string(),
+            created_date: invitation.created_date,
+            expire_date: invitation.expire_date,
+            accepted_date: invitation.accepted_date,
+        }
+    }
+}
+
+fn unknown_invitation() -> ApiError {
+    ApiError::BadArgument {
+        argument_name: "id",
+    }
+}
+
+/// the token goes out by email only, it is never stored or returned
+async fn send_invitation(
+    pool: &DbPool,
+    config: &Config,
+    email_sender: &(dyn EmailSender + Send + Sync),
+    tenant: &Tenant,
+    invitation: &Invitation,
+) -> ApiResult<()> {
+    let claims = InvitationClaims {
+        iss: config.issuer.clone(),
+        sub: invitation.id.to_string(),
+        aud: INVITATION_AUDIENCE.to_string(),
+        exp: invitation.expire_date.timestamp(),
+        iat: Utc::now().timestamp(),
+        jti: invitation.token_id.clone(),
+        tenant: tenant.id.clone(),
+    };
+    let token = encode_token(pool, config, &claims).await?;
+    let message = Message {
+        to: invitation
+            .email_address
+            .parse()
+            .map_err(|_| ApiError::InvalidEmailAddress)?,
+        subject: format!("You are invited to {}", tenant.name),
+        body: format!(
+            "accept the invitation at {}?token={token} before {}",
+            config.invitation_uri,
+            invitation.expire_date.to_rfc2822()
+        ),
+    };
+    email_sender.send_email(message).await
+}
+
+#[post("/invitations")]
+#[allow(clippy::too_many_arguments)]
+pub async fn create_invitation(
+    args: Json<CreateInvitationArgs>,
+    admin: AuthenticatedUser,
+    tenant: Tenant,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    email_sender: Data<dyn EmailSender + Send + Sync>,
+    email_domain_lists: Data<EmailDomainLists>,
+) -> ApiResult<Json<InvitationInfo>> {
+    admin.require_permission(&pool, "invitations:write").await?;
+    validate_email_address(&args.email_address)?;
+    tenant
+        .settings
+        .check_email_address(&args.email_address, &email_domain_lists)?;
+    if let Some(role) = &args.role {
+        // accepting assigns the role, so inviting with one is assigning it
+        admin.require_permission(&pool, "roles:write").await?;
+        if !does_role_exist(&pool, role).await? {
+            return Err(ApiError::BadArgument {
+                argument_name: "role",
+            });
+        }
+    }
+
+    let token_id = generate_random_token();
+    let expire_date = Utc::now() + config.invitation_lifetime;
+    let id = insert_invitation(
+        &pool,
+        &NewInvitation {
+            tenant_id: &tenant.id,
+            email_address: &args.email_address,
+            role_name: args.role.as_deref(),
+            invited_by: &admin.email_address,
+            token_id: &token_id,
+            expire_date,
+        },
+    )
+    .await?;
+    let invitation = get_invitation(&pool, &tenant.id, id)
+        .await?
+        .ok_or(unknown_invitation())?;
+    send_invitation(&pool, &config, email_sender.as_ref(), &tenant, &invitation).await?;
+    info.audit(
+        &pool,
+        &admin.email_address,
+        "invitation.create",
+        &args.email_address,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok(Json(invitation.into()))
+}
+
+/// newest first
+#[get("/invitations")]
+pub async fn list_invitations(
+    admin: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<Vec<InvitationInfo>>> {
+    admin.require_permission(&pool, "invitations:read").await?;
+    let invitations = get_invitations(&pool, &admin.tenant_id)
+        .await?
+        .into_iter()
+        .map(InvitationInfo::from)
+        .collect();
+    Ok(Json(invitations))
+}
+
+/// sends a new email with a fresh expiry, links from earlier emails stop working
+#[post("/invitations/{id}/resend")]
+pub async fn resend_invitation(
+    id: Path<i64>,
+    admin: AuthenticatedUser,
+    tenant: Tenant,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    email_sender: Data<dyn EmailSender + Send + Sync>,
+) -> ApiResult<&'static str> {
+    admin.require_permission(&pool, "invitations:write").await?;
+    let token_id = generate_random_token();
+    let expire_date = Utc::now() + config.invitation_lifetime;
+    if !renew_invitation(&pool, &tenant.id, *id, &token_id, expire_date).await? {
+        return Err(unknown_invitation());
+    }
+    let invitation = get_invitation(&pool, &tenant.id, *id)
+        .await?
+        .ok_or(unknown_invitation())?;
+    send_invitation(&pool, &config, email_sender.as_ref(), &tenant, &invitation).await?;
+    info.audit(
+        &pool,
+        &admin.email_address,
+        "invitation.resend",
+        &invitation.email_address,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+#[delete("/invitations/{id}")]
+pub async fn revoke_invitation_by_id(
+    id: Path<i64>,
+    admin: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    admin.require_permission(&pool, "invitations:write").await?;
+    let invitation = get_invitation(&pool, &admin.tenant_id, *id)
+        .await?
+        .ok_or(unknown_invitation())?;
+    if !revoke_invitation(&pool, &admin.tenant_id, invitation.id).await? {
+        return Err(unknown_invitation());
+    }
+    info.audit(
+        &pool,
+        &admin.email_address,
+        "invitation.revoke",
+        &invitation.email_address,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+/// the pending invitation a token belongs to, only the newest token of an invitation is valid
+async fn invitation_of_token(
+    pool: &DbPool,
+    config: &Config,
+    tenant: &Tenant,
+    token: &str,
+) -> ApiResult<Invitation> {
+    let claims: InvitationClaims = decode_token(pool, config, token, Some(INVITATION_AUDIENCE))
+        .await
+        .map_err(|_| ApiError::InvalidInvitation)?;
+    if claims.tenant != tenant.id {
+        return Err(ApiError::InvalidInvitation);
+    }
+    let id = claims
+        .sub
+        .parse()
+        .map_err(|_| ApiError::InvalidInvitation)?;
+    let invitation = get_invitation(pool, &tenant.id, id)
+        .await?
+        .ok_or(ApiError::InvalidInvitation)?;
+    let is_valid = invitation.status == InvitationStatus::Pending
+        && invitation.token_id == claims.jti
+        && invitation.expire_date > Utc::now();
+    is_valid
+        .then_some(invitation)
+        .ok_or(ApiError::InvalidInvitation)
+}
+
+/// `invitation_uri`, the link of the invitation email. it checks the token without using it up
+#[get("/invitation")]
+pub async fn invitation_page(
+    args: Query<InvitationPageArgs>,
+    tenant: Tenant,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+) -> ApiResult<Json<InvitationPage>> {
+    let invitation = invitation_of_token(&pool, &config, &tenant, &args.token).await?;
+    let account_exists = get_user(&pool, &tenant.id, &invitation.email_address)
+        .await?
+        .is_some();
+    Ok(Json(InvitationPage {
+        email_address: invitation.email_address,
+        tenant_name: tenant.name,
+        role: invitation.role_name,
+        expire_date: invitation.expire_date,
+        account_exists,
+    }))
+}
+
+/// returns the user id and whether the account had to be created
+async fn accept(
+    pool: &DbPool,
+    config: &Config,
+    tenant: &Tenant,
+    breached_passwords: &BreachedPasswords,
+    args: &AcceptInvitationArgs,
+) -> ApiResult<(Invitation, i64, bool)> {
+    let invitation = invitation_of_token(pool, config, tenant, &args.token).await?;
+    let existing_user = get_user(pool, &tenant.id, &invitation.email_address).await?;
+
+    // the invitation is used up only once everything needed to finish is known to be valid
+    let new_account = match (&existing_user, &args.name, &args.password) {
+        (Some(_), _, _) => None,
+        (None, Some(name), Some(password)) => {
+            validate_name(name)?;
+            validate_password(password)?;
+            tenant.settings.check_password(
+                password,
+                &[name, &invitation.email_address],
+                breached_passwords,
+            )?;
+            Some((name, sha256_hash(password)))
+        }
+        (None, None, _) => {
+            return Err(ApiError::BadArgument {
+                argument_name: "name",
+            })
+        }
+        (None, Some(_), None) => {
+            return Err(ApiError::BadArgument {
+                argument_name: "password",
+            })
+        }
+    };
+    if !accept_invitation(pool, invitation.id, &invitation.token_id).await? {
+        return Err(ApiError::InvalidInvitation);
+    }
+
+    // the email with the token proves control of the address, no email code is needed
+    let (user_id, created) = match (existing_user, new_account) {
+        (Some(user), _) => (user.id, false),
+        (None, Some((name, hashed_password))) => {
+            let user_id = insert_user(
+                pool,
+                &tenant.id,
+                name,
+                &hashed_password,
+                &invitation.email_address,
+            )
+            .await?;
+            (user_id, true)
+        }
+        (None, None) => unreachable!("new accounts always have a name and password"),
+    };
+    if let Some(role) = &invitation.role_name {
+        assign_role(pool, user_id, role).await?;
+    }
+    Ok((invitation, user_id, created))
+}
+
+/// creates the account of the invited address, or attaches the existing one
+#[post("/invitations/accept")]
+pub async fn accept_invitation_token(
+    args: Json<AcceptInvitationArgs>,
+    tenant: Tenant,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    breached_passwords: Data<BreachedPasswords>,
+) -> ApiResult<Json<AcceptInvitationResponse>> {
+    let result = accept(&pool, &config, &tenant, &breached_passwords, &args).await;
+    let target = match &result {
+        Ok((invitation, ..)) => invitation.email_address.clone(),
+        Err(_) => "unknown".to_string(),
+    };
+    let (invitation, _, created) = info
+        .audit_result(&pool, &target, "invitation.accept", &target, result)
+        .await?;
+
+    if created {
+        emit_event(
+            &pool,
+            USER_REGISTERED,
+            json!({
+                "tenant_id": tenant.id,
+                "email_address": invitation.email_address,
+                "name": args.name,
+            }),
+        )
+        .await?;
+    }
+    Ok(Json(AcceptInvitationResponse {
+        email_address: invitation.email_address,
+        created,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{
+            roles::{get_user_roles, grant_permission, insert_role},
+            tenants::DEFAULT_TENANT_ID,
+        },
+        email_sender::MockEmailSender,
+        test::helper::{create_test_db, create_test_user_with_session, test_user_id},
+    };
+    use actix_web::{
+        http::{
+            header::{ContentType, AUTHORIZATION},
+            StatusCode,
+        },
+        test::{self, TestRequest},
+        App,
+    };
+    use std::{
+        future::ready,
+        sync::{Arc, Mutex},
+    };
+
+    /// an email sender that keeps the tokens of the invitations it sends
+    fn capturing_email_sender() -> (Arc<dyn EmailSender + Send + Sync>, Arc<Mutex<Vec<String>>>) {
+        let tokens = Arc::new(Mutex::new(Vec::new()));
+        let sent = tokens.clone();
+        let mut email_mock = MockEmailSender::new();
+        email_mock.expect_send_email().returning(move |message| {
+            let token = message.body.split("token=").nth(1).unwrap();
+            let token = token.split_whitespace().next().unwrap().to_string();
+            sent.lock().unwrap().push(token);
+            Box::pin(ready(Ok(())))
+        });
+        (Arc::new(email_mock), tokens)
+    }
+
+    fn accept_request(token: &str, name: Option<&str>, password: Option<&str>) -> TestRequest {
+        TestRequest::post()
+            .uri("/invitations/accept")
+            .set_json(AcceptInvitationArgs {
+                token: token.to_string(),
+                name: name.map(str::to_string),
+                password: password.map(str::to_string),
+            })
+    }
+
+    #[actix_web::test]
+    async fn emailed_link_shows_the_invitation() {
+        let db = create_test_db().await;
+        let admin_token = create_test_user_with_session(&db, "admin@gmail.com").await;
+        let admin_id = test_user_id(&db, "admin@gmail.com").await;
+        assign_role(&db, admin_id, "admin").await.unwrap();
+        let links = Arc::new(Mutex::new(Vec::new()));
+        let sent = links.clone();
+        let mut email_mock = MockEmailSender::new();
+        email_mock.expect_send_email().returning(move |message| {
+            let link = message.body.split_whitespace().nth(4).unwrap().to_string();
+            sent.lock().unwrap().push(link);
+            Box::pin(ready(Ok(())))
+        });
+        let email_sender: Arc<dyn EmailSender + Send + Sync> = Arc::new(email_mock);
+        let config = Config::default();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(config.clone()))
+                .app_data(Data::from(email_sender))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(accept_invitation_token)
+                .service(create_invitation)
+                .service(invitation_page),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/invitations")
+            .set_payload(r#"{"email_address": "arian@gmail.com", "role": "admin"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let link = links.lock().unwrap()[0].clone();
+        let uri = link.strip_prefix(&config.issuer).unwrap();
+
+        // looking at the invitation doesn't use it up
+        for _ in 0..2 {
+            let req = TestRequest::get().uri(uri).to_request();
+            let page: InvitationPage = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(page.email_address, "arian@gmail.com");
+            assert_eq!(page.role.as_deref(), Some("admin"));
+            assert!(!page.account_exists);
+        }
+
+        let token = link.split("token=").nth(1).unwrap();
+        let req = accept_request(token, Some("arian"), Some("some_hard_password")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn invite_and_accept() {
+        let db = create_test_db().await;
+        let admin_token = create_test_user_with_session(&db, "admin@gmail.com").await;
+        let admin_id = test_user_id(&db, "admin@gmail.com").await;
+        assign_role(&db, admin_id, "admin").await.unwrap();
+        let (email_sender, tokens) = capturing_email_sender();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(Config::default()))
+                .app_data(Data::from(email_sender))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(accept_invitation_token)
+                .service(create_invitation)
+                .service(list_invitations),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/invitations")
+            .set_payload(r#"{"email_address": "arian@gmail.com", "role": "nope"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::post()
+            .uri("/invitations")
+            .set_payload(r#"{"email_address": "arian@gmail.com", "role": "admin"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let invitation: InvitationInfo = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(invitation.status, "pending");
+        assert_eq!(invitation.invited_by, "admin@gmail.com");
+        let token = tokens.lock().unwrap()[0].clone();
+
+        // a new account needs a name and password
+        let req = accept_request(&token, None, None).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = accept_request(&token, Some("arian"), Some("some_hard_password")).to_request();
+        let resp: AcceptInvitationResponse = test::call_and_read_body_json(&app, req).await;
+        assert!(resp.created);
+        let user = get_user(&db, DEFAULT_TENANT_ID, "arian@gmail.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.name, "arian");
+        assert_eq!(get_user_roles(&db, user.id).await.unwrap(), vec!["admin"]);
+
+        // tokens can only be used once
+        let req = accept_request(&token, Some("arian"), Some("some_hard_password")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::get()
+            .uri("/invitations")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let invitations: Vec<InvitationInfo> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(invitations.len(), 1);
+        assert_eq!(invitations[0].status, "accepted");
+        assert!(invitations[0].accepted_date.is_some());
+    }
+
+    #[actix_web::test]
+    async fn resend_revoke_and_attach_existing_user() {
+        let db = create_test_db().await;
+        let admin_token = create_test_user_with_session(&db, "admin@gmail.com").await;
+        let admin_id = test_user_id(&db, "admin@gmail.com").await;
+        assign_role(&db, admin_id, "admin").await.unwrap();
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+        let (email_sender, tokens) = capturing_email_sender();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(Config::default()))
+                .app_data(Data::from(email_sender))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(accept_invitation_token)
+                .service(create_invitation)
+                .service(resend_invitation)
+                .service(revoke_invitation_by_id),
+        )
+        .await;
+
+        let mut ids = Vec::new();
+        for email_address in ["arian@gmail.com", "pouya@gmail.com"] {
+            let req = TestRequest::post()
+                .uri("/invitations")
+                .set_json(json!({ "email_address": email_address, "role": "admin" }))
+                .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+                .to_request();
+            let invitation: InvitationInfo = test::call_and_read_body_json(&app, req).await;
+            ids.push(invitation.id);
+        }
+
+        // resending invalidates the earlier link
+        let req = TestRequest::post()
+            .uri(&format!("/invitations/{}/resend", ids[0]))
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let old_token = tokens.lock().unwrap()[0].clone();
+        let new_token = tokens.lock().unwrap()[2].clone();
+        let req = accept_request(&old_token, None, None).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // an existing account is attached without a name or password
+        let req = accept_request(&new_token, None, None).to_request();
+        let resp: AcceptInvitationResponse = test::call_and_read_body_json(&app, req).await;
+        assert!(!resp.created);
+        let user_id = test_user_id(&db, "arian@gmail.com").await;
+        assert_eq!(get_user_roles(&db, user_id).await.unwrap(), vec!["admin"]);
+
+        let req = TestRequest::delete()
+            .uri(&format!("/invitations/{}", ids[1]))
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let revoked_token = tokens.lock().unwrap()[1].clone();
+        let req =
+            accept_request(&revoked_token, Some("pouya"), Some("some_hard_password")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // accepted invitations can't be resent or revoked
+        for req in [
+            TestRequest::post().uri(&format!("/invitations/{}/resend", ids[0])),
+            TestRequest::delete().uri(&format!("/invitations/{}", ids[0])),
+        ] {
+            let req = req
+                .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[actix_web::test]
+    async fn inviting_with_a_role_needs_roles_write() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "inviter@gmail.com").await;
+        let user_id = test_user_id(&db, "inviter@gmail.com").await;
+        insert_role(&db, "inviter", "invites users").await.unwrap();
+        grant_permission(&db, "inviter", "invitations:write")
+            .await
+            .unwrap();
+        assign_role(&db, user_id, "inviter").await.unwrap();
+        let (email_sender, tokens) = capturing_email_sender();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(Config::default()))
+                .app_data(Data::from(email_sender))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(create_invitation),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/invitations")
+            .set_json(json!({ "email_address": "inviter@gmail.com", "role": "admin" }))
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(tokens.lock().unwrap().is_empty());
+
+        let req = TestRequest::post()
+            .uri("/invitations")
+            .set_json(json!({ "email_address": "pouya@gmail.com" }))
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
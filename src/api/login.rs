@@ -1,13 +1,28 @@
 use crate::{
-    db::{user::does_user_exists, DbPool},
+    audit::RequestInfo,
+    auth::SESSION_COOKIE_NAME,
+    clock::Clock,
+    config::Config,
+    db::{
+        roles::get_user_roles,
+        sessions::{insert_password_change_session, insert_session},
+        tenants::Tenant,
+        user::{get_user_with_password, AccountStatus, User},
+        DbPool,
+    },
     error::{ApiError, ApiResult},
-    utils::{hash::sha256_hash, validators::*},
+    metrics::record_login,
+    utils::{hash::sha256_hash, random::generate_random_token, validators::*},
+    webhooks::{emit_event, USER_LOGGED_IN},
 };
 use actix_web::{
+    cookie::{Cookie, SameSite},
     post,
     web::{Data, Json},
+    HttpResponse,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 #[derive(Serialize, Deserialize)]
 pub struct LoginArgs {
@@ -15,38 +30,150 @@ pub struct LoginArgs {
     password: String,
 }
 
-#[post("/login")]
-pub async fn login(args: Json<LoginArgs>, pool: Data<DbPool>) -> ApiResult<&'static str> {
+#[derive(Serialize, Deserialize)]
+pub struct LoginResponse {
+    session_token: String,
+    /// roles of the user, so that clients can tell what the user is allowed to do
+    roles: Vec<String>,
+    /// the password has to be changed first, the session can do nothing else until then
+    #[serde(default)]
+    password_expired: bool,
+}
+
+/// browsers get the session as a cookie so that `/oauth/authorize` can see it
+pub fn session_cookie(config: &Config, session_token: &str) -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE_NAME, session_token.to_string())
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(config.issuer.starts_with("https://"))
+        .max_age(actix_web::cookie::time::Duration::seconds(
+            config.session_lifetime.num_seconds(),
+        ))
+        .finish()
+}
+
+async fn check_credentials(pool: &DbPool, tenant: &Tenant, args: &LoginArgs) -> ApiResult<User> {
     validate_email_address(&args.email_address)?;
     validate_password(&args.password)?;
     let hashed_password = sha256_hash(&args.password);
 
-    does_user_exists(&pool, &args.email_address, &hashed_password)
-        .await?
-        .then_some("")
-        .ok_or(ApiError::WrongCredentials)
+    let user = match get_user_with_password(pool, &tenant.id, &args.email_address).await? {
+        Some((user, password)) if password == hashed_password => user,
+        _ => return Err(ApiError::WrongCredentials),
+    };
+    // the password was right, so it's fine to tell why the login is refused
+    match user.status {
+        AccountStatus::Active => {}
+        AccountStatus::Pending => return Err(ApiError::AccountPending),
+        AccountStatus::Suspended => return Err(ApiError::AccountSuspended),
+        AccountStatus::Locked => return Err(ApiError::AccountLocked),
+        AccountStatus::Deleted => return Err(ApiError::AccountDeletionScheduled),
+    }
+    if user.password_reset_required {
+        return Err(ApiError::PasswordResetRequired);
+    }
+    Ok(user)
+}
+
+#[post("/login")]
+pub async fn login(
+    args: Json<LoginArgs>,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    clock: Data<dyn Clock + Send + Sync>,
+    tenant: Tenant,
+    info: RequestInfo,
+) -> ApiResult<HttpResponse> {
+    let result = check_credentials(&pool, &tenant, &args).await;
+    record_login(&result);
+    let user = info
+        .audit_result(
+            &pool,
+            &args.email_address,
+            "user.login",
+            &args.email_address,
+            result,
+        )
+        .await?;
+
+    let session_token = generate_random_token();
+    let token_hash = sha256_hash(&session_token);
+    let now = clock.now();
+    let password_expired = tenant
+        .settings
+        .is_password_expired(user.password_changed_at, now);
+    if password_expired {
+        let expire_date = now + config.password_change_session_lifetime;
+        insert_password_change_session(&pool, &token_hash, user.id, expire_date).await?;
+    } else {
+        let expire_date = now + config.session_lifetime;
+        insert_session(&pool, &token_hash, user.id, expire_date).await?;
+    }
+
+    let cookie = session_cookie(&config, &session_token);
+    let roles = get_user_roles(&pool, user.id).await?;
+    emit_event(
+        &pool,
+        USER_LOGGED_IN,
+        json!({ "tenant_id": tenant.id, "email_address": user.email_address }),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().cookie(cookie).json(LoginResponse {
+        session_token,
+        roles,
+        password_expired,
+    }))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{db::user::insert_user, test::helper::create_test_db};
+    use crate::{
+        api::me::{change_password, get_profile, ChangePasswordArgs, ChangePasswordResponse},
+        breached_passwords::BreachedPasswords,
+        clock::FakeClock,
+        db::{
+            audit_log::{get_audit_events, AuditFilter, AuditOutcome},
+            roles::assign_role,
+            sessions::get_session,
+            tenants::{
+                get_tenant, insert_tenant, update_tenant, TenantSettings, DEFAULT_TENANT_ID,
+            },
+            user::{insert_user, set_user_status, update_canonical_email_addresses},
+        },
+        tenant::TENANT_HEADER,
+        test::helper::{create_test_clock, create_test_db},
+    };
     use actix_web::{
-        http::{header::ContentType, StatusCode},
+        http::{
+            header::{ContentType, AUTHORIZATION},
+            StatusCode,
+        },
         test::{self, TestRequest},
         App,
     };
+    use chrono::{Duration, Utc};
+    use std::sync::Arc;
 
     #[actix_web::test]
     async fn login_should_work() {
         let db = create_test_db().await;
         let email_address = "arian@gmail.com";
         let password = sha256_hash("some_hard_password");
-        insert_user(&db, "idk", &password, &email_address)
+        let user_id = insert_user(&db, DEFAULT_TENANT_ID, "idk", &password, email_address)
             .await
             .unwrap();
+        assign_role(&db, user_id, "admin").await.unwrap();
 
-        let app = test::init_service(App::new().app_data(Data::new(db)).service(login)).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(login),
+        )
+        .await;
         let req = TestRequest::post()
             .uri("/login")
             .set_payload(
@@ -57,12 +184,130 @@ mod tests {
 
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp
+            .response()
+            .cookies()
+            .any(|cookie| cookie.name() == SESSION_COOKIE_NAME));
+
+        let body: LoginResponse = test::read_body_json(resp).await;
+        assert_eq!(body.roles, vec!["admin"]);
+        let session = get_session(&db, &sha256_hash(&body.session_token))
+            .await
+            .unwrap();
+        assert_eq!(session.unwrap().user_id, user_id);
+    }
+
+    #[actix_web::test]
+    async fn password_of_an_account_sharing_the_mailbox_is_refused() {
+        let db = create_test_db().await;
+        let first_id = insert_user(
+            &db,
+            DEFAULT_TENANT_ID,
+            "first",
+            &sha256_hash("first_hard_password"),
+            "arian@gmail.com",
+        )
+        .await
+        .unwrap();
+        let second_id = insert_user(
+            &db,
+            DEFAULT_TENANT_ID,
+            "second",
+            &sha256_hash("second_hard_password"),
+            "arian@googlemail.com",
+        )
+        .await
+        .unwrap();
+        // both addresses are now the same mailbox, the second account loses its canonical address
+        let mut tenant = get_tenant(&db, DEFAULT_TENANT_ID).await.unwrap().unwrap();
+        tenant.settings.canonicalize_email_providers = true;
+        update_tenant(&db, DEFAULT_TENANT_ID, &tenant.name, None, &tenant.settings)
+            .await
+            .unwrap();
+        update_canonical_email_addresses(&db, DEFAULT_TENANT_ID)
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(login),
+        )
+        .await;
+        let login_request = |password: &str| {
+            TestRequest::post()
+                .uri("/login")
+                .set_json(json!({ "email_address": "arian@googlemail.com", "password": password }))
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, login_request("first_hard_password")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            test::read_body(resp).await,
+            ApiError::WrongCredentials.to_string()
+        );
+
+        let resp = test::call_service(&app, login_request("second_hard_password")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: LoginResponse = test::read_body_json(resp).await;
+        let session = get_session(&db, &sha256_hash(&body.session_token))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.user_id, second_id);
+        assert_ne!(session.user_id, first_id);
+    }
+
+    #[actix_web::test]
+    async fn login_only_sees_users_of_the_tenant() {
+        let db = create_test_db().await;
+        insert_tenant(&db, "shop", "Shop", None).await.unwrap();
+        let password = sha256_hash("some_hard_password");
+        insert_user(&db, "shop", "idk", &password, "arian@gmail.com")
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(login),
+        )
+        .await;
+        let payload = r#"{"email_address": "arian@gmail.com", "password": "some_hard_password"}"#;
+        let req = TestRequest::post()
+            .uri("/login")
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::post()
+            .uri("/login")
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .insert_header((TENANT_HEADER, "shop"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
     }
 
     #[actix_web::test]
     async fn login_to_not_existed_account() {
         let db = create_test_db().await;
-        let app = test::init_service(App::new().app_data(Data::new(db)).service(login)).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(login),
+        )
+        .await;
         let req = TestRequest::post()
             .uri("/login")
             .set_payload(
@@ -80,11 +325,18 @@ mod tests {
         let db = create_test_db().await;
         let email_address = "arian@gmail.com";
         let password = sha256_hash("some_hard_password");
-        insert_user(&db, "idk", &password, &email_address)
+        insert_user(&db, DEFAULT_TENANT_ID, "idk", &password, email_address)
             .await
             .unwrap();
 
-        let app = test::init_service(App::new().app_data(Data::new(db)).service(login)).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(login),
+        )
+        .await;
         let req = TestRequest::post()
             .uri("/login")
             .set_payload(r#"{"email_address": "arian@gmail.com", "password": "another_password"}"#)
@@ -93,5 +345,215 @@ mod tests {
 
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let filter = AuditFilter {
+            action: Some("user.login".to_string()),
+            ..Default::default()
+        };
+        let events = get_audit_events(&db, &filter, 10).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].outcome, AuditOutcome::Failure);
+    }
+
+    #[actix_web::test]
+    async fn login_tells_why_the_account_is_refused() {
+        let db = create_test_db().await;
+        let password = sha256_hash("some_hard_password");
+        let user_id = insert_user(&db, DEFAULT_TENANT_ID, "idk", &password, "arian@gmail.com")
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(login),
+        )
+        .await;
+        for (status, message) in [
+            (AccountStatus::Pending, "account isn't activated yet"),
+            (AccountStatus::Suspended, "account is suspended"),
+            (AccountStatus::Locked, "account is locked"),
+            (AccountStatus::Deleted, "account is scheduled for deletion"),
+        ] {
+            set_user_status(&db, user_id, status, Some("testing"))
+                .await
+                .unwrap();
+            let req = TestRequest::post()
+                .uri("/login")
+                .set_payload(
+                    r#"{"email_address": "arian@gmail.com", "password": "some_hard_password"}"#,
+                )
+                .insert_header(ContentType::json())
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(test::read_body(resp).await, message);
+
+            // the status isn't revealed without the right password
+            let req = TestRequest::post()
+                .uri("/login")
+                .set_payload(
+                    r#"{"email_address": "arian@gmail.com", "password": "another_password"}"#,
+                )
+                .insert_header(ContentType::json())
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(test::read_body(resp).await, "wrong credentials");
+        }
+    }
+
+    #[actix_web::test]
+    async fn expired_password_only_allows_changing_it() {
+        let db = create_test_db().await;
+        let settings = TenantSettings {
+            password_max_age_days: 30,
+            ..get_tenant(&db, DEFAULT_TENANT_ID)
+                .await
+                .unwrap()
+                .unwrap()
+                .settings
+        };
+        update_tenant(&db, DEFAULT_TENANT_ID, "Default", None, &settings)
+            .await
+            .unwrap();
+        let password = sha256_hash("some_hard_password");
+        let user_id = insert_user(&db, DEFAULT_TENANT_ID, "idk", &password, "arian@gmail.com")
+            .await
+            .unwrap();
+        let changed_at = (Utc::now() - Duration::days(31)).to_rfc3339();
+        sqlx::query!(
+            "UPDATE users SET password_changed_at=? WHERE id=?",
+            changed_at,
+            user_id
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .app_data(Data::new(BreachedPasswords::default()))
+                .service(login)
+                .service(get_profile)
+                .service(change_password),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/login")
+            .set_payload(
+                r#"{"email_address": "arian@gmail.com", "password": "some_hard_password"}"#,
+            )
+            .insert_header(ContentType::json())
+            .to_request();
+        let body: LoginResponse = test::call_and_read_body_json(&app, req).await;
+        assert!(body.password_expired);
+        let token = body.session_token;
+
+        let req = TestRequest::get()
+            .uri("/me")
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            test::read_body(resp).await,
+            "password has expired and has to be changed"
+        );
+
+        let req = TestRequest::put()
+            .uri("/me/password")
+            .set_json(ChangePasswordArgs {
+                current_password: "some_hard_password".to_string(),
+                new_password: "another_hard_password".to_string(),
+            })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let body: ChangePasswordResponse = test::call_and_read_body_json(&app, req).await;
+
+        let req = TestRequest::get()
+            .uri("/me")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", body.session_token)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = TestRequest::post()
+            .uri("/login")
+            .set_payload(
+                r#"{"email_address": "arian@gmail.com", "password": "another_hard_password"}"#,
+            )
+            .insert_header(ContentType::json())
+            .to_request();
+        let body: LoginResponse = test::call_and_read_body_json(&app, req).await;
+        assert!(!body.password_expired);
+    }
+
+    #[actix_web::test]
+    async fn sessions_and_passwords_expire_with_time() {
+        let db = create_test_db().await;
+        let settings = TenantSettings {
+            password_max_age_days: 30,
+            ..get_tenant(&db, DEFAULT_TENANT_ID)
+                .await
+                .unwrap()
+                .unwrap()
+                .settings
+        };
+        update_tenant(&db, DEFAULT_TENANT_ID, "Default", None, &settings)
+            .await
+            .unwrap();
+        let password = sha256_hash("some_hard_password");
+        insert_user(&db, DEFAULT_TENANT_ID, "idk", &password, "arian@gmail.com")
+            .await
+            .unwrap();
+        let config = Config::default();
+        let clock = Arc::new(FakeClock::new(Utc::now()));
+        let app_clock: Arc<dyn Clock + Send + Sync> = clock.clone();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::from(app_clock))
+                .app_data(Data::new(config.clone()))
+                .service(login)
+                .service(get_profile),
+        )
+        .await;
+        let login_request = || {
+            TestRequest::post()
+                .uri("/login")
+                .set_payload(
+                    r#"{"email_address": "arian@gmail.com", "password": "some_hard_password"}"#,
+                )
+                .insert_header(ContentType::json())
+                .to_request()
+        };
+        let profile_request = |token: &str| {
+            TestRequest::get()
+                .uri("/me")
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+                .to_request()
+        };
+
+        let body: LoginResponse = test::call_and_read_body_json(&app, login_request()).await;
+        assert!(!body.password_expired);
+        clock.advance(config.session_lifetime - Duration::seconds(1));
+        let resp = test::call_service(&app, profile_request(&body.session_token)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        clock.advance(Duration::seconds(1));
+        let resp = test::call_service(&app, profile_request(&body.session_token)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // the password was set when the user was inserted, by the real clock
+        clock.advance(Duration::days(30) - config.session_lifetime);
+        let body: LoginResponse = test::call_and_read_body_json(&app, login_request()).await;
+        assert!(body.password_expired);
+        clock.advance(config.password_change_session_lifetime);
+        let resp = test::call_service(&app, profile_request(&body.session_token)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
     }
 }
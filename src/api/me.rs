@@ -0,0 +1,769 @@
+use crate::{
+    api::{
+        admin::audit_log::AuditEventInfo, api_keys::ApiKeyInfo, invitations::InvitationInfo,
+        login::session_cookie,
+    },
+    audit::RequestInfo,
+    auth::{AuthenticatedUser, PasswordChangeUser},
+    breached_passwords::BreachedPasswords,
+    config::Config,
+    db::{
+        api_keys::get_api_keys_of_user,
+        audit_log::{get_audit_events_of_user, AuditOutcome},
+        email_changes::{get_email_changes_of_user, EmailChange, EmailChangeStatus},
+        invitations::get_invitations_of_address,
+        oauth::delete_refresh_tokens_of_user,
+        roles::get_user_roles,
+        sessions::{delete_sessions_of_user, get_sessions_of_user, insert_session},
+        tenants::Tenant,
+        user::{
+            does_user_exists, get_user_by_id, get_user_profile, update_user_password,
+            update_user_profile, Profile,
+        },
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    utils::{hash::sha256_hash, random::generate_random_token, validators::*},
+    webhooks::{emit_event, PASSWORD_CHANGED, PROFILE_UPDATED},
+};
+use actix_web::{
+    get, patch, put,
+    web::{Data, Json},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+
+#[derive(Serialize, Deserialize)]
+pub struct ProfileInfo {
+    pub id: i64,
+    pub tenant_id: String,
+    pub email_address: String,
+    pub name: String,
+    pub display_name: Option<String>,
+    pub locale: Option<String>,
+    pub time_zone: Option<String>,
+    pub avatar_url: Option<String>,
+    pub roles: Vec<String>,
+}
+
+/// missing fields are left alone, optional fields are cleared with `null`
+#[derive(Default, Serialize, Deserialize)]
+pub struct UpdateProfileArgs {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub display_name: Option<Option<String>>,
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub locale: Option<Option<String>>,
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub time_zone: Option<Option<String>>,
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub avatar_url: Option<Option<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangePasswordArgs {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangePasswordResponse {
+    /// every other session was ended, this one replaces the one the request was made with
+    pub session_token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub created_date: DateTime<Utc>,
+    pub expire_date: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AccountStatusInfo {
+    pub status: String,
+    pub status_reason: Option<String>,
+    pub status_date: Option<DateTime<Utc>>,
+    pub password_reset_required: bool,
+    pub password_changed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EmailChangeInfo {
+    pub old_email_address: String,
+    pub new_email_address: String,
+    pub status: String,
+    pub created_date: DateTime<Utc>,
+    pub confirmed_date: Option<DateTime<Utc>>,
+}
+
+impl From<EmailChange> for EmailChangeInfo {
+    fn from(change: EmailChange) -> Self {
+        EmailChangeInfo {
+            old_email_address: change.old_email_address,
+            new_email_address: change.new_email_address,
+            status: change.status.as_str().to_string(),
+            created_date: change.created_date,
+            confirmed_date: change.confirmed_date,
+        }
+    }
+}
+
+/// everything stored about a user, secrets like password and token hashes left out. the
+/// profile has the roles of the user
+#[derive(Serialize, Deserialize)]
+pub struct AccountExport {
+    pub exported_date: DateTime<Utc>,
+    pub profile: ProfileInfo,
+    pub account: AccountStatusInfo,
+    pub sessions: Vec<SessionInfo>,
+    pub api_keys: Vec<ApiKeyInfo>,
+    /// pending ones and the history, newest first
+    pub email_changes: Vec<EmailChangeInfo>,
+    /// invitations sent to any address the user had
+    pub invitations: Vec<InvitationInfo>,
+    /// events the user did or that were done to the user, under any address while the user had it
+    pub audit_events: Vec<AuditEventInfo>,
+}
+
+/// tells a field that is `null` apart from one that is missing, which `default` turns into `None`
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+fn validate_optional(
+    value: &Option<Option<String>>,
+    validator: fn(&str) -> ApiResult<()>,
+) -> ApiResult<()> {
+    match value {
+        Some(Some(value)) => validator(value),
+        _ => Ok(()),
+    }
+}
+
+/// the profile with the changes applied and the names of the fields that changed
+fn apply_changes(mut profile: Profile, args: UpdateProfileArgs) -> (Profile, Vec<&'static str>) {
+    let mut changed = Vec::new();
+    if let Some(name) = args.name {
+        if name != profile.name {
+            profile.name = name;
+            changed.push("name");
+        }
+    }
+    for (field, value, name) in [
+        (&mut profile.display_name, args.display_name, "display_name"),
+        (&mut profile.locale, args.locale, "locale"),
+        (&mut profile.time_zone, args.time_zone, "time_zone"),
+        (&mut profile.avatar_url, args.avatar_url, "avatar_url"),
+    ] {
+        if let Some(value) = value {
+            if value != *field {
+                *field = value;
+                changed.push(name);
+            }
+        }
+    }
+    (profile, changed)
+}
+
+async fn profile_info(pool: &DbPool, user: &AuthenticatedUser) -> ApiResult<ProfileInfo> {
+    let profile = get_user_profile(pool, user.id)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+    Ok(ProfileInfo {
+        id: user.id,
+        tenant_id: user.tenant_id.clone(),
+        email_address: user.email_address.clone(),
+        name: profile.name,
+        display_name: profile.display_name,
+        locale: profile.locale,
+        time_zone: profile.time_zone,
+        avatar_url: profile.avatar_url,
+        roles: get_user_roles(pool, user.id).await?,
+    })
+}
+
+#[get("/me")]
+pub async fn get_profile(
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<ProfileInfo>> {
+    user.require_scope("profile:read")?;
+    Ok(Json(profile_info(&pool, &user).await?))
+}
+
+#[patch("/me")]
+pub async fn update_profile(
+    args: Json<UpdateProfileArgs>,
+    user: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<ProfileInfo>> {
+    user.require_scope("profile:write")?;
+    if let Some(name) = &args.name {
+        validate_name(name)?;
+    }
+    validate_optional(&args.display_name, validate_display_name)?;
+    validate_optional(&args.locale, validate_locale)?;
+    validate_optional(&args.time_zone, validate_time_zone)?;
+    validate_optional(&args.avatar_url, validate_avatar_url)?;
+
+    let profile = get_user_profile(&pool, user.id)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+    let (profile, changed) = apply_changes(profile, args.into_inner());
+    if !changed.is_empty() {
+        update_user_profile(&pool, user.id, &profile).await?;
+        info.audit(
+            &pool,
+            &user.email_address,
+            "user.update_profile",
+            &user.email_address,
+            AuditOutcome::Success,
+        )
+        .await?;
+        emit_event(
+            &pool,
+            PROFILE_UPDATED,
+            json!({
+                "tenant_id": user.tenant_id,
+                "email_address": user.email_address,
+                "changed_fields": changed,
+            }),
+        )
+        .await?;
+    }
+    Ok(Json(profile_info(&pool, &user).await?))
+}
+
+/// a json archive of the account, only with a session since it holds the whole history
+#[get("/me/export")]
+pub async fn export_account(
+    user: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<HttpResponse> {
+    user.require_session()?;
+    let sessions = get_sessions_of_user(&pool, user.id)
+        .await?
+        .into_iter()
+        .map(|session| SessionInfo {
+            created_date: session.created_date,
+            expire_date: session.expire_date,
+        })
+        .collect();
+    let api_keys = get_api_keys_of_user(&pool, user.id)
+        .await?
+        .into_iter()
+        .map(ApiKeyInfo::from)
+        .collect();
+    let account = get_user_by_id(&pool, user.id)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+    let email_changes = get_email_changes_of_user(&pool, user.id).await?;
+
+    // the current address and every one the account had through confirmed changes
+    let mut addresses = vec![user.email_address.clone()];
+    for change in &email_changes {
+        if matches!(
+            change.status,
+            EmailChangeStatus::Confirmed | EmailChangeStatus::Undone
+        ) {
+            addresses.push(change.old_email_address.clone());
+            addresses.push(change.new_email_address.clone());
+        }
+    }
+    addresses.sort();
+    addresses.dedup();
+
+    let mut invitations = Vec::new();
+    for address in &addresses {
+        invitations.extend(get_invitations_of_address(&pool, &user.tenant_id, address).await?);
+    }
+    invitations.sort_by_key(|invitation| std::cmp::Reverse(invitation.id));
+    // by id, whoever had one of the addresses before isn't part of the account
+    let audit_events = get_audit_events_of_user(&pool, user.id).await?;
+
+    let export = AccountExport {
+        exported_date: Utc::now(),
+        profile: profile_info(&pool, &user).await?,
+        account: AccountStatusInfo {
+            status: account.status.as_str().to_string(),
+            status_reason: account.status_reason,
+            status_date: account.status_date,
+            password_reset_required: account.password_reset_required,
+            password_changed_at: account.password_changed_at,
+        },
+        sessions,
+        api_keys,
+        email_changes: email_changes
+            .into_iter()
+            .map(EmailChangeInfo::from)
+            .collect(),
+        invitations: invitations.into_iter().map(InvitationInfo::from).collect(),
+        audit_events: audit_events.into_iter().map(AuditEventInfo::from).collect(),
+    };
+
+    info.audit(
+        &pool,
+        &user.email_address,
+        "user.export",
+        &user.email_address,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            "Content-Disposition",
+            "attachment; filename=\"account_export.json\"",
+        ))
+        .json(export))
+}
+
+async fn change_user_password(
+    pool: &DbPool,
+    tenant: &Tenant,
+    breached_passwords: &BreachedPasswords,
+    user: &AuthenticatedUser,
+    args: &ChangePasswordArgs,
+) -> ApiResult<()> {
+    let hashed_password = sha256_hash(&args.current_password);
+    if !does_user_exists(pool, &user.tenant_id, &user.email_address, &hashed_password).await? {
+        return Err(ApiError::WrongCredentials);
+    }
+    validate_password(&args.new_password)?;
+    let name = get_user_profile(pool, user.id)
+        .await?
+        .ok_or(ApiError::Unauthorized)?
+        .name;
+    tenant.settings.check_password(
+        &args.new_password,
+        &[&name, &user.email_address],
+        breached_passwords,
+    )?;
+    let hashed_password = sha256_hash(&args.new_password);
+    tenant
+        .settings
+        .check_password_reuse(pool, user.id, &hashed_password)
+        .await?;
+
+    update_user_password(pool, user.id, &hashed_password).await?;
+    // whoever knew the old password shouldn't stay logged in
+    delete_sessions_of_user(pool, user.id).await?;
+    delete_refresh_tokens_of_user(pool, user.id).await
+}
+
+/// only works with a session, so a leaked api key can't take over the account. also takes the
+/// session `login` hands out when the password expired
+#[put("/me/password")]
+pub async fn change_password(
+    args: Json<ChangePasswordArgs>,
+    PasswordChangeUser(user): PasswordChangeUser,
+    tenant: Tenant,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    breached_passwords: Data<BreachedPasswords>,
+) -> ApiResult<HttpResponse> {
+    user.require_session()?;
+    let result = change_user_password(&pool, &tenant, &breached_passwords, &user, &args).await;
+    info.audit_result(
+        &pool,
+        &user.email_address,
+        "user.change_password",
+        &user.email_address,
+        result,
+    )
+    .await?;
+
+    let session_token = generate_random_token();
+    let expire_date = Utc::now() + config.session_lifetime;
+    insert_session(&pool, &sha256_hash(&session_token), user.id, expire_date).await?;
+    emit_event(
+        &pool,
+        PASSWORD_CHANGED,
+        json!({ "tenant_id": user.tenant_id, "email_address": user.email_address }),
+    )
+    .await?;
+    Ok(HttpResponse::Ok()
+        .cookie(session_cookie(&config, &session_token))
+        .json(ChangePasswordResponse { session_token }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{
+            api_keys::{insert_api_key, NewApiKey},
+            email_changes::{insert_email_change, update_email_change_status, NewEmailChange},
+            invitations::{insert_invitation, NewInvitation},
+            roles::assign_role,
+            sessions::get_session,
+            tenants::{get_tenant, update_tenant, TenantSettings, DEFAULT_TENANT_ID},
+            user::{change_user_email_address, delete_user},
+        },
+        test::helper::{create_test_db, create_test_user_with_session, test_user_id},
+    };
+    use actix_web::{
+        http::{
+            header::{ContentType, AUTHORIZATION},
+            StatusCode,
+        },
+        test::{self, TestRequest},
+        App,
+    };
+
+    #[actix_web::test]
+    async fn get_and_update_profile() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .service(get_profile)
+                .service(update_profile),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/me")
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let profile: ProfileInfo = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(profile.email_address, "arian@gmail.com");
+        assert_eq!(profile.display_name, None);
+
+        for payload in [
+            r#"{"locale": "not a locale"}"#,
+            r#"{"time_zone": "Mars"}"#,
+            r#"{"avatar_url": "javascript:alert(1)"}"#,
+            r#"{"display_name": ""}"#,
+        ] {
+            let req = TestRequest::patch()
+                .uri("/me")
+                .set_payload(payload)
+                .insert_header(ContentType::json())
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{payload}");
+        }
+
+        let req = TestRequest::patch()
+            .uri("/me")
+            .set_payload(
+                r#"{"display_name": "Arian", "locale": "en-US", "time_zone": "Europe/Berlin",
+                "avatar_url": "https://example.com/arian.png"}"#,
+            )
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let profile: ProfileInfo = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(profile.display_name.as_deref(), Some("Arian"));
+        assert_eq!(profile.time_zone.as_deref(), Some("Europe/Berlin"));
+
+        // missing fields stay, null clears
+        let req = TestRequest::patch()
+            .uri("/me")
+            .set_payload(r#"{"name": "pouya", "avatar_url": null}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let profile: ProfileInfo = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(profile.name, "pouya");
+        assert_eq!(profile.locale.as_deref(), Some("en-US"));
+        assert_eq!(profile.avatar_url, None);
+    }
+
+    #[actix_web::test]
+    async fn export_leaves_out_an_earlier_owner_of_the_address() {
+        let db = create_test_db().await;
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+        let earlier_id = test_user_id(&db, "arian@gmail.com").await;
+        let earlier_info = RequestInfo {
+            tenant_id: DEFAULT_TENANT_ID.to_string(),
+            ip: Some("10.0.0.1".to_string()),
+            user_agent: None,
+            now: Utc::now(),
+        };
+        earlier_info
+            .audit(
+                &db,
+                "arian@gmail.com",
+                "user.login",
+                "arian@gmail.com",
+                AuditOutcome::Success,
+            )
+            .await
+            .unwrap();
+        delete_user(&db, earlier_id).await.unwrap();
+
+        let token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let app =
+            test::init_service(App::new().app_data(Data::new(db)).service(export_account)).await;
+        let req = TestRequest::get()
+            .uri("/me/export")
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let export: AccountExport = test::call_and_read_body_json(&app, req).await;
+        assert!(export.audit_events.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn export_has_everything_about_the_user() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let user_id = test_user_id(&db, "arian@gmail.com").await;
+        create_test_user_with_session(&db, "other@gmail.com").await;
+        insert_api_key(
+            &db,
+            &NewApiKey {
+                user_id,
+                name: "laptop",
+                prefix: "ak_test",
+                key_hash: "hash",
+                scopes: &[],
+                expire_date: None,
+            },
+        )
+        .await
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .service(update_profile)
+                .service(export_account),
+        )
+        .await;
+
+        let req = TestRequest::patch()
+            .uri("/me")
+            .set_payload(r#"{"locale": "en-US"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        test::call_service(&app, req).await;
+
+        assign_role(&db, user_id, "admin").await.unwrap();
+        for email_address in ["arian@gmail.com", "other@gmail.com"] {
+            insert_invitation(
+                &db,
+                &NewInvitation {
+                    tenant_id: DEFAULT_TENANT_ID,
+                    email_address,
+                    role_name: None,
+                    invited_by: "admin@gmail.com",
+                    token_id: email_address,
+                    expire_date: Utc::now() + chrono::Duration::days(1),
+                },
+            )
+            .await
+            .unwrap();
+        }
+        // the events and invitations of the old address still belong to the user
+        let change_id = insert_email_change(
+            &db,
+            &NewEmailChange {
+                user_id,
+                old_email_address: "arian@gmail.com",
+                new_email_address: "new@gmail.com",
+                code: 123456,
+                undo_token_hash: "undo",
+                code_expire_date: Utc::now() + chrono::Duration::hours(1),
+                undo_expire_date: Utc::now() + chrono::Duration::days(7),
+            },
+        )
+        .await
+        .unwrap();
+        update_email_change_status(
+            &db,
+            change_id,
+            EmailChangeStatus::Pending,
+            EmailChangeStatus::Confirmed,
+        )
+        .await
+        .unwrap();
+        change_user_email_address(&db, user_id, "new@gmail.com")
+            .await
+            .unwrap();
+
+        let req = TestRequest::get()
+            .uri("/me/export")
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let export: AccountExport = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(export.profile.email_address, "new@gmail.com");
+        assert_eq!(export.profile.locale.as_deref(), Some("en-US"));
+        assert_eq!(export.profile.roles, vec!["admin"]);
+        assert_eq!(export.account.status, "active");
+        assert_eq!(export.sessions.len(), 1);
+        assert_eq!(export.api_keys.len(), 1);
+        assert_eq!(export.api_keys[0].name, "laptop");
+        assert_eq!(export.email_changes.len(), 1);
+        assert_eq!(export.email_changes[0].status, "confirmed");
+        assert_eq!(export.invitations.len(), 1);
+        assert_eq!(export.invitations[0].email_address, "arian@gmail.com");
+        let actions: Vec<&str> = export
+            .audit_events
+            .iter()
+            .map(|event| event.action.as_str())
+            .collect();
+        assert_eq!(actions, vec!["user.update_profile"]);
+    }
+
+    #[actix_web::test]
+    async fn change_password_ends_other_sessions() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let user_id = test_user_id(&db, "arian@gmail.com").await;
+        let api_key = "ak_test_key";
+        insert_api_key(
+            &db,
+            &NewApiKey {
+                user_id,
+                name: "key",
+                prefix: "ak_test",
+                key_hash: &sha256_hash(api_key),
+                scopes: &[],
+                expire_date: None,
+            },
+        )
+        .await
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(Config::default()))
+                .service(change_password),
+        )
+        .await;
+
+        let req = TestRequest::put()
+            .uri("/me/password")
+            .set_json(ChangePasswordArgs {
+                current_password: "wrong".to_string(),
+                new_password: "new_password".to_string(),
+            })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::put()
+            .uri("/me/password")
+            .set_json(ChangePasswordArgs {
+                current_password: "some_hard_password".to_string(),
+                new_password: "new_password".to_string(),
+            })
+            .insert_header((AUTHORIZATION, format!("Bearer {api_key}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let req = TestRequest::put()
+            .uri("/me/password")
+            .set_json(ChangePasswordArgs {
+                current_password: "some_hard_password".to_string(),
+                new_password: "new_password".to_string(),
+            })
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let resp: ChangePasswordResponse = test::call_and_read_body_json(&app, req).await;
+
+        assert!(get_session(&db, &sha256_hash(&token))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(get_session(&db, &sha256_hash(&resp.session_token))
+            .await
+            .unwrap()
+            .is_some());
+        assert!(does_user_exists(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            &sha256_hash("new_password")
+        )
+        .await
+        .unwrap());
+    }
+
+    #[actix_web::test]
+    async fn change_password_refuses_recent_passwords() {
+        let db = create_test_db().await;
+        let settings = TenantSettings {
+            password_history_size: 2,
+            ..get_tenant(&db, DEFAULT_TENANT_ID)
+                .await
+                .unwrap()
+                .unwrap()
+                .settings
+        };
+        update_tenant(&db, DEFAULT_TENANT_ID, "Default", None, &settings)
+            .await
+            .unwrap();
+        let mut token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(Config::default()))
+                .service(change_password),
+        )
+        .await;
+
+        for (current_password, new_password, reused) in [
+            ("some_hard_password", "some_hard_password", true),
+            ("some_hard_password", "another_hard_password", false),
+            ("another_hard_password", "some_hard_password", true),
+            ("another_hard_password", "third_hard_password", false),
+            // only the last two passwords are remembered
+            ("third_hard_password", "some_hard_password", false),
+        ] {
+            let req = TestRequest::put()
+                .uri("/me/password")
+                .set_json(ChangePasswordArgs {
+                    current_password: current_password.to_string(),
+                    new_password: new_password.to_string(),
+                })
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            if reused {
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+                let body: serde_json::Value = test::read_body_json(resp).await;
+                assert_eq!(
+                    body["violations"],
+                    json!([{ "code": "reused", "history_size": 2 }])
+                );
+            } else {
+                let body: ChangePasswordResponse = test::read_body_json(resp).await;
+                token = body.session_token;
+            }
+        }
+    }
+}
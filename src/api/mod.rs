@@ -1,3 +1,82 @@
+pub mod account_deletion;
+pub mod admin;
+pub mod api_keys;
+pub mod email_change;
+pub mod invitations;
 pub mod login;
+pub mod me;
+pub mod oauth;
 pub mod register;
+pub mod reset_password;
+pub mod roles;
 pub mod send_email_code;
+pub mod service_accounts;
+pub mod tenant;
+pub mod webhooks;
+
+use actix_web::web::ServiceConfig;
+
+/// registers every endpoint, the server mounts them at the root and under `/t/{tenant}`
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(register::register)
+        .service(send_email_code::send_email_code)
+        .service(login::login)
+        .service(reset_password::reset_password)
+        .service(me::get_profile)
+        .service(me::update_profile)
+        .service(me::change_password)
+        .service(me::export_account)
+        .service(account_deletion::delete_account)
+        .service(account_deletion::cancel_account_deletion_by_token)
+        .service(account_deletion::account_deletion_cancel_page)
+        .service(email_change::request_email_change)
+        .service(email_change::confirm_email_change)
+        .service(email_change::undo_email_change)
+        .service(email_change::email_change_undo_page)
+        .service(tenant::current_tenant)
+        .service(oauth::authorize::authorize)
+        .service(oauth::token::token)
+        .service(oauth::clients::register_client)
+        .service(oauth::discovery::openid_configuration)
+        .service(oauth::discovery::jwks)
+        .service(oauth::userinfo::userinfo)
+        .service(oauth::device::device_authorization)
+        .service(oauth::device::verify_device)
+        .service(oauth::device::device_page)
+        .service(api_keys::create_api_key)
+        .service(api_keys::list_api_keys)
+        .service(api_keys::revoke_api_key)
+        .service(service_accounts::create_service_account)
+        .service(service_accounts::get_service_account_info)
+        .service(service_accounts::rotate_secret)
+        .service(service_accounts::delete_secret)
+        .service(roles::create_role)
+        .service(roles::list_roles)
+        .service(roles::grant_role_permission)
+        .service(roles::revoke_role_permission)
+        .service(roles::assign_user_role)
+        .service(roles::unassign_user_role)
+        .service(admin::users::list_users)
+        .service(admin::users::get_user_details)
+        .service(admin::users::create_user)
+        .service(admin::users::force_password_reset)
+        .service(admin::users::set_status)
+        .service(admin::users::change_email_address)
+        .service(admin::users::delete_user)
+        .service(admin::audit_log::query_audit_log)
+        .service(admin::audit_log::verify_audit_log)
+        .service(admin::tenants::create_tenant)
+        .service(admin::tenants::list_tenants)
+        .service(admin::tenants::update_tenant_settings)
+        .service(invitations::accept_invitation_token)
+        .service(invitations::invitation_page)
+        .service(invitations::create_invitation)
+        .service(invitations::list_invitations)
+        .service(invitations::resend_invitation)
+        .service(invitations::revoke_invitation_by_id)
+        .service(webhooks::create_webhook)
+        .service(webhooks::list_webhooks)
+        .service(webhooks::remove_webhook)
+        .service(webhooks::list_deliveries)
+        .service(webhooks::redeliver_delivery);
+}
@@ -0,0 +1,309 @@
+use super::parse_scope;
+use crate::{
+    auth::AuthenticatedUser,
+    clock::Clock,
+    config::Config,
+    db::{
+        oauth::{get_oauth_client, insert_authorization_code, AuthorizationCode},
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    utils::{hash::sha256_hash, random::generate_random_token},
+};
+use actix_web::{
+    get,
+    http::header::LOCATION,
+    web::{Data, Query},
+    HttpResponse,
+};
+use serde::Deserialize;
+use url::Url;
+
+#[derive(Deserialize)]
+pub struct AuthorizeArgs {
+    response_type: String,
+    client_id: String,
+    redirect_uri: String,
+    scope: Option<String>,
+    state: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+    /// openid connect clients use it to tie the id token to their session
+    nonce: Option<String>,
+}
+
+fn redirect(redirect_uri: &str, params: &[(&str, &str)], state: Option<&str>) -> HttpResponse {
+    // redirect uri was validated when the client got registered
+    let mut url = Url::parse(redirect_uri).unwrap();
+    url.query_pairs_mut().extend_pairs(params);
+    if let Some(state) = state {
+        url.query_pairs_mut().append_pair("state", state);
+    }
+    HttpResponse::Found()
+        .insert_header((LOCATION, url.to_string()))
+        .finish()
+}
+
+/// authenticates the user with the session that `login` created and redirects back
+/// to the client with an authorization code, only the authorization code flow with
+/// pkce `S256` is supported
+#[get("/oauth/authorize")]
+pub async fn authorize(
+    args: Query<AuthorizeArgs>,
+    user: Option<AuthenticatedUser>,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    clock: Data<dyn Clock + Send + Sync>,
+) -> ApiResult<HttpResponse> {
+    // errors about the client or redirect uri must not redirect, see rfc 6749 section 4.1.2.1
+    let client = get_oauth_client(&pool, &args.client_id)
+        .await?
+        .ok_or(ApiError::OAuth {
+            error: "invalid_client",
+            description: "unknown client",
+        })?;
+    if !client.redirect_uris.contains(&args.redirect_uri) {
+        return Err(ApiError::OAuth {
+            error: "invalid_request",
+            description: "redirect_uri is not registered for this client",
+        });
+    }
+
+    let state = args.state.as_deref();
+    let error_redirect = |error: &str, description: &str| {
+        Ok(redirect(
+            &args.redirect_uri,
+            &[("error", error), ("error_description", description)],
+            state,
+        ))
+    };
+
+    if args.response_type != "code" {
+        return error_redirect("unsupported_response_type", "only 'code' is supported");
+    }
+
+    let Some(code_challenge) = args.code_challenge.as_deref() else {
+        return error_redirect("invalid_request", "code_challenge is required");
+    };
+    if args.code_challenge_method.as_deref() != Some("S256") {
+        return error_redirect("invalid_request", "code_challenge_method must be 'S256'");
+    }
+
+    let scopes = match args.scope.as_deref() {
+        Some(scope) => parse_scope(scope),
+        None => client.scopes.clone(),
+    };
+    if !scopes.iter().all(|scope| client.scopes.contains(scope)) {
+        return error_redirect("invalid_scope", "scope is not allowed for this client");
+    }
+
+    // an api key can't stand in for a login, or it could get tokens with more scopes than it has
+    let Some(user) = user.filter(|user| user.require_session().is_ok()) else {
+        return error_redirect("login_required", "user is not logged in");
+    };
+
+    let code = generate_random_token();
+    let authorization_code = AuthorizationCode {
+        client_id: client.client_id,
+        user_id: user.id,
+        redirect_uri: args.redirect_uri.clone(),
+        scope: scopes.join(" "),
+        code_challenge: code_challenge.to_string(),
+        nonce: args.nonce.clone(),
+        expire_date: clock.now() + config.authorization_code_lifetime,
+    };
+    insert_authorization_code(&pool, &sha256_hash(&code), &authorization_code).await?;
+
+    Ok(redirect(&args.redirect_uri, &[("code", &code)], state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::api_keys::{insert_api_key, NewApiKey},
+        test::helper::{
+            create_test_clock, create_test_db, create_test_oauth_client,
+            create_test_user_with_session, test_user_id,
+        },
+    };
+    use actix_web::{
+        http::{header::AUTHORIZATION, StatusCode},
+        test::{self, TestRequest},
+        App,
+    };
+    use std::collections::HashMap;
+
+    const CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+    fn location_params(resp: &actix_web::dev::ServiceResponse) -> HashMap<String, String> {
+        let location = resp.headers().get(LOCATION).unwrap().to_str().unwrap();
+        Url::parse(location)
+            .unwrap()
+            .query_pairs()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn authorize_should_redirect_with_code() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(authorize),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri(&format!("/oauth/authorize?response_type=code&client_id=test_client&redirect_uri=https://app.example.com/callback&scope=openid&state=xyz&code_challenge={CHALLENGE}&code_challenge_method=S256"))
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let params = location_params(&resp);
+        assert!(params.contains_key("code"));
+        assert_eq!(params["state"], "xyz");
+    }
+
+    #[actix_web::test]
+    async fn authorize_without_login() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(authorize),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri(&format!("/oauth/authorize?response_type=code&client_id=test_client&redirect_uri=https://app.example.com/callback&state=xyz&code_challenge={CHALLENGE}&code_challenge_method=S256"))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let params = location_params(&resp);
+        assert_eq!(params["error"], "login_required");
+        assert_eq!(params["state"], "xyz");
+    }
+
+    #[actix_web::test]
+    async fn authorize_refuses_api_keys() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+        insert_api_key(
+            &db,
+            &NewApiKey {
+                user_id: test_user_id(&db, "arian@gmail.com").await,
+                name: "ci",
+                prefix: "abcd1234",
+                key_hash: &sha256_hash("ak_abcd1234_secret"),
+                scopes: &["profile:read".to_string()],
+                expire_date: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(authorize),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri(&format!("/oauth/authorize?response_type=code&client_id=test_client&redirect_uri=https://app.example.com/callback&scope=openid&state=xyz&code_challenge={CHALLENGE}&code_challenge_method=S256"))
+            .insert_header((AUTHORIZATION, "Bearer ak_abcd1234_secret"))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let params = location_params(&resp);
+        assert!(!params.contains_key("code"));
+        assert_eq!(params["error"], "login_required");
+    }
+
+    #[actix_web::test]
+    async fn authorize_without_pkce() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(authorize),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri(&format!("/oauth/authorize?response_type=code&client_id=test_client&redirect_uri=https://app.example.com/callback&code_challenge={CHALLENGE}&code_challenge_method=plain"))
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(location_params(&resp)["error"], "invalid_request");
+    }
+
+    #[actix_web::test]
+    async fn authorize_with_unregistered_redirect_uri() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(authorize),
+        )
+        .await;
+        // exact matching, so a different path isn't accepted either
+        let req = TestRequest::get()
+            .uri(&format!("/oauth/authorize?response_type=code&client_id=test_client&redirect_uri=https://app.example.com/callback/evil&code_challenge={CHALLENGE}&code_challenge_method=S256"))
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(resp.headers().get(LOCATION).is_none());
+    }
+
+    #[actix_web::test]
+    async fn authorize_with_not_allowed_scope() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(authorize),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri(&format!("/oauth/authorize?response_type=code&client_id=test_client&redirect_uri=https://app.example.com/callback&scope=openid%20admin&code_challenge={CHALLENGE}&code_challenge_method=S256"))
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(location_params(&resp)["error"], "invalid_scope");
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{
+    auth::AuthenticatedUser,
+    db::{
+        oauth::{insert_oauth_client, OAuthClient},
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    utils::{hash::sha256_hash, random::generate_random_token, validators::validate_scope},
+};
+use actix_web::{
+    post,
+    web::{Data, Json},
+};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Deserialize)]
+pub struct RegisterClientArgs {
+    name: String,
+    redirect_uris: Vec<String>,
+    scopes: Vec<String>,
+    /// confidential clients get a secret, spa and mobile apps should be public
+    confidential: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RegisterClientResponse {
+    pub client_id: String,
+    /// only shown once, just its hash is stored
+    pub client_secret: Option<String>,
+}
+
+fn validate_redirect_uri(redirect_uri: &str) -> ApiResult<()> {
+    // rfc 6749 section 3.1.2, must be absolute and must not have a fragment
+    Url::parse(redirect_uri)
+        .ok()
+        .filter(|url| url.fragment().is_none())
+        .map(|_| ())
+        .ok_or(ApiError::BadArgument {
+            argument_name: "redirect_uris",
+        })
+}
+
+#[post("/oauth/clients")]
+pub async fn register_client(
+    args: Json<RegisterClientArgs>,
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<RegisterClientResponse>> {
+    user.require_session()?;
+    if args.name.is_empty() || args.name.len() > 64 {
+        return Err(ApiError::BadArgument {
+            argument_name: "name",
+        });
+    }
+    if args.redirect_uris.is_empty() {
+        return Err(ApiError::BadArgument {
+            argument_name: "redirect_uris",
+        });
+    }
+    args.redirect_uris
+        .iter()
+        .try_for_each(|uri| validate_redirect_uri(uri))?;
+    args.scopes
+        .iter()
+        .try_for_each(|scope| validate_scope(scope))?;
+
+    let client_id = generate_random_token();
+    let client_secret = args.confidential.then(generate_random_token);
+    let client = OAuthClient {
+        client_id: client_id.clone(),
+        client_secret_hash: client_secret.as_deref().map(sha256_hash),
+        name: args.name.clone(),
+        redirect_uris: args.redirect_uris.clone(),
+        scopes: args.scopes.clone(),
+        owner_email_address: user.email_address,
+    };
+    insert_oauth_client(&pool, &client).await?;
+
+    Ok(Json(RegisterClientResponse {
+        client_id,
+        client_secret,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::oauth::get_oauth_client,
+        test::helper::{create_test_db, create_test_user_with_session},
+    };
+    use actix_web::{
+        http::{
+            header::{ContentType, AUTHORIZATION},
+            StatusCode,
+        },
+        test::{self, TestRequest},
+        App,
+    };
+
+    #[actix_web::test]
+    async fn register_client_should_work() {
+        let db = create_test_db().await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .service(register_client),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/oauth/clients")
+            .set_payload(r#"{"name": "grafana", "redirect_uris": ["https://grafana.example.com/login/generic_oauth"], "scopes": ["openid"], "confidential": true}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body: RegisterClientResponse = test::read_body_json(resp).await;
+        let client = get_oauth_client(&db, &body.client_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            client.client_secret_hash,
+            Some(sha256_hash(&body.client_secret.unwrap()))
+        );
+        assert_eq!(client.owner_email_address, "arian@gmail.com");
+    }
+
+    #[actix_web::test]
+    async fn register_client_with_invalid_redirect_uri() {
+        let db = create_test_db().await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let app =
+            test::init_service(App::new().app_data(Data::new(db)).service(register_client)).await;
+
+        let req = TestRequest::post()
+            .uri("/oauth/clients")
+            .set_payload(r#"{"name": "spa", "redirect_uris": ["https://app.example.com/#callback"], "scopes": [], "confidential": false}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn register_client_without_login() {
+        let db = create_test_db().await;
+        let app =
+            test::init_service(App::new().app_data(Data::new(db)).service(register_client)).await;
+
+        let req = TestRequest::post()
+            .uri("/oauth/clients")
+            .set_payload(r#"{"name": "spa", "redirect_uris": ["https://app.example.com/callback"], "scopes": [], "confidential": false}"#)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
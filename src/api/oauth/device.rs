@@ -0,0 +1,486 @@
+use super::{authenticate_client, parse_scope};
+use crate::{
+    auth::AuthenticatedUser,
+    clock::Clock,
+    config::Config,
+    db::{
+        device_codes::{
+            decide_device_code, get_device_code_by_user_code, insert_device_code, DeviceCode,
+            DeviceCodeStatus,
+        },
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    utils::{
+        hash::sha256_hash,
+        random::{generate_random_token, generate_user_code},
+    },
+};
+use actix_web::{
+    get, post,
+    web::{Data, Form, Json, Query},
+    HttpRequest,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct DeviceCodeArgs {
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    scope: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeviceCodeResponse {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String,
+    pub expires_in: i64,
+    pub interval: i64,
+}
+
+#[derive(Deserialize)]
+pub struct DevicePageArgs {
+    user_code: Option<String>,
+}
+
+/// what the user is asked to approve, the client and scopes are only known once there's a code
+#[derive(Serialize, Deserialize)]
+pub struct DeviceVerificationInfo {
+    pub user_code: Option<String>,
+    pub client_id: Option<String>,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyDeviceArgs {
+    user_code: String,
+    approve: bool,
+}
+
+/// users may type the code in lowercase or without the dash
+fn normalize_user_code(user_code: &str) -> String {
+    let mut code: String = user_code
+        .chars()
+        .filter(|char| char.is_ascii_alphanumeric())
+        .map(|char| char.to_ascii_uppercase())
+        .collect();
+    if code.len() == 8 {
+        code.insert(4, '-');
+    }
+    code
+}
+
+/// starts the device authorization grant, see rfc 8628 section 3.1
+#[post("/device/code")]
+pub async fn device_authorization(
+    req: HttpRequest,
+    args: Form<DeviceCodeArgs>,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    clock: Data<dyn Clock + Send + Sync>,
+) -> ApiResult<Json<DeviceCodeResponse>> {
+    let client = authenticate_client(
+        &pool,
+        &req,
+        args.client_id.as_deref(),
+        args.client_secret.as_deref(),
+    )
+    .await?;
+
+    let scopes = match args.scope.as_deref() {
+        Some(scope) => parse_scope(scope),
+        None => client.scopes.clone(),
+    };
+    if !scopes.iter().all(|scope| client.scopes.contains(scope)) {
+        return Err(ApiError::OAuth {
+            error: "invalid_scope",
+            description: "scope is not allowed for this client",
+        });
+    }
+
+    let device_code = generate_random_token();
+    let user_code = generate_user_code();
+    let interval = config.device_code_poll_interval.num_seconds();
+    insert_device_code(
+        &pool,
+        &sha256_hash(&device_code),
+        &user_code,
+        &client.client_id,
+        &scopes.join(" "),
+        interval,
+        clock.now(),
+    )
+    .await?;
+
+    Ok(Json(DeviceCodeResponse {
+        device_code,
+        verification_uri_complete: format!(
+            "{}?user_code={user_code}",
+            config.device_verification_uri
+        ),
+        user_code,
+        verification_uri: config.device_verification_uri.clone(),
+        expires_in: config.device_code_lifetime.num_seconds(),
+        interval,
+    }))
+}
+
+/// the code of `user_code` if it still waits for a decision
+async fn get_pending_device_code(
+    pool: &DbPool,
+    config: &Config,
+    user_code: &str,
+    now: DateTime<Utc>,
+) -> ApiResult<DeviceCode> {
+    let invalid_code = ApiError::BadArgument {
+        argument_name: "user_code",
+    };
+    let Some(device_code) = get_device_code_by_user_code(pool, user_code).await? else {
+        return Err(invalid_code);
+    };
+    if device_code.status != DeviceCodeStatus::Pending
+        || device_code.created_date + config.device_code_lifetime <= now
+    {
+        return Err(invalid_code);
+    }
+    Ok(device_code)
+}
+
+/// `verification_uri`, shows a logged in user what they approve or deny with `verify_device`.
+/// `verification_uri_complete` links here with the code, without it the user has to type it
+#[get("/device")]
+pub async fn device_page(
+    args: Query<DevicePageArgs>,
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    clock: Data<dyn Clock + Send + Sync>,
+) -> ApiResult<Json<DeviceVerificationInfo>> {
+    user.require_session()?;
+    let Some(user_code) = args.user_code.as_deref() else {
+        return Ok(Json(DeviceVerificationInfo {
+            user_code: None,
+            client_id: None,
+            scopes: vec![],
+        }));
+    };
+    let device_code =
+        get_pending_device_code(&pool, &config, &normalize_user_code(user_code), clock.now())
+            .await?;
+    Ok(Json(DeviceVerificationInfo {
+        scopes: parse_scope(&device_code.scope),
+        user_code: Some(device_code.user_code),
+        client_id: Some(device_code.client_id),
+    }))
+}
+
+/// lets a logged in user approve or deny the code shown on their device
+#[post("/device/verify")]
+pub async fn verify_device(
+    args: Json<VerifyDeviceArgs>,
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    clock: Data<dyn Clock + Send + Sync>,
+) -> ApiResult<&'static str> {
+    user.require_session()?;
+    let user_code = normalize_user_code(&args.user_code);
+    get_pending_device_code(&pool, &config, &user_code, clock.now()).await?;
+
+    let status = if args.approve {
+        DeviceCodeStatus::Approved
+    } else {
+        DeviceCodeStatus::Denied
+    };
+    decide_device_code(&pool, &user_code, status, user.id)
+        .await?
+        .then_some("")
+        .ok_or(ApiError::BadArgument {
+            argument_name: "user_code",
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::oauth::token::{token, TokenResponse, DEVICE_CODE_GRANT_TYPE},
+        clock::FakeClock,
+        test::helper::{
+            create_test_clock, create_test_db, create_test_oauth_client,
+            create_test_user_with_session,
+        },
+    };
+    use actix_web::{
+        dev::{Service, ServiceResponse},
+        http::{
+            header::{ContentType, AUTHORIZATION},
+            StatusCode,
+        },
+        test::{self, TestRequest},
+        App,
+    };
+    use chrono::Duration;
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    async fn start_device_flow<S, B>(app: &S) -> DeviceCodeResponse
+    where
+        S: Service<actix_http::Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+        B: actix_web::body::MessageBody,
+    {
+        let req = TestRequest::post()
+            .uri("/device/code")
+            .set_payload("client_id=test_client&scope=openid email")
+            .insert_header(ContentType::form_url_encoded())
+            .to_request();
+        test::call_and_read_body_json(app, req).await
+    }
+
+    fn poll_request(device_code: &str) -> actix_http::Request {
+        TestRequest::post()
+            .uri("/oauth/token")
+            .set_payload(format!(
+                "grant_type={DEVICE_CODE_GRANT_TYPE}&client_id=test_client&device_code={device_code}"
+            ))
+            .insert_header(ContentType::form_url_encoded())
+            .to_request()
+    }
+
+    fn verify_request(session_token: &str, user_code: &str, approve: bool) -> actix_http::Request {
+        TestRequest::post()
+            .uri("/device/verify")
+            .set_payload(format!(
+                r#"{{"user_code": "{user_code}", "approve": {approve}}}"#
+            ))
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request()
+    }
+
+    #[actix_web::test]
+    async fn whole_device_flow_should_work() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(device_authorization)
+                .service(verify_device)
+                .service(token),
+        )
+        .await;
+        let device = start_device_flow(&app).await;
+        assert_eq!(device.interval, 5);
+        assert!(device
+            .verification_uri_complete
+            .ends_with(&device.user_code));
+
+        let resp = test::call_service(&app, poll_request(&device.device_code)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "authorization_pending");
+
+        // polling again right away isn't allowed
+        let resp = test::call_service(&app, poll_request(&device.device_code)).await;
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "slow_down");
+
+        let user_code = device.user_code.replace('-', "").to_lowercase();
+        let resp = test::call_service(&app, verify_request(&session_token, &user_code, true)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // a code can only be decided once
+        let resp =
+            test::call_service(&app, verify_request(&session_token, &user_code, false)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = test::call_service(&app, poll_request(&device.device_code)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let tokens: TokenResponse = test::read_body_json(resp).await;
+        assert_eq!(tokens.scope, "openid email");
+        assert!(tokens.id_token.is_some());
+
+        let resp = test::call_service(&app, poll_request(&device.device_code)).await;
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "invalid_grant");
+    }
+
+    #[actix_web::test]
+    async fn verification_uri_complete_shows_the_request() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let config = Config::default();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(config.clone()))
+                .service(device_authorization)
+                .service(device_page)
+                .service(verify_device),
+        )
+        .await;
+        let device = start_device_flow(&app).await;
+        let uri = device
+            .verification_uri_complete
+            .strip_prefix(&config.issuer)
+            .unwrap();
+
+        // the user has to log in first
+        let req = TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let req = TestRequest::get()
+            .uri(uri)
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+        let info: DeviceVerificationInfo = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(info.user_code.as_deref(), Some(device.user_code.as_str()));
+        assert_eq!(info.client_id.as_deref(), Some("test_client"));
+        assert_eq!(info.scopes, ["openid", "email"]);
+
+        let resp = test::call_service(
+            &app,
+            verify_request(&session_token, &device.user_code, true),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // decided codes aren't shown anymore
+        let req = TestRequest::get()
+            .uri(uri)
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // without a code the user is asked to type it
+        let req = TestRequest::get()
+            .uri(
+                device
+                    .verification_uri
+                    .strip_prefix(&config.issuer)
+                    .unwrap(),
+            )
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+        let info: DeviceVerificationInfo = test::call_and_read_body_json(&app, req).await;
+        assert!(info.user_code.is_none());
+    }
+
+    #[actix_web::test]
+    async fn polling_interval_and_expiry_follow_the_clock() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let config = Config::default();
+        let clock = Arc::new(FakeClock::new(Utc::now()));
+        let app_clock: Arc<dyn Clock + Send + Sync> = clock.clone();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(Data::from(app_clock))
+                .app_data(Data::new(config.clone()))
+                .service(device_authorization)
+                .service(verify_device)
+                .service(token),
+        )
+        .await;
+        let device = start_device_flow(&app).await;
+        let poll = || async {
+            let resp = test::call_service(&app, poll_request(&device.device_code)).await;
+            let body: Value = test::read_body_json(resp).await;
+            body["error"].as_str().unwrap().to_string()
+        };
+
+        assert_eq!(poll().await, "authorization_pending");
+        clock.advance(Duration::seconds(device.interval - 1));
+        assert_eq!(poll().await, "slow_down");
+        // slowing down adds 5 seconds to the interval
+        clock.advance(Duration::seconds(device.interval + 4));
+        assert_eq!(poll().await, "slow_down");
+        clock.advance(Duration::seconds(device.interval + 10));
+        assert_eq!(poll().await, "authorization_pending");
+
+        clock.advance(config.device_code_lifetime);
+        let resp = test::call_service(
+            &app,
+            verify_request(&session_token, &device.user_code, true),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(poll().await, "expired_token");
+        assert_eq!(poll().await, "invalid_grant");
+    }
+
+    #[actix_web::test]
+    async fn denied_device_should_not_get_tokens() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(device_authorization)
+                .service(verify_device)
+                .service(token),
+        )
+        .await;
+        let device = start_device_flow(&app).await;
+
+        let resp = test::call_service(
+            &app,
+            verify_request(&session_token, &device.user_code, false),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::call_service(&app, poll_request(&device.device_code)).await;
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "access_denied");
+    }
+
+    #[actix_web::test]
+    async fn device_code_with_unknown_scope() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(device_authorization),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/device/code")
+            .set_payload("client_id=test_client&scope=admin")
+            .insert_header(ContentType::form_url_encoded())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn normalize_user_code_should_work() {
+        assert_eq!(normalize_user_code("bcdf-ghjk"), "BCDF-GHJK");
+        assert_eq!(normalize_user_code("BCDF GHJK"), "BCDF-GHJK");
+        assert_eq!(normalize_user_code("bcdfghjk"), "BCDF-GHJK");
+    }
+}
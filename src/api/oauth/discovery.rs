@@ -0,0 +1,106 @@
+use super::token::DEVICE_CODE_GRANT_TYPE;
+use crate::{
+    config::Config,
+    db::DbPool,
+    error::ApiResult,
+    jwt::{jwk, verification_keys, Jwk},
+};
+use actix_web::{
+    get,
+    web::{Data, Json},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Serialize, Deserialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+/// openid connect discovery document, see openid connect discovery section 3
+#[get("/.well-known/openid-configuration")]
+pub async fn openid_configuration(config: Data<Config>) -> Json<Value> {
+    let issuer = config.issuer.trim_end_matches('/');
+    Json(json!({
+        "issuer": config.issuer,
+        "authorization_endpoint": format!("{issuer}/oauth/authorize"),
+        "token_endpoint": format!("{issuer}/oauth/token"),
+        "userinfo_endpoint": format!("{issuer}/userinfo"),
+        "jwks_uri": format!("{issuer}/jwks.json"),
+        "device_authorization_endpoint": format!("{issuer}/device/code"),
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code", "refresh_token", "client_credentials", DEVICE_CODE_GRANT_TYPE],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["ES256"],
+        "scopes_supported": ["openid", "email", "profile"],
+        "claims_supported": ["iss", "sub", "aud", "exp", "iat", "nonce", "email", "email_verified", "name"],
+        "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post", "none"],
+        "code_challenge_methods_supported": ["S256"],
+    }))
+}
+
+/// every key that a still valid token may be signed with, so retired keys stay
+/// published until the tokens they signed have expired
+#[get("/jwks.json")]
+pub async fn jwks(pool: Data<DbPool>, config: Data<Config>) -> ApiResult<Json<JwkSet>> {
+    let keys = verification_keys(&pool, &config)
+        .await?
+        .iter()
+        .map(jwk)
+        .collect::<ApiResult<Vec<Jwk>>>()?;
+    Ok(Json(JwkSet { keys }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::signing_keys::insert_signing_key,
+        jwt::{current_signing_key, generate_signing_key},
+        test::helper::create_test_db,
+    };
+    use actix_web::{
+        test::{self, TestRequest},
+        App,
+    };
+    use chrono::Utc;
+
+    #[actix_web::test]
+    async fn openid_configuration_should_work() {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(Config::default()))
+                .service(openid_configuration),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/.well-known/openid-configuration")
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["issuer"], Config::default().issuer);
+        assert_eq!(body["jwks_uri"], "http://127.0.0.1:8000/jwks.json");
+    }
+
+    #[actix_web::test]
+    async fn jwks_contains_retired_keys_in_overlap() {
+        let db = create_test_db().await;
+        let config = Config::default();
+
+        let mut retired_key = generate_signing_key().unwrap();
+        retired_key.created_date = Utc::now() - config.signing_key_rotation_period;
+        insert_signing_key(&db, &retired_key).await.unwrap();
+        let current_key = current_signing_key(&db, &config).await.unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(Data::new(config))
+                .service(jwks),
+        )
+        .await;
+        let req = TestRequest::get().uri("/jwks.json").to_request();
+        let body: JwkSet = test::call_and_read_body_json(&app, req).await;
+        let kids: Vec<String> = body.keys.into_iter().map(|key| key.kid).collect();
+        assert_eq!(kids, vec![current_key.kid, retired_key.kid]);
+    }
+}
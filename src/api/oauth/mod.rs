@@ -0,0 +1,94 @@
+pub mod authorize;
+pub mod clients;
+pub mod device;
+pub mod discovery;
+pub mod token;
+pub mod userinfo;
+
+use crate::{
+    db::{
+        oauth::{get_oauth_client, OAuthClient},
+        user::User,
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    jwt::UserClaims,
+    utils::hash::sha256_hash,
+};
+use actix_web::{http::header::AUTHORIZATION, HttpRequest};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+const INVALID_CLIENT: ApiError = ApiError::OAuth {
+    error: "invalid_client",
+    description: "client authentication failed",
+};
+
+/// client credentials from the `Authorization: Basic` header (client_secret_basic)
+/// or from the form body (client_secret_post)
+fn client_credentials(
+    req: &HttpRequest,
+    client_id: Option<&str>,
+    client_secret: Option<&str>,
+) -> Option<(String, Option<String>)> {
+    let basic = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|value| STANDARD.decode(value).ok())
+        .and_then(|value| String::from_utf8(value).ok());
+
+    if let Some(basic) = basic {
+        let (client_id, client_secret) = basic.split_once(':')?;
+        return Some((client_id.to_string(), Some(client_secret.to_string())));
+    }
+
+    client_id.map(|client_id| (client_id.to_string(), client_secret.map(str::to_string)))
+}
+
+/// compares in constant time, so that the time taken doesn't give away how much of a secret
+/// hash was right
+fn hashes_match(a: &str, b: &str) -> bool {
+    ring::constant_time::verify_slices_are_equal(a.as_bytes(), b.as_bytes()).is_ok()
+}
+
+async fn authenticate_client(
+    pool: &DbPool,
+    req: &HttpRequest,
+    client_id: Option<&str>,
+    client_secret: Option<&str>,
+) -> ApiResult<OAuthClient> {
+    let (client_id, client_secret) =
+        client_credentials(req, client_id, client_secret).ok_or(INVALID_CLIENT)?;
+    let client = get_oauth_client(pool, &client_id)
+        .await?
+        .ok_or(INVALID_CLIENT)?;
+
+    // public clients have no secret, pkce is what protects their codes
+    match (&client.client_secret_hash, client_secret) {
+        (None, _) => Ok(client),
+        (Some(hash), Some(secret)) if hashes_match(hash, &sha256_hash(&secret)) => Ok(client),
+        _ => Err(INVALID_CLIENT),
+    }
+}
+
+/// scopes are sent as a space separated list, see rfc 6749 section 3.3
+fn parse_scope(scope: &str) -> Vec<String> {
+    scope.split_whitespace().map(str::to_string).collect()
+}
+
+/// claims that the granted scopes allow the client to see, see openid connect core section 5.4
+fn user_claims(user: User, scope: &str) -> UserClaims {
+    let scopes = parse_scope(scope);
+
+    let mut claims = UserClaims::default();
+    if scopes.iter().any(|scope| scope == "email") {
+        // register only works with a code sent to the address, so every address is verified
+        claims.email = Some(user.email_address);
+        claims.email_verified = Some(true);
+    }
+    if scopes.iter().any(|scope| scope == "profile") {
+        claims.name = Some(user.name);
+    }
+    claims
+}
@@ -0,0 +1,818 @@
+use super::{authenticate_client, client_credentials, parse_scope, user_claims, INVALID_CLIENT};
+use crate::{
+    clock::Clock,
+    config::Config,
+    db::{
+        device_codes::{
+            delete_device_code, get_device_code, update_device_code_poll, DeviceCodeStatus,
+        },
+        oauth::{
+            insert_refresh_token, take_authorization_code, take_refresh_token, OAuthClient,
+            RefreshToken,
+        },
+        roles::get_user_roles,
+        service_accounts::{get_service_account, verify_service_account_secret},
+        user::{get_user_by_id, AccountStatus},
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    jwt::{encode_token, AccessTokenClaims, IdTokenClaims},
+    utils::{
+        hash::{sha256_base64url, sha256_hash},
+        random::generate_random_token,
+    },
+};
+use actix_web::{
+    post,
+    web::{Data, Form},
+    HttpRequest, HttpResponse,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct TokenArgs {
+    grant_type: String,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<String>,
+    refresh_token: Option<String>,
+    scope: Option<String>,
+    device_code: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    /// not issued for client_credentials, the client can just ask for a new token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub scope: String,
+    /// only issued when the `openid` scope is granted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+pub const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+fn invalid_grant(description: &'static str) -> ApiError {
+    ApiError::OAuth {
+        error: "invalid_grant",
+        description,
+    }
+}
+
+async fn issue_tokens(
+    pool: &DbPool,
+    config: &Config,
+    client_id: &str,
+    user_id: i64,
+    scope: &str,
+    nonce: Option<String>,
+    now_date: DateTime<Utc>,
+) -> ApiResult<TokenResponse> {
+    let user = get_user_by_id(pool, user_id)
+        .await?
+        .ok_or(invalid_grant("user doesn't exist anymore"))?;
+    // refresh tokens and codes issued before a suspension must not outlive it
+    if user.status != AccountStatus::Active {
+        return Err(invalid_grant("account isn't active"));
+    }
+    // email addresses are only unique per tenant and can change, the id is neither
+    let claims = AccessTokenClaims {
+        iss: config.issuer.clone(),
+        sub: user.id.to_string(),
+        aud: client_id.to_string(),
+        exp: (now_date + config.access_token_lifetime).timestamp(),
+        iat: now_date.timestamp(),
+        scope: scope.to_string(),
+        roles: get_user_roles(pool, user.id).await?,
+        tenant: Some(user.tenant_id.clone()),
+    };
+    let access_token = encode_token(pool, config, &claims).await?;
+
+    let refresh_token = generate_random_token();
+    let refresh_token_record = RefreshToken {
+        client_id: client_id.to_string(),
+        user_id,
+        scope: scope.to_string(),
+        expire_date: now_date + config.refresh_token_lifetime,
+    };
+    insert_refresh_token(pool, &sha256_hash(&refresh_token), &refresh_token_record).await?;
+
+    let id_token = if parse_scope(scope).iter().any(|scope| scope == "openid") {
+        let claims = IdTokenClaims {
+            iss: config.issuer.clone(),
+            sub: user.id.to_string(),
+            aud: client_id.to_string(),
+            exp: (now_date + config.access_token_lifetime).timestamp(),
+            iat: now_date.timestamp(),
+            nonce,
+            user_claims: user_claims(user, scope),
+        };
+        Some(encode_token(pool, config, &claims).await?)
+    } else {
+        None
+    };
+
+    Ok(TokenResponse {
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: config.access_token_lifetime.num_seconds(),
+        refresh_token: Some(refresh_token),
+        scope: scope.to_string(),
+        id_token,
+    })
+}
+
+async fn authorization_code_grant(
+    pool: &DbPool,
+    config: &Config,
+    client: &OAuthClient,
+    args: &TokenArgs,
+    now_date: DateTime<Utc>,
+) -> ApiResult<TokenResponse> {
+    let (Some(code), Some(redirect_uri), Some(code_verifier)) =
+        (&args.code, &args.redirect_uri, &args.code_verifier)
+    else {
+        return Err(ApiError::OAuth {
+            error: "invalid_request",
+            description: "code, redirect_uri and code_verifier are required",
+        });
+    };
+
+    let authorization_code = take_authorization_code(pool, &sha256_hash(code))
+        .await?
+        .ok_or(invalid_grant("unknown authorization code"))?;
+
+    if authorization_code.expire_date <= now_date {
+        return Err(invalid_grant("expired authorization code"));
+    }
+    if authorization_code.client_id != client.client_id
+        || authorization_code.redirect_uri != *redirect_uri
+    {
+        return Err(invalid_grant(
+            "authorization code was issued to another client",
+        ));
+    }
+    // rfc 7636 section 4.1, verifier is 43 to 128 characters
+    if !(43..=128).contains(&code_verifier.len())
+        || sha256_base64url(code_verifier) != authorization_code.code_challenge
+    {
+        return Err(invalid_grant("code_verifier doesn't match code_challenge"));
+    }
+
+    issue_tokens(
+        pool,
+        config,
+        &client.client_id,
+        authorization_code.user_id,
+        &authorization_code.scope,
+        authorization_code.nonce,
+        now_date,
+    )
+    .await
+}
+
+async fn refresh_token_grant(
+    pool: &DbPool,
+    config: &Config,
+    client: &OAuthClient,
+    args: &TokenArgs,
+    now_date: DateTime<Utc>,
+) -> ApiResult<TokenResponse> {
+    let Some(refresh_token) = &args.refresh_token else {
+        return Err(ApiError::OAuth {
+            error: "invalid_request",
+            description: "refresh_token is required",
+        });
+    };
+
+    let refresh_token = take_refresh_token(pool, &sha256_hash(refresh_token))
+        .await?
+        .ok_or(invalid_grant("unknown refresh token"))?;
+
+    if refresh_token.expire_date <= now_date {
+        return Err(invalid_grant("expired refresh token"));
+    }
+    if refresh_token.client_id != client.client_id {
+        return Err(invalid_grant("refresh token was issued to another client"));
+    }
+
+    // the client may ask for a subset of the originally granted scopes
+    let granted_scopes = parse_scope(&refresh_token.scope);
+    let scope = match args.scope.as_deref() {
+        Some(scope) => {
+            let scopes = parse_scope(scope);
+            if !scopes.iter().all(|scope| granted_scopes.contains(scope)) {
+                return Err(ApiError::OAuth {
+                    error: "invalid_scope",
+                    description: "scope exceeds the originally granted scope",
+                });
+            }
+            scopes.join(" ")
+        }
+        None => refresh_token.scope,
+    };
+
+    issue_tokens(
+        pool,
+        config,
+        &client.client_id,
+        refresh_token.user_id,
+        &scope,
+        None,
+        now_date,
+    )
+    .await
+}
+
+fn device_error(error: &'static str, description: &'static str) -> ApiError {
+    ApiError::OAuth { error, description }
+}
+
+/// polled by devices until the user approves or denies, see rfc 8628 section 3.4
+async fn device_code_grant(
+    pool: &DbPool,
+    config: &Config,
+    client: &OAuthClient,
+    args: &TokenArgs,
+    now_date: DateTime<Utc>,
+) -> ApiResult<TokenResponse> {
+    let Some(device_code) = &args.device_code else {
+        return Err(ApiError::OAuth {
+            error: "invalid_request",
+            description: "device_code is required",
+        });
+    };
+    let device_code_hash = sha256_hash(device_code);
+    let device_code = get_device_code(pool, &device_code_hash)
+        .await?
+        .ok_or(invalid_grant("unknown device code"))?;
+    if device_code.client_id != client.client_id {
+        return Err(invalid_grant("device code was issued to another client"));
+    }
+
+    if device_code.created_date + config.device_code_lifetime <= now_date {
+        delete_device_code(pool, &device_code_hash).await?;
+        return Err(device_error("expired_token", "device code has expired"));
+    }
+
+    match device_code.status {
+        DeviceCodeStatus::Pending => {
+            let polled_too_soon = device_code.last_poll_date.is_some_and(|last_poll_date| {
+                now_date - last_poll_date < Duration::seconds(device_code.poll_interval)
+            });
+            if polled_too_soon {
+                // rfc 8628 section 3.5, interval must be increased by 5 seconds
+                update_device_code_poll(
+                    pool,
+                    &device_code_hash,
+                    device_code.poll_interval + 5,
+                    now_date,
+                )
+                .await?;
+                return Err(device_error("slow_down", "polling too fast"));
+            }
+            update_device_code_poll(pool, &device_code_hash, device_code.poll_interval, now_date)
+                .await?;
+            Err(device_error(
+                "authorization_pending",
+                "user hasn't approved the device yet",
+            ))
+        }
+        DeviceCodeStatus::Denied => {
+            delete_device_code(pool, &device_code_hash).await?;
+            Err(device_error("access_denied", "user denied the device"))
+        }
+        DeviceCodeStatus::Approved => {
+            let Some(user_id) = device_code.user_id else {
+                return Err(invalid_grant("device code has no user"));
+            };
+            // deleting first makes sure that concurrent polls only get one set of tokens
+            if !delete_device_code(pool, &device_code_hash).await? {
+                return Err(invalid_grant("unknown device code"));
+            }
+            issue_tokens(
+                pool,
+                config,
+                &client.client_id,
+                user_id,
+                &device_code.scope,
+                None,
+                now_date,
+            )
+            .await
+        }
+    }
+}
+
+/// machine to machine grant for service accounts, see rfc 6749 section 4.4
+async fn client_credentials_grant(
+    pool: &DbPool,
+    config: &Config,
+    req: &HttpRequest,
+    args: &TokenArgs,
+    now_date: DateTime<Utc>,
+) -> ApiResult<TokenResponse> {
+    let (client_id, Some(client_secret)) = client_credentials(
+        req,
+        args.client_id.as_deref(),
+        args.client_secret.as_deref(),
+    )
+    .ok_or(INVALID_CLIENT)?
+    else {
+        return Err(INVALID_CLIENT);
+    };
+    let account = get_service_account(pool, &client_id)
+        .await?
+        .ok_or(INVALID_CLIENT)?;
+    if !verify_service_account_secret(pool, &client_id, &sha256_hash(&client_secret)).await? {
+        return Err(INVALID_CLIENT);
+    }
+
+    let scope = match args.scope.as_deref() {
+        Some(scope) => {
+            let scopes = parse_scope(scope);
+            if !scopes.iter().all(|scope| account.scopes.contains(scope)) {
+                return Err(ApiError::OAuth {
+                    error: "invalid_scope",
+                    description: "scope is not allowed for this service account",
+                });
+            }
+            scopes.join(" ")
+        }
+        None => account.scopes.join(" "),
+    };
+
+    let claims = AccessTokenClaims {
+        iss: config.issuer.clone(),
+        sub: account.client_id.clone(),
+        aud: account.client_id,
+        exp: (now_date + config.access_token_lifetime).timestamp(),
+        iat: now_date.timestamp(),
+        scope: scope.clone(),
+        roles: vec![],
+        tenant: None,
+    };
+
+    Ok(TokenResponse {
+        access_token: encode_token(pool, config, &claims).await?,
+        token_type: "Bearer".to_string(),
+        expires_in: config.access_token_lifetime.num_seconds(),
+        refresh_token: None,
+        scope,
+        id_token: None,
+    })
+}
+
+#[post("/oauth/token")]
+pub async fn token(
+    req: HttpRequest,
+    args: Form<TokenArgs>,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    clock: Data<dyn Clock + Send + Sync>,
+) -> ApiResult<HttpResponse> {
+    let now_date = clock.now();
+    let response = match args.grant_type.as_str() {
+        "authorization_code" => {
+            let client = authenticate_client(
+                &pool,
+                &req,
+                args.client_id.as_deref(),
+                args.client_secret.as_deref(),
+            )
+            .await?;
+            authorization_code_grant(&pool, &config, &client, &args, now_date).await?
+        }
+        "refresh_token" => {
+            let client = authenticate_client(
+                &pool,
+                &req,
+                args.client_id.as_deref(),
+                args.client_secret.as_deref(),
+            )
+            .await?;
+            refresh_token_grant(&pool, &config, &client, &args, now_date).await?
+        }
+        "client_credentials" => client_credentials_grant(&pool, &config, &req, &args, now_date).await?,
+        DEVICE_CODE_GRANT_TYPE => {
+            let client = authenticate_client(
+                &pool,
+                &req,
+                args.client_id.as_deref(),
+                args.client_secret.as_deref(),
+            )
+            .await?;
+            device_code_grant(&pool, &config, &client, &args, now_date).await?
+        }
+        _ => return Err(ApiError::OAuth {
+            error: "unsupported_grant_type",
+            description:
+                "only 'authorization_code', 'refresh_token', 'client_credentials' and device code grants are supported",
+        }),
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{login::login, oauth::authorize::authorize},
+        clock::FakeClock,
+        db::{
+            service_accounts::{
+                insert_service_account, rotate_service_account_secret, ServiceAccount,
+            },
+            tenants::DEFAULT_TENANT_ID,
+            user::set_user_status,
+        },
+        jwt::decode_token,
+        test::helper::{
+            create_test_clock, create_test_db, create_test_oauth_client,
+            create_test_user_with_session, test_user_id,
+        },
+    };
+    use actix_web::{
+        body::MessageBody,
+        dev::{Service, ServiceResponse},
+        http::{
+            header::{ContentType, AUTHORIZATION, LOCATION},
+            StatusCode,
+        },
+        test::{self, TestRequest},
+        App,
+    };
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use std::sync::Arc;
+    use url::Url;
+
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+    async fn get_code<S, B>(app: &S, session_token: &str) -> String
+    where
+        S: Service<actix_http::Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+        B: MessageBody,
+    {
+        let req = TestRequest::get()
+            .uri(&format!("/oauth/authorize?response_type=code&client_id=test_client&redirect_uri=https://app.example.com/callback&scope=openid&code_challenge={CHALLENGE}&code_challenge_method=S256"))
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+        let resp = test::call_service(app, req).await;
+        let location = resp.headers().get(LOCATION).unwrap().to_str().unwrap();
+        Url::parse(location)
+            .unwrap()
+            .query_pairs()
+            .find(|(key, _)| key == "code")
+            .unwrap()
+            .1
+            .to_string()
+    }
+
+    fn token_request(payload: String) -> actix_http::Request {
+        TestRequest::post()
+            .uri("/oauth/token")
+            .set_payload(payload)
+            .insert_header(ContentType::form_url_encoded())
+            .to_request()
+    }
+
+    #[actix_web::test]
+    async fn whole_authorization_code_flow_should_work() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(login)
+                .service(authorize)
+                .service(token),
+        )
+        .await;
+
+        // login, like a browser would before being sent to the authorize endpoint
+        let req = TestRequest::post()
+            .uri("/login")
+            .set_payload(
+                r#"{"email_address": "arian@gmail.com", "password": "some_hard_password"}"#,
+            )
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let session_cookie = resp.response().cookies().next().unwrap().into_owned();
+
+        let req = TestRequest::get()
+            .uri(&format!("/oauth/authorize?response_type=code&client_id=test_client&redirect_uri=https://app.example.com/callback&scope=openid%20email&state=abc&nonce=n-0S6&code_challenge={CHALLENGE}&code_challenge_method=S256"))
+            .cookie(session_cookie)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let location = resp.headers().get(LOCATION).unwrap().to_str().unwrap();
+        let code = Url::parse(location)
+            .unwrap()
+            .query_pairs()
+            .find(|(key, _)| key == "code")
+            .unwrap()
+            .1
+            .to_string();
+
+        let req = token_request(format!("grant_type=authorization_code&client_id=test_client&code={code}&redirect_uri=https://app.example.com/callback&code_verifier={VERIFIER}"));
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let tokens: TokenResponse = test::read_body_json(resp).await;
+        assert_eq!(tokens.token_type, "Bearer");
+        assert_eq!(tokens.scope, "openid email");
+
+        let claims: AccessTokenClaims = decode_token(
+            &db,
+            &Config::default(),
+            &tokens.access_token,
+            Some("test_client"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            claims.sub,
+            test_user_id(&db, "arian@gmail.com").await.to_string()
+        );
+        assert_eq!(claims.tenant.as_deref(), Some(DEFAULT_TENANT_ID));
+        assert_eq!(claims.scope, "openid email");
+
+        let id_token: IdTokenClaims = decode_token(
+            &db,
+            &Config::default(),
+            tokens.id_token.as_deref().unwrap(),
+            Some("test_client"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(id_token.nonce.as_deref(), Some("n-0S6"));
+        assert_eq!(
+            id_token.user_claims.email.as_deref(),
+            Some("arian@gmail.com")
+        );
+        assert_eq!(id_token.user_claims.email_verified, Some(true));
+        assert_eq!(id_token.user_claims.name, None);
+
+        // the code is single use
+        let req = token_request(format!("grant_type=authorization_code&client_id=test_client&code={code}&redirect_uri=https://app.example.com/callback&code_verifier={VERIFIER}"));
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let refresh_token = tokens.refresh_token.unwrap();
+        let req = token_request(format!(
+            "grant_type=refresh_token&client_id=test_client&refresh_token={refresh_token}"
+        ));
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let refreshed: TokenResponse = test::read_body_json(resp).await;
+        assert_ne!(refreshed.refresh_token.unwrap(), refresh_token);
+
+        // refresh tokens get rotated, so the old one doesn't work anymore
+        let req = token_request(format!(
+            "grant_type=refresh_token&client_id=test_client&refresh_token={refresh_token}"
+        ));
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn codes_and_refresh_tokens_expire() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let config = Config::default();
+        let clock = Arc::new(FakeClock::new(Utc::now()));
+        let app_clock: Arc<dyn Clock + Send + Sync> = clock.clone();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(Data::from(app_clock))
+                .app_data(Data::new(config.clone()))
+                .service(authorize)
+                .service(token),
+        )
+        .await;
+        let code_request = |code: &str| {
+            token_request(format!("grant_type=authorization_code&client_id=test_client&code={code}&redirect_uri=https://app.example.com/callback&code_verifier={VERIFIER}"))
+        };
+        let refresh_request = |refresh_token: &str| {
+            token_request(format!(
+                "grant_type=refresh_token&client_id=test_client&refresh_token={refresh_token}"
+            ))
+        };
+
+        let code = get_code(&app, &session_token).await;
+        clock.advance(config.authorization_code_lifetime);
+        let resp = test::call_service(&app, code_request(&code)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_description"], "expired authorization code");
+
+        let code = get_code(&app, &session_token).await;
+        let tokens: TokenResponse = test::call_and_read_body_json(&app, code_request(&code)).await;
+
+        // a refresh token works until its lifetime is over, the rotated one starts over
+        clock.advance(config.refresh_token_lifetime - Duration::minutes(1));
+        let req = refresh_request(&tokens.refresh_token.unwrap());
+        let tokens: TokenResponse = test::call_and_read_body_json(&app, req).await;
+        clock.advance(config.refresh_token_lifetime);
+        let resp = test::call_service(&app, refresh_request(&tokens.refresh_token.unwrap())).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_description"], "expired refresh token");
+    }
+
+    #[actix_web::test]
+    async fn suspended_user_can_not_refresh() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(authorize)
+                .service(token),
+        )
+        .await;
+        let code = get_code(&app, &session_token).await;
+        let req = token_request(format!("grant_type=authorization_code&client_id=test_client&code={code}&redirect_uri=https://app.example.com/callback&code_verifier={VERIFIER}"));
+        let tokens: TokenResponse = test::call_and_read_body_json(&app, req).await;
+
+        let user_id = test_user_id(&db, "arian@gmail.com").await;
+        set_user_status(&db, user_id, AccountStatus::Suspended, None)
+            .await
+            .unwrap();
+        let refresh_token = tokens.refresh_token.unwrap();
+        let req = token_request(format!(
+            "grant_type=refresh_token&client_id=test_client&refresh_token={refresh_token}"
+        ));
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "invalid_grant");
+    }
+
+    #[actix_web::test]
+    async fn token_with_wrong_code_verifier() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(authorize)
+                .service(token),
+        )
+        .await;
+        let code = get_code(&app, &session_token).await;
+
+        let req = token_request(format!("grant_type=authorization_code&client_id=test_client&code={code}&redirect_uri=https://app.example.com/callback&code_verifier=aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"));
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "invalid_grant");
+    }
+
+    #[actix_web::test]
+    async fn confidential_client_needs_its_secret() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, Some("secret")).await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(authorize)
+                .service(token),
+        )
+        .await;
+        let code = get_code(&app, &session_token).await;
+
+        let req = token_request(format!("grant_type=authorization_code&client_id=test_client&client_secret=wrong&code={code}&redirect_uri=https://app.example.com/callback&code_verifier={VERIFIER}"));
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let code = get_code(&app, &session_token).await;
+        let req = TestRequest::post()
+            .uri("/oauth/token")
+            .set_payload(format!("grant_type=authorization_code&code={code}&redirect_uri=https://app.example.com/callback&code_verifier={VERIFIER}"))
+            .insert_header(ContentType::form_url_encoded())
+            .insert_header((
+                AUTHORIZATION,
+                format!("Basic {}", STANDARD.encode("test_client:secret")),
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn client_credentials_should_work() {
+        let db = create_test_db().await;
+        let account = ServiceAccount {
+            client_id: "billing_job".to_string(),
+            name: "billing job".to_string(),
+            scopes: vec!["invoices:read".to_string(), "invoices:write".to_string()],
+            owner_email_address: "arian@gmail.com".to_string(),
+            last_used_date: None,
+        };
+        insert_service_account(&db, &account).await.unwrap();
+        rotate_service_account_secret(&db, "billing_job", &sha256_hash("old_secret"))
+            .await
+            .unwrap();
+        rotate_service_account_secret(&db, "billing_job", &sha256_hash("new_secret"))
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(token),
+        )
+        .await;
+
+        // both secrets work while rotating
+        for secret in ["old_secret", "new_secret"] {
+            let req = token_request(format!("grant_type=client_credentials&client_id=billing_job&client_secret={secret}&scope=invoices:read"));
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let tokens: TokenResponse = test::read_body_json(resp).await;
+            assert_eq!(tokens.scope, "invoices:read");
+            assert!(tokens.refresh_token.is_none());
+        }
+
+        let req = token_request("grant_type=client_credentials&client_id=billing_job&client_secret=new_secret&scope=admin".to_string());
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = token_request(
+            "grant_type=client_credentials&client_id=billing_job&client_secret=wrong".to_string(),
+        );
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let account = get_service_account(&db, "billing_job")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(account.last_used_date.is_some());
+    }
+
+    #[actix_web::test]
+    async fn service_account_can_not_login() {
+        let db = create_test_db().await;
+        let account = ServiceAccount {
+            client_id: "billing_job".to_string(),
+            name: "billing job".to_string(),
+            scopes: vec![],
+            owner_email_address: "arian@gmail.com".to_string(),
+            last_used_date: None,
+        };
+        insert_service_account(&db, &account).await.unwrap();
+        rotate_service_account_secret(&db, "billing_job", &sha256_hash("some_hard_password"))
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(Data::new(Config::default()))
+                .service(login),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/login")
+            .set_payload(r#"{"email_address": "billing_job", "password": "some_hard_password"}"#)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,148 @@
+use super::{parse_scope, user_claims};
+use crate::{
+    config::Config,
+    db::{
+        user::{get_user_by_id, AccountStatus},
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    jwt::{decode_token, AccessTokenClaims, UserClaims},
+};
+use actix_web::{
+    http::header::AUTHORIZATION,
+    route,
+    web::{Data, Json},
+    HttpRequest,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct UserInfoResponse {
+    pub sub: String,
+    #[serde(flatten)]
+    pub user_claims: UserClaims,
+}
+
+/// returns claims about the owner of an access token, see openid connect core section 5.3
+#[route("/userinfo", method = "GET", method = "POST")]
+pub async fn userinfo(
+    req: HttpRequest,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+) -> ApiResult<Json<UserInfoResponse>> {
+    let access_token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(ApiError::Unauthorized)?;
+
+    let claims: AccessTokenClaims = decode_token(&pool, &config, access_token, None).await?;
+    if !parse_scope(&claims.scope)
+        .iter()
+        .any(|scope| scope == "openid")
+    {
+        return Err(ApiError::Unauthorized);
+    }
+
+    // service account tokens have the client id as subject, they have no user to describe
+    let user_id = claims.sub.parse().map_err(|_| ApiError::Unauthorized)?;
+    let user = get_user_by_id(&pool, user_id)
+        .await?
+        .filter(|user| user.status == AccountStatus::Active)
+        .ok_or(ApiError::Unauthorized)?;
+    Ok(Json(UserInfoResponse {
+        user_claims: user_claims(user, &claims.scope),
+        sub: claims.sub,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        jwt::encode_token,
+        test::helper::{
+            create_test_clock, create_test_db, create_test_user_with_session, test_user_id,
+        },
+    };
+    use actix_web::{
+        http::StatusCode,
+        test::{self, TestRequest},
+        App,
+    };
+
+    async fn access_token(pool: &DbPool, config: &Config, scope: &str) -> String {
+        let now_date = create_test_clock().now();
+        let claims = AccessTokenClaims {
+            iss: config.issuer.clone(),
+            sub: test_user_id(pool, "arian@gmail.com").await.to_string(),
+            aud: "test_client".to_string(),
+            exp: (now_date + config.access_token_lifetime).timestamp(),
+            iat: now_date.timestamp(),
+            scope: scope.to_string(),
+            roles: vec![],
+            tenant: Some("default".to_string()),
+        };
+        encode_token(pool, config, &claims).await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn userinfo_returns_claims_of_granted_scopes() {
+        let db = create_test_db().await;
+        let config = Config::default();
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+        let email_token = access_token(&db, &config, "openid email").await;
+        let profile_token = access_token(&db, &config, "openid profile").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(Data::new(config))
+                .service(userinfo),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/userinfo")
+            .insert_header((AUTHORIZATION, format!("Bearer {email_token}")))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!({"sub": "1", "email": "arian@gmail.com", "email_verified": true})
+        );
+
+        let req = TestRequest::post()
+            .uri("/userinfo")
+            .insert_header((AUTHORIZATION, format!("Bearer {profile_token}")))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!({"sub": "1", "name": "arian"}));
+    }
+
+    #[actix_web::test]
+    async fn userinfo_needs_openid_scope() {
+        let db = create_test_db().await;
+        let config = Config::default();
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+        let token = access_token(&db, &config, "email").await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(Data::new(config))
+                .service(userinfo),
+        )
+        .await;
+
+        for authorization in [format!("Bearer {token}"), "Bearer not_a_jwt".to_string()] {
+            let req = TestRequest::get()
+                .uri("/userinfo")
+                .insert_header((AUTHORIZATION, authorization))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+}
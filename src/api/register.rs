@@ -1,15 +1,27 @@
 use crate::{
-    db::{email_codes::get_last_sent_email_code, user::insert_user, DbPool},
-    error::{ApiError, ApiResult},
+    audit::RequestInfo,
+    breached_passwords::BreachedPasswords,
+    clock::Clock,
+    config::{Config, EmailCodePurpose},
+    db::{
+        email_codes::{delete_email_code, verify_email_code},
+        tenants::Tenant,
+        user::insert_user,
+        DbPool,
+    },
+    email_domains::EmailDomainLists,
+    error::ApiResult,
+    metrics::record_email_code_verification,
     utils::hash::sha256_hash,
     utils::validators::*,
+    webhooks::{emit_event, USER_REGISTERED},
 };
 use actix_web::{
     post,
     web::{Data, Json},
 };
-use chrono::{Duration, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 #[derive(Serialize, Deserialize)]
 pub struct RegisterArgs {
@@ -19,33 +31,119 @@ pub struct RegisterArgs {
     email_code: u32,
 }
 
-#[post("/register")]
-pub async fn register(args: Json<RegisterArgs>, pool: Data<DbPool>) -> ApiResult<&'static str> {
+async fn register_user(
+    pool: &DbPool,
+    config: &Config,
+    clock: &(dyn Clock + Send + Sync),
+    tenant: &Tenant,
+    breached_passwords: &BreachedPasswords,
+    email_domain_lists: &EmailDomainLists,
+    args: &RegisterArgs,
+) -> ApiResult<()> {
     validate_email_address(&args.email_address)?;
     validate_name(&args.name)?;
     validate_password(&args.password)?;
+    tenant
+        .settings
+        .check_email_address(&args.email_address, email_domain_lists)?;
+    tenant.settings.check_password(
+        &args.password,
+        &[&args.name, &args.email_address],
+        breached_passwords,
+    )?;
 
-    let Some(email_code) = get_last_sent_email_code(&pool, &args.email_address).await? else {
-        return Err(ApiError::ExpiredEmailCode);
-    };
-
-    let now_date = Utc::now();
-    if (email_code.sent_date - now_date) > Duration::hours(1) {
-        return Err(ApiError::ExpiredEmailCode);
-    }
-
-    if email_code.code != args.email_code {
-        return Err(ApiError::WrongEmailCode);
-    }
+    let verification = verify_email_code(
+        pool,
+        &tenant.id,
+        &args.email_address,
+        EmailCodePurpose::Registration,
+        args.email_code,
+        config.email_code_lifetime(EmailCodePurpose::Registration),
+        clock.now(),
+    )
+    .await;
+    record_email_code_verification(EmailCodePurpose::Registration, &verification);
+    verification?;
 
     let hashed_password = sha256_hash(&args.password);
-    insert_user(&pool, &args.name, &hashed_password, &args.email_address).await?;
+    insert_user(
+        pool,
+        &tenant.id,
+        &args.name,
+        &hashed_password,
+        &args.email_address,
+    )
+    .await?;
+    // the code registered this account, it mustn't register another one
+    delete_email_code(
+        pool,
+        &tenant.id,
+        &args.email_address,
+        EmailCodePurpose::Registration,
+    )
+    .await?;
+    Ok(())
+}
+
+#[post("/register")]
+#[allow(clippy::too_many_arguments)]
+pub async fn register(
+    args: Json<RegisterArgs>,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    clock: Data<dyn Clock + Send + Sync>,
+    breached_passwords: Data<BreachedPasswords>,
+    email_domain_lists: Data<EmailDomainLists>,
+    tenant: Tenant,
+    info: RequestInfo,
+) -> ApiResult<&'static str> {
+    let result = register_user(
+        &pool,
+        &config,
+        clock.as_ref(),
+        &tenant,
+        &breached_passwords,
+        &email_domain_lists,
+        &args,
+    )
+    .await;
+    info.audit_result(
+        &pool,
+        &args.email_address,
+        "user.register",
+        &args.email_address,
+        result,
+    )
+    .await?;
+    emit_event(
+        &pool,
+        USER_REGISTERED,
+        json!({
+            "tenant_id": tenant.id,
+            "email_address": args.email_address,
+            "name": args.name,
+        }),
+    )
+    .await?;
     Ok("")
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{db::email_codes::insert_or_update_email_code, test::helper::create_test_db};
+    use crate::{
+        clock::FakeClock,
+        db::{
+            email_codes::insert_or_update_email_code,
+            tenants::{
+                get_tenant, insert_tenant, update_tenant, TenantSettings, DEFAULT_TENANT_ID,
+            },
+        },
+        error::ApiError,
+        tenant::TENANT_HEADER,
+        test::helper::{create_test_breached_passwords, create_test_clock, create_test_db},
+    };
+    use chrono::{Duration, Utc};
+    use std::sync::Arc;
 
     use super::*;
     use actix_web::{
@@ -58,11 +156,27 @@ mod tests {
     #[actix_web::test]
     async fn register_should_work() {
         let db = create_test_db().await;
-        insert_or_update_email_code(&db, "arian@gmail.com", 123456)
-            .await
-            .unwrap();
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            EmailCodePurpose::Registration,
+            123456,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
 
-        let app = test::init_service(App::new().app_data(Data::new(db)).service(register)).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(Data::new(Config::default()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(register),
+        )
+        .await;
         let req = TestRequest::post()
             .uri("/register")
             .set_payload(
@@ -75,22 +189,92 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::OK);
     }
 
+    #[actix_web::test]
+    async fn registration_code_can_not_be_reused() {
+        let db = create_test_db().await;
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            EmailCodePurpose::Registration,
+            123456,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(Config::default()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(register),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/register")
+            .set_payload(r#"{"name": "arian", "password": "idkkkkl", "email_address": "arian@gmail.com", "email_code": 123456}"#)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        assert!(verify_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            EmailCodePurpose::Registration,
+            123456,
+            Config::default().email_code_lifetime(EmailCodePurpose::Registration),
+            Utc::now(),
+        )
+        .await
+        .is_err());
+    }
+
     #[actix_web::test]
     async fn register_with_already_registered_email_address() {
         let db = create_test_db().await;
-        insert_or_update_email_code(&db, "arian@gmail.com", 789102)
-            .await
-            .unwrap();
-        let app = test::init_service(App::new().app_data(Data::new(db)).service(register)).await;
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            EmailCodePurpose::Registration,
+            789102,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(Config::default()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(register),
+        )
+        .await;
 
         let req = TestRequest::post()
             .uri("/register")
-            .set_payload(r#"{"name": "arian", "password": "idkkk", "email_address": "arian@gmail.com", "email_code": 789102}"#)
+            .set_payload(r#"{"name": "arian", "password": "idkkkkl", "email_address": "arian@gmail.com", "email_code": 789102}"#)
             .insert_header(ContentType::json())
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::OK);
 
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            EmailCodePurpose::Registration,
+            789102,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
         let req = TestRequest::post()
             .uri("/register")
             .set_payload(r#"{"name": "pouya", "password": "okkok", "email_address": "arian@gmail.com", "email_code": 789102}"#)
@@ -100,10 +284,63 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     }
 
+    #[actix_web::test]
+    async fn register_refuses_other_forms_of_a_registered_address() {
+        let db = create_test_db().await;
+        for email_address in ["arian@gmail.com", "Arian@GMAIL.com"] {
+            insert_or_update_email_code(
+                &db,
+                DEFAULT_TENANT_ID,
+                email_address,
+                EmailCodePurpose::Registration,
+                789102,
+                Utc::now(),
+            )
+            .await
+            .unwrap();
+        }
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(Config::default()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(register),
+        )
+        .await;
+
+        for (email_address, expected) in [
+            ("arian@gmail.com", StatusCode::OK),
+            ("Arian@GMAIL.com", StatusCode::BAD_REQUEST),
+        ] {
+            let req = TestRequest::post()
+                .uri("/register")
+                .set_json(json!({
+                    "name": "arian",
+                    "password": "some_hard_password",
+                    "email_address": email_address,
+                    "email_code": 789102
+                }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), expected, "{email_address}");
+        }
+    }
+
     #[actix_web::test]
     async fn register_with_invalid_email_address() {
         let db = create_test_db().await;
-        let app = test::init_service(App::new().app_data(Data::new(db)).service(register)).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(Data::new(Config::default()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(register),
+        )
+        .await;
         let req = TestRequest::post()
             .uri("/register")
             .set_payload(r#"{"name": "arian", "password": "idkkk", "email_address": "arian", "email_code": 238218}"#)
@@ -116,7 +353,16 @@ mod tests {
     #[actix_web::test]
     async fn register_when_email_code_not_exists() {
         let db = create_test_db().await;
-        let app = test::init_service(App::new().app_data(Data::new(db)).service(register)).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(Data::new(Config::default()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(register),
+        )
+        .await;
         let req = TestRequest::post()
             .uri("/register")
             .set_payload(r#"{"name": "arian", "password": "idkkk", "email_address": "arian@gmail.com", "email_code": 123456}"#)
@@ -125,4 +371,200 @@ mod tests {
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     }
+
+    #[actix_web::test]
+    async fn register_follows_tenant_settings() {
+        let db = create_test_db().await;
+        insert_tenant(&db, "shop", "Shop", None).await.unwrap();
+        let settings = TenantSettings {
+            allowed_email_domains: vec!["shop.com".to_string()],
+            min_password_length: 10,
+            ..get_tenant(&db, DEFAULT_TENANT_ID)
+                .await
+                .unwrap()
+                .unwrap()
+                .settings
+        };
+        update_tenant(&db, "shop", "Shop", None, &settings)
+            .await
+            .unwrap();
+        for email_address in ["arian@gmail.com", "arian@shop.com"] {
+            insert_or_update_email_code(
+                &db,
+                "shop",
+                email_address,
+                EmailCodePurpose::Registration,
+                123456,
+                Utc::now(),
+            )
+            .await
+            .unwrap();
+        }
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(Data::new(Config::default()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(register),
+        )
+        .await;
+
+        for (payload, expected) in [
+            (
+                r#"{"name": "arian", "password": "long_password", "email_address": "arian@gmail.com", "email_code": 123456}"#,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                r#"{"name": "arian", "password": "short", "email_address": "arian@shop.com", "email_code": 123456}"#,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                r#"{"name": "arian", "password": "long_password", "email_address": "arian@shop.com", "email_code": 123456}"#,
+                StatusCode::OK,
+            ),
+        ] {
+            let req = TestRequest::post()
+                .uri("/register")
+                .set_payload(payload)
+                .insert_header(ContentType::json())
+                .insert_header((TENANT_HEADER, "shop"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), expected);
+        }
+    }
+
+    #[actix_web::test]
+    async fn register_lists_every_password_violation() {
+        let db = create_test_db().await;
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            EmailCodePurpose::Registration,
+            123456,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(Data::new(Config::default()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(register),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/register")
+            .set_payload(r#"{"name": "arian", "password": "arian", "email_address": "arian@gmail.com", "email_code": 123456}"#)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let codes: Vec<&str> = body["violations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|violation| violation["code"].as_str().unwrap())
+            .collect();
+        assert_eq!(codes, vec!["contains_personal_info", "too_weak"]);
+    }
+
+    #[actix_web::test]
+    async fn register_refuses_breached_passwords() {
+        let db = create_test_db().await;
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            EmailCodePurpose::Registration,
+            123456,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        let breached_passwords = create_test_breached_passwords(&["correct horse battery staple"]);
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(Data::new(Config::default()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(breached_passwords))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(register),
+        )
+        .await;
+
+        let register_request = |password: &str| {
+            TestRequest::post()
+                .uri("/register")
+                .set_json(json!({
+                    "name": "arian",
+                    "password": password,
+                    "email_address": "arian@gmail.com",
+                    "email_code": 123456,
+                }))
+                .to_request()
+        };
+        let resp = test::call_service(&app, register_request("correct horse battery staple")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            body["violations"],
+            json!([{ "code": "breached", "count": 10 }])
+        );
+
+        let resp =
+            test::call_service(&app, register_request("correct horse battery stapler")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn register_refuses_expired_codes() {
+        let db = create_test_db().await;
+        let clock = Arc::new(FakeClock::new(Utc::now()));
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            EmailCodePurpose::Registration,
+            123456,
+            clock.now(),
+        )
+        .await
+        .unwrap();
+        let config = Config {
+            registration_email_code_lifetime: Duration::minutes(10),
+            ..Config::default()
+        };
+        let app_clock: Arc<dyn Clock + Send + Sync> = clock.clone();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(Data::new(config))
+                .app_data(Data::from(app_clock))
+                .app_data(Data::new(BreachedPasswords::default()))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(register),
+        )
+        .await;
+
+        clock.advance(Duration::minutes(10));
+        let req = TestRequest::post()
+            .uri("/register")
+            .insert_header(ContentType::json())
+            .set_payload(r#"{"name": "arian", "password": "idkkkkl", "email_address": "arian@gmail.com", "email_code": 123456}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = test::read_body(resp).await;
+        assert_eq!(body, ApiError::ExpiredEmailCode.to_string());
+    }
 }
@@ -0,0 +1,292 @@
+use crate::{
+    audit::RequestInfo,
+    breached_passwords::BreachedPasswords,
+    clock::Clock,
+    config::{Config, EmailCodePurpose},
+    db::{
+        email_codes::{delete_email_code, verify_email_code},
+        oauth::delete_refresh_tokens_of_user,
+        sessions::delete_sessions_of_user,
+        tenants::Tenant,
+        user::{get_user, update_user_password},
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    metrics::record_email_code_verification,
+    utils::{hash::sha256_hash, validators::*},
+    webhooks::{emit_event, PASSWORD_CHANGED},
+};
+use actix_web::{
+    post,
+    web::{Data, Json},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Serialize, Deserialize)]
+pub struct ResetPasswordArgs {
+    email_address: String,
+    email_code: u32,
+    new_password: String,
+}
+
+async fn reset_user_password(
+    pool: &DbPool,
+    config: &Config,
+    clock: &(dyn Clock + Send + Sync),
+    tenant: &Tenant,
+    breached_passwords: &BreachedPasswords,
+    args: &ResetPasswordArgs,
+) -> ApiResult<()> {
+    validate_email_address(&args.email_address)?;
+    validate_password(&args.new_password)?;
+
+    let verification = verify_email_code(
+        pool,
+        &tenant.id,
+        &args.email_address,
+        EmailCodePurpose::PasswordReset,
+        args.email_code,
+        config.email_code_lifetime(EmailCodePurpose::PasswordReset),
+        clock.now(),
+    )
+    .await;
+    record_email_code_verification(EmailCodePurpose::PasswordReset, &verification);
+    verification?;
+
+    let user = get_user(pool, &tenant.id, &args.email_address)
+        .await?
+        .ok_or(ApiError::WrongCredentials)?;
+    tenant.settings.check_password(
+        &args.new_password,
+        &[&user.name, &user.email_address],
+        breached_passwords,
+    )?;
+    let hashed_password = sha256_hash(&args.new_password);
+    tenant
+        .settings
+        .check_password_reuse(pool, user.id, &hashed_password)
+        .await?;
+    update_user_password(pool, user.id, &hashed_password).await?;
+    delete_email_code(
+        pool,
+        &tenant.id,
+        &args.email_address,
+        EmailCodePurpose::PasswordReset,
+    )
+    .await?;
+    // whoever knew the old password shouldn't stay logged in
+    delete_sessions_of_user(pool, user.id).await?;
+    delete_refresh_tokens_of_user(pool, user.id).await
+}
+
+/// sets a new password with a code from `send_email_code`, also used after an admin forced a reset
+#[post("/reset_password")]
+pub async fn reset_password(
+    args: Json<ResetPasswordArgs>,
+    pool: Data<DbPool>,
+    config: Data<Config>,
+    clock: Data<dyn Clock + Send + Sync>,
+    breached_passwords: Data<BreachedPasswords>,
+    tenant: Tenant,
+    info: RequestInfo,
+) -> ApiResult<&'static str> {
+    let result = reset_user_password(
+        &pool,
+        &config,
+        clock.as_ref(),
+        &tenant,
+        &breached_passwords,
+        &args,
+    )
+    .await;
+    info.audit_result(
+        &pool,
+        &args.email_address,
+        "user.reset_password",
+        &args.email_address,
+        result,
+    )
+    .await?;
+    emit_event(
+        &pool,
+        PASSWORD_CHANGED,
+        json!({ "tenant_id": tenant.id, "email_address": args.email_address }),
+    )
+    .await?;
+    Ok("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::FakeClock,
+        db::{
+            email_codes::insert_or_update_email_code,
+            tenants::DEFAULT_TENANT_ID,
+            user::{does_user_exists, set_password_reset_required},
+        },
+        test::helper::{
+            create_test_clock, create_test_db, create_test_user_with_session, test_user_id,
+        },
+    };
+    use actix_web::{
+        http::{header::ContentType, StatusCode},
+        test::{self, TestRequest},
+        App,
+    };
+    use chrono::{Duration, Utc};
+    use std::sync::Arc;
+
+    #[actix_web::test]
+    async fn reset_password_should_work() {
+        let db = create_test_db().await;
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+        let user_id = test_user_id(&db, "arian@gmail.com").await;
+        set_password_reset_required(&db, user_id).await.unwrap();
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            EmailCodePurpose::PasswordReset,
+            123456,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(Config::default()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(BreachedPasswords::default()))
+                .service(reset_password),
+        )
+        .await;
+        let payload = r#"{"email_address": "arian@gmail.com", "email_code": 123456, "new_password": "another_password"}"#;
+        let req = TestRequest::post()
+            .uri("/reset_password")
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(does_user_exists(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            &sha256_hash("another_password")
+        )
+        .await
+        .unwrap());
+
+        // the code only works once
+        let req = TestRequest::post()
+            .uri("/reset_password")
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn reset_codes_have_their_own_lifetime() {
+        let db = create_test_db().await;
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+        let clock = Arc::new(FakeClock::new(Utc::now()));
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            EmailCodePurpose::PasswordReset,
+            123456,
+            clock.now(),
+        )
+        .await
+        .unwrap();
+        let config = Config {
+            registration_email_code_lifetime: Duration::hours(1),
+            password_reset_email_code_lifetime: Duration::minutes(15),
+            ..Config::default()
+        };
+        let app_clock: Arc<dyn Clock + Send + Sync> = clock.clone();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(config))
+                .app_data(Data::from(app_clock))
+                .app_data(Data::new(BreachedPasswords::default()))
+                .service(reset_password),
+        )
+        .await;
+
+        clock.advance(Duration::minutes(15));
+        let req = TestRequest::post()
+            .uri("/reset_password")
+            .set_payload(r#"{"email_address": "arian@gmail.com", "email_code": 123456, "new_password": "another_password"}"#)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            test::read_body(resp).await,
+            ApiError::ExpiredEmailCode.to_string()
+        );
+        assert!(!does_user_exists(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            &sha256_hash("another_password")
+        )
+        .await
+        .unwrap());
+    }
+
+    #[actix_web::test]
+    async fn registration_codes_cant_reset_passwords() {
+        let db = create_test_db().await;
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            EmailCodePurpose::Registration,
+            123456,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(Config::default()))
+                .app_data(create_test_clock())
+                .app_data(Data::new(BreachedPasswords::default()))
+                .service(reset_password),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/reset_password")
+            .set_payload(r#"{"email_address": "arian@gmail.com", "email_code": 123456, "new_password": "another_password"}"#)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            test::read_body(resp).await,
+            ApiError::ExpiredEmailCode.to_string()
+        );
+        assert!(!does_user_exists(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian@gmail.com",
+            &sha256_hash("another_password")
+        )
+        .await
+        .unwrap());
+    }
+}
@@ -0,0 +1,372 @@
+use crate::{
+    audit::RequestInfo,
+    auth::AuthenticatedUser,
+    db::{
+        audit_log::AuditOutcome,
+        roles::{
+            assign_role, does_permission_exist, does_role_exist, get_roles, grant_permission,
+            insert_role, revoke_permission, unassign_role,
+        },
+        user::{get_user, User},
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+};
+use actix_web::{
+    delete, get, post, put,
+    web::{Data, Json, Path},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct CreateRoleArgs {
+    name: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RoleInfo {
+    pub name: String,
+    pub description: String,
+    pub permissions: Vec<String>,
+}
+
+async fn check_role_exists(pool: &DbPool, role_name: &str) -> ApiResult<()> {
+    does_role_exist(pool, role_name)
+        .await?
+        .then_some(())
+        .ok_or(ApiError::BadArgument {
+            argument_name: "role",
+        })
+}
+
+/// roles are only assigned to users of the admin's own tenant
+async fn get_tenant_user(
+    pool: &DbPool,
+    admin: &AuthenticatedUser,
+    email_address: &str,
+) -> ApiResult<User> {
+    get_user(pool, &admin.tenant_id, email_address)
+        .await?
+        .ok_or(ApiError::BadArgument {
+            argument_name: "email_address",
+        })
+}
+
+#[post("/roles")]
+pub async fn create_role(
+    args: Json<CreateRoleArgs>,
+    user: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    user.require_global_permission(&pool, "roles:write").await?;
+    let valid_name = (1..=64).contains(&args.name.len())
+        && args
+            .name
+            .chars()
+            .all(|char| char.is_ascii_lowercase() || char.is_ascii_digit() || char == '_');
+    if !valid_name {
+        return Err(ApiError::BadArgument {
+            argument_name: "name",
+        });
+    }
+
+    insert_role(&pool, &args.name, &args.description).await?;
+    info.audit(
+        &pool,
+        &user.email_address,
+        "role.create",
+        &args.name,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+#[get("/roles")]
+pub async fn list_roles(
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<Vec<RoleInfo>>> {
+    user.require_permission(&pool, "roles:read").await?;
+    let roles = get_roles(&pool)
+        .await?
+        .into_iter()
+        .map(|role| RoleInfo {
+            name: role.name,
+            description: role.description,
+            permissions: role.permissions,
+        })
+        .collect();
+    Ok(Json(roles))
+}
+
+#[put("/roles/{role}/permissions/{permission}")]
+pub async fn grant_role_permission(
+    path: Path<(String, String)>,
+    user: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    user.require_global_permission(&pool, "roles:write").await?;
+    let (role_name, permission) = path.into_inner();
+    check_role_exists(&pool, &role_name).await?;
+    if !does_permission_exist(&pool, &permission).await? {
+        return Err(ApiError::BadArgument {
+            argument_name: "permission",
+        });
+    }
+
+    grant_permission(&pool, &role_name, &permission).await?;
+    info.audit(
+        &pool,
+        &user.email_address,
+        &format!("role.grant_permission:{permission}"),
+        &role_name,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+#[delete("/roles/{role}/permissions/{permission}")]
+pub async fn revoke_role_permission(
+    path: Path<(String, String)>,
+    user: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    user.require_global_permission(&pool, "roles:write").await?;
+    let (role_name, permission) = path.into_inner();
+    if !revoke_permission(&pool, &role_name, &permission).await? {
+        return Err(ApiError::BadArgument {
+            argument_name: "permission",
+        });
+    }
+    info.audit(
+        &pool,
+        &user.email_address,
+        &format!("role.revoke_permission:{permission}"),
+        &role_name,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+#[put("/users/{email_address}/roles/{role}")]
+pub async fn assign_user_role(
+    path: Path<(String, String)>,
+    user: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    user.require_permission(&pool, "roles:write").await?;
+    let (email_address, role_name) = path.into_inner();
+    check_role_exists(&pool, &role_name).await?;
+    let target = get_tenant_user(&pool, &user, &email_address).await?;
+
+    assign_role(&pool, target.id, &role_name).await?;
+    info.audit(
+        &pool,
+        &user.email_address,
+        &format!("user.assign_role:{role_name}"),
+        &email_address,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+#[delete("/users/{email_address}/roles/{role}")]
+pub async fn unassign_user_role(
+    path: Path<(String, String)>,
+    user: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    user.require_permission(&pool, "roles:write").await?;
+    let (email_address, role_name) = path.into_inner();
+    let target = get_tenant_user(&pool, &user, &email_address).await?;
+    if !unassign_role(&pool, target.id, &role_name).await? {
+        return Err(ApiError::BadArgument {
+            argument_name: "role",
+        });
+    }
+    info.audit(
+        &pool,
+        &user.email_address,
+        &format!("user.unassign_role:{role_name}"),
+        &email_address,
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{sessions::insert_session, tenants::insert_tenant, user::insert_user},
+        tenant::TENANT_HEADER,
+        test::helper::{create_test_db, create_test_user_with_session, test_user_id},
+        utils::{hash::sha256_hash, random::generate_random_token},
+    };
+    use actix_web::{
+        http::{
+            header::{ContentType, AUTHORIZATION},
+            StatusCode,
+        },
+        test::{self, TestRequest},
+        App,
+    };
+    use chrono::{Duration, Utc};
+
+    #[actix_web::test]
+    async fn manage_roles_as_admin() {
+        let db = create_test_db().await;
+        let admin_token = create_test_user_with_session(&db, "admin@gmail.com").await;
+        let user_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let admin_id = test_user_id(&db, "admin@gmail.com").await;
+        assign_role(&db, admin_id, "admin").await.unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .service(create_role)
+                .service(list_roles)
+                .service(grant_role_permission)
+                .service(revoke_role_permission)
+                .service(assign_user_role)
+                .service(unassign_user_role),
+        )
+        .await;
+
+        // users without roles can't see anything
+        let req = TestRequest::get()
+            .uri("/roles")
+            .insert_header((AUTHORIZATION, format!("Bearer {user_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let req = TestRequest::post()
+            .uri("/roles")
+            .set_payload(r#"{"name": "auditor", "description": "reads roles"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = TestRequest::put()
+            .uri("/roles/auditor/permissions/roles:read")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = TestRequest::put()
+            .uri("/roles/auditor/permissions/not_a_permission")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::put()
+            .uri("/users/arian@gmail.com/roles/auditor")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = TestRequest::get()
+            .uri("/roles")
+            .insert_header((AUTHORIZATION, format!("Bearer {user_token}")))
+            .to_request();
+        let roles: Vec<RoleInfo> = test::call_and_read_body_json(&app, req).await;
+        let auditor = roles.iter().find(|role| role.name == "auditor").unwrap();
+        assert_eq!(auditor.permissions, vec!["roles:read"]);
+
+        // reading isn't writing
+        let req = TestRequest::delete()
+            .uri("/roles/auditor/permissions/roles:read")
+            .insert_header((AUTHORIZATION, format!("Bearer {user_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let req = TestRequest::delete()
+            .uri("/users/arian@gmail.com/roles/auditor")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = TestRequest::get()
+            .uri("/roles")
+            .insert_header((AUTHORIZATION, format!("Bearer {user_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn tenant_admins_only_manage_their_own_users() {
+        let db = create_test_db().await;
+        insert_tenant(&db, "shop", "Shop", None).await.unwrap();
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+        let shop_admin_id = insert_user(&db, "shop", "admin", "password", "admin@shop.com")
+            .await
+            .unwrap();
+        assign_role(&db, shop_admin_id, "admin").await.unwrap();
+        let shop_admin_token = generate_random_token();
+        insert_session(
+            &db,
+            &sha256_hash(&shop_admin_token),
+            shop_admin_id,
+            Utc::now() + Duration::days(1),
+        )
+        .await
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .service(create_role)
+                .service(assign_user_role),
+        )
+        .await;
+
+        // users of the default tenant don't exist for the shop admin
+        let req = TestRequest::put()
+            .uri("/users/arian@gmail.com/roles/admin")
+            .insert_header((AUTHORIZATION, format!("Bearer {shop_admin_token}")))
+            .insert_header((TENANT_HEADER, "shop"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // role definitions are shared, so tenant admins can't change them
+        let req = TestRequest::post()
+            .uri("/roles")
+            .set_payload(r#"{"name": "auditor"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {shop_admin_token}")))
+            .insert_header((TENANT_HEADER, "shop"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // and the session doesn't work outside of its tenant
+        let req = TestRequest::put()
+            .uri("/users/arian@gmail.com/roles/admin")
+            .insert_header((AUTHORIZATION, format!("Bearer {shop_admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -1,8 +1,13 @@
 use crate::{
-    db::{email_codes::insert_or_update_email_code, DbPool},
+    audit::RequestInfo,
+    clock::Clock,
+    config::EmailCodePurpose,
+    db::{email_codes::insert_or_update_email_code, tenants::Tenant, DbPool},
+    email_domains::EmailDomainLists,
     email_sender::{EmailSender, Message},
     error::{ApiError, ApiResult},
-    utils::random::generate_random_six_digit_code,
+    rng::RngProvider,
+    utils::random::generate_six_digit_code,
 };
 use actix_web::{
     post,
@@ -13,26 +18,74 @@ use serde::Deserialize;
 #[derive(Deserialize)]
 pub struct SendEmailCodeArgs {
     email_address: String,
+    /// the code only works for this, `registration` when missing
+    #[serde(default)]
+    purpose: EmailCodePurpose,
+}
+
+async fn send_code(
+    pool: &DbPool,
+    email_sender: &(dyn EmailSender + Send + Sync),
+    email_domain_lists: &EmailDomainLists,
+    clock: &(dyn Clock + Send + Sync),
+    rng: &(dyn RngProvider + Send + Sync),
+    tenant: &Tenant,
+    args: &SendEmailCodeArgs,
+) -> ApiResult<()> {
+    let email_address = &args.email_address;
+    let to = email_address
+        .parse()
+        .map_err(|_| ApiError::InvalidEmailAddress)?;
+    tenant
+        .settings
+        .check_email_address(email_address, email_domain_lists)?;
+    let random_code = generate_six_digit_code(&mut *rng.rng());
+    let (subject, body) = tenant.settings.email_code_message(random_code);
+
+    email_sender
+        .send_email(Message { to, subject, body })
+        .await?;
+    insert_or_update_email_code(
+        pool,
+        &tenant.id,
+        email_address,
+        args.purpose,
+        random_code,
+        clock.now(),
+    )
+    .await
 }
 
 #[post("/send_email_code")]
+#[allow(clippy::too_many_arguments)]
 pub async fn send_email_code(
     args: Json<SendEmailCodeArgs>,
     email_sender: Data<dyn EmailSender + Send + Sync>,
+    email_domain_lists: Data<EmailDomainLists>,
+    clock: Data<dyn Clock + Send + Sync>,
+    rng: Data<dyn RngProvider + Send + Sync>,
     pool: Data<DbPool>,
+    tenant: Tenant,
+    info: RequestInfo,
 ) -> ApiResult<&'static str> {
-    let random_code = generate_random_six_digit_code();
-    let message = Message {
-        to: args
-            .email_address
-            .parse()
-            .map_err(|_| ApiError::InvalidEmailAddress)?,
-        subject: "Confirm register".to_string(),
-        body: format!("your register code is: {random_code}"),
-    };
-
-    email_sender.send_email(message).await?;
-    insert_or_update_email_code(&pool, &args.email_address, random_code).await?;
+    let result = send_code(
+        &pool,
+        email_sender.as_ref(),
+        &email_domain_lists,
+        clock.as_ref(),
+        rng.as_ref(),
+        &tenant,
+        &args,
+    )
+    .await;
+    info.audit_result(
+        &pool,
+        &args.email_address,
+        "email_code.send",
+        &args.email_address,
+        result,
+    )
+    .await?;
     Ok("")
 }
 
@@ -41,12 +94,20 @@ mod tests {
     use std::{future::ready, sync::Arc};
 
     use super::*;
-    use crate::{email_sender::MockEmailSender, test::helper::create_test_db};
+    use crate::{
+        clock::FakeClock,
+        config::Config,
+        db::{email_codes::verify_email_code, tenants::DEFAULT_TENANT_ID},
+        email_sender::MockEmailSender,
+        rng::SeededRngProvider,
+        test::helper::{create_test_clock, create_test_db, create_test_rng},
+    };
     use actix_web::{
         http::{header::ContentType, StatusCode},
         test::{self, TestRequest},
         App,
     };
+    use chrono::{Duration, Utc};
 
     #[actix_web::test]
     async fn send_email_code_should_work() {
@@ -61,7 +122,10 @@ mod tests {
         let app = test::init_service(
             App::new()
                 .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(create_test_rng())
                 .app_data(Data::from(email_provider))
+                .app_data(Data::new(EmailDomainLists::default()))
                 .service(send_email_code),
         )
         .await;
@@ -84,7 +148,10 @@ mod tests {
         let app = test::init_service(
             App::new()
                 .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(create_test_rng())
                 .app_data(Data::from(email_provider))
+                .app_data(Data::new(EmailDomainLists::default()))
                 .service(send_email_code),
         )
         .await;
@@ -97,4 +164,105 @@ mod tests {
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     }
+
+    #[actix_web::test]
+    async fn send_email_code_refuses_disposable_addresses() {
+        let email_mock = MockEmailSender::new();
+        let email_provider: Arc<dyn EmailSender + Send + Sync> = Arc::new(email_mock);
+
+        let db = create_test_db().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(create_test_clock())
+                .app_data(create_test_rng())
+                .app_data(Data::from(email_provider))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(send_email_code),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/send_email_code")
+            .set_payload(r#"{"email_address": "arian@inbox.Mailinator.com"}"#)
+            .insert_header(ContentType::json())
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            test::read_body(resp).await,
+            "disposable email addresses are not allowed"
+        );
+    }
+
+    #[actix_web::test]
+    async fn a_new_code_replaces_the_last_one() {
+        let mut email_mock = MockEmailSender::new();
+        email_mock
+            .expect_send_email()
+            .times(2)
+            .returning(|_| Box::pin(ready(Ok(()))));
+        let email_provider: Arc<dyn EmailSender + Send + Sync> = Arc::new(email_mock);
+        let clock = Arc::new(FakeClock::new(Utc::now()));
+        let app_clock: Arc<dyn Clock + Send + Sync> = clock.clone();
+        let rng: Arc<dyn RngProvider + Send + Sync> = Arc::new(SeededRngProvider::new(42));
+
+        let db = create_test_db().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::from(app_clock))
+                .app_data(Data::from(rng))
+                .app_data(Data::from(email_provider))
+                .app_data(Data::new(EmailDomainLists::default()))
+                .service(send_email_code),
+        )
+        .await;
+        let send_request = || {
+            TestRequest::post()
+                .uri("/send_email_code")
+                .set_payload(r#"{"email_address": "arian@gmail.com"}"#)
+                .insert_header(ContentType::json())
+                .to_request()
+        };
+        // the same seed hands out the same codes
+        let expected = SeededRngProvider::new(42);
+        let first_code = generate_six_digit_code(&mut *expected.rng());
+        let second_code = generate_six_digit_code(&mut *expected.rng());
+        assert_ne!(first_code, second_code);
+
+        let lifetime = Config::default().registration_email_code_lifetime;
+        let verify = |code: u32, now| {
+            verify_email_code(
+                &db,
+                DEFAULT_TENANT_ID,
+                "arian@gmail.com",
+                EmailCodePurpose::Registration,
+                code,
+                lifetime,
+                now,
+            )
+        };
+        let resp = test::call_service(&app, send_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(verify(first_code, clock.now()).await, Ok(()));
+
+        clock.advance(Duration::minutes(30));
+        let resp = test::call_service(&app, send_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            verify(first_code, clock.now()).await,
+            Err(ApiError::WrongEmailCode)
+        );
+        assert_eq!(verify(second_code, clock.now()).await, Ok(()));
+
+        // the lifetime starts over with the new code
+        clock.advance(Duration::minutes(45));
+        assert_eq!(verify(second_code, clock.now()).await, Ok(()));
+        clock.advance(Duration::minutes(15));
+        assert_eq!(
+            verify(second_code, clock.now()).await,
+            Err(ApiError::ExpiredEmailCode)
+        );
+    }
 }
@@ -0,0 +1,228 @@
+use crate::{
+    auth::AuthenticatedUser,
+    db::{
+        service_accounts::{
+            delete_service_account_secret, get_service_account, get_service_account_secrets,
+            insert_service_account, rotate_service_account_secret, ServiceAccount,
+        },
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    utils::{hash::sha256_hash, random::generate_random_token, validators::validate_scope},
+};
+use actix_web::{
+    delete, get, post,
+    web::{Data, Json, Path},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct CreateServiceAccountArgs {
+    name: String,
+    scopes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ServiceAccountSecretResponse {
+    pub client_id: String,
+    pub secret_id: i64,
+    /// only shown once, just its hash is stored
+    pub client_secret: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SecretInfo {
+    pub id: i64,
+    pub created_date: DateTime<Utc>,
+    pub last_used_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ServiceAccountResponse {
+    pub client_id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub last_used_date: Option<DateTime<Utc>>,
+    pub secrets: Vec<SecretInfo>,
+}
+
+/// accounts of other users are reported as not existing
+async fn get_owned_service_account(
+    pool: &DbPool,
+    user: &AuthenticatedUser,
+    client_id: &str,
+) -> ApiResult<ServiceAccount> {
+    user.require_session()?;
+    get_service_account(pool, client_id)
+        .await?
+        .filter(|account| account.owner_email_address == user.email_address)
+        .ok_or(ApiError::BadArgument {
+            argument_name: "client_id",
+        })
+}
+
+async fn add_secret(pool: &DbPool, client_id: String) -> ApiResult<ServiceAccountSecretResponse> {
+    let client_secret = generate_random_token();
+    let secret_id =
+        rotate_service_account_secret(pool, &client_id, &sha256_hash(&client_secret)).await?;
+    Ok(ServiceAccountSecretResponse {
+        client_id,
+        secret_id,
+        client_secret,
+    })
+}
+
+#[post("/service_accounts")]
+pub async fn create_service_account(
+    args: Json<CreateServiceAccountArgs>,
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<ServiceAccountSecretResponse>> {
+    user.require_session()?;
+    if args.name.is_empty() || args.name.len() > 64 {
+        return Err(ApiError::BadArgument {
+            argument_name: "name",
+        });
+    }
+    args.scopes
+        .iter()
+        .try_for_each(|scope| validate_scope(scope))?;
+
+    let account = ServiceAccount {
+        client_id: generate_random_token(),
+        name: args.name.clone(),
+        scopes: args.scopes.clone(),
+        owner_email_address: user.email_address,
+        last_used_date: None,
+    };
+    insert_service_account(&pool, &account).await?;
+    Ok(Json(add_secret(&pool, account.client_id).await?))
+}
+
+#[get("/service_accounts/{client_id}")]
+pub async fn get_service_account_info(
+    client_id: Path<String>,
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<ServiceAccountResponse>> {
+    let account = get_owned_service_account(&pool, &user, &client_id).await?;
+    let secrets = get_service_account_secrets(&pool, &account.client_id)
+        .await?
+        .into_iter()
+        .map(|secret| SecretInfo {
+            id: secret.id,
+            created_date: secret.created_date,
+            last_used_date: secret.last_used_date,
+        })
+        .collect();
+
+    Ok(Json(ServiceAccountResponse {
+        client_id: account.client_id,
+        name: account.name,
+        scopes: account.scopes,
+        last_used_date: account.last_used_date,
+        secrets,
+    }))
+}
+
+/// adds a new secret, the previous one keeps working until it's deleted or rotated out
+#[post("/service_accounts/{client_id}/secrets")]
+pub async fn rotate_secret(
+    client_id: Path<String>,
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<ServiceAccountSecretResponse>> {
+    let account = get_owned_service_account(&pool, &user, &client_id).await?;
+    Ok(Json(add_secret(&pool, account.client_id).await?))
+}
+
+#[delete("/service_accounts/{client_id}/secrets/{secret_id}")]
+pub async fn delete_secret(
+    path: Path<(String, i64)>,
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    let (client_id, secret_id) = path.into_inner();
+    let account = get_owned_service_account(&pool, &user, &client_id).await?;
+    delete_service_account_secret(&pool, &account.client_id, secret_id)
+        .await?
+        .then_some("")
+        .ok_or(ApiError::BadArgument {
+            argument_name: "secret_id",
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::helper::{create_test_db, create_test_user_with_session};
+    use actix_web::{
+        http::{
+            header::{ContentType, AUTHORIZATION},
+            StatusCode,
+        },
+        test::{self, TestRequest},
+        App,
+    };
+
+    #[actix_web::test]
+    async fn create_and_rotate_service_account() {
+        let db = create_test_db().await;
+        let session_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let other_session_token = create_test_user_with_session(&db, "pouya@gmail.com").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .service(create_service_account)
+                .service(get_service_account_info)
+                .service(rotate_secret)
+                .service(delete_secret),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/service_accounts")
+            .set_payload(r#"{"name": "billing job", "scopes": ["invoices:read"]}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+        let created: ServiceAccountSecretResponse = test::call_and_read_body_json(&app, req).await;
+
+        let req = TestRequest::post()
+            .uri(&format!("/service_accounts/{}/secrets", created.client_id))
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+        let rotated: ServiceAccountSecretResponse = test::call_and_read_body_json(&app, req).await;
+        assert_ne!(rotated.client_secret, created.client_secret);
+
+        let req = TestRequest::get()
+            .uri(&format!("/service_accounts/{}", created.client_id))
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+        let info: ServiceAccountResponse = test::call_and_read_body_json(&app, req).await;
+        let secret_ids: Vec<i64> = info.secrets.iter().map(|secret| secret.id).collect();
+        assert_eq!(secret_ids, vec![rotated.secret_id, created.secret_id]);
+
+        // only the owner can see or change the account
+        let req = TestRequest::delete()
+            .uri(&format!(
+                "/service_accounts/{}/secrets/{}",
+                created.client_id, created.secret_id
+            ))
+            .insert_header((AUTHORIZATION, format!("Bearer {other_session_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::delete()
+            .uri(&format!(
+                "/service_accounts/{}/secrets/{}",
+                created.client_id, created.secret_id
+            ))
+            .insert_header((AUTHORIZATION, format!("Bearer {session_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::{db::tenants::Tenant, error::ApiResult, utils::password_policy::CharacterClass};
+use actix_web::{get, web::Json};
+use serde::{Deserialize, Serialize};
+
+/// what clients need to know to build login and register forms for a tenant
+#[derive(Serialize, Deserialize)]
+pub struct PublicTenantInfo {
+    pub id: String,
+    pub name: String,
+    pub allowed_email_domains: Vec<String>,
+    pub min_password_length: i64,
+    pub max_password_length: i64,
+    pub password_character_classes: Vec<CharacterClass>,
+    pub min_password_score: i64,
+    pub password_history_size: i64,
+    pub password_max_age_days: i64,
+    pub mfa_required: bool,
+}
+
+#[get("/tenant")]
+pub async fn current_tenant(tenant: Tenant) -> ApiResult<Json<PublicTenantInfo>> {
+    Ok(Json(PublicTenantInfo {
+        id: tenant.id,
+        name: tenant.name,
+        allowed_email_domains: tenant.settings.allowed_email_domains,
+        min_password_length: tenant.settings.min_password_length,
+        max_password_length: tenant.settings.max_password_length,
+        password_character_classes: tenant.settings.password_character_classes,
+        min_password_score: tenant.settings.min_password_score,
+        password_history_size: tenant.settings.password_history_size,
+        password_max_age_days: tenant.settings.password_max_age_days,
+        mfa_required: tenant.settings.mfa_required,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::tenants::insert_tenant, test::helper::create_test_db};
+    use actix_web::{
+        test::{self, TestRequest},
+        web::{self, Data},
+        App,
+    };
+
+    #[actix_web::test]
+    async fn current_tenant_comes_from_the_path() {
+        let db = create_test_db().await;
+        insert_tenant(&db, "shop", "Shop", None).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .service(web::scope("/t/{tenant}").service(current_tenant)),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/t/shop/tenant").to_request();
+        let tenant: PublicTenantInfo = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(tenant.name, "Shop");
+        assert_eq!(tenant.min_password_length, 5);
+    }
+}
@@ -0,0 +1,480 @@
+use crate::{
+    audit::RequestInfo,
+    auth::AuthenticatedUser,
+    db::{
+        audit_log::AuditOutcome,
+        webhooks::{
+            delete_webhook, does_webhook_exist, get_deliveries_of_webhook, get_delivery_attempts,
+            get_webhooks, insert_webhook, redeliver, NewWebhook,
+        },
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    utils::random::generate_random_token,
+    webhooks::EVENT_TYPES,
+};
+use actix_web::{
+    delete, get, post,
+    web::{Data, Json, Path},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// how many deliveries of a webhook are listed
+const DELIVERY_LOG_LIMIT: i64 = 50;
+
+#[derive(Deserialize)]
+pub struct CreateWebhookArgs {
+    url: String,
+    /// subscribes to every event when empty
+    #[serde(default)]
+    events: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateWebhookResponse {
+    pub id: i64,
+    /// only shown once, receivers need it to check signatures
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WebhookInfo {
+    pub id: i64,
+    pub url: String,
+    pub events: Vec<String>,
+    pub created_by: String,
+    pub created_date: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeliveryAttemptInfo {
+    pub attempted_date: DateTime<Utc>,
+    pub status_code: Option<i64>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeliveryInfo {
+    pub id: i64,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    /// pending, succeeded or failed
+    pub status: String,
+    pub attempts: i64,
+    pub next_attempt_date: DateTime<Utc>,
+    pub created_date: DateTime<Utc>,
+    pub attempt_log: Vec<DeliveryAttemptInfo>,
+}
+
+fn is_valid_webhook_url(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| {
+        matches!(url.scheme(), "http" | "https") && url.host().is_some() && url.username() == ""
+    })
+}
+
+async fn check_webhook_exists(pool: &DbPool, id: i64) -> ApiResult<()> {
+    does_webhook_exist(pool, id)
+        .await?
+        .then_some(())
+        .ok_or(ApiError::BadArgument {
+            argument_name: "id",
+        })
+}
+
+/// webhooks get the events of every tenant, so only admins of the default tenant manage them
+#[post("/webhooks")]
+pub async fn create_webhook(
+    args: Json<CreateWebhookArgs>,
+    user: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<CreateWebhookResponse>> {
+    user.require_global_permission(&pool, "webhooks:write")
+        .await?;
+    if !is_valid_webhook_url(&args.url) {
+        return Err(ApiError::BadArgument {
+            argument_name: "url",
+        });
+    }
+    if !args
+        .events
+        .iter()
+        .all(|event| EVENT_TYPES.contains(&event.as_str()))
+    {
+        return Err(ApiError::BadArgument {
+            argument_name: "events",
+        });
+    }
+
+    let secret = generate_random_token();
+    let id = insert_webhook(
+        &pool,
+        &NewWebhook {
+            url: &args.url,
+            secret: &secret,
+            events: &args.events,
+            created_by: &user.email_address,
+        },
+    )
+    .await?;
+    info.audit(
+        &pool,
+        &user.email_address,
+        "webhook.create",
+        &id.to_string(),
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok(Json(CreateWebhookResponse { id, secret }))
+}
+
+#[get("/webhooks")]
+pub async fn list_webhooks(
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<Vec<WebhookInfo>>> {
+    user.require_global_permission(&pool, "webhooks:read")
+        .await?;
+    let webhooks = get_webhooks(&pool)
+        .await?
+        .into_iter()
+        .map(|webhook| WebhookInfo {
+            id: webhook.id,
+            url: webhook.url,
+            events: webhook.events,
+            created_by: webhook.created_by,
+            created_date: webhook.created_date,
+        })
+        .collect();
+    Ok(Json(webhooks))
+}
+
+#[delete("/webhooks/{id}")]
+pub async fn remove_webhook(
+    id: Path<i64>,
+    user: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    user.require_global_permission(&pool, "webhooks:write")
+        .await?;
+    if !delete_webhook(&pool, *id).await? {
+        return Err(ApiError::BadArgument {
+            argument_name: "id",
+        });
+    }
+    info.audit(
+        &pool,
+        &user.email_address,
+        "webhook.delete",
+        &id.to_string(),
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+/// the latest deliveries of a webhook with every attempt to send them
+#[get("/webhooks/{id}/deliveries")]
+pub async fn list_deliveries(
+    id: Path<i64>,
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> ApiResult<Json<Vec<DeliveryInfo>>> {
+    user.require_global_permission(&pool, "webhooks:read")
+        .await?;
+    check_webhook_exists(&pool, *id).await?;
+
+    let mut deliveries = Vec::new();
+    for delivery in get_deliveries_of_webhook(&pool, *id, DELIVERY_LOG_LIMIT).await? {
+        let attempt_log = get_delivery_attempts(&pool, delivery.id)
+            .await?
+            .into_iter()
+            .map(|attempt| DeliveryAttemptInfo {
+                attempted_date: attempt.attempted_date,
+                status_code: attempt.status_code,
+                error: attempt.error,
+            })
+            .collect();
+        deliveries.push(DeliveryInfo {
+            id: delivery.id,
+            event_type: delivery.event_type,
+            payload: serde_json::from_str(&delivery.payload).unwrap_or_default(),
+            status: delivery.status.as_str().to_string(),
+            attempts: delivery.attempts,
+            next_attempt_date: delivery.next_attempt_date,
+            created_date: delivery.created_date,
+            attempt_log,
+        });
+    }
+    Ok(Json(deliveries))
+}
+
+#[post("/webhooks/{id}/deliveries/{delivery_id}/redeliver")]
+pub async fn redeliver_delivery(
+    path: Path<(i64, i64)>,
+    user: AuthenticatedUser,
+    info: RequestInfo,
+    pool: Data<DbPool>,
+) -> ApiResult<&'static str> {
+    user.require_global_permission(&pool, "webhooks:write")
+        .await?;
+    let (id, delivery_id) = path.into_inner();
+    if !redeliver(&pool, id, delivery_id).await? {
+        return Err(ApiError::BadArgument {
+            argument_name: "delivery_id",
+        });
+    }
+    info.audit(
+        &pool,
+        &user.email_address,
+        "webhook.redeliver",
+        &delivery_id.to_string(),
+        AuditOutcome::Success,
+    )
+    .await?;
+    Ok("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        db::roles::assign_role,
+        test::helper::{create_test_db, create_test_user_with_session, test_user_id},
+        webhooks::{
+            deliver_due_webhooks, emit_event, sign_payload, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+            USER_REGISTERED,
+        },
+    };
+    use actix_web::{
+        http::{
+            header::{ContentType, HeaderMap, AUTHORIZATION},
+            StatusCode,
+        },
+        test::{self, TestRequest},
+        web, App, HttpRequest, HttpResponse, HttpServer,
+    };
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    /// headers and body of every request a receiver got
+    type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+    /// a receiver that records what it got and answers with the given status
+    fn start_receiver(status: StatusCode) -> (String, Received) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+        let server = HttpServer::new(move || {
+            let received = received_clone.clone();
+            App::new().default_service(web::to(move |req: HttpRequest, body: String| {
+                received.lock().unwrap().push((req.headers().clone(), body));
+                async move { HttpResponse::build(status).finish() }
+            }))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}/hook", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        (url, received)
+    }
+
+    #[actix_web::test]
+    async fn deliveries_are_signed_and_logged() {
+        let db = create_test_db().await;
+        let admin_token = create_test_user_with_session(&db, "admin@gmail.com").await;
+        let admin_id = test_user_id(&db, "admin@gmail.com").await;
+        assign_role(&db, admin_id, "admin").await.unwrap();
+        let (url, received) = start_receiver(StatusCode::OK);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .service(create_webhook)
+                .service(list_webhooks)
+                .service(list_deliveries),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/webhooks")
+            .set_payload(json!({"url": url, "events": ["user.registered"]}).to_string())
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let created: CreateWebhookResponse = test::call_and_read_body_json(&app, req).await;
+
+        let req = TestRequest::get()
+            .uri("/webhooks")
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert!(!String::from_utf8_lossy(&body).contains(&created.secret));
+
+        emit_event(
+            &db,
+            USER_REGISTERED,
+            json!({"email_address": "arian@gmail.com"}),
+        )
+        .await
+        .unwrap();
+        let client = reqwest::Client::new();
+        let config = Config::default();
+        assert_eq!(
+            deliver_due_webhooks(&db, &config, &client).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            deliver_due_webhooks(&db, &config, &client).await.unwrap(),
+            0
+        );
+
+        {
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 1);
+            let (headers, body) = &received[0];
+            let header = |name| headers.get(name).unwrap().to_str().unwrap();
+            let timestamp: i64 = header(TIMESTAMP_HEADER).parse().unwrap();
+            assert_eq!(
+                header(SIGNATURE_HEADER),
+                sign_payload(&created.secret, timestamp, body)
+            );
+            let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!(payload["type"], "user.registered");
+            assert_eq!(payload["data"]["email_address"], "arian@gmail.com");
+        }
+
+        let req = TestRequest::get()
+            .uri(&format!("/webhooks/{}/deliveries", created.id))
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let deliveries: Vec<DeliveryInfo> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].status, "succeeded");
+        assert_eq!(deliveries[0].attempt_log.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn failed_deliveries_are_retried_and_can_be_redelivered() {
+        let db = create_test_db().await;
+        let admin_token = create_test_user_with_session(&db, "admin@gmail.com").await;
+        let admin_id = test_user_id(&db, "admin@gmail.com").await;
+        assign_role(&db, admin_id, "admin").await.unwrap();
+        let (url, received) = start_receiver(StatusCode::INTERNAL_SERVER_ERROR);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .service(create_webhook)
+                .service(list_deliveries)
+                .service(redeliver_delivery),
+        )
+        .await;
+
+        for url in ["ftp://example.com/hook", "not a url"] {
+            let req = TestRequest::post()
+                .uri("/webhooks")
+                .set_payload(json!({ "url": url }).to_string())
+                .insert_header(ContentType::json())
+                .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+        let req = TestRequest::post()
+            .uri("/webhooks")
+            .set_payload(json!({"url": url, "events": ["user.unknown"]}).to_string())
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::post()
+            .uri("/webhooks")
+            .set_payload(json!({ "url": url }).to_string())
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let created: CreateWebhookResponse = test::call_and_read_body_json(&app, req).await;
+
+        emit_event(&db, USER_REGISTERED, json!({})).await.unwrap();
+        let client = reqwest::Client::new();
+        let config = Config {
+            webhook_max_attempts: 2,
+            webhook_retry_delay: chrono::Duration::zero(),
+            ..Config::default()
+        };
+        // the first failure schedules a retry, the second one gives up
+        assert_eq!(
+            deliver_due_webhooks(&db, &config, &client).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            deliver_due_webhooks(&db, &config, &client).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            deliver_due_webhooks(&db, &config, &client).await.unwrap(),
+            0
+        );
+        assert_eq!(received.lock().unwrap().len(), 2);
+
+        let req = TestRequest::get()
+            .uri(&format!("/webhooks/{}/deliveries", created.id))
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let deliveries: Vec<DeliveryInfo> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(deliveries[0].status, "failed");
+        assert_eq!(deliveries[0].attempt_log.len(), 2);
+        assert_eq!(deliveries[0].attempt_log[0].status_code, Some(500));
+
+        let req = TestRequest::post()
+            .uri(&format!(
+                "/webhooks/{}/deliveries/{}/redeliver",
+                created.id, deliveries[0].id
+            ))
+            .insert_header((AUTHORIZATION, format!("Bearer {admin_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            deliver_due_webhooks(&db, &config, &client).await.unwrap(),
+            1
+        );
+        assert_eq!(received.lock().unwrap().len(), 3);
+    }
+
+    #[actix_web::test]
+    async fn webhooks_require_permissions() {
+        let db = create_test_db().await;
+        let user_token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .service(create_webhook)
+                .service(list_webhooks),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/webhooks")
+            .insert_header((AUTHORIZATION, format!("Bearer {user_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let req = TestRequest::post()
+            .uri("/webhooks")
+            .set_payload(r#"{"url": "https://crm.example.com/hook"}"#)
+            .insert_header(ContentType::json())
+            .insert_header((AUTHORIZATION, format!("Bearer {user_token}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::{
+    clock::Clock,
+    db::{
+        audit_log::{insert_audit_event, AuditOutcome, NewAuditEvent},
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    tenant::resolve_tenant,
+};
+use actix_web::{dev::Payload, http::header::USER_AGENT, web::Data, FromRequest, HttpRequest};
+use chrono::{DateTime, Utc};
+use std::{future::Future, pin::Pin};
+
+/// where a request came from, recorded with every audit event
+#[derive(Debug)]
+pub struct RequestInfo {
+    pub tenant_id: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// when the request came in, the time of its audit events
+    pub now: DateTime<Utc>,
+}
+
+impl RequestInfo {
+    /// appends an event to the audit log
+    pub async fn audit(
+        &self,
+        pool: &DbPool,
+        actor: &str,
+        action: &str,
+        target: &str,
+        outcome: AuditOutcome,
+    ) -> ApiResult<()> {
+        let event = NewAuditEvent {
+            tenant_id: &self.tenant_id,
+            actor,
+            action,
+            target,
+            outcome,
+            ip: self.ip.as_deref(),
+            user_agent: self.user_agent.as_deref(),
+        };
+        insert_audit_event(pool, &event, self.now).await
+    }
+
+    /// audits the outcome of `result` and passes it through
+    pub async fn audit_result<T>(
+        &self,
+        pool: &DbPool,
+        actor: &str,
+        action: &str,
+        target: &str,
+        result: ApiResult<T>,
+    ) -> ApiResult<T> {
+        let outcome = match result {
+            Ok(_) => AuditOutcome::Success,
+            Err(_) => AuditOutcome::Failure,
+        };
+        self.audit(pool, actor, action, target, outcome).await?;
+        result
+    }
+}
+
+impl FromRequest for RequestInfo {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = ApiResult<Self>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let ip = req.peer_addr().map(|addr| addr.ip().to_string());
+        let user_agent = req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        // apps without a clock, like some tests, get the system time
+        let now = req
+            .app_data::<Data<dyn Clock + Send + Sync>>()
+            .map_or_else(Utc::now, |clock| clock.now());
+        let req = req.clone();
+        Box::pin(async move {
+            Ok(RequestInfo {
+                tenant_id: resolve_tenant(req).await?.id,
+                ip,
+                user_agent,
+                now,
+            })
+        })
+    }
+}
@@ -0,0 +1,382 @@
+use crate::{
+    clock::Clock,
+    db::{
+        api_keys::{get_api_key_by_hash, update_api_key_last_use},
+        roles::has_permission,
+        sessions::get_session,
+        tenants::DEFAULT_TENANT_ID,
+        user::{get_user_by_id, AccountStatus},
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    tenant::resolve_tenant,
+    utils::hash::sha256_hash,
+};
+use actix_web::{dev::Payload, http::header::AUTHORIZATION, web::Data, FromRequest, HttpRequest};
+use chrono::{DateTime, Utc};
+use std::{future::Future, pin::Pin};
+
+pub const SESSION_COOKIE_NAME: &str = "session";
+pub const API_KEY_PREFIX: &str = "ak_";
+
+#[derive(Debug, PartialEq)]
+pub enum Credential {
+    Session,
+    ApiKey {
+        id: i64,
+        scopes: Vec<String>,
+    },
+    /// session of a user whose password expired, only `PasswordChangeUser` accepts it
+    PasswordChangeSession,
+}
+
+/// user of a request, authenticated by the session token that `login` returns or by
+/// one of the user's api keys, either from the `Authorization: Bearer` header or from
+/// the session cookie. the credential only works for requests to the user's own tenant
+#[derive(Debug, PartialEq)]
+pub struct AuthenticatedUser {
+    pub id: i64,
+    pub tenant_id: String,
+    pub email_address: String,
+    pub credential: Credential,
+}
+
+impl AuthenticatedUser {
+    /// sessions can do anything, api keys only what their scopes allow
+    pub fn require_scope(&self, scope: &str) -> ApiResult<()> {
+        let allowed = match &self.credential {
+            Credential::Session => true,
+            Credential::ApiKey { scopes, .. } => {
+                scopes.is_empty() || scopes.iter().any(|item| item == scope)
+            }
+            Credential::PasswordChangeSession => false,
+        };
+        allowed.then_some(()).ok_or(ApiError::Forbidden)
+    }
+
+    /// guard for privileged routes, the permission has to come from one of the user's roles
+    /// and api keys also need it as one of their scopes
+    pub async fn require_permission(&self, pool: &DbPool, permission: &str) -> ApiResult<()> {
+        self.require_scope(permission)?;
+        has_permission(pool, self.id, permission)
+            .await?
+            .then_some(())
+            .ok_or(ApiError::Forbidden)
+    }
+
+    /// role definitions and tenants are shared by every tenant, so only admins of the default
+    /// tenant may change them
+    pub async fn require_global_permission(
+        &self,
+        pool: &DbPool,
+        permission: &str,
+    ) -> ApiResult<()> {
+        self.require_permission(pool, permission).await?;
+        (self.tenant_id == DEFAULT_TENANT_ID)
+            .then_some(())
+            .ok_or(ApiError::Forbidden)
+    }
+
+    /// for endpoints that manage credentials, so a leaked api key can't be used to mint new ones
+    pub fn require_session(&self) -> ApiResult<()> {
+        match self.credential {
+            Credential::Session | Credential::PasswordChangeSession => Ok(()),
+            Credential::ApiKey { .. } => Err(ApiError::Unauthorized),
+        }
+    }
+}
+
+fn extract_token(req: &HttpRequest) -> Option<String> {
+    let bearer = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+
+    bearer.or_else(|| {
+        req.cookie(SESSION_COOKIE_NAME)
+            .map(|cookie| cookie.value().to_string())
+    })
+}
+
+/// the user id and credential of an api key
+async fn authenticate_api_key(
+    pool: &DbPool,
+    key_hash: &str,
+    ip: Option<String>,
+    now: DateTime<Utc>,
+) -> ApiResult<Option<(i64, Credential)>> {
+    let Some(api_key) = get_api_key_by_hash(pool, key_hash).await? else {
+        return Ok(None);
+    };
+    if api_key
+        .expire_date
+        .is_some_and(|expire_date| expire_date <= now)
+    {
+        return Err(ApiError::Unauthorized);
+    }
+
+    update_api_key_last_use(pool, api_key.id, ip.as_deref()).await?;
+    Ok(Some((
+        api_key.user_id,
+        Credential::ApiKey {
+            id: api_key.id,
+            scopes: api_key.scopes,
+        },
+    )))
+}
+
+async fn authenticate_token(
+    pool: &DbPool,
+    token: &str,
+    ip: Option<String>,
+    now: DateTime<Utc>,
+) -> ApiResult<(i64, Credential)> {
+    let token_hash = sha256_hash(token);
+
+    // session tokens are random too, so one could start with the prefix by chance
+    if token.starts_with(API_KEY_PREFIX) {
+        if let Some(authenticated) = authenticate_api_key(pool, &token_hash, ip, now).await? {
+            return Ok(authenticated);
+        }
+    }
+
+    let session = get_session(pool, &token_hash)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+    if session.expire_date <= now {
+        return Err(ApiError::Unauthorized);
+    }
+    let credential = if session.password_change_only {
+        Credential::PasswordChangeSession
+    } else {
+        Credential::Session
+    };
+    Ok((session.user_id, credential))
+}
+
+async fn authenticate(req: HttpRequest) -> ApiResult<AuthenticatedUser> {
+    let (Some(pool), Some(token)) = (req.app_data::<Data<DbPool>>().cloned(), extract_token(&req))
+    else {
+        return Err(ApiError::Unauthorized);
+    };
+    let ip = req.peer_addr().map(|addr| addr.ip().to_string());
+    // apps that don't share a clock use the system time
+    let now = req
+        .app_data::<Data<dyn Clock + Send + Sync>>()
+        .map_or_else(Utc::now, |clock| clock.now());
+
+    let (user_id, credential) = authenticate_token(&pool, &token, ip, now).await?;
+    let user = get_user_by_id(&pool, user_id)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+    if user.status != AccountStatus::Active || user.tenant_id != resolve_tenant(req).await?.id {
+        return Err(ApiError::Unauthorized);
+    }
+
+    Ok(AuthenticatedUser {
+        id: user.id,
+        tenant_id: user.tenant_id,
+        email_address: user.email_address,
+        credential,
+    })
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = ApiResult<Self>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let user = authenticate(req).await?;
+            match user.credential {
+                Credential::PasswordChangeSession => Err(ApiError::PasswordExpired),
+                _ => Ok(user),
+            }
+        })
+    }
+}
+
+/// user of a request that may also be authenticated by the session `login` hands out when the
+/// password expired, for the endpoint that changes the password and nothing else
+#[derive(Debug, PartialEq)]
+pub struct PasswordChangeUser(pub AuthenticatedUser);
+
+impl FromRequest for PasswordChangeUser {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = ApiResult<Self>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move { authenticate(req).await.map(PasswordChangeUser) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{
+            sessions::{insert_password_change_session, insert_session},
+            tenants::{insert_tenant, DEFAULT_TENANT_ID},
+            user::insert_user,
+        },
+        tenant::TENANT_HEADER,
+        test::helper::create_test_db,
+    };
+    use actix_web::{
+        get,
+        http::StatusCode,
+        test::{self, TestRequest},
+        App,
+    };
+    use chrono::Duration;
+
+    #[get("/whoami")]
+    async fn whoami(user: AuthenticatedUser) -> String {
+        user.email_address
+    }
+
+    #[actix_web::test]
+    async fn authenticate_with_session_token() {
+        let db = create_test_db().await;
+        let user_id = insert_user(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian",
+            "password",
+            "arian@gmail.com",
+        )
+        .await
+        .unwrap();
+        insert_session(
+            &db,
+            &sha256_hash("valid"),
+            user_id,
+            Utc::now() + Duration::days(1),
+        )
+        .await
+        .unwrap();
+        insert_session(
+            &db,
+            &sha256_hash("expired"),
+            user_id,
+            Utc::now() - Duration::days(1),
+        )
+        .await
+        .unwrap();
+        let app = test::init_service(App::new().app_data(Data::new(db)).service(whoami)).await;
+
+        let req = TestRequest::get()
+            .uri("/whoami")
+            .insert_header((AUTHORIZATION, "Bearer valid"))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "arian@gmail.com");
+
+        let req = TestRequest::get()
+            .uri("/whoami")
+            .cookie(actix_web::cookie::Cookie::new(SESSION_COOKIE_NAME, "valid"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        for token in ["expired", "unknown"] {
+            let req = TestRequest::get()
+                .uri("/whoami")
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let req = TestRequest::get().uri("/whoami").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[get("/change_password")]
+    async fn change_password(user: PasswordChangeUser) -> String {
+        user.0.email_address
+    }
+
+    #[actix_web::test]
+    async fn password_change_sessions_only_change_the_password() {
+        let db = create_test_db().await;
+        let user_id = insert_user(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian",
+            "password",
+            "arian@gmail.com",
+        )
+        .await
+        .unwrap();
+        let expire_date = Utc::now() + Duration::days(1);
+        insert_session(&db, &sha256_hash("full"), user_id, expire_date)
+            .await
+            .unwrap();
+        insert_password_change_session(&db, &sha256_hash("restricted"), user_id, expire_date)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .service(whoami)
+                .service(change_password),
+        )
+        .await;
+
+        for (uri, token, expected) in [
+            ("/whoami", "full", StatusCode::OK),
+            ("/change_password", "full", StatusCode::OK),
+            ("/whoami", "restricted", StatusCode::FORBIDDEN),
+            ("/change_password", "restricted", StatusCode::OK),
+        ] {
+            let req = TestRequest::get()
+                .uri(uri)
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), expected, "{uri} {token}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn credentials_only_work_in_their_tenant() {
+        let db = create_test_db().await;
+        insert_tenant(&db, "shop", "Shop", None).await.unwrap();
+        // the same address in two tenants are two unrelated users
+        for (tenant_id, token) in [(DEFAULT_TENANT_ID, "default_token"), ("shop", "shop_token")] {
+            let user_id = insert_user(&db, tenant_id, "arian", "password", "arian@gmail.com")
+                .await
+                .unwrap();
+            insert_session(
+                &db,
+                &sha256_hash(token),
+                user_id,
+                Utc::now() + Duration::days(1),
+            )
+            .await
+            .unwrap();
+        }
+        let app = test::init_service(App::new().app_data(Data::new(db)).service(whoami)).await;
+
+        for (tenant_id, token, expected) in [
+            (DEFAULT_TENANT_ID, "default_token", StatusCode::OK),
+            ("shop", "shop_token", StatusCode::OK),
+            ("shop", "default_token", StatusCode::UNAUTHORIZED),
+            (DEFAULT_TENANT_ID, "shop_token", StatusCode::UNAUTHORIZED),
+        ] {
+            let req = TestRequest::get()
+                .uri("/whoami")
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+                .insert_header((TENANT_HEADER, tenant_id))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), expected);
+        }
+    }
+}
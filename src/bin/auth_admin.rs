@@ -0,0 +1,212 @@
+//! offline administration of the auth database, works without the http server running
+
+use anyhow::{bail, Result};
+use auth_system::{
+    admin_commands::{create_admin, parse_status, reset_password, set_status, AUDIT_ACTOR},
+    audit::RequestInfo,
+    breached_passwords::BreachedPasswords,
+    config::Config,
+    db::{
+        self,
+        audit_log::AuditOutcome,
+        email_codes::delete_email_codes_sent_before,
+        stats::get_db_stats,
+        tenants::{get_tenant, insert_tenant, DEFAULT_TENANT_ID},
+        user::{search_users, UserFilter},
+    },
+};
+use chrono::{Duration, Utc};
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+
+#[derive(Parser)]
+#[command(about = "offline administration of the auth database")]
+struct Args {
+    /// defaults to the DATABASE_URL environment variable
+    #[arg(long, env = "DATABASE_URL")]
+    database_url: String,
+    /// tenant of the users the command works on
+    #[arg(long, default_value = DEFAULT_TENANT_ID)]
+    tenant: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// applies pending migrations
+    Migrate,
+    CreateTenant {
+        #[arg(long)]
+        id: String,
+        #[arg(long)]
+        name: String,
+        /// requests to this host belong to the tenant
+        #[arg(long)]
+        host: Option<String>,
+    },
+    /// creates a user with the admin role, or gives an existing user the admin role
+    CreateAdmin {
+        #[arg(long)]
+        email_address: String,
+        #[arg(long, default_value = "admin")]
+        name: String,
+    },
+    /// sets a new password and logs the user out everywhere
+    ResetPassword {
+        #[arg(long)]
+        email_address: String,
+    },
+    ListUsers {
+        /// part of the email address or name
+        #[arg(long)]
+        search: Option<String>,
+        /// pending, active, suspended, locked or deleted
+        #[arg(long)]
+        status: Option<String>,
+        #[arg(long)]
+        role: Option<String>,
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+        #[arg(long, default_value_t = 0)]
+        offset: i64,
+    },
+    /// moves the account to another status, only active accounts can be used
+    SetStatus {
+        #[arg(long)]
+        email_address: String,
+        /// pending, active, suspended, locked or deleted
+        #[arg(long)]
+        status: String,
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// deletes email codes that are too old to be used
+    PurgeEmailCodes {
+        #[arg(long, default_value_t = 3600)]
+        older_than_secs: i64,
+    },
+    Stats,
+}
+
+#[actix_web::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let args = Args::parse();
+    let pool = db::establish_connection(&args.database_url).await?;
+    // there is no request, so audit events have no ip or user agent
+    let info = RequestInfo {
+        tenant_id: args.tenant,
+        ip: None,
+        user_agent: None,
+        now: Utc::now(),
+    };
+    let needs_tenant = !matches!(
+        args.command,
+        Command::Migrate | Command::CreateTenant { .. } | Command::Stats
+    );
+    if needs_tenant && get_tenant(&pool, &info.tenant_id).await?.is_none() {
+        bail!("tenant {} doesn't exist", info.tenant_id);
+    }
+
+    match args.command {
+        Command::Migrate => {
+            db::setup(&pool).await?;
+            println!("database is up to date");
+        }
+        Command::CreateTenant { id, name, host } => {
+            insert_tenant(&pool, &id, &name, host.as_deref()).await?;
+            info.audit(
+                &pool,
+                AUDIT_ACTOR,
+                "tenant.create",
+                &id,
+                AuditOutcome::Success,
+            )
+            .await?;
+            println!("created tenant {id}");
+        }
+        Command::CreateAdmin {
+            email_address,
+            name,
+        } => {
+            let breached_passwords = BreachedPasswords::from_config(&Config::from_env())?;
+            let created = create_admin(
+                &pool,
+                &info,
+                &breached_passwords,
+                &|prompt: &str| rpassword::prompt_password(prompt),
+                &email_address,
+                &name,
+            )
+            .await?;
+            if created.created_user {
+                println!("created user {email_address}");
+            }
+            if created.assigned_role {
+                println!("{email_address} is now an admin");
+            } else {
+                println!("{email_address} already is an admin");
+            }
+        }
+        Command::ResetPassword { email_address } => {
+            let breached_passwords = BreachedPasswords::from_config(&Config::from_env())?;
+            reset_password(
+                &pool,
+                &info,
+                &breached_passwords,
+                &|prompt: &str| rpassword::prompt_password(prompt),
+                &email_address,
+            )
+            .await?;
+            println!("password of {email_address} was reset");
+        }
+        Command::ListUsers {
+            search,
+            status,
+            role,
+            limit,
+            offset,
+        } => {
+            let filter = UserFilter {
+                search,
+                status: status.as_deref().map(parse_status).transpose()?,
+                role,
+            };
+            for user in search_users(&pool, &info.tenant_id, &filter, limit, offset).await? {
+                println!(
+                    "{}\t{}\t{}",
+                    user.email_address,
+                    user.name,
+                    user.status.as_str()
+                );
+            }
+        }
+        Command::SetStatus {
+            email_address,
+            status,
+            reason,
+        } => {
+            let status = parse_status(&status)?;
+            set_status(&pool, &info, &email_address, status, reason.as_deref()).await?;
+            println!("{email_address} is {}", status.as_str());
+        }
+        Command::PurgeEmailCodes { older_than_secs } => {
+            let sent_before = Utc::now() - Duration::seconds(older_than_secs);
+            let deleted = delete_email_codes_sent_before(&pool, sent_before).await?;
+            println!("deleted {deleted} email codes");
+        }
+        Command::Stats => {
+            let stats = get_db_stats(&pool).await?;
+            println!("users: {}", stats.users);
+            println!("suspended users: {}", stats.suspended_users);
+            println!("active sessions: {}", stats.active_sessions);
+            println!("api keys: {}", stats.api_keys);
+            println!("oauth clients: {}", stats.oauth_clients);
+            println!("service accounts: {}", stats.service_accounts);
+            println!("email codes: {}", stats.email_codes);
+        }
+    }
+    Ok(())
+}
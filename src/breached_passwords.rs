@@ -0,0 +1,136 @@
+//! offline check of passwords against a breach corpus, no password or hash ever leaves the server
+
+use crate::config::Config;
+use memmap2::Mmap;
+use sha1::{Digest, Sha1};
+use std::{cmp::Ordering, fs::File, io, path::Path};
+
+/// a file in the ordered-by-hash format of Pwned Passwords: one `SHA1:COUNT` line per password,
+/// the hash in uppercase hex and the lines sorted by hash. the file is memory-mapped and
+/// binary searched, so it's never read into memory as a whole
+#[derive(Default)]
+pub struct BreachedPasswords {
+    /// `None` if no list is configured, then nothing counts as breached
+    hashes: Option<Mmap>,
+    /// passwords seen less often than this are allowed
+    min_count: u64,
+}
+
+impl BreachedPasswords {
+    pub fn open(path: &Path, min_count: u64) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // mapping an empty file fails, and an empty list has nothing to find anyway
+        if file.metadata()?.len() == 0 {
+            return Ok(BreachedPasswords {
+                hashes: None,
+                min_count,
+            });
+        }
+        // SAFETY: the list is only replaced between restarts, never changed while mapped
+        let hashes = unsafe { Mmap::map(&file)? };
+        Ok(BreachedPasswords {
+            hashes: Some(hashes),
+            min_count,
+        })
+    }
+
+    /// disabled unless `breached_passwords_path` is set
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        match &config.breached_passwords_path {
+            Some(path) => Self::open(Path::new(path), config.breached_password_min_count),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// how often the password was seen in breaches, `None` if it's allowed
+    pub fn breach_count(&self, password: &str) -> Option<u64> {
+        let hashes = self.hashes.as_ref()?;
+        let hash = format!("{:X}", Sha1::digest(password.as_bytes()));
+        find_count(hashes, hash.as_bytes()).filter(|count| *count >= self.min_count.max(1))
+    }
+}
+
+/// binary search over byte offsets, every probe is moved to the start of its line
+fn find_count(data: &[u8], hash: &[u8]) -> Option<u64> {
+    let (mut low, mut high) = (0, data.len());
+    while low < high {
+        let middle = (low + high) / 2;
+        let line_start = data[..middle]
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |index| index + 1);
+        let line_end = data[middle..]
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map_or(data.len(), |index| middle + index);
+        let line = &data[line_start..line_end];
+        let (line_hash, count) = match line.iter().position(|byte| *byte == b':') {
+            Some(index) => (&line[..index], &line[index + 1..]),
+            None => (line, &b""[..]),
+        };
+
+        match line_hash.cmp(hash) {
+            Ordering::Less => low = line_end + 1,
+            Ordering::Greater => high = line_start,
+            Ordering::Equal => {
+                let count = std::str::from_utf8(count).ok()?.trim();
+                // a list without counts still marks the password as breached
+                return Some(count.parse().unwrap_or(1));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    // sha1 of `password` is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+    const LIST: &str = "0000000A0E3B9F25FF41DE4B5AC238C2D545C7A8:15\r\n\
+        5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n\
+        7C4A8D09CA3762AF61E59520943DC26494F8941B:37359195\r\n\
+        FFFFFFFEE3E4E2A3A6E2D9B2C4BB61DD6E1C52E0:2\r\n";
+
+    #[test]
+    fn find_count_should_work() {
+        let data = LIST.as_bytes();
+        for (hash, count) in [
+            ("0000000A0E3B9F25FF41DE4B5AC238C2D545C7A8", Some(15)),
+            ("5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8", Some(9545824)),
+            ("FFFFFFFEE3E4E2A3A6E2D9B2C4BB61DD6E1C52E0", Some(2)),
+            ("5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD9", None),
+            ("0000000000000000000000000000000000000000", None),
+            ("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF", None),
+        ] {
+            assert_eq!(find_count(data, hash.as_bytes()), count, "{hash}");
+        }
+        assert_eq!(
+            find_count(b"", b"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8"),
+            None
+        );
+    }
+
+    #[test]
+    fn open_and_check_passwords() {
+        let path = std::env::temp_dir().join(format!("breached_{}.txt", std::process::id()));
+        File::create(&path)
+            .unwrap()
+            .write_all(LIST.as_bytes())
+            .unwrap();
+
+        let breached_passwords = BreachedPasswords::open(&path, 1).unwrap();
+        assert_eq!(breached_passwords.breach_count("password"), Some(9545824));
+        assert_eq!(breached_passwords.breach_count("123456"), Some(37359195));
+        assert_eq!(breached_passwords.breach_count("some_hard_password"), None);
+
+        // rare passwords are let through with a higher threshold
+        let breached_passwords = BreachedPasswords::open(&path, 10_000_000).unwrap();
+        assert_eq!(breached_passwords.breach_count("password"), None);
+        assert_eq!(breached_passwords.breach_count("123456"), Some(37359195));
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(BreachedPasswords::default().breach_count("password"), None);
+    }
+}
@@ -0,0 +1,285 @@
+use chrono::Duration;
+use serde::Deserialize;
+use std::{env, str::FromStr};
+
+/// what the code of `send_email_code` is entered for, each has its own lifetime. a code only
+/// works for the purpose it was sent for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailCodePurpose {
+    #[default]
+    Registration,
+    PasswordReset,
+}
+
+impl EmailCodePurpose {
+    pub const ALL: [EmailCodePurpose; 2] = [
+        EmailCodePurpose::Registration,
+        EmailCodePurpose::PasswordReset,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailCodePurpose::Registration => "registration",
+            EmailCodePurpose::PasswordReset => "password_reset",
+        }
+    }
+}
+
+/// how log lines are written to stdout
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// one json object per line, for log collectors
+    Json,
+}
+
+/// Settings that handlers need at runtime, shared with them through app data.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// value of the `iss` claim, should be the public url of this server
+    pub issuer: String,
+    pub session_lifetime: Duration,
+    /// lifetime of the session `login` hands out when the password expired, it can only be
+    /// used to change the password
+    pub password_change_session_lifetime: Duration,
+    pub authorization_code_lifetime: Duration,
+    pub access_token_lifetime: Duration,
+    pub refresh_token_lifetime: Duration,
+    /// how long a signing key is used to sign new tokens before a new one gets generated
+    pub signing_key_rotation_period: Duration,
+    pub device_code_lifetime: Duration,
+    /// minimum time a device has to wait between two polls of the token endpoint
+    pub device_code_poll_interval: Duration,
+    /// page where users enter the code shown on their device, `device_page` by default
+    pub device_verification_uri: String,
+    /// a webhook delivery is given up after this many failed attempts
+    pub webhook_max_attempts: i64,
+    /// delay before the first retry of a webhook delivery, doubled for every further retry
+    pub webhook_retry_delay: Duration,
+    /// how often the queue is checked for due webhook deliveries
+    pub webhook_poll_interval: Duration,
+    pub webhook_timeout: Duration,
+    pub invitation_lifetime: Duration,
+    /// page where invited users accept, the token is appended as `?token=`. `invitation_page`
+    /// by default
+    pub invitation_uri: String,
+    pub registration_email_code_lifetime: Duration,
+    pub password_reset_email_code_lifetime: Duration,
+    /// how often expired email codes are deleted
+    pub email_code_sweep_interval: Duration,
+    /// how long the code sent to the new address of an email change can be confirmed
+    pub email_change_code_lifetime: Duration,
+    /// how long the old address can undo an email change, counted from the request
+    pub email_change_undo_lifetime: Duration,
+    /// page the old address is sent to for undoing a change, the token is appended as `?token=`.
+    /// `email_change_undo_page` by default
+    pub email_change_undo_uri: String,
+    /// how long a deleted account can still be restored before it is purged
+    pub account_deletion_grace_period: Duration,
+    /// page the cancel link of an account deletion points to, the token is appended as
+    /// `?token=`. `account_deletion_cancel_page` by default
+    pub account_deletion_cancel_uri: String,
+    /// how often accounts past their grace period are looked for
+    pub account_deletion_poll_interval: Duration,
+    /// sorted `SHA1:COUNT` list of breached passwords, new passwords found in it are refused
+    pub breached_passwords_path: Option<String>,
+    /// passwords seen in fewer breaches than this are still allowed
+    pub breached_password_min_count: u64,
+    /// list of throwaway email domains that replaces the bundled one
+    pub disposable_email_domains_path: Option<String>,
+    /// list of email domains no tenant accepts
+    pub denied_email_domains_path: Option<String>,
+    /// how often the email domain list files are checked for changes
+    pub email_domain_lists_reload_interval: Duration,
+    /// address of a separate server for `/metrics`, so it doesn't have to be public. without
+    /// it the metrics are served next to the api
+    pub metrics_address: Option<String>,
+    pub log_format: LogFormat,
+    /// base url of an OTLP/HTTP collector, like `http://localhost:4318`. spans are only
+    /// exported when it's set
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            issuer: "http://127.0.0.1:8000".to_string(),
+            session_lifetime: Duration::days(7),
+            password_change_session_lifetime: Duration::minutes(15),
+            authorization_code_lifetime: Duration::minutes(1),
+            access_token_lifetime: Duration::minutes(15),
+            refresh_token_lifetime: Duration::days(30),
+            signing_key_rotation_period: Duration::days(30),
+            device_code_lifetime: Duration::minutes(10),
+            device_code_poll_interval: Duration::seconds(5),
+            device_verification_uri: "http://127.0.0.1:8000/device".to_string(),
+            webhook_max_attempts: 8,
+            webhook_retry_delay: Duration::seconds(30),
+            webhook_poll_interval: Duration::seconds(5),
+            webhook_timeout: Duration::seconds(10),
+            invitation_lifetime: Duration::days(7),
+            invitation_uri: "http://127.0.0.1:8000/invitation".to_string(),
+            registration_email_code_lifetime: Duration::hours(1),
+            password_reset_email_code_lifetime: Duration::hours(1),
+            email_code_sweep_interval: Duration::minutes(10),
+            email_change_code_lifetime: Duration::hours(1),
+            email_change_undo_lifetime: Duration::days(7),
+            email_change_undo_uri: "http://127.0.0.1:8000/email_change/undo".to_string(),
+            account_deletion_grace_period: Duration::days(30),
+            account_deletion_cancel_uri: "http://127.0.0.1:8000/account_deletion/cancel"
+                .to_string(),
+            account_deletion_poll_interval: Duration::hours(1),
+            breached_passwords_path: None,
+            breached_password_min_count: 1,
+            disposable_email_domains_path: None,
+            denied_email_domains_path: None,
+            email_domain_lists_reload_interval: Duration::minutes(1),
+            metrics_address: None,
+            log_format: LogFormat::Text,
+            otlp_endpoint: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let default = Config::default();
+        let issuer = env::var("ISSUER").unwrap_or(default.issuer);
+        Config {
+            device_verification_uri: env::var("DEVICE_VERIFICATION_URI")
+                .unwrap_or_else(|_| format!("{}/device", issuer.trim_end_matches('/'))),
+            invitation_uri: env::var("INVITATION_URI")
+                .unwrap_or_else(|_| format!("{}/invitation", issuer.trim_end_matches('/'))),
+            email_change_undo_uri: env::var("EMAIL_CHANGE_UNDO_URI")
+                .unwrap_or_else(|_| format!("{}/email_change/undo", issuer.trim_end_matches('/'))),
+            account_deletion_cancel_uri: env::var("ACCOUNT_DELETION_CANCEL_URI").unwrap_or_else(
+                |_| format!("{}/account_deletion/cancel", issuer.trim_end_matches('/')),
+            ),
+            issuer,
+            session_lifetime: duration_from_env("SESSION_LIFETIME_SECS", default.session_lifetime),
+            password_change_session_lifetime: duration_from_env(
+                "PASSWORD_CHANGE_SESSION_LIFETIME_SECS",
+                default.password_change_session_lifetime,
+            ),
+            authorization_code_lifetime: duration_from_env(
+                "AUTHORIZATION_CODE_LIFETIME_SECS",
+                default.authorization_code_lifetime,
+            ),
+            access_token_lifetime: duration_from_env(
+                "ACCESS_TOKEN_LIFETIME_SECS",
+                default.access_token_lifetime,
+            ),
+            refresh_token_lifetime: duration_from_env(
+                "REFRESH_TOKEN_LIFETIME_SECS",
+                default.refresh_token_lifetime,
+            ),
+            signing_key_rotation_period: duration_from_env(
+                "SIGNING_KEY_ROTATION_PERIOD_SECS",
+                default.signing_key_rotation_period,
+            ),
+            device_code_lifetime: duration_from_env(
+                "DEVICE_CODE_LIFETIME_SECS",
+                default.device_code_lifetime,
+            ),
+            device_code_poll_interval: duration_from_env(
+                "DEVICE_CODE_POLL_INTERVAL_SECS",
+                default.device_code_poll_interval,
+            ),
+            webhook_max_attempts: number_from_env(
+                "WEBHOOK_MAX_ATTEMPTS",
+                default.webhook_max_attempts,
+            ),
+            webhook_retry_delay: duration_from_env(
+                "WEBHOOK_RETRY_DELAY_SECS",
+                default.webhook_retry_delay,
+            ),
+            webhook_poll_interval: duration_from_env(
+                "WEBHOOK_POLL_INTERVAL_SECS",
+                default.webhook_poll_interval,
+            ),
+            webhook_timeout: duration_from_env("WEBHOOK_TIMEOUT_SECS", default.webhook_timeout),
+            invitation_lifetime: duration_from_env(
+                "INVITATION_LIFETIME_SECS",
+                default.invitation_lifetime,
+            ),
+            registration_email_code_lifetime: duration_from_env(
+                "REGISTRATION_EMAIL_CODE_LIFETIME_SECS",
+                default.registration_email_code_lifetime,
+            ),
+            password_reset_email_code_lifetime: duration_from_env(
+                "PASSWORD_RESET_EMAIL_CODE_LIFETIME_SECS",
+                default.password_reset_email_code_lifetime,
+            ),
+            email_code_sweep_interval: duration_from_env(
+                "EMAIL_CODE_SWEEP_INTERVAL_SECS",
+                default.email_code_sweep_interval,
+            ),
+            email_change_code_lifetime: duration_from_env(
+                "EMAIL_CHANGE_CODE_LIFETIME_SECS",
+                default.email_change_code_lifetime,
+            ),
+            email_change_undo_lifetime: duration_from_env(
+                "EMAIL_CHANGE_UNDO_LIFETIME_SECS",
+                default.email_change_undo_lifetime,
+            ),
+            account_deletion_grace_period: duration_from_env(
+                "ACCOUNT_DELETION_GRACE_PERIOD_SECS",
+                default.account_deletion_grace_period,
+            ),
+            account_deletion_poll_interval: duration_from_env(
+                "ACCOUNT_DELETION_POLL_INTERVAL_SECS",
+                default.account_deletion_poll_interval,
+            ),
+            breached_passwords_path: env::var("BREACHED_PASSWORDS_PATH").ok(),
+            breached_password_min_count: number_from_env(
+                "BREACHED_PASSWORD_MIN_COUNT",
+                default.breached_password_min_count,
+            ),
+            disposable_email_domains_path: env::var("DISPOSABLE_EMAIL_DOMAINS_PATH").ok(),
+            denied_email_domains_path: env::var("DENIED_EMAIL_DOMAINS_PATH").ok(),
+            email_domain_lists_reload_interval: duration_from_env(
+                "EMAIL_DOMAIN_LISTS_RELOAD_INTERVAL_SECS",
+                default.email_domain_lists_reload_interval,
+            ),
+            metrics_address: env::var("METRICS_ADDRESS").ok(),
+            log_format: match env::var("LOG_FORMAT").as_deref() {
+                Ok("json") => LogFormat::Json,
+                _ => default.log_format,
+            },
+            otlp_endpoint: env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok(),
+        }
+    }
+
+    pub fn email_code_lifetime(&self, purpose: EmailCodePurpose) -> Duration {
+        match purpose {
+            EmailCodePurpose::Registration => self.registration_email_code_lifetime,
+            EmailCodePurpose::PasswordReset => self.password_reset_email_code_lifetime,
+        }
+    }
+}
+
+fn duration_from_env(name: &str, default: Duration) -> Duration {
+    Duration::seconds(number_from_env(name, default.num_seconds()))
+}
+
+fn number_from_env<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("'{name}' is invalid")),
+        Err(_) => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "'TEST_NEGATIVE_COUNT' is invalid")]
+    fn negative_counts_are_invalid() {
+        env::set_var("TEST_NEGATIVE_COUNT", "-1");
+        number_from_env::<u64>("TEST_NEGATIVE_COUNT", 1);
+    }
+}
@@ -1,7 +1,22 @@
+pub mod account_deletions;
+pub mod api_keys;
+pub mod audit_log;
+pub mod device_codes;
+pub mod email_changes;
 pub mod email_codes;
+pub mod invitations;
+pub mod oauth;
+pub mod roles;
+pub mod service_accounts;
+pub mod sessions;
+pub mod signing_keys;
+pub mod stats;
+pub mod tenants;
 pub mod user;
+pub mod webhooks;
 
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
 
 pub type DbPool = SqlitePool;
@@ -19,3 +34,8 @@ pub async fn setup(pool: &DbPool) -> Result<()> {
     sqlx::migrate!("./src/db/migrations").run(pool).await?;
     Ok(())
 }
+
+/// dates are stored as rfc3339 strings
+fn parse_date(date: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(date).unwrap().into()
+}
@@ -0,0 +1,153 @@
+use super::{parse_date, DbPool};
+use crate::error::{ApiError, ApiResult};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, PartialEq)]
+pub struct AccountDeletion {
+    pub user_id: i64,
+    pub requested_date: DateTime<Utc>,
+    pub delete_date: DateTime<Utc>,
+}
+
+/// asking again replaces the earlier request, so only the newest link can cancel
+#[tracing::instrument(skip_all)]
+pub async fn schedule_account_deletion(
+    pool: &DbPool,
+    user_id: i64,
+    cancel_token_hash: &str,
+    delete_date: DateTime<Utc>,
+) -> ApiResult<()> {
+    let now_date = Utc::now().to_rfc3339();
+    let delete_date = delete_date.to_rfc3339();
+    sqlx::query!(
+        "INSERT OR REPLACE INTO account_deletions (user_id, cancel_token_hash, requested_date,
+        delete_date) VALUES (?, ?, ?, ?)",
+        user_id,
+        cancel_token_hash,
+        now_date,
+        delete_date
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_account_deletion(
+    pool: &DbPool,
+    user_id: i64,
+) -> ApiResult<Option<AccountDeletion>> {
+    let record = sqlx::query!(
+        "SELECT user_id, requested_date, delete_date FROM account_deletions WHERE user_id=?",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| AccountDeletion {
+        user_id: r.user_id,
+        requested_date: parse_date(&r.requested_date),
+        delete_date: parse_date(&r.delete_date),
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_account_deletion_by_cancel_token(
+    pool: &DbPool,
+    cancel_token_hash: &str,
+) -> ApiResult<Option<AccountDeletion>> {
+    let record = sqlx::query!(
+        "SELECT user_id, requested_date, delete_date FROM account_deletions
+        WHERE cancel_token_hash=?",
+        cancel_token_hash
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| AccountDeletion {
+        user_id: r.user_id,
+        requested_date: parse_date(&r.requested_date),
+        delete_date: parse_date(&r.delete_date),
+    }))
+}
+
+/// returns false if no deletion was scheduled
+#[tracing::instrument(skip_all)]
+pub async fn cancel_account_deletion(pool: &DbPool, user_id: i64) -> ApiResult<bool> {
+    let result = sqlx::query!("DELETE FROM account_deletions WHERE user_id=?", user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// ids of the users whose grace period is over
+#[tracing::instrument(skip_all)]
+pub async fn get_due_account_deletions(pool: &DbPool, now: DateTime<Utc>) -> ApiResult<Vec<i64>> {
+    let now_date = now.to_rfc3339();
+    let records = sqlx::query!(
+        "SELECT user_id FROM account_deletions WHERE julianday(delete_date) <= julianday(?)
+        ORDER BY user_id",
+        now_date
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(records.into_iter().map(|r| r.user_id).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{tenants::DEFAULT_TENANT_ID, user::insert_user},
+        test::helper::create_test_db,
+    };
+    use chrono::Duration;
+
+    #[actix_web::test]
+    async fn schedule_and_cancel() {
+        let db = create_test_db().await;
+        let mut ids = vec![];
+        for email_address in ["a@gmail.com", "b@gmail.com"] {
+            let id = insert_user(&db, DEFAULT_TENANT_ID, "arian", "password", email_address)
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        schedule_account_deletion(&db, ids[0], "old", Utc::now() + Duration::days(1))
+            .await
+            .unwrap();
+        schedule_account_deletion(&db, ids[0], "new", Utc::now() - Duration::seconds(1))
+            .await
+            .unwrap();
+        schedule_account_deletion(&db, ids[1], "other", Utc::now() + Duration::days(1))
+            .await
+            .unwrap();
+
+        assert!(get_account_deletion_by_cancel_token(&db, "old")
+            .await
+            .unwrap()
+            .is_none());
+        let deletion = get_account_deletion_by_cancel_token(&db, "new")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(deletion.user_id, ids[0]);
+        assert_eq!(
+            get_due_account_deletions(&db, Utc::now()).await.unwrap(),
+            vec![ids[0]]
+        );
+
+        assert!(cancel_account_deletion(&db, ids[0]).await.unwrap());
+        assert!(!cancel_account_deletion(&db, ids[0]).await.unwrap());
+        assert!(get_account_deletion(&db, ids[0]).await.unwrap().is_none());
+        assert!(get_due_account_deletions(&db, Utc::now())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use super::{parse_date, DbPool};
+use crate::error::{ApiError, ApiResult};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, PartialEq)]
+pub struct ApiKey {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub prefix: String,
+    /// empty means the key isn't restricted
+    pub scopes: Vec<String>,
+    pub created_date: DateTime<Utc>,
+    pub expire_date: Option<DateTime<Utc>>,
+    pub last_used_date: Option<DateTime<Utc>>,
+    pub last_used_ip: Option<String>,
+}
+
+pub struct NewApiKey<'a> {
+    pub user_id: i64,
+    pub name: &'a str,
+    pub prefix: &'a str,
+    pub key_hash: &'a str,
+    pub scopes: &'a [String],
+    pub expire_date: Option<DateTime<Utc>>,
+}
+
+/// returns the id of the new key
+#[tracing::instrument(skip_all)]
+pub async fn insert_api_key(pool: &DbPool, key: &NewApiKey<'_>) -> ApiResult<i64> {
+    let now_date = Utc::now().to_rfc3339();
+    let scopes = key.scopes.join(" ");
+    let expire_date = key.expire_date.map(|date| date.to_rfc3339());
+    let result = sqlx::query!(
+        "INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, created_date, expire_date)
+        VALUES (?, ?, ?, ?, ?, ?, ?)",
+        key.user_id,
+        key.name,
+        key.prefix,
+        key.key_hash,
+        scopes,
+        now_date,
+        expire_date
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.last_insert_rowid())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_api_keys_of_user(pool: &DbPool, user_id: i64) -> ApiResult<Vec<ApiKey>> {
+    let records = sqlx::query!(
+        "SELECT id, user_id, name, prefix, scopes, created_date, expire_date, last_used_date, last_used_ip
+        FROM api_keys WHERE user_id=? ORDER BY id",
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| ApiKey {
+            id: r.id,
+            user_id: r.user_id,
+            name: r.name,
+            prefix: r.prefix,
+            scopes: r.scopes.split_whitespace().map(str::to_string).collect(),
+            created_date: parse_date(&r.created_date),
+            expire_date: r.expire_date.as_deref().map(parse_date),
+            last_used_date: r.last_used_date.as_deref().map(parse_date),
+            last_used_ip: r.last_used_ip,
+        })
+        .collect())
+}
+
+/// keys of accounts that aren't active aren't returned
+#[tracing::instrument(skip_all)]
+pub async fn get_api_key_by_hash(pool: &DbPool, key_hash: &str) -> ApiResult<Option<ApiKey>> {
+    let record = sqlx::query!(
+        r#"SELECT id as "id!", user_id, name, prefix, scopes, created_date, expire_date, last_used_date, last_used_ip
+        FROM api_keys WHERE key_hash=?
+        AND user_id IN (SELECT id FROM users WHERE status='active') LIMIT 1"#,
+        key_hash
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| ApiKey {
+        id: r.id,
+        user_id: r.user_id,
+        name: r.name,
+        prefix: r.prefix,
+        scopes: r.scopes.split_whitespace().map(str::to_string).collect(),
+        created_date: parse_date(&r.created_date),
+        expire_date: r.expire_date.as_deref().map(parse_date),
+        last_used_date: r.last_used_date.as_deref().map(parse_date),
+        last_used_ip: r.last_used_ip,
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn update_api_key_last_use(pool: &DbPool, id: i64, ip: Option<&str>) -> ApiResult<()> {
+    let now_date = Utc::now().to_rfc3339();
+    sqlx::query!(
+        "UPDATE api_keys SET last_used_date=?, last_used_ip=? WHERE id=?",
+        now_date,
+        ip,
+        id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+/// returns whether a key was deleted, keys of other users are left alone
+#[tracing::instrument(skip_all)]
+pub async fn delete_api_key(pool: &DbPool, user_id: i64, id: i64) -> ApiResult<bool> {
+    let result = sqlx::query!("DELETE FROM api_keys WHERE user_id=? AND id=?", user_id, id)
+        .execute(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{tenants::DEFAULT_TENANT_ID, user::insert_user},
+        test::helper::create_test_db,
+    };
+
+    #[actix_web::test]
+    async fn insert_use_and_delete_api_key() {
+        let db = create_test_db().await;
+        let user_id = insert_user(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian",
+            "password",
+            "arian@gmail.com",
+        )
+        .await
+        .unwrap();
+
+        let scopes = vec!["invoices:read".to_string()];
+        let id = insert_api_key(
+            &db,
+            &NewApiKey {
+                user_id,
+                name: "ci",
+                prefix: "abcd1234",
+                key_hash: "hash",
+                scopes: &scopes,
+                expire_date: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        update_api_key_last_use(&db, id, Some("10.0.0.1"))
+            .await
+            .unwrap();
+        let key = get_api_key_by_hash(&db, "hash").await.unwrap().unwrap();
+        assert_eq!(key.id, id);
+        assert_eq!(key.scopes, scopes);
+        assert_eq!(key.last_used_ip.as_deref(), Some("10.0.0.1"));
+        assert!(key.last_used_date.is_some());
+        assert_eq!(get_api_keys_of_user(&db, user_id).await.unwrap(), vec![key]);
+
+        assert!(!delete_api_key(&db, user_id + 1, id).await.unwrap());
+        assert!(delete_api_key(&db, user_id, id).await.unwrap());
+        assert!(get_api_key_by_hash(&db, "hash").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,424 @@
+use super::{parse_date, tenants::DEFAULT_TENANT_ID, DbPool};
+use crate::{
+    error::{ApiError, ApiResult},
+    utils::hash::sha256_hash,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// how many times an insert is retried when another event was appended concurrently
+const MAX_APPEND_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+        }
+    }
+
+    fn parse(outcome: &str) -> Self {
+        match outcome {
+            "failure" => Self::Failure,
+            _ => Self::Success,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub tenant_id: String,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub outcome: AuditOutcome,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_date: DateTime<Utc>,
+    pub prev_hash: Option<String>,
+    pub hash: Option<String>,
+}
+
+pub struct NewAuditEvent<'a> {
+    /// tenant the action happened in
+    pub tenant_id: &'a str,
+    /// email address of the user that did the action, or whoever tried to
+    pub actor: &'a str,
+    pub action: &'a str,
+    /// what the action was done on, like the email address of a user
+    pub target: &'a str,
+    pub outcome: AuditOutcome,
+    pub ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+/// filters for querying the audit log, `None` means no filtering
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub tenant_id: Option<String>,
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub outcome: Option<AuditOutcome>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// only events with a smaller id, for paging from newest to oldest
+    pub before_id: Option<i64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ChainVerification {
+    pub checked_events: i64,
+    /// id of the first event whose hash doesn't match, `None` if the chain is intact
+    pub first_invalid_id: Option<i64>,
+    /// hash of the newest event, can be kept somewhere else to detect truncation
+    pub last_hash: Option<String>,
+}
+
+/// every field of the event and the hash before it, json encoded so fields can't bleed into
+/// each other. the tenant is only hashed when it isn't the default one, so that events from
+/// before tenants existed still verify
+fn event_hash(prev_hash: &str, created_date: &str, event: &NewAuditEvent) -> String {
+    let mut fields = vec![
+        serde_json::json!(prev_hash),
+        serde_json::json!(created_date),
+        serde_json::json!(event.actor),
+        serde_json::json!(event.action),
+        serde_json::json!(event.target),
+        serde_json::json!(event.outcome.as_str()),
+        serde_json::json!(event.ip),
+        serde_json::json!(event.user_agent),
+    ];
+    if event.tenant_id != DEFAULT_TENANT_ID {
+        fields.push(serde_json::json!(event.tenant_id));
+    }
+    sha256_hash(&serde_json::Value::Array(fields).to_string())
+}
+
+/// appends an event to the hash chain
+#[tracing::instrument(skip_all)]
+pub async fn insert_audit_event(
+    pool: &DbPool,
+    event: &NewAuditEvent<'_>,
+    now: DateTime<Utc>,
+) -> ApiResult<()> {
+    for _ in 0..MAX_APPEND_ATTEMPTS {
+        let prev_hash = sqlx::query!(
+            "SELECT hash FROM audit_log WHERE hash IS NOT NULL ORDER BY id DESC LIMIT 1"
+        )
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?
+        .and_then(|r| r.hash)
+        .unwrap_or_default();
+
+        let now_date = now.to_rfc3339();
+        let hash = event_hash(&prev_hash, &now_date, event);
+        let outcome = event.outcome.as_str();
+        // the unique prev_hash makes this a no-op if another event took our place in the chain
+        let result = sqlx::query!(
+            "INSERT OR IGNORE INTO audit_log
+            (tenant_id, actor, action, target, outcome, ip, user_agent, created_date, prev_hash, hash,
+            actor_user_id, target_user_id)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10,
+            (SELECT id FROM users WHERE tenant_id=?1 AND email_address=?2),
+            (SELECT id FROM users WHERE tenant_id=?1 AND email_address=?4))",
+            event.tenant_id,
+            event.actor,
+            event.action,
+            event.target,
+            outcome,
+            event.ip,
+            event.user_agent,
+            now_date,
+            prev_hash,
+            hash
+        )
+        .execute(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+        if result.rows_affected() > 0 {
+            return Ok(());
+        }
+    }
+
+    Err(ApiError::SqlError {
+        msg: "couldn't append to the audit log".to_string(),
+    })
+}
+
+/// newest first
+#[tracing::instrument(skip_all)]
+pub async fn get_audit_events(
+    pool: &DbPool,
+    filter: &AuditFilter,
+    limit: i64,
+) -> ApiResult<Vec<AuditEvent>> {
+    let outcome = filter.outcome.map(|outcome| outcome.as_str());
+    let since = filter.since.map(|date| date.to_rfc3339());
+    let until = filter.until.map(|date| date.to_rfc3339());
+    let records = sqlx::query!(
+        r#"SELECT id as "id!", tenant_id, actor, action, target, outcome, ip, user_agent,
+        created_date, prev_hash, hash FROM audit_log
+        WHERE (?1 IS NULL OR actor = ?1)
+        AND (?2 IS NULL OR action = ?2)
+        AND (?3 IS NULL OR target = ?3)
+        AND (?4 IS NULL OR outcome = ?4)
+        AND (?5 IS NULL OR julianday(created_date) >= julianday(?5))
+        AND (?6 IS NULL OR julianday(created_date) < julianday(?6))
+        AND (?7 IS NULL OR id < ?7)
+        AND (?8 IS NULL OR tenant_id = ?8)
+        ORDER BY id DESC LIMIT ?9"#,
+        filter.actor,
+        filter.action,
+        filter.target,
+        outcome,
+        since,
+        until,
+        filter.before_id,
+        filter.tenant_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| AuditEvent {
+            id: r.id,
+            tenant_id: r.tenant_id,
+            actor: r.actor,
+            action: r.action,
+            target: r.target,
+            outcome: AuditOutcome::parse(&r.outcome),
+            ip: r.ip,
+            user_agent: r.user_agent,
+            created_date: parse_date(&r.created_date),
+            prev_hash: r.prev_hash,
+            hash: r.hash,
+        })
+        .collect())
+}
+
+/// every event the user was the actor or the target of while having the address, newest first
+#[tracing::instrument(skip_all)]
+pub async fn get_audit_events_of_user(pool: &DbPool, user_id: i64) -> ApiResult<Vec<AuditEvent>> {
+    let records = sqlx::query!(
+        r#"SELECT id as "id!", tenant_id, actor, action, target, outcome, ip, user_agent,
+        created_date, prev_hash, hash FROM audit_log
+        WHERE actor_user_id = ?1 OR target_user_id = ?1
+        ORDER BY id DESC"#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| AuditEvent {
+            id: r.id,
+            tenant_id: r.tenant_id,
+            actor: r.actor,
+            action: r.action,
+            target: r.target,
+            outcome: AuditOutcome::parse(&r.outcome),
+            ip: r.ip,
+            user_agent: r.user_agent,
+            created_date: parse_date(&r.created_date),
+            prev_hash: r.prev_hash,
+            hash: r.hash,
+        })
+        .collect())
+}
+
+/// every event of a tenant the address was the actor or the target of, newest first
+#[tracing::instrument(skip_all)]
+pub async fn get_audit_events_of_address(
+    pool: &DbPool,
+    tenant_id: &str,
+    email_address: &str,
+) -> ApiResult<Vec<AuditEvent>> {
+    let records = sqlx::query!(
+        r#"SELECT id as "id!", tenant_id, actor, action, target, outcome, ip, user_agent,
+        created_date, prev_hash, hash FROM audit_log
+        WHERE tenant_id = ?1 AND (actor = ?2 OR target = ?2)
+        ORDER BY id DESC"#,
+        tenant_id,
+        email_address
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| AuditEvent {
+            id: r.id,
+            tenant_id: r.tenant_id,
+            actor: r.actor,
+            action: r.action,
+            target: r.target,
+            outcome: AuditOutcome::parse(&r.outcome),
+            ip: r.ip,
+            user_agent: r.user_agent,
+            created_date: parse_date(&r.created_date),
+            prev_hash: r.prev_hash,
+            hash: r.hash,
+        })
+        .collect())
+}
+
+/// walks the whole chain from the oldest event and recomputes every hash
+#[tracing::instrument(skip_all)]
+pub async fn verify_audit_chain(pool: &DbPool) -> ApiResult<ChainVerification> {
+    let records = sqlx::query!(
+        r#"SELECT id as "id!", tenant_id, actor, action, target, outcome, ip, user_agent,
+        created_date, prev_hash, hash FROM audit_log WHERE hash IS NOT NULL ORDER BY id"#
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    let mut expected_prev_hash = String::new();
+    let mut checked_events = 0;
+    for r in records {
+        let event = NewAuditEvent {
+            tenant_id: &r.tenant_id,
+            actor: &r.actor,
+            action: &r.action,
+            target: &r.target,
+            outcome: AuditOutcome::parse(&r.outcome),
+            ip: r.ip.as_deref(),
+            user_agent: r.user_agent.as_deref(),
+        };
+        let hash = event_hash(&expected_prev_hash, &r.created_date, &event);
+        if r.prev_hash.as_deref() != Some(expected_prev_hash.as_str())
+            || r.hash.as_deref() != Some(hash.as_str())
+            // unknown outcomes parse as success, so the raw value has to be compared too
+            || AuditOutcome::parse(&r.outcome).as_str() != r.outcome
+        {
+            return Ok(ChainVerification {
+                checked_events,
+                first_invalid_id: Some(r.id),
+                last_hash: None,
+            });
+        }
+        expected_prev_hash = hash;
+        checked_events += 1;
+    }
+
+    Ok(ChainVerification {
+        checked_events,
+        first_invalid_id: None,
+        last_hash: (checked_events > 0).then_some(expected_prev_hash),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::helper::create_test_db;
+
+    fn admin_event<'a>(action: &'a str, target: &'a str) -> NewAuditEvent<'a> {
+        NewAuditEvent {
+            tenant_id: DEFAULT_TENANT_ID,
+            actor: "admin@gmail.com",
+            action,
+            target,
+            outcome: AuditOutcome::Success,
+            ip: Some("10.0.0.1"),
+            user_agent: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn insert_and_filter_audit_events() {
+        let db = create_test_db().await;
+        insert_audit_event(
+            &db,
+            &admin_event("user.disable", "arian@gmail.com"),
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        insert_audit_event(
+            &db,
+            &admin_event("user.enable", "arian@gmail.com"),
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        insert_audit_event(
+            &db,
+            &admin_event("user.delete", "other@gmail.com"),
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+
+        let filter = AuditFilter {
+            target: Some("arian@gmail.com".to_string()),
+            ..Default::default()
+        };
+        let events = get_audit_events(&db, &filter, 10).await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].action, "user.enable");
+        assert_eq!(events[1].ip.as_deref(), Some("10.0.0.1"));
+        assert_eq!(events[0].prev_hash, events[1].hash);
+
+        let filter = AuditFilter {
+            before_id: Some(events[0].id),
+            ..Default::default()
+        };
+        let events = get_audit_events(&db, &filter, 10).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].action, "user.disable");
+    }
+
+    #[actix_web::test]
+    async fn audit_log_is_append_only_and_tamper_evident() {
+        let db = create_test_db().await;
+        for action in ["user.create", "user.disable", "user.enable"] {
+            insert_audit_event(&db, &admin_event(action, "arian@gmail.com"), Utc::now())
+                .await
+                .unwrap();
+        }
+        let verification = verify_audit_chain(&db).await.unwrap();
+        assert_eq!(verification.checked_events, 3);
+        assert_eq!(verification.first_invalid_id, None);
+
+        assert!(sqlx::query!("DELETE FROM audit_log")
+            .execute(&db)
+            .await
+            .is_err());
+        assert!(
+            sqlx::query!("UPDATE audit_log SET actor='someone@gmail.com' WHERE id=2")
+                .execute(&db)
+                .await
+                .is_err()
+        );
+
+        // even with the trigger gone, changes show up in the chain
+        sqlx::query!(
+            "DROP TRIGGER audit_log_no_update;
+            UPDATE audit_log SET actor='someone@gmail.com' WHERE id=2"
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        let verification = verify_audit_chain(&db).await.unwrap();
+        assert_eq!(verification.checked_events, 1);
+        assert_eq!(verification.first_invalid_id, Some(2));
+    }
+}
@@ -0,0 +1,238 @@
+use super::{parse_date, DbPool};
+use crate::error::{ApiError, ApiResult};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DeviceCodeStatus {
+    Pending,
+    Approved,
+    Denied,
+}
+
+impl DeviceCodeStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Denied => "denied",
+        }
+    }
+
+    fn parse(status: &str) -> Self {
+        match status {
+            "approved" => Self::Approved,
+            "denied" => Self::Denied,
+            _ => Self::Pending,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DeviceCode {
+    pub device_code_hash: String,
+    pub user_code: String,
+    pub client_id: String,
+    pub scope: String,
+    pub status: DeviceCodeStatus,
+    /// user that approved or denied the code
+    pub user_id: Option<i64>,
+    pub created_date: DateTime<Utc>,
+    pub last_poll_date: Option<DateTime<Utc>>,
+    pub poll_interval: i64,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_device_code(
+    pool: &DbPool,
+    device_code_hash: &str,
+    user_code: &str,
+    client_id: &str,
+    scope: &str,
+    poll_interval: i64,
+    now: DateTime<Utc>,
+) -> ApiResult<()> {
+    let now_date = now.to_rfc3339();
+    let status = DeviceCodeStatus::Pending.as_str();
+    sqlx::query!(
+        "INSERT INTO device_codes (device_code_hash, user_code, client_id, scope, status, created_date, poll_interval)
+        VALUES (?, ?, ?, ?, ?, ?, ?)",
+        device_code_hash,
+        user_code,
+        client_id,
+        scope,
+        status,
+        now_date,
+        poll_interval
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_device_code(
+    pool: &DbPool,
+    device_code_hash: &str,
+) -> ApiResult<Option<DeviceCode>> {
+    let record = sqlx::query!(
+        "SELECT device_code_hash, user_code, client_id, scope, status, user_id, created_date, last_poll_date, poll_interval
+        FROM device_codes WHERE device_code_hash=? LIMIT 1",
+        device_code_hash
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| DeviceCode {
+        device_code_hash: r.device_code_hash,
+        user_code: r.user_code,
+        client_id: r.client_id,
+        scope: r.scope,
+        status: DeviceCodeStatus::parse(&r.status),
+        user_id: r.user_id,
+        created_date: parse_date(&r.created_date),
+        last_poll_date: r.last_poll_date.as_deref().map(parse_date),
+        poll_interval: r.poll_interval,
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_device_code_by_user_code(
+    pool: &DbPool,
+    user_code: &str,
+) -> ApiResult<Option<DeviceCode>> {
+    let record = sqlx::query!(
+        "SELECT device_code_hash, user_code, client_id, scope, status, user_id, created_date, last_poll_date, poll_interval
+        FROM device_codes WHERE user_code=? LIMIT 1",
+        user_code
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| DeviceCode {
+        device_code_hash: r.device_code_hash,
+        user_code: r.user_code,
+        client_id: r.client_id,
+        scope: r.scope,
+        status: DeviceCodeStatus::parse(&r.status),
+        user_id: r.user_id,
+        created_date: parse_date(&r.created_date),
+        last_poll_date: r.last_poll_date.as_deref().map(parse_date),
+        poll_interval: r.poll_interval,
+    }))
+}
+
+/// approves or denies a pending code, returns false if the code was already decided
+#[tracing::instrument(skip_all)]
+pub async fn decide_device_code(
+    pool: &DbPool,
+    user_code: &str,
+    status: DeviceCodeStatus,
+    user_id: i64,
+) -> ApiResult<bool> {
+    let status = status.as_str();
+    let pending = DeviceCodeStatus::Pending.as_str();
+    let result = sqlx::query!(
+        "UPDATE device_codes SET status=?, user_id=? WHERE user_code=? AND status=?",
+        status,
+        user_id,
+        user_code,
+        pending
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn update_device_code_poll(
+    pool: &DbPool,
+    device_code_hash: &str,
+    poll_interval: i64,
+    now: DateTime<Utc>,
+) -> ApiResult<()> {
+    let now_date = now.to_rfc3339();
+    sqlx::query!(
+        "UPDATE device_codes SET last_poll_date=?, poll_interval=? WHERE device_code_hash=?",
+        now_date,
+        poll_interval,
+        device_code_hash
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+/// returns whether the code was deleted, so that it can only be exchanged once
+#[tracing::instrument(skip_all)]
+pub async fn delete_device_code(pool: &DbPool, device_code_hash: &str) -> ApiResult<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM device_codes WHERE device_code_hash=?",
+        device_code_hash
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::helper::{
+        create_test_db, create_test_oauth_client, create_test_user_with_session, test_user_id,
+    };
+
+    #[actix_web::test]
+    async fn device_code_can_only_be_decided_once() {
+        let db = create_test_db().await;
+        create_test_oauth_client(&db, None).await;
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+        let user_id = test_user_id(&db, "arian@gmail.com").await;
+
+        insert_device_code(
+            &db,
+            "hash",
+            "BCDF-GHJK",
+            "test_client",
+            "openid",
+            5,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        let code = get_device_code_by_user_code(&db, "BCDF-GHJK")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(code.status, DeviceCodeStatus::Pending);
+        assert_eq!(code.user_id, None);
+
+        assert!(
+            decide_device_code(&db, "BCDF-GHJK", DeviceCodeStatus::Approved, user_id)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !decide_device_code(&db, "BCDF-GHJK", DeviceCodeStatus::Denied, user_id)
+                .await
+                .unwrap()
+        );
+
+        update_device_code_poll(&db, "hash", 10, Utc::now())
+            .await
+            .unwrap();
+        let code = get_device_code(&db, "hash").await.unwrap().unwrap();
+        assert_eq!(code.status, DeviceCodeStatus::Approved);
+        assert_eq!(code.user_id, Some(user_id));
+        assert_eq!(code.poll_interval, 10);
+        assert!(code.last_poll_date.is_some());
+
+        assert!(delete_device_code(&db, "hash").await.unwrap());
+        assert!(!delete_device_code(&db, "hash").await.unwrap());
+    }
+}
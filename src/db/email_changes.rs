@@ -0,0 +1,303 @@
+use super::{parse_date, DbPool};
+use crate::error::{ApiError, ApiResult};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmailChangeStatus {
+    Pending,
+    Confirmed,
+    Cancelled,
+    Undone,
+}
+
+impl EmailChangeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Confirmed => "confirmed",
+            Self::Cancelled => "cancelled",
+            Self::Undone => "undone",
+        }
+    }
+
+    fn parse(status: &str) -> Self {
+        match status {
+            "confirmed" => Self::Confirmed,
+            "cancelled" => Self::Cancelled,
+            "undone" => Self::Undone,
+            _ => Self::Pending,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct EmailChange {
+    pub id: i64,
+    pub user_id: i64,
+    pub old_email_address: String,
+    pub new_email_address: String,
+    pub code: u32,
+    pub attempts: i64,
+    pub status: EmailChangeStatus,
+    pub created_date: DateTime<Utc>,
+    pub code_expire_date: DateTime<Utc>,
+    pub undo_expire_date: DateTime<Utc>,
+    pub confirmed_date: Option<DateTime<Utc>>,
+}
+
+pub struct NewEmailChange<'a> {
+    pub user_id: i64,
+    pub old_email_address: &'a str,
+    pub new_email_address: &'a str,
+    pub code: u32,
+    pub undo_token_hash: &'a str,
+    pub code_expire_date: DateTime<Utc>,
+    pub undo_expire_date: DateTime<Utc>,
+}
+
+/// a user has at most one pending change, an earlier one is cancelled
+#[tracing::instrument(skip_all)]
+pub async fn insert_email_change(pool: &DbPool, change: &NewEmailChange<'_>) -> ApiResult<i64> {
+    let now_date = Utc::now().to_rfc3339();
+    let code_expire_date = change.code_expire_date.to_rfc3339();
+    let undo_expire_date = change.undo_expire_date.to_rfc3339();
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    sqlx::query!(
+        "UPDATE email_changes SET status='cancelled' WHERE user_id=? AND status='pending'",
+        change.user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    let result = sqlx::query!(
+        "INSERT INTO email_changes (user_id, old_email_address, new_email_address, code,
+        undo_token_hash, status, created_date, code_expire_date, undo_expire_date)
+        VALUES (?, ?, ?, ?, ?, 'pending', ?, ?, ?)",
+        change.user_id,
+        change.old_email_address,
+        change.new_email_address,
+        change.code,
+        change.undo_token_hash,
+        now_date,
+        code_expire_date,
+        undo_expire_date
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.last_insert_rowid())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_pending_email_change(
+    pool: &DbPool,
+    user_id: i64,
+) -> ApiResult<Option<EmailChange>> {
+    let record = sqlx::query!(
+        r#"SELECT id as "id!", user_id, old_email_address, new_email_address, code, attempts,
+        status, created_date, code_expire_date, undo_expire_date, confirmed_date
+        FROM email_changes WHERE user_id=? AND status='pending' LIMIT 1"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| EmailChange {
+        id: r.id,
+        user_id: r.user_id,
+        old_email_address: r.old_email_address,
+        new_email_address: r.new_email_address,
+        code: r.code as u32,
+        attempts: r.attempts,
+        status: EmailChangeStatus::parse(&r.status),
+        created_date: parse_date(&r.created_date),
+        code_expire_date: parse_date(&r.code_expire_date),
+        undo_expire_date: parse_date(&r.undo_expire_date),
+        confirmed_date: r.confirmed_date.as_deref().map(parse_date),
+    }))
+}
+
+/// every change the user asked for, newest first
+#[tracing::instrument(skip_all)]
+pub async fn get_email_changes_of_user(pool: &DbPool, user_id: i64) -> ApiResult<Vec<EmailChange>> {
+    let records = sqlx::query!(
+        r#"SELECT id as "id!", user_id, old_email_address, new_email_address, code, attempts,
+        status, created_date, code_expire_date, undo_expire_date, confirmed_date
+        FROM email_changes WHERE user_id=? ORDER BY id DESC"#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| EmailChange {
+            id: r.id,
+            user_id: r.user_id,
+            old_email_address: r.old_email_address,
+            new_email_address: r.new_email_address,
+            code: r.code as u32,
+            attempts: r.attempts,
+            status: EmailChangeStatus::parse(&r.status),
+            created_date: parse_date(&r.created_date),
+            code_expire_date: parse_date(&r.code_expire_date),
+            undo_expire_date: parse_date(&r.undo_expire_date),
+            confirmed_date: r.confirmed_date.as_deref().map(parse_date),
+        })
+        .collect())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_email_change_by_undo_token(
+    pool: &DbPool,
+    undo_token_hash: &str,
+) -> ApiResult<Option<EmailChange>> {
+    let record = sqlx::query!(
+        r#"SELECT id as "id!", user_id, old_email_address, new_email_address, code, attempts,
+        status, created_date, code_expire_date, undo_expire_date, confirmed_date
+        FROM email_changes WHERE undo_token_hash=? LIMIT 1"#,
+        undo_token_hash
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| EmailChange {
+        id: r.id,
+        user_id: r.user_id,
+        old_email_address: r.old_email_address,
+        new_email_address: r.new_email_address,
+        code: r.code as u32,
+        attempts: r.attempts,
+        status: EmailChangeStatus::parse(&r.status),
+        created_date: parse_date(&r.created_date),
+        code_expire_date: parse_date(&r.code_expire_date),
+        undo_expire_date: parse_date(&r.undo_expire_date),
+        confirmed_date: r.confirmed_date.as_deref().map(parse_date),
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn record_wrong_email_change_code(pool: &DbPool, id: i64) -> ApiResult<()> {
+    sqlx::query!(
+        "UPDATE email_changes SET attempts=attempts + 1 WHERE id=?",
+        id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+/// moves a change from one status to another, only one of several concurrent callers gets true.
+/// the confirmation date is set when it becomes confirmed
+#[tracing::instrument(skip_all)]
+pub async fn update_email_change_status(
+    pool: &DbPool,
+    id: i64,
+    from: EmailChangeStatus,
+    to: EmailChangeStatus,
+) -> ApiResult<bool> {
+    let from = from.as_str();
+    let to = to.as_str();
+    let now_date = Utc::now().to_rfc3339();
+    let result = sqlx::query!(
+        "UPDATE email_changes SET status=?1,
+        confirmed_date=CASE WHEN ?1='confirmed' THEN ?2 ELSE confirmed_date END
+        WHERE id=?3 AND status=?4",
+        to,
+        now_date,
+        id,
+        from
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{tenants::DEFAULT_TENANT_ID, user::insert_user},
+        test::helper::create_test_db,
+    };
+    use chrono::Duration;
+
+    #[actix_web::test]
+    async fn only_the_newest_change_is_pending() {
+        let db = create_test_db().await;
+        let user_id = insert_user(&db, DEFAULT_TENANT_ID, "arian", "password", "old@gmail.com")
+            .await
+            .unwrap();
+        let mut change = NewEmailChange {
+            user_id,
+            old_email_address: "old@gmail.com",
+            new_email_address: "first@gmail.com",
+            code: 123456,
+            undo_token_hash: "first",
+            code_expire_date: Utc::now() + Duration::hours(1),
+            undo_expire_date: Utc::now() + Duration::days(7),
+        };
+        let first_id = insert_email_change(&db, &change).await.unwrap();
+        change.new_email_address = "second@gmail.com";
+        change.undo_token_hash = "second";
+        let second_id = insert_email_change(&db, &change).await.unwrap();
+
+        let pending = get_pending_email_change(&db, user_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pending.id, second_id);
+        assert_eq!(pending.new_email_address, "second@gmail.com");
+        let first = get_email_change_by_undo_token(&db, "first")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.id, first_id);
+        assert_eq!(first.status, EmailChangeStatus::Cancelled);
+
+        record_wrong_email_change_code(&db, second_id)
+            .await
+            .unwrap();
+        assert!(update_email_change_status(
+            &db,
+            second_id,
+            EmailChangeStatus::Pending,
+            EmailChangeStatus::Confirmed
+        )
+        .await
+        .unwrap());
+        assert!(!update_email_change_status(
+            &db,
+            second_id,
+            EmailChangeStatus::Pending,
+            EmailChangeStatus::Cancelled
+        )
+        .await
+        .unwrap());
+        let second = get_email_change_by_undo_token(&db, "second")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.status, EmailChangeStatus::Confirmed);
+        assert_eq!(second.attempts, 1);
+        assert!(second.confirmed_date.is_some());
+        assert!(get_pending_email_change(&db, user_id)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
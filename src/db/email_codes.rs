@@ -1,6 +1,9 @@
-use super::DbPool;
-use crate::error::{ApiError, ApiResult};
-use chrono::{DateTime, Utc};
+use super::{parse_date, DbPool};
+use crate::{
+    config::EmailCodePurpose,
+    error::{ApiError, ApiResult},
+};
+use chrono::{DateTime, Duration, Utc};
 
 #[derive(Debug, PartialEq)]
 pub struct EmailCode {
@@ -8,34 +11,27 @@ pub struct EmailCode {
     pub sent_date: DateTime<Utc>,
 }
 
+/// codes are per tenant, an address can be registered separately in every tenant. a new code
+/// only replaces the last one of the same purpose
+#[tracing::instrument(skip_all)]
 pub async fn insert_or_update_email_code(
     pool: &DbPool,
+    tenant_id: &str,
     email_address: &str,
+    purpose: EmailCodePurpose,
     code: u32,
+    now: DateTime<Utc>,
 ) -> ApiResult<()> {
-    let now_date = Utc::now().to_rfc3339();
-    let insert_result = sqlx::query!(
-        "INSERT OR IGNORE INTO email_codes (email_address, last_sent_code, last_sent_date) VALUES (?, ?, ?)",
-        email_address, code, now_date
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
-
-    if insert_result.rows_affected() == 0 {
-        update_email_code(pool, email_address, code).await?;
-    }
-
-    Ok(())
-}
-
-async fn update_email_code(pool: &DbPool, email_address: &str, new_code: u32) -> ApiResult<()> {
-    let now_date = Utc::now().to_rfc3339();
+    let now_date = now.to_rfc3339();
+    let purpose = purpose.as_str();
     sqlx::query!(
-        "UPDATE email_codes SET last_sent_code=?, last_sent_date=? WHERE email_address=?",
-        new_code,
-        now_date,
+        "INSERT OR REPLACE INTO email_codes (tenant_id, email_address, purpose, last_sent_code,
+        last_sent_date) VALUES (?, ?, ?, ?, ?)",
+        tenant_id,
         email_address,
+        purpose,
+        code,
+        now_date
     )
     .execute(pool)
     .await
@@ -43,13 +39,20 @@ async fn update_email_code(pool: &DbPool, email_address: &str, new_code: u32) ->
     Ok(())
 }
 
+#[tracing::instrument(skip_all)]
 pub async fn get_last_sent_email_code(
     pool: &DbPool,
+    tenant_id: &str,
     email_address: &str,
+    purpose: EmailCodePurpose,
 ) -> ApiResult<Option<EmailCode>> {
+    let purpose = purpose.as_str();
     let record = sqlx::query!(
-        "SELECT last_sent_code, last_sent_date FROM email_codes WHERE email_address=? LIMIT 1",
-        email_address
+        "SELECT last_sent_code, last_sent_date FROM email_codes
+        WHERE tenant_id=? AND email_address=? AND purpose=? LIMIT 1",
+        tenant_id,
+        email_address,
+        purpose
     )
     .fetch_optional(pool)
     .await
@@ -57,16 +60,109 @@ pub async fn get_last_sent_email_code(
 
     Ok(record.map(|r| EmailCode {
         code: r.last_sent_code.try_into().unwrap(),
-        sent_date: DateTime::parse_from_rfc3339(&r.last_sent_date)
-            .unwrap()
-            .try_into()
-            .unwrap(),
+        sent_date: parse_date(&r.last_sent_date),
     }))
 }
 
+/// checks the code a user entered against the last one sent to the address for `purpose`.
+/// codes older than `lifetime` are treated as missing
+#[tracing::instrument(skip_all)]
+pub async fn verify_email_code(
+    pool: &DbPool,
+    tenant_id: &str,
+    email_address: &str,
+    purpose: EmailCodePurpose,
+    code: u32,
+    lifetime: Duration,
+    now: DateTime<Utc>,
+) -> ApiResult<()> {
+    let sent_after = (now - lifetime).to_rfc3339();
+    let purpose = purpose.as_str();
+    let record = sqlx::query!(
+        "SELECT last_sent_code FROM email_codes
+        WHERE tenant_id=? AND email_address=? AND purpose=?
+        AND julianday(last_sent_date) > julianday(?)",
+        tenant_id,
+        email_address,
+        purpose,
+        sent_after
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    match record {
+        None => Err(ApiError::ExpiredEmailCode),
+        Some(r) if r.last_sent_code != i64::from(code) => Err(ApiError::WrongEmailCode),
+        Some(_) => Ok(()),
+    }
+}
+
+/// so that a code can't be used twice
+#[tracing::instrument(skip_all)]
+pub async fn delete_email_code(
+    pool: &DbPool,
+    tenant_id: &str,
+    email_address: &str,
+    purpose: EmailCodePurpose,
+) -> ApiResult<()> {
+    let purpose = purpose.as_str();
+    sqlx::query!(
+        "DELETE FROM email_codes WHERE tenant_id=? AND email_address=? AND purpose=?",
+        tenant_id,
+        email_address,
+        purpose
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+/// deletes codes of any purpose sent before `sent_before`, returns how many were deleted
+#[tracing::instrument(skip_all)]
+pub async fn delete_email_codes_sent_before(
+    pool: &DbPool,
+    sent_before: DateTime<Utc>,
+) -> ApiResult<u64> {
+    let sent_before = sent_before.to_rfc3339();
+    let result = sqlx::query!(
+        "DELETE FROM email_codes WHERE julianday(last_sent_date) < julianday(?)",
+        sent_before
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected())
+}
+
+/// deletes the codes of `purpose` sent before `sent_before`, returns how many were deleted
+#[tracing::instrument(skip_all)]
+pub async fn delete_email_codes_of_purpose_sent_before(
+    pool: &DbPool,
+    purpose: EmailCodePurpose,
+    sent_before: DateTime<Utc>,
+) -> ApiResult<u64> {
+    let sent_before = sent_before.to_rfc3339();
+    let purpose = purpose.as_str();
+    let result = sqlx::query!(
+        "DELETE FROM email_codes WHERE purpose=? AND julianday(last_sent_date) < julianday(?)",
+        purpose,
+        sent_before
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected())
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::test::helper::create_test_db;
+    use crate::{
+        clock::{Clock, FakeClock},
+        db::tenants::DEFAULT_TENANT_ID,
+        test::helper::create_test_db,
+    };
 
     use super::*;
 
@@ -75,27 +171,245 @@ mod tests {
         let db = create_test_db().await;
         let email_address = "arianmoadabb@gmail.com";
 
-        assert!(get_last_sent_email_code(&db, email_address)
-            .await
-            .unwrap()
-            .is_none());
+        assert!(get_last_sent_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            email_address,
+            EmailCodePurpose::Registration
+        )
+        .await
+        .unwrap()
+        .is_none());
 
-        assert!(insert_or_update_email_code(&db, email_address, 123456)
-            .await
-            .is_ok());
-        let last_sent_email_code = get_last_sent_email_code(&db, email_address)
-            .await
-            .unwrap()
-            .unwrap();
+        assert!(insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            email_address,
+            EmailCodePurpose::Registration,
+            123456,
+            Utc::now()
+        )
+        .await
+        .is_ok());
+        let last_sent_email_code = get_last_sent_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            email_address,
+            EmailCodePurpose::Registration,
+        )
+        .await
+        .unwrap()
+        .unwrap();
         assert_eq!(last_sent_email_code.code, 123456);
 
-        assert!(insert_or_update_email_code(&db, email_address, 789102)
-            .await
-            .is_ok());
-        let last_sent_email_code = get_last_sent_email_code(&db, email_address)
+        assert!(insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            email_address,
+            EmailCodePurpose::Registration,
+            789102,
+            Utc::now()
+        )
+        .await
+        .is_ok());
+        let last_sent_email_code = get_last_sent_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            email_address,
+            EmailCodePurpose::Registration,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(last_sent_email_code.code, 789102);
+    }
+
+    #[actix_web::test]
+    async fn codes_expire_after_their_lifetime() {
+        let db = create_test_db().await;
+        let clock = FakeClock::new(Utc::now());
+        let email_address = "arian@gmail.com";
+        let lifetime = Duration::hours(1);
+        let verify = |code: u32, now: DateTime<Utc>| {
+            verify_email_code(
+                &db,
+                DEFAULT_TENANT_ID,
+                email_address,
+                EmailCodePurpose::Registration,
+                code,
+                lifetime,
+                now,
+            )
+        };
+
+        assert_eq!(
+            verify(123456, clock.now()).await,
+            Err(ApiError::ExpiredEmailCode)
+        );
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            email_address,
+            EmailCodePurpose::Registration,
+            123456,
+            clock.now(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(verify(123456, clock.now()).await, Ok(()));
+        assert_eq!(
+            verify(654321, clock.now()).await,
+            Err(ApiError::WrongEmailCode)
+        );
+
+        clock.advance(Duration::minutes(59));
+        assert_eq!(verify(123456, clock.now()).await, Ok(()));
+        clock.advance(Duration::minutes(1));
+        assert_eq!(
+            verify(123456, clock.now()).await,
+            Err(ApiError::ExpiredEmailCode)
+        );
+
+        // a new code starts a new lifetime
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            email_address,
+            EmailCodePurpose::Registration,
+            654321,
+            clock.now(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(verify(654321, clock.now()).await, Ok(()));
+    }
+
+    #[actix_web::test]
+    async fn codes_only_work_for_their_purpose() {
+        let db = create_test_db().await;
+        let now = Utc::now();
+        let email_address = "arian@gmail.com";
+        let lifetime = Duration::hours(1);
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            email_address,
+            EmailCodePurpose::Registration,
+            123456,
+            now,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            verify_email_code(
+                &db,
+                DEFAULT_TENANT_ID,
+                email_address,
+                EmailCodePurpose::PasswordReset,
+                123456,
+                lifetime,
+                now
+            )
+            .await,
+            Err(ApiError::ExpiredEmailCode)
+        );
+
+        // each purpose keeps its own last code
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            email_address,
+            EmailCodePurpose::PasswordReset,
+            654321,
+            now,
+        )
+        .await
+        .unwrap();
+        for (purpose, code) in [
+            (EmailCodePurpose::Registration, 123456),
+            (EmailCodePurpose::PasswordReset, 654321),
+        ] {
+            assert_eq!(
+                verify_email_code(
+                    &db,
+                    DEFAULT_TENANT_ID,
+                    email_address,
+                    purpose,
+                    code,
+                    lifetime,
+                    now
+                )
+                .await,
+                Ok(())
+            );
+        }
+        delete_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            email_address,
+            EmailCodePurpose::PasswordReset,
+        )
+        .await
+        .unwrap();
+        assert!(get_last_sent_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            email_address,
+            EmailCodePurpose::Registration
+        )
+        .await
+        .unwrap()
+        .is_some());
+    }
+
+    #[actix_web::test]
+    async fn delete_old_email_codes() {
+        let db = create_test_db().await;
+        let clock = FakeClock::new(Utc::now());
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "old@gmail.com",
+            EmailCodePurpose::Registration,
+            123456,
+            clock.now(),
+        )
+        .await
+        .unwrap();
+        clock.advance(Duration::seconds(1));
+        let sent_before = clock.now();
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "new@gmail.com",
+            EmailCodePurpose::Registration,
+            123456,
+            clock.now(),
+        )
+        .await
+        .unwrap();
+
+        let deleted = delete_email_codes_sent_before(&db, sent_before)
             .await
-            .unwrap()
             .unwrap();
-        assert_eq!(last_sent_email_code.code, 789102);
+        assert_eq!(deleted, 1);
+        assert!(get_last_sent_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "old@gmail.com",
+            EmailCodePurpose::Registration
+        )
+        .await
+        .unwrap()
+        .is_none());
+        assert!(get_last_sent_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "new@gmail.com",
+            EmailCodePurpose::Registration
+        )
+        .await
+        .unwrap()
+        .is_some());
     }
 }
@@ -0,0 +1,305 @@
+use super::{parse_date, DbPool};
+use crate::error::{ApiError, ApiResult};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InvitationStatus {
+    Pending,
+    Accepted,
+    Revoked,
+}
+
+impl InvitationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Accepted => "accepted",
+            Self::Revoked => "revoked",
+        }
+    }
+
+    fn parse(status: &str) -> Self {
+        match status {
+            "accepted" => Self::Accepted,
+            "revoked" => Self::Revoked,
+            _ => Self::Pending,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Invitation {
+    pub id: i64,
+    pub tenant_id: String,
+    pub email_address: String,
+    pub role_name: Option<String>,
+    pub invited_by: String,
+    pub token_id: String,
+    pub status: InvitationStatus,
+    pub created_date: DateTime<Utc>,
+    pub expire_date: DateTime<Utc>,
+    pub accepted_date: Option<DateTime<Utc>>,
+}
+
+pub struct NewInvitation<'a> {
+    pub tenant_id: &'a str,
+    pub email_address: &'a str,
+    pub role_name: Option<&'a str>,
+    pub invited_by: &'a str,
+    pub token_id: &'a str,
+    pub expire_date: DateTime<Utc>,
+}
+
+/// fails if the address already has a pending invitation to the tenant
+#[tracing::instrument(skip_all)]
+pub async fn insert_invitation(pool: &DbPool, invitation: &NewInvitation<'_>) -> ApiResult<i64> {
+    let status = InvitationStatus::Pending.as_str();
+    let now_date = Utc::now().to_rfc3339();
+    let expire_date = invitation.expire_date.to_rfc3339();
+    let result = sqlx::query!(
+        "INSERT OR IGNORE INTO invitations (tenant_id, email_address, role_name, invited_by,
+        token_id, status, created_date, expire_date) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        invitation.tenant_id,
+        invitation.email_address,
+        invitation.role_name,
+        invitation.invited_by,
+        invitation.token_id,
+        status,
+        now_date,
+        expire_date
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    if result.rows_affected() == 0 {
+        Err(ApiError::BadArgument {
+            argument_name: "email_address",
+        })
+    } else {
+        Ok(result.last_insert_rowid())
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_invitation(
+    pool: &DbPool,
+    tenant_id: &str,
+    id: i64,
+) -> ApiResult<Option<Invitation>> {
+    let record = sqlx::query!(
+        r#"SELECT id as "id!", tenant_id, email_address, role_name, invited_by, token_id, status,
+        created_date, expire_date, accepted_date FROM invitations
+        WHERE tenant_id=? AND id=? LIMIT 1"#,
+        tenant_id,
+        id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| Invitation {
+        id: r.id,
+        tenant_id: r.tenant_id,
+        email_address: r.email_address,
+        role_name: r.role_name,
+        invited_by: r.invited_by,
+        token_id: r.token_id,
+        status: InvitationStatus::parse(&r.status),
+        created_date: parse_date(&r.created_date),
+        expire_date: parse_date(&r.expire_date),
+        accepted_date: r.accepted_date.as_deref().map(parse_date),
+    }))
+}
+
+/// newest first
+#[tracing::instrument(skip_all)]
+pub async fn get_invitations(pool: &DbPool, tenant_id: &str) -> ApiResult<Vec<Invitation>> {
+    let records = sqlx::query!(
+        r#"SELECT id as "id!", tenant_id, email_address, role_name, invited_by, token_id, status,
+        created_date, expire_date, accepted_date FROM invitations
+        WHERE tenant_id=? ORDER BY id DESC"#,
+        tenant_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| Invitation {
+            id: r.id,
+            tenant_id: r.tenant_id,
+            email_address: r.email_address,
+            role_name: r.role_name,
+            invited_by: r.invited_by,
+            token_id: r.token_id,
+            status: InvitationStatus::parse(&r.status),
+            created_date: parse_date(&r.created_date),
+            expire_date: parse_date(&r.expire_date),
+            accepted_date: r.accepted_date.as_deref().map(parse_date),
+        })
+        .collect())
+}
+
+/// every invitation sent to the address, newest first
+#[tracing::instrument(skip_all)]
+pub async fn get_invitations_of_address(
+    pool: &DbPool,
+    tenant_id: &str,
+    email_address: &str,
+) -> ApiResult<Vec<Invitation>> {
+    let records = sqlx::query!(
+        r#"SELECT id as "id!", tenant_id, email_address, role_name, invited_by, token_id, status,
+        created_date, expire_date, accepted_date FROM invitations
+        WHERE tenant_id=? AND email_address=? ORDER BY id DESC"#,
+        tenant_id,
+        email_address
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| Invitation {
+            id: r.id,
+            tenant_id: r.tenant_id,
+            email_address: r.email_address,
+            role_name: r.role_name,
+            invited_by: r.invited_by,
+            token_id: r.token_id,
+            status: InvitationStatus::parse(&r.status),
+            created_date: parse_date(&r.created_date),
+            expire_date: parse_date(&r.expire_date),
+            accepted_date: r.accepted_date.as_deref().map(parse_date),
+        })
+        .collect())
+}
+
+/// gives a pending invitation a new token and expiry, returns false if it isn't pending
+#[tracing::instrument(skip_all)]
+pub async fn renew_invitation(
+    pool: &DbPool,
+    tenant_id: &str,
+    id: i64,
+    token_id: &str,
+    expire_date: DateTime<Utc>,
+) -> ApiResult<bool> {
+    let expire_date = expire_date.to_rfc3339();
+    let result = sqlx::query!(
+        "UPDATE invitations SET token_id=?, expire_date=?
+        WHERE tenant_id=? AND id=? AND status='pending'",
+        token_id,
+        expire_date,
+        tenant_id,
+        id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// returns false if the invitation isn't pending
+#[tracing::instrument(skip_all)]
+pub async fn revoke_invitation(pool: &DbPool, tenant_id: &str, id: i64) -> ApiResult<bool> {
+    let result = sqlx::query!(
+        "UPDATE invitations SET status='revoked' WHERE tenant_id=? AND id=? AND status='pending'",
+        tenant_id,
+        id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// marks the invitation as accepted if it is pending and the token is its newest one, only one
+/// of several concurrent acceptances gets true
+#[tracing::instrument(skip_all)]
+pub async fn accept_invitation(pool: &DbPool, id: i64, token_id: &str) -> ApiResult<bool> {
+    let now_date = Utc::now().to_rfc3339();
+    let result = sqlx::query!(
+        "UPDATE invitations SET status='accepted', accepted_date=?
+        WHERE id=? AND token_id=? AND status='pending'",
+        now_date,
+        id,
+        token_id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// forgets every invitation of an address, used when its account is purged
+#[tracing::instrument(skip_all)]
+pub async fn delete_invitations_of_address(
+    pool: &DbPool,
+    tenant_id: &str,
+    email_address: &str,
+) -> ApiResult<()> {
+    sqlx::query!(
+        "DELETE FROM invitations WHERE tenant_id=? AND email_address=?",
+        tenant_id,
+        email_address
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::tenants::DEFAULT_TENANT_ID, test::helper::create_test_db};
+    use chrono::Duration;
+
+    #[actix_web::test]
+    async fn invitation_lifecycle() {
+        let db = create_test_db().await;
+        let new_invitation = NewInvitation {
+            tenant_id: DEFAULT_TENANT_ID,
+            email_address: "arian@gmail.com",
+            role_name: Some("admin"),
+            invited_by: "admin@gmail.com",
+            token_id: "first",
+            expire_date: Utc::now() + Duration::days(1),
+        };
+        let id = insert_invitation(&db, &new_invitation).await.unwrap();
+        assert!(insert_invitation(&db, &new_invitation).await.is_err());
+
+        assert!(
+            renew_invitation(&db, DEFAULT_TENANT_ID, id, "second", Utc::now())
+                .await
+                .unwrap()
+        );
+        assert!(!accept_invitation(&db, id, "first").await.unwrap());
+        assert!(accept_invitation(&db, id, "second").await.unwrap());
+        assert!(!accept_invitation(&db, id, "second").await.unwrap());
+        assert!(!revoke_invitation(&db, DEFAULT_TENANT_ID, id).await.unwrap());
+
+        let invitation = get_invitation(&db, DEFAULT_TENANT_ID, id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(invitation.status, InvitationStatus::Accepted);
+        assert_eq!(invitation.role_name.as_deref(), Some("admin"));
+        assert!(invitation.accepted_date.is_some());
+
+        // once the old one is done, the address can be invited again
+        let id = insert_invitation(&db, &new_invitation).await.unwrap();
+        assert!(revoke_invitation(&db, DEFAULT_TENANT_ID, id).await.unwrap());
+        assert_eq!(
+            get_invitations(&db, DEFAULT_TENANT_ID)
+                .await
+                .unwrap()
+                .iter()
+                .map(|invitation| invitation.status)
+                .collect::<Vec<_>>(),
+            vec![InvitationStatus::Revoked, InvitationStatus::Accepted]
+        );
+    }
+}
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS sessions (
+    token_hash VARCHAR(64) PRIMARY KEY NOT NULL,
+    email_address VARCHAR(64) NOT NULL REFERENCES users(email_address) ON DELETE CASCADE,
+    created_date VARCHAR(32) NOT NULL,
+    expire_date VARCHAR(32) NOT NULL
+)
@@ -0,0 +1,37 @@
+CREATE TABLE IF NOT EXISTS oauth_clients (
+    client_id VARCHAR(64) PRIMARY KEY NOT NULL,
+    -- NULL for public clients (spa and mobile apps) that can't keep a secret
+    client_secret_hash VARCHAR(64),
+    name VARCHAR(64) NOT NULL,
+    -- newline separated, matched exactly against the requested redirect_uri
+    redirect_uris TEXT NOT NULL,
+    -- space separated
+    scopes TEXT NOT NULL,
+    owner_email_address VARCHAR(64) NOT NULL,
+    created_date VARCHAR(32) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS oauth_authorization_codes (
+    code_hash VARCHAR(64) PRIMARY KEY NOT NULL,
+    client_id VARCHAR(64) NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+    email_address VARCHAR(64) NOT NULL REFERENCES users(email_address) ON DELETE CASCADE,
+    redirect_uri TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    code_challenge VARCHAR(128) NOT NULL,
+    expire_date VARCHAR(32) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS oauth_refresh_tokens (
+    token_hash VARCHAR(64) PRIMARY KEY NOT NULL,
+    client_id VARCHAR(64) NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+    email_address VARCHAR(64) NOT NULL REFERENCES users(email_address) ON DELETE CASCADE,
+    scope TEXT NOT NULL,
+    expire_date VARCHAR(32) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS signing_keys (
+    kid VARCHAR(64) PRIMARY KEY NOT NULL,
+    -- pkcs8 encoded P-256 private key
+    private_key BLOB NOT NULL,
+    created_date VARCHAR(32) NOT NULL
+)
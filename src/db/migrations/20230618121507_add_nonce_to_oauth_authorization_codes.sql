@@ -0,0 +1,2 @@
+-- openid connect clients send a nonce to the authorize endpoint that has to end up in the id token
+ALTER TABLE oauth_authorization_codes ADD COLUMN nonce TEXT
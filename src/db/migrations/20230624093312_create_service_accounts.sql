@@ -0,0 +1,19 @@
+-- machine identities for backend jobs, kept apart from users so they can never use login
+CREATE TABLE IF NOT EXISTS service_accounts (
+    client_id VARCHAR(64) PRIMARY KEY NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    -- space separated, the most a client_credentials token can be granted
+    scopes TEXT NOT NULL,
+    owner_email_address VARCHAR(64) NOT NULL,
+    created_date VARCHAR(32) NOT NULL,
+    last_used_date VARCHAR(32)
+);
+
+-- at most two secrets are kept per account so they can be rotated without downtime
+CREATE TABLE IF NOT EXISTS service_account_secrets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    client_id VARCHAR(64) NOT NULL REFERENCES service_accounts(client_id) ON DELETE CASCADE,
+    secret_hash VARCHAR(64) NOT NULL UNIQUE,
+    created_date VARCHAR(32) NOT NULL,
+    last_used_date VARCHAR(32)
+)
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS api_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    email_address VARCHAR(64) NOT NULL REFERENCES users(email_address) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    -- public part of the key, shown in listings so users can tell their keys apart
+    prefix VARCHAR(16) NOT NULL,
+    key_hash VARCHAR(64) NOT NULL UNIQUE,
+    -- space separated, empty means the key isn't restricted
+    scopes TEXT NOT NULL,
+    created_date VARCHAR(32) NOT NULL,
+    expire_date VARCHAR(32),
+    last_used_date VARCHAR(32),
+    last_used_ip VARCHAR(64)
+)
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS device_codes (
+    device_code_hash VARCHAR(64) PRIMARY KEY NOT NULL,
+    user_code VARCHAR(16) NOT NULL UNIQUE,
+    client_id VARCHAR(64) NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+    scope TEXT NOT NULL,
+    -- pending, approved or denied
+    status VARCHAR(16) NOT NULL,
+    -- user that approved or denied the code
+    email_address VARCHAR(64) REFERENCES users(email_address) ON DELETE CASCADE,
+    created_date VARCHAR(32) NOT NULL,
+    last_poll_date VARCHAR(32),
+    -- seconds the client has to wait between polls, grows on every slow_down
+    poll_interval INTEGER NOT NULL
+)
@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS roles (
+    name VARCHAR(64) PRIMARY KEY NOT NULL,
+    description TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS permissions (
+    name VARCHAR(64) PRIMARY KEY NOT NULL,
+    description TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS role_permissions (
+    role_name VARCHAR(64) NOT NULL REFERENCES roles(name) ON DELETE CASCADE,
+    permission_name VARCHAR(64) NOT NULL REFERENCES permissions(name) ON DELETE CASCADE,
+    PRIMARY KEY (role_name, permission_name)
+);
+
+CREATE TABLE IF NOT EXISTS user_roles (
+    email_address VARCHAR(64) NOT NULL REFERENCES users(email_address) ON DELETE CASCADE,
+    role_name VARCHAR(64) NOT NULL REFERENCES roles(name) ON DELETE CASCADE,
+    PRIMARY KEY (email_address, role_name)
+);
+
+INSERT INTO roles (name, description) VALUES ('admin', 'can do everything');
+INSERT INTO permissions (name, description) VALUES
+    ('roles:read', 'list roles and their permissions'),
+    ('roles:write', 'create roles, change their permissions and assign them to users');
+-- later migrations that add permissions grant them to admin too
+INSERT INTO role_permissions (role_name, permission_name) SELECT 'admin', name FROM permissions;
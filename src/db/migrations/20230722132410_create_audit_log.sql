@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- email address of the user that did the action
+    actor VARCHAR(64) NOT NULL,
+    action VARCHAR(64) NOT NULL,
+    -- what the action was done on, like the email address of a user
+    target VARCHAR(64) NOT NULL,
+    created_date VARCHAR(32) NOT NULL
+)
@@ -0,0 +1,22 @@
+ALTER TABLE audit_log ADD COLUMN outcome VARCHAR(16) NOT NULL DEFAULT 'success';
+ALTER TABLE audit_log ADD COLUMN ip VARCHAR(64);
+ALTER TABLE audit_log ADD COLUMN user_agent TEXT;
+-- every event stores the hash of the event before it, the unique index makes sure the chain
+-- can't fork. both are NULL for events recorded before the chain existed
+ALTER TABLE audit_log ADD COLUMN prev_hash VARCHAR(64);
+ALTER TABLE audit_log ADD COLUMN hash VARCHAR(64);
+CREATE UNIQUE INDEX IF NOT EXISTS audit_log_prev_hash ON audit_log(prev_hash);
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit log is append only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit log is append only');
+END;
+
+INSERT INTO permissions (name, description) VALUES
+    ('audit_log:read', 'query, export and verify the audit log');
+INSERT INTO role_permissions (role_name, permission_name) VALUES ('admin', 'audit_log:read');
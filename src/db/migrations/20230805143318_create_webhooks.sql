@@ -0,0 +1,40 @@
+CREATE TABLE IF NOT EXISTS webhooks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    url TEXT NOT NULL,
+    -- used to sign deliveries, kept in plain text because the signature needs it
+    secret VARCHAR(64) NOT NULL,
+    -- space separated event types, empty means every event
+    events TEXT NOT NULL,
+    created_by VARCHAR(64) NOT NULL,
+    created_date VARCHAR(32) NOT NULL
+);
+
+-- the queue of outgoing events, rows stay around as the delivery log
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    webhook_id INTEGER NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+    event_type VARCHAR(64) NOT NULL,
+    payload TEXT NOT NULL,
+    -- pending, succeeded or failed
+    status VARCHAR(16) NOT NULL,
+    attempts INTEGER NOT NULL,
+    next_attempt_date VARCHAR(32) NOT NULL,
+    created_date VARCHAR(32) NOT NULL
+);
+CREATE INDEX IF NOT EXISTS webhook_deliveries_status ON webhook_deliveries(status, next_attempt_date);
+
+CREATE TABLE IF NOT EXISTS webhook_delivery_attempts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    delivery_id INTEGER NOT NULL REFERENCES webhook_deliveries(id) ON DELETE CASCADE,
+    attempted_date VARCHAR(32) NOT NULL,
+    -- NULL when no response was received
+    status_code INTEGER,
+    error TEXT
+);
+
+INSERT INTO permissions (name, description) VALUES
+    ('webhooks:read', 'list webhooks and their deliveries'),
+    ('webhooks:write', 'create, delete and redeliver webhooks');
+INSERT INTO role_permissions (role_name, permission_name) VALUES
+    ('admin', 'webhooks:read'),
+    ('admin', 'webhooks:write');
@@ -0,0 +1,159 @@
+-- every user belongs to a tenant, users of different tenants never see each other
+CREATE TABLE IF NOT EXISTS tenants (
+    id VARCHAR(64) PRIMARY KEY NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    -- requests to this host belong to the tenant, NULL if it's only reachable by header or path
+    host VARCHAR(255) UNIQUE,
+    -- space separated, empty means every domain is allowed
+    allowed_email_domains TEXT NOT NULL DEFAULT '',
+    min_password_length INTEGER NOT NULL DEFAULT 5,
+    mfa_required BOOLEAN NOT NULL DEFAULT FALSE,
+    email_code_subject TEXT NOT NULL DEFAULT 'Confirm register',
+    -- `{code}` is replaced with the code
+    email_code_template TEXT NOT NULL DEFAULT 'your register code is: {code}',
+    created_date VARCHAR(32) NOT NULL
+);
+-- everything that existed before tenants belongs to this one
+INSERT INTO tenants (id, name, created_date) VALUES ('default', 'default', strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now'));
+
+-- email addresses are only unique per tenant now, so users get a surrogate key and every
+-- table that pointed at users(email_address) points at users(id) instead. sqlite can't change
+-- a primary key in place, so the tables are rebuilt. children are copied out and dropped first,
+-- otherwise dropping users would cascade into them
+CREATE TABLE users_copy AS SELECT * FROM users;
+CREATE TABLE sessions_copy AS SELECT * FROM sessions;
+CREATE TABLE oauth_authorization_codes_copy AS SELECT * FROM oauth_authorization_codes;
+CREATE TABLE oauth_refresh_tokens_copy AS SELECT * FROM oauth_refresh_tokens;
+CREATE TABLE api_keys_copy AS SELECT * FROM api_keys;
+CREATE TABLE device_codes_copy AS SELECT * FROM device_codes;
+CREATE TABLE user_roles_copy AS SELECT * FROM user_roles;
+CREATE TABLE email_codes_copy AS SELECT * FROM email_codes;
+DROP TABLE sessions;
+DROP TABLE oauth_authorization_codes;
+DROP TABLE oauth_refresh_tokens;
+DROP TABLE api_keys;
+DROP TABLE device_codes;
+DROP TABLE user_roles;
+DROP TABLE email_codes;
+DROP TABLE users;
+
+CREATE TABLE users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tenant_id VARCHAR(64) NOT NULL REFERENCES tenants(id),
+    email_address VARCHAR(64) NOT NULL,
+    name VARCHAR(32) NOT NULL,
+    password VARCHAR(255) NOT NULL,
+    disabled BOOLEAN NOT NULL DEFAULT FALSE,
+    password_reset_required BOOLEAN NOT NULL DEFAULT FALSE,
+    UNIQUE (tenant_id, email_address)
+);
+INSERT INTO users (tenant_id, email_address, name, password, disabled, password_reset_required)
+SELECT 'default', email_address, name, password, disabled, password_reset_required FROM users_copy;
+
+CREATE TABLE email_codes (
+    tenant_id VARCHAR(64) NOT NULL REFERENCES tenants(id),
+    email_address VARCHAR(64) NOT NULL,
+    last_sent_code UNSIGNED INT NOT NULL,
+    last_sent_date VARCHAR(32) NOT NULL,
+    PRIMARY KEY (tenant_id, email_address)
+);
+INSERT INTO email_codes (tenant_id, email_address, last_sent_code, last_sent_date)
+SELECT 'default', email_address, last_sent_code, last_sent_date FROM email_codes_copy;
+
+CREATE TABLE sessions (
+    token_hash VARCHAR(64) PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_date VARCHAR(32) NOT NULL,
+    expire_date VARCHAR(32) NOT NULL
+);
+INSERT INTO sessions (token_hash, user_id, created_date, expire_date)
+SELECT c.token_hash, users.id, c.created_date, c.expire_date
+FROM sessions_copy c JOIN users ON users.email_address = c.email_address;
+
+CREATE TABLE oauth_authorization_codes (
+    code_hash VARCHAR(64) PRIMARY KEY NOT NULL,
+    client_id VARCHAR(64) NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    redirect_uri TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    code_challenge VARCHAR(128) NOT NULL,
+    expire_date VARCHAR(32) NOT NULL,
+    nonce TEXT
+);
+INSERT INTO oauth_authorization_codes (code_hash, client_id, user_id, redirect_uri, scope, code_challenge, expire_date, nonce)
+SELECT c.code_hash, c.client_id, users.id, c.redirect_uri, c.scope, c.code_challenge, c.expire_date, c.nonce
+FROM oauth_authorization_codes_copy c JOIN users ON users.email_address = c.email_address;
+
+CREATE TABLE oauth_refresh_tokens (
+    token_hash VARCHAR(64) PRIMARY KEY NOT NULL,
+    client_id VARCHAR(64) NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    scope TEXT NOT NULL,
+    expire_date VARCHAR(32) NOT NULL
+);
+INSERT INTO oauth_refresh_tokens (token_hash, client_id, user_id, scope, expire_date)
+SELECT c.token_hash, c.client_id, users.id, c.scope, c.expire_date
+FROM oauth_refresh_tokens_copy c JOIN users ON users.email_address = c.email_address;
+
+CREATE TABLE api_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    -- public part of the key, shown in listings so users can tell their keys apart
+    prefix VARCHAR(16) NOT NULL,
+    key_hash VARCHAR(64) NOT NULL UNIQUE,
+    -- space separated, empty means the key isn't restricted
+    scopes TEXT NOT NULL,
+    created_date VARCHAR(32) NOT NULL,
+    expire_date VARCHAR(32),
+    last_used_date VARCHAR(32),
+    last_used_ip VARCHAR(64)
+);
+INSERT INTO api_keys (id, user_id, name, prefix, key_hash, scopes, created_date, expire_date, last_used_date, last_used_ip)
+SELECT c.id, users.id, c.name, c.prefix, c.key_hash, c.scopes, c.created_date, c.expire_date, c.last_used_date, c.last_used_ip
+FROM api_keys_copy c JOIN users ON users.email_address = c.email_address;
+
+CREATE TABLE device_codes (
+    device_code_hash VARCHAR(64) PRIMARY KEY NOT NULL,
+    user_code VARCHAR(16) NOT NULL UNIQUE,
+    client_id VARCHAR(64) NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+    scope TEXT NOT NULL,
+    -- pending, approved or denied
+    status VARCHAR(16) NOT NULL,
+    -- user that approved or denied the code
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    created_date VARCHAR(32) NOT NULL,
+    last_poll_date VARCHAR(32),
+    -- seconds the client has to wait between polls, grows on every slow_down
+    poll_interval INTEGER NOT NULL
+);
+INSERT INTO device_codes (device_code_hash, user_code, client_id, scope, status, user_id, created_date, last_poll_date, poll_interval)
+SELECT c.device_code_hash, c.user_code, c.client_id, c.scope, c.status, users.id, c.created_date, c.last_poll_date, c.poll_interval
+FROM device_codes_copy c LEFT JOIN users ON users.email_address = c.email_address;
+
+-- roles are defined globally but assigned per user, so an admin is only an admin of their tenant
+CREATE TABLE user_roles (
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role_name VARCHAR(64) NOT NULL REFERENCES roles(name) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, role_name)
+);
+INSERT INTO user_roles (user_id, role_name)
+SELECT users.id, c.role_name FROM user_roles_copy c JOIN users ON users.email_address = c.email_address;
+
+DROP TABLE users_copy;
+DROP TABLE sessions_copy;
+DROP TABLE oauth_authorization_codes_copy;
+DROP TABLE oauth_refresh_tokens_copy;
+DROP TABLE api_keys_copy;
+DROP TABLE device_codes_copy;
+DROP TABLE user_roles_copy;
+DROP TABLE email_codes_copy;
+
+ALTER TABLE audit_log ADD COLUMN tenant_id VARCHAR(64) NOT NULL DEFAULT 'default';
+
+INSERT INTO permissions (name, description) VALUES
+    ('tenants:read', 'list tenants and their settings'),
+    ('tenants:write', 'create tenants and change their settings');
+INSERT INTO role_permissions (role_name, permission_name) VALUES
+    ('admin', 'tenants:read'),
+    ('admin', 'tenants:write');
@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS invitations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tenant_id VARCHAR(64) NOT NULL REFERENCES tenants(id),
+    email_address VARCHAR(64) NOT NULL,
+    -- given to the user on acceptance, NULL to just add the user
+    role_name VARCHAR(64) REFERENCES roles(name) ON DELETE SET NULL,
+    invited_by VARCHAR(64) NOT NULL,
+    -- `jti` of the newest token, resending replaces it so older emails stop working
+    token_id VARCHAR(64) NOT NULL,
+    -- pending, accepted or revoked
+    status VARCHAR(16) NOT NULL,
+    created_date VARCHAR(32) NOT NULL,
+    expire_date VARCHAR(32) NOT NULL,
+    accepted_date VARCHAR(32)
+);
+-- an address has at most one open invitation per tenant
+CREATE UNIQUE INDEX IF NOT EXISTS invitations_pending ON invitations (tenant_id, email_address) WHERE status = 'pending';
+
+INSERT INTO permissions (name, description) VALUES
+    ('invitations:read', 'list invitations'),
+    ('invitations:write', 'invite users, resend and revoke invitations');
+INSERT INTO role_permissions (role_name, permission_name) VALUES
+    ('admin', 'invitations:read'),
+    ('admin', 'invitations:write');
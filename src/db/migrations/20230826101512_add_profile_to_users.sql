@@ -0,0 +1,7 @@
+-- optional profile fields the user manages through `/me`, NULL when not set
+ALTER TABLE users ADD COLUMN display_name VARCHAR(64);
+-- a bcp 47 language tag like `en-US`
+ALTER TABLE users ADD COLUMN locale VARCHAR(35);
+-- an iana time zone name like `Europe/Berlin`
+ALTER TABLE users ADD COLUMN time_zone VARCHAR(64);
+ALTER TABLE users ADD COLUMN avatar_url VARCHAR(2048);
@@ -0,0 +1,20 @@
+-- a requested change of a user's email address, applied once the code sent to the new address
+-- is confirmed. the old address gets a link with the undo token, which cancels the change while
+-- it is pending and reverts it after it was confirmed
+CREATE TABLE IF NOT EXISTS email_changes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    old_email_address VARCHAR(64) NOT NULL,
+    new_email_address VARCHAR(64) NOT NULL,
+    code INTEGER NOT NULL,
+    -- wrong codes entered so far
+    attempts INTEGER NOT NULL DEFAULT 0,
+    undo_token_hash VARCHAR(64) NOT NULL UNIQUE,
+    -- pending, confirmed, cancelled or undone
+    status VARCHAR(16) NOT NULL,
+    created_date VARCHAR(32) NOT NULL,
+    code_expire_date VARCHAR(32) NOT NULL,
+    undo_expire_date VARCHAR(32) NOT NULL,
+    confirmed_date VARCHAR(32)
+);
+CREATE INDEX IF NOT EXISTS email_changes_user_id ON email_changes (user_id);
@@ -0,0 +1,8 @@
+-- accounts their users asked to delete, the account is locked until `delete_date` and then
+-- purged by the background job. deleting the row, through the emailed link, cancels it
+CREATE TABLE IF NOT EXISTS account_deletions (
+    user_id INTEGER PRIMARY KEY NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    cancel_token_hash VARCHAR(64) NOT NULL UNIQUE,
+    requested_date VARCHAR(32) NOT NULL,
+    delete_date VARCHAR(32) NOT NULL
+);
@@ -0,0 +1,12 @@
+-- pending, active, suspended, locked or deleted, only active accounts can log in or use their
+-- credentials. `status_reason` and `status_date` describe the last transition
+ALTER TABLE users ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'active';
+ALTER TABLE users ADD COLUMN status_reason TEXT;
+ALTER TABLE users ADD COLUMN status_date VARCHAR(32);
+
+-- `disabled` is replaced by the suspended status
+UPDATE users SET status='suspended', status_date=strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now') WHERE disabled;
+UPDATE users SET status='deleted',
+    status_date=(SELECT requested_date FROM account_deletions WHERE user_id = users.id)
+WHERE id IN (SELECT user_id FROM account_deletions);
+ALTER TABLE users DROP COLUMN disabled;
@@ -0,0 +1,6 @@
+-- the rest of the password policy, lengths are counted in unicode characters
+ALTER TABLE tenants ADD COLUMN max_password_length INTEGER NOT NULL DEFAULT 64;
+-- space separated classes every password needs: lowercase, uppercase, digit or symbol
+ALTER TABLE tenants ADD COLUMN password_character_classes TEXT NOT NULL DEFAULT '';
+-- estimated strength from 0 (trivial) to 4 (very strong) a password needs at least
+ALTER TABLE tenants ADD COLUMN min_password_score INTEGER NOT NULL DEFAULT 2;
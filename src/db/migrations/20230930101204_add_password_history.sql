@@ -0,0 +1,21 @@
+-- hashes of the passwords users had before, so they can't go back to one of them. only the
+-- newest `MAX_PASSWORD_HISTORY` of each user are kept
+CREATE TABLE IF NOT EXISTS password_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    password VARCHAR(64) NOT NULL,
+    created_date VARCHAR(32) NOT NULL
+);
+CREATE INDEX IF NOT EXISTS password_history_user_id ON password_history (user_id);
+
+-- existing passwords count as changed now, so no one is locked out by the migration
+ALTER TABLE users ADD COLUMN password_changed_at VARCHAR(32);
+UPDATE users SET password_changed_at=strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now');
+
+-- sessions handed out for an expired password, they can only change the password
+ALTER TABLE sessions ADD COLUMN password_change_only BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- how many of the last passwords, the current one included, can't be reused. 0 turns it off
+ALTER TABLE tenants ADD COLUMN password_history_size INTEGER NOT NULL DEFAULT 0;
+-- days after which passwords have to be changed, 0 means they never expire
+ALTER TABLE tenants ADD COLUMN password_max_age_days INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,17 @@
+-- the form two addresses of the same mailbox share, see `EmailAddress::canonical`.
+-- `email_address` keeps the address as the user typed it
+ALTER TABLE users ADD COLUMN canonical_email_address VARCHAR(255);
+
+-- existing addresses only get their case folded. when that makes two of them the same, the
+-- oldest account keeps the address and the others are left without a canonical form, they can
+-- still log in with the exact address they registered with
+UPDATE users SET canonical_email_address=lower(email_address)
+WHERE id=(SELECT min(id) FROM users AS other
+    WHERE other.tenant_id=users.tenant_id AND lower(other.email_address)=lower(users.email_address));
+CREATE UNIQUE INDEX IF NOT EXISTS users_canonical_email_address
+ON users (tenant_id, canonical_email_address);
+
+-- case of the local part is ignored unless turned off, the dots and `+tags` of well known
+-- providers only if turned on
+ALTER TABLE tenants ADD COLUMN lowercase_email_local_part BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE tenants ADD COLUMN canonicalize_email_providers BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,5 @@
+-- space separated domains the tenant refuses, `*.example.com` stands for the subdomains of
+-- example.com. `allowed_email_domains` takes the same patterns
+ALTER TABLE tenants ADD COLUMN denied_email_domains TEXT NOT NULL DEFAULT '';
+-- refuse the throwaway domains of the server wide list, unless they're allowed explicitly
+ALTER TABLE tenants ADD COLUMN block_disposable_email_domains BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,11 @@
+-- a code only works for what it was sent for, so an address can have one code per purpose.
+-- the old codes can't be told apart, they live an hour at most and can be sent again
+DROP TABLE email_codes;
+CREATE TABLE email_codes (
+    tenant_id VARCHAR(64) NOT NULL REFERENCES tenants(id),
+    email_address VARCHAR(64) NOT NULL,
+    purpose VARCHAR(32) NOT NULL,
+    last_sent_code UNSIGNED INT NOT NULL,
+    last_sent_date VARCHAR(32) NOT NULL,
+    PRIMARY KEY (tenant_id, email_address, purpose)
+);
@@ -0,0 +1,8 @@
+-- the accounts that had the actor and target addresses when the event was recorded, so events
+-- stay with their account when the address changes or is registered again after a purge.
+-- older events can't be told apart from the ones of an earlier owner of the address and stay
+-- NULL. user ids are never reused, so these don't reference users and outlive purges
+ALTER TABLE audit_log ADD COLUMN actor_user_id INTEGER;
+ALTER TABLE audit_log ADD COLUMN target_user_id INTEGER;
+CREATE INDEX IF NOT EXISTS audit_log_actor_user_id ON audit_log(actor_user_id);
+CREATE INDEX IF NOT EXISTS audit_log_target_user_id ON audit_log(target_user_id);
@@ -0,0 +1,308 @@
+use super::{parse_date, DbPool};
+use crate::error::{ApiError, ApiResult};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, PartialEq)]
+pub struct OAuthClient {
+    pub client_id: String,
+    /// `None` for public clients
+    pub client_secret_hash: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    pub owner_email_address: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AuthorizationCode {
+    pub client_id: String,
+    pub user_id: i64,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub code_challenge: String,
+    pub nonce: Option<String>,
+    pub expire_date: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RefreshToken {
+    pub client_id: String,
+    pub user_id: i64,
+    pub scope: String,
+    pub expire_date: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_oauth_client(pool: &DbPool, client: &OAuthClient) -> ApiResult<()> {
+    let now_date = Utc::now().to_rfc3339();
+    let redirect_uris = client.redirect_uris.join("\n");
+    let scopes = client.scopes.join(" ");
+    sqlx::query!(
+        "INSERT INTO oauth_clients (client_id, client_secret_hash, name, redirect_uris, scopes, owner_email_address, created_date)
+        VALUES (?, ?, ?, ?, ?, ?, ?)",
+        client.client_id,
+        client.client_secret_hash,
+        client.name,
+        redirect_uris,
+        scopes,
+        client.owner_email_address,
+        now_date
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_oauth_client(pool: &DbPool, client_id: &str) -> ApiResult<Option<OAuthClient>> {
+    let record = sqlx::query!(
+        "SELECT client_id, client_secret_hash, name, redirect_uris, scopes, owner_email_address
+        FROM oauth_clients WHERE client_id=? LIMIT 1",
+        client_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| OAuthClient {
+        client_id: r.client_id,
+        client_secret_hash: r.client_secret_hash,
+        name: r.name,
+        redirect_uris: r.redirect_uris.lines().map(str::to_string).collect(),
+        scopes: r.scopes.split_whitespace().map(str::to_string).collect(),
+        owner_email_address: r.owner_email_address,
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_authorization_code(
+    pool: &DbPool,
+    code_hash: &str,
+    code: &AuthorizationCode,
+) -> ApiResult<()> {
+    let expire_date = code.expire_date.to_rfc3339();
+    sqlx::query!(
+        "INSERT INTO oauth_authorization_codes (code_hash, client_id, user_id, redirect_uri, scope, code_challenge, nonce, expire_date)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        code_hash,
+        code.client_id,
+        code.user_id,
+        code.redirect_uri,
+        code.scope,
+        code.code_challenge,
+        code.nonce,
+        expire_date
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+/// authorization codes are single use, so the code gets deleted when it's taken
+#[tracing::instrument(skip_all)]
+pub async fn take_authorization_code(
+    pool: &DbPool,
+    code_hash: &str,
+) -> ApiResult<Option<AuthorizationCode>> {
+    let record = sqlx::query!(
+        "SELECT client_id, user_id, redirect_uri, scope, code_challenge, nonce, expire_date
+        FROM oauth_authorization_codes WHERE code_hash=? LIMIT 1",
+        code_hash
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    let Some(record) = record else {
+        return Ok(None);
+    };
+
+    let result = sqlx::query!(
+        "DELETE FROM oauth_authorization_codes WHERE code_hash=?",
+        code_hash
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    // someone else took it between our select and delete
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(AuthorizationCode {
+        client_id: record.client_id,
+        user_id: record.user_id,
+        redirect_uri: record.redirect_uri,
+        scope: record.scope,
+        code_challenge: record.code_challenge,
+        nonce: record.nonce,
+        expire_date: parse_date(&record.expire_date),
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_refresh_token(
+    pool: &DbPool,
+    token_hash: &str,
+    token: &RefreshToken,
+) -> ApiResult<()> {
+    let expire_date = token.expire_date.to_rfc3339();
+    sqlx::query!(
+        "INSERT INTO oauth_refresh_tokens (token_hash, client_id, user_id, scope, expire_date)
+        VALUES (?, ?, ?, ?, ?)",
+        token_hash,
+        token.client_id,
+        token.user_id,
+        token.scope,
+        expire_date
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+/// refresh tokens are rotated on every use, so the token gets deleted when it's taken
+#[tracing::instrument(skip_all)]
+pub async fn take_refresh_token(
+    pool: &DbPool,
+    token_hash: &str,
+) -> ApiResult<Option<RefreshToken>> {
+    let record = sqlx::query!(
+        "SELECT client_id, user_id, scope, expire_date FROM oauth_refresh_tokens WHERE token_hash=? LIMIT 1",
+        token_hash
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    let Some(record) = record else {
+        return Ok(None);
+    };
+
+    let result = sqlx::query!(
+        "DELETE FROM oauth_refresh_tokens WHERE token_hash=?",
+        token_hash
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(RefreshToken {
+        client_id: record.client_id,
+        user_id: record.user_id,
+        scope: record.scope,
+        expire_date: parse_date(&record.expire_date),
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_refresh_tokens_of_user(pool: &DbPool, user_id: i64) -> ApiResult<()> {
+    sqlx::query!("DELETE FROM oauth_refresh_tokens WHERE user_id=?", user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{tenants::DEFAULT_TENANT_ID, user::insert_user},
+        test::helper::create_test_db,
+    };
+    use chrono::Duration;
+
+    fn test_client() -> OAuthClient {
+        OAuthClient {
+            client_id: "spa".to_string(),
+            client_secret_hash: None,
+            name: "Single page app".to_string(),
+            redirect_uris: vec![
+                "https://app.example.com/callback".to_string(),
+                "http://localhost:3000/callback".to_string(),
+            ],
+            scopes: vec!["openid".to_string(), "profile".to_string()],
+            owner_email_address: "arian@gmail.com".to_string(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn insert_and_get_oauth_client() {
+        let db = create_test_db().await;
+        assert!(get_oauth_client(&db, "spa").await.unwrap().is_none());
+
+        let client = test_client();
+        insert_oauth_client(&db, &client).await.unwrap();
+        assert_eq!(get_oauth_client(&db, "spa").await.unwrap(), Some(client));
+    }
+
+    #[actix_web::test]
+    async fn authorization_code_can_only_be_taken_once() {
+        let db = create_test_db().await;
+        insert_oauth_client(&db, &test_client()).await.unwrap();
+        let user_id = insert_user(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian",
+            "password",
+            "arian@gmail.com",
+        )
+        .await
+        .unwrap();
+
+        let code = AuthorizationCode {
+            client_id: "spa".to_string(),
+            user_id,
+            redirect_uri: "https://app.example.com/callback".to_string(),
+            scope: "openid".to_string(),
+            code_challenge: "challenge".to_string(),
+            nonce: Some("nonce".to_string()),
+            expire_date: parse_date(&(Utc::now() + Duration::minutes(1)).to_rfc3339()),
+        };
+        insert_authorization_code(&db, "hash", &code).await.unwrap();
+
+        assert_eq!(
+            take_authorization_code(&db, "hash").await.unwrap(),
+            Some(code)
+        );
+        assert!(take_authorization_code(&db, "hash")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[actix_web::test]
+    async fn refresh_token_can_only_be_taken_once() {
+        let db = create_test_db().await;
+        insert_oauth_client(&db, &test_client()).await.unwrap();
+        let user_id = insert_user(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian",
+            "password",
+            "arian@gmail.com",
+        )
+        .await
+        .unwrap();
+
+        let token = RefreshToken {
+            client_id: "spa".to_string(),
+            user_id,
+            scope: "openid".to_string(),
+            expire_date: parse_date(&(Utc::now() + Duration::days(1)).to_rfc3339()),
+        };
+        insert_refresh_token(&db, "hash", &token).await.unwrap();
+
+        assert_eq!(take_refresh_token(&db, "hash").await.unwrap(), Some(token));
+        assert!(take_refresh_token(&db, "hash").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,212 @@
+use super::DbPool;
+use crate::error::{ApiError, ApiResult};
+
+#[derive(Debug, PartialEq)]
+pub struct Role {
+    pub name: String,
+    pub description: String,
+    pub permissions: Vec<String>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_role(pool: &DbPool, name: &str, description: &str) -> ApiResult<()> {
+    let result = sqlx::query!(
+        "INSERT OR IGNORE INTO roles (name, description) VALUES (?, ?)",
+        name,
+        description
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    if result.rows_affected() == 0 {
+        Err(ApiError::BadArgument {
+            argument_name: "name",
+        })
+    } else {
+        Ok(())
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_roles(pool: &DbPool) -> ApiResult<Vec<Role>> {
+    let roles = sqlx::query!("SELECT name, description FROM roles ORDER BY name")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    let grants = sqlx::query!(
+        "SELECT role_name, permission_name FROM role_permissions ORDER BY permission_name"
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(roles
+        .into_iter()
+        .map(|role| Role {
+            permissions: grants
+                .iter()
+                .filter(|grant| grant.role_name == role.name)
+                .map(|grant| grant.permission_name.clone())
+                .collect(),
+            name: role.name,
+            description: role.description,
+        })
+        .collect())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn does_role_exist(pool: &DbPool, name: &str) -> ApiResult<bool> {
+    let result = sqlx::query!("SELECT name FROM roles WHERE name=? LIMIT 1", name)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.is_some())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn does_permission_exist(pool: &DbPool, name: &str) -> ApiResult<bool> {
+    let result = sqlx::query!("SELECT name FROM permissions WHERE name=? LIMIT 1", name)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.is_some())
+}
+
+/// returns false if the role already had the permission
+#[tracing::instrument(skip_all)]
+pub async fn grant_permission(pool: &DbPool, role_name: &str, permission: &str) -> ApiResult<bool> {
+    let result = sqlx::query!(
+        "INSERT OR IGNORE INTO role_permissions (role_name, permission_name) VALUES (?, ?)",
+        role_name,
+        permission
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// returns false if the role didn't have the permission
+#[tracing::instrument(skip_all)]
+pub async fn revoke_permission(
+    pool: &DbPool,
+    role_name: &str,
+    permission: &str,
+) -> ApiResult<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM role_permissions WHERE role_name=? AND permission_name=?",
+        role_name,
+        permission
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// returns false if the user already had the role
+#[tracing::instrument(skip_all)]
+pub async fn assign_role(pool: &DbPool, user_id: i64, role_name: &str) -> ApiResult<bool> {
+    let result = sqlx::query!(
+        "INSERT OR IGNORE INTO user_roles (user_id, role_name) VALUES (?, ?)",
+        user_id,
+        role_name
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// returns false if the user didn't have the role
+#[tracing::instrument(skip_all)]
+pub async fn unassign_role(pool: &DbPool, user_id: i64, role_name: &str) -> ApiResult<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM user_roles WHERE user_id=? AND role_name=?",
+        user_id,
+        role_name
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_user_roles(pool: &DbPool, user_id: i64) -> ApiResult<Vec<String>> {
+    let records = sqlx::query!(
+        "SELECT role_name FROM user_roles WHERE user_id=? ORDER BY role_name",
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(records.into_iter().map(|r| r.role_name).collect())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn has_permission(pool: &DbPool, user_id: i64, permission: &str) -> ApiResult<bool> {
+    let result = sqlx::query!(
+        "SELECT user_roles.role_name FROM user_roles
+        JOIN role_permissions ON role_permissions.role_name = user_roles.role_name
+        WHERE user_roles.user_id=? AND role_permissions.permission_name=? LIMIT 1",
+        user_id,
+        permission
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{tenants::DEFAULT_TENANT_ID, user::insert_user},
+        test::helper::create_test_db,
+    };
+
+    #[actix_web::test]
+    async fn admin_role_is_seeded() {
+        let db = create_test_db().await;
+        let roles = get_roles(&db).await.unwrap();
+        assert_eq!(roles.len(), 1);
+        assert_eq!(roles[0].name, "admin");
+        assert!(roles[0].permissions.contains(&"roles:write".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn permissions_come_from_roles() {
+        let db = create_test_db().await;
+        let id = insert_user(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian",
+            "password",
+            "arian@gmail.com",
+        )
+        .await
+        .unwrap();
+        insert_role(&db, "support", "helps users").await.unwrap();
+        assert!(insert_role(&db, "support", "again").await.is_err());
+        assert!(grant_permission(&db, "support", "roles:read")
+            .await
+            .unwrap());
+
+        assert!(!has_permission(&db, id, "roles:read").await.unwrap());
+        assert!(assign_role(&db, id, "support").await.unwrap());
+        assert!(!assign_role(&db, id, "support").await.unwrap());
+        assert!(has_permission(&db, id, "roles:read").await.unwrap());
+        assert!(!has_permission(&db, id, "roles:write").await.unwrap());
+        assert_eq!(get_user_roles(&db, id).await.unwrap(), vec!["support"]);
+
+        assert!(revoke_permission(&db, "support", "roles:read")
+            .await
+            .unwrap());
+        assert!(!has_permission(&db, id, "roles:read").await.unwrap());
+        assert!(unassign_role(&db, id, "support").await.unwrap());
+        assert!(get_user_roles(&db, id).await.unwrap().is_empty());
+    }
+}
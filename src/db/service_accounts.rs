@@ -0,0 +1,260 @@
+use super::{parse_date, DbPool};
+use crate::error::{ApiError, ApiResult};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, PartialEq)]
+pub struct ServiceAccount {
+    pub client_id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub owner_email_address: String,
+    pub last_used_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ServiceAccountSecret {
+    pub id: i64,
+    pub created_date: DateTime<Utc>,
+    pub last_used_date: Option<DateTime<Utc>>,
+}
+
+/// how many secrets an account can have at once, one in use and one being rotated in
+pub const MAX_ACTIVE_SECRETS: usize = 2;
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_service_account(pool: &DbPool, account: &ServiceAccount) -> ApiResult<()> {
+    let now_date = Utc::now().to_rfc3339();
+    let scopes = account.scopes.join(" ");
+    sqlx::query!(
+        "INSERT INTO service_accounts (client_id, name, scopes, owner_email_address, created_date) VALUES (?, ?, ?, ?, ?)",
+        account.client_id,
+        account.name,
+        scopes,
+        account.owner_email_address,
+        now_date
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_service_account(
+    pool: &DbPool,
+    client_id: &str,
+) -> ApiResult<Option<ServiceAccount>> {
+    let record = sqlx::query!(
+        "SELECT client_id, name, scopes, owner_email_address, last_used_date FROM service_accounts WHERE client_id=? LIMIT 1",
+        client_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| ServiceAccount {
+        client_id: r.client_id,
+        name: r.name,
+        scopes: r.scopes.split_whitespace().map(str::to_string).collect(),
+        owner_email_address: r.owner_email_address,
+        last_used_date: r.last_used_date.as_deref().map(parse_date),
+    }))
+}
+
+/// returns the secrets of an account, newest first
+#[tracing::instrument(skip_all)]
+pub async fn get_service_account_secrets(
+    pool: &DbPool,
+    client_id: &str,
+) -> ApiResult<Vec<ServiceAccountSecret>> {
+    let records = sqlx::query!(
+        "SELECT id, created_date, last_used_date FROM service_account_secrets WHERE client_id=? ORDER BY id DESC",
+        client_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| ServiceAccountSecret {
+            id: r.id,
+            created_date: parse_date(&r.created_date),
+            last_used_date: r.last_used_date.as_deref().map(parse_date),
+        })
+        .collect())
+}
+
+/// adds a new secret and deletes the oldest ones so that at most `MAX_ACTIVE_SECRETS` remain,
+/// returns the id of the new secret
+#[tracing::instrument(skip_all)]
+pub async fn rotate_service_account_secret(
+    pool: &DbPool,
+    client_id: &str,
+    secret_hash: &str,
+) -> ApiResult<i64> {
+    let now_date = Utc::now().to_rfc3339();
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    let id = sqlx::query!(
+        "INSERT INTO service_account_secrets (client_id, secret_hash, created_date) VALUES (?, ?, ?)",
+        client_id,
+        secret_hash,
+        now_date
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?
+    .last_insert_rowid();
+
+    let keep = MAX_ACTIVE_SECRETS as i64;
+    sqlx::query!(
+        "DELETE FROM service_account_secrets WHERE client_id=? AND id NOT IN
+        (SELECT id FROM service_account_secrets WHERE client_id=? ORDER BY id DESC LIMIT ?)",
+        client_id,
+        client_id,
+        keep
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(id)
+}
+
+/// returns whether a secret was deleted
+#[tracing::instrument(skip_all)]
+pub async fn delete_service_account_secret(
+    pool: &DbPool,
+    client_id: &str,
+    id: i64,
+) -> ApiResult<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM service_account_secrets WHERE client_id=? AND id=?",
+        client_id,
+        id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// checks the secret and records that the account and the secret were used
+#[tracing::instrument(skip_all)]
+pub async fn verify_service_account_secret(
+    pool: &DbPool,
+    client_id: &str,
+    secret_hash: &str,
+) -> ApiResult<bool> {
+    let now_date = Utc::now().to_rfc3339();
+    let result = sqlx::query!(
+        "UPDATE service_account_secrets SET last_used_date=? WHERE client_id=? AND secret_hash=?",
+        now_date,
+        client_id,
+        secret_hash
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        "UPDATE service_accounts SET last_used_date=? WHERE client_id=?",
+        now_date,
+        client_id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::helper::create_test_db;
+
+    async fn insert_test_account(pool: &DbPool) {
+        let account = ServiceAccount {
+            client_id: "billing_job".to_string(),
+            name: "billing job".to_string(),
+            scopes: vec!["invoices:read".to_string()],
+            owner_email_address: "arian@gmail.com".to_string(),
+            last_used_date: None,
+        };
+        insert_service_account(pool, &account).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn only_two_newest_secrets_are_kept() {
+        let db = create_test_db().await;
+        insert_test_account(&db).await;
+
+        let first = rotate_service_account_secret(&db, "billing_job", "first")
+            .await
+            .unwrap();
+        let second = rotate_service_account_secret(&db, "billing_job", "second")
+            .await
+            .unwrap();
+        let third = rotate_service_account_secret(&db, "billing_job", "third")
+            .await
+            .unwrap();
+
+        let ids: Vec<i64> = get_service_account_secrets(&db, "billing_job")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|secret| secret.id)
+            .collect();
+        assert_eq!(ids, vec![third, second]);
+        assert!(!verify_service_account_secret(&db, "billing_job", "first")
+            .await
+            .unwrap());
+        assert!(!delete_service_account_secret(&db, "billing_job", first)
+            .await
+            .unwrap());
+    }
+
+    #[actix_web::test]
+    async fn verify_secret_tracks_last_use() {
+        let db = create_test_db().await;
+        insert_test_account(&db).await;
+        rotate_service_account_secret(&db, "billing_job", "secret")
+            .await
+            .unwrap();
+
+        let account = get_service_account(&db, "billing_job")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(account.last_used_date.is_none());
+
+        assert!(!verify_service_account_secret(&db, "billing_job", "wrong")
+            .await
+            .unwrap());
+        assert!(verify_service_account_secret(&db, "billing_job", "secret")
+            .await
+            .unwrap());
+
+        let account = get_service_account(&db, "billing_job")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(account.last_used_date.is_some());
+        let secrets = get_service_account_secrets(&db, "billing_job")
+            .await
+            .unwrap();
+        assert!(secrets[0].last_used_date.is_some());
+    }
+}
@@ -0,0 +1,152 @@
+use super::{parse_date, DbPool};
+use crate::error::{ApiError, ApiResult};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, PartialEq)]
+pub struct Session {
+    pub user_id: i64,
+    pub expire_date: DateTime<Utc>,
+    /// handed out at login when the password expired, it can only change the password
+    pub password_change_only: bool,
+}
+
+/// what a user sees about one of their sessions, the token itself is only known to its holder
+#[derive(Debug, PartialEq)]
+pub struct SessionSummary {
+    pub created_date: DateTime<Utc>,
+    pub expire_date: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_session(
+    pool: &DbPool,
+    token_hash: &str,
+    user_id: i64,
+    expire_date: DateTime<Utc>,
+) -> ApiResult<()> {
+    insert_session_with_access(pool, token_hash, user_id, expire_date, false).await
+}
+
+/// a session for a user whose password expired, it's only good for changing the password
+#[tracing::instrument(skip_all)]
+pub async fn insert_password_change_session(
+    pool: &DbPool,
+    token_hash: &str,
+    user_id: i64,
+    expire_date: DateTime<Utc>,
+) -> ApiResult<()> {
+    insert_session_with_access(pool, token_hash, user_id, expire_date, true).await
+}
+
+async fn insert_session_with_access(
+    pool: &DbPool,
+    token_hash: &str,
+    user_id: i64,
+    expire_date: DateTime<Utc>,
+    password_change_only: bool,
+) -> ApiResult<()> {
+    let now_date = Utc::now().to_rfc3339();
+    let expire_date = expire_date.to_rfc3339();
+    sqlx::query!(
+        "INSERT INTO sessions (token_hash, user_id, created_date, expire_date, password_change_only)
+        VALUES (?, ?, ?, ?, ?)",
+        token_hash,
+        user_id,
+        now_date,
+        expire_date,
+        password_change_only
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+/// sessions of accounts that aren't active aren't returned
+#[tracing::instrument(skip_all)]
+pub async fn get_session(pool: &DbPool, token_hash: &str) -> ApiResult<Option<Session>> {
+    let record = sqlx::query!(
+        "SELECT user_id, expire_date, password_change_only FROM sessions WHERE token_hash=?
+        AND user_id IN (SELECT id FROM users WHERE status='active') LIMIT 1",
+        token_hash
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| Session {
+        user_id: r.user_id,
+        expire_date: parse_date(&r.expire_date),
+        password_change_only: r.password_change_only,
+    }))
+}
+
+/// newest first
+#[tracing::instrument(skip_all)]
+pub async fn get_sessions_of_user(pool: &DbPool, user_id: i64) -> ApiResult<Vec<SessionSummary>> {
+    let records = sqlx::query!(
+        "SELECT created_date, expire_date FROM sessions WHERE user_id=?
+        ORDER BY julianday(created_date) DESC",
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| SessionSummary {
+            created_date: parse_date(&r.created_date),
+            expire_date: parse_date(&r.expire_date),
+        })
+        .collect())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_sessions_of_user(pool: &DbPool, user_id: i64) -> ApiResult<()> {
+    sqlx::query!("DELETE FROM sessions WHERE user_id=?", user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{tenants::DEFAULT_TENANT_ID, user::insert_user},
+        test::helper::create_test_db,
+    };
+    use chrono::Duration;
+
+    #[actix_web::test]
+    async fn insert_and_get_session() {
+        let db = create_test_db().await;
+        let user_id = insert_user(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian",
+            "password",
+            "arian@gmail.com",
+        )
+        .await
+        .unwrap();
+        assert!(get_session(&db, "hash").await.unwrap().is_none());
+
+        let expire_date = Utc::now() + Duration::days(1);
+        insert_session(&db, "hash", user_id, expire_date)
+            .await
+            .unwrap();
+        let session = get_session(&db, "hash").await.unwrap().unwrap();
+        assert_eq!(session.user_id, user_id);
+        assert_eq!(session.expire_date.timestamp(), expire_date.timestamp());
+        assert!(!session.password_change_only);
+
+        insert_password_change_session(&db, "restricted", user_id, expire_date)
+            .await
+            .unwrap();
+        let session = get_session(&db, "restricted").await.unwrap().unwrap();
+        assert!(session.password_change_only);
+    }
+}
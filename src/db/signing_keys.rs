@@ -0,0 +1,87 @@
+use super::{parse_date, DbPool};
+use crate::error::{ApiError, ApiResult};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SigningKey {
+    pub kid: String,
+    /// pkcs8 encoded P-256 private key
+    pub private_key: Vec<u8>,
+    pub created_date: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_signing_key(pool: &DbPool, key: &SigningKey) -> ApiResult<()> {
+    let created_date = key.created_date.to_rfc3339();
+    sqlx::query!(
+        "INSERT INTO signing_keys (kid, private_key, created_date) VALUES (?, ?, ?)",
+        key.kid,
+        key.private_key,
+        created_date
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+/// returns every stored key, newest first
+#[tracing::instrument(skip_all)]
+pub async fn get_signing_keys(pool: &DbPool) -> ApiResult<Vec<SigningKey>> {
+    let records = sqlx::query!("SELECT kid, private_key, created_date FROM signing_keys")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    let mut keys: Vec<SigningKey> = records
+        .into_iter()
+        .map(|r| SigningKey {
+            kid: r.kid,
+            private_key: r.private_key,
+            created_date: parse_date(&r.created_date),
+        })
+        .collect();
+    keys.sort_by_key(|key| std::cmp::Reverse(key.created_date));
+    Ok(keys)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_signing_key(pool: &DbPool, kid: &str) -> ApiResult<()> {
+    sqlx::query!("DELETE FROM signing_keys WHERE kid=?", kid)
+        .execute(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::helper::create_test_db;
+    use chrono::Duration;
+
+    #[actix_web::test]
+    async fn signing_keys_are_sorted_newest_first() {
+        let db = create_test_db().await;
+        let now_date = parse_date(&Utc::now().to_rfc3339());
+        let old_key = SigningKey {
+            kid: "old".to_string(),
+            private_key: vec![1, 2, 3],
+            created_date: now_date - Duration::days(10),
+        };
+        let new_key = SigningKey {
+            kid: "new".to_string(),
+            private_key: vec![4, 5, 6],
+            created_date: now_date,
+        };
+        insert_signing_key(&db, &old_key).await.unwrap();
+        insert_signing_key(&db, &new_key).await.unwrap();
+        assert_eq!(
+            get_signing_keys(&db).await.unwrap(),
+            vec![new_key, old_key.clone()]
+        );
+
+        delete_signing_key(&db, "new").await.unwrap();
+        assert_eq!(get_signing_keys(&db).await.unwrap(), vec![old_key]);
+    }
+}
@@ -0,0 +1,81 @@
+use super::DbPool;
+use crate::error::{ApiError, ApiResult};
+use chrono::Utc;
+
+#[derive(Debug, PartialEq)]
+pub struct DbStats {
+    pub users: i64,
+    pub suspended_users: i64,
+    pub active_sessions: i64,
+    pub api_keys: i64,
+    pub oauth_clients: i64,
+    pub service_accounts: i64,
+    pub email_codes: i64,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_db_stats(pool: &DbPool) -> ApiResult<DbStats> {
+    let now_date = Utc::now().to_rfc3339();
+    let record = sqlx::query!(
+        r#"SELECT
+            (SELECT COUNT(*) FROM users) as "users!: i64",
+            (SELECT COUNT(*) FROM users WHERE status='suspended') as "suspended_users!: i64",
+            (SELECT COUNT(*) FROM sessions WHERE julianday(expire_date) > julianday(?)) as "active_sessions!: i64",
+            (SELECT COUNT(*) FROM api_keys) as "api_keys!: i64",
+            (SELECT COUNT(*) FROM oauth_clients) as "oauth_clients!: i64",
+            (SELECT COUNT(*) FROM service_accounts) as "service_accounts!: i64",
+            (SELECT COUNT(*) FROM email_codes) as "email_codes!: i64""#,
+        now_date
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(DbStats {
+        users: record.users,
+        suspended_users: record.suspended_users,
+        active_sessions: record.active_sessions,
+        api_keys: record.api_keys,
+        oauth_clients: record.oauth_clients,
+        service_accounts: record.service_accounts,
+        email_codes: record.email_codes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{
+            sessions::insert_session,
+            user::{set_user_status, AccountStatus},
+        },
+        test::helper::{create_test_db, create_test_user_with_session, test_user_id},
+    };
+    use chrono::Duration;
+
+    #[actix_web::test]
+    async fn db_stats_should_work() {
+        let db = create_test_db().await;
+        create_test_user_with_session(&db, "arian@gmail.com").await;
+        create_test_user_with_session(&db, "pouya@gmail.com").await;
+        let pouya_id = test_user_id(&db, "pouya@gmail.com").await;
+        set_user_status(&db, pouya_id, AccountStatus::Suspended, None)
+            .await
+            .unwrap();
+        insert_session(
+            &db,
+            "expired",
+            test_user_id(&db, "arian@gmail.com").await,
+            Utc::now() - Duration::days(1),
+        )
+        .await
+        .unwrap();
+
+        let stats = get_db_stats(&db).await.unwrap();
+        assert_eq!(stats.users, 2);
+        assert_eq!(stats.suspended_users, 1);
+        assert_eq!(stats.active_sessions, 2);
+        assert_eq!(stats.api_keys, 0);
+    }
+}
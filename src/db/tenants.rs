@@ -0,0 +1,258 @@
+use super::{parse_date, DbPool};
+use crate::{
+    error::{ApiError, ApiResult},
+    utils::password_policy::CharacterClass,
+};
+use chrono::{DateTime, Utc};
+
+/// tenant of everything that existed before tenants, and of requests that don't name one
+pub const DEFAULT_TENANT_ID: &str = "default";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TenantSettings {
+    /// empty means every domain is allowed, `*.example.com` allows the subdomains of example.com
+    pub allowed_email_domains: Vec<String>,
+    /// refused even if they're allowed, takes the same patterns
+    pub denied_email_domains: Vec<String>,
+    /// refuse the domains of the server wide disposable list that aren't allowed explicitly
+    pub block_disposable_email_domains: bool,
+    /// `Arian@example.com` and `arian@example.com` are the same account
+    pub lowercase_email_local_part: bool,
+    /// dots and `+tags` are ignored for the providers known to ignore them
+    pub canonicalize_email_providers: bool,
+    pub min_password_length: i64,
+    pub max_password_length: i64,
+    /// classes every password needs at least one character of
+    pub password_character_classes: Vec<CharacterClass>,
+    /// estimated strength from 0 to 4 a password needs at least
+    pub min_password_score: i64,
+    /// how many of the last passwords, the current one included, can't be reused
+    pub password_history_size: i64,
+    /// days after which passwords have to be changed, 0 means they never expire
+    pub password_max_age_days: i64,
+    pub mfa_required: bool,
+    pub email_code_subject: String,
+    /// `{code}` is replaced with the code
+    pub email_code_template: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tenant {
+    pub id: String,
+    pub name: String,
+    pub host: Option<String>,
+    pub settings: TenantSettings,
+    pub created_date: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_tenant(
+    pool: &DbPool,
+    id: &str,
+    name: &str,
+    host: Option<&str>,
+) -> ApiResult<()> {
+    let now_date = Utc::now().to_rfc3339();
+    let result = sqlx::query!(
+        "INSERT OR IGNORE INTO tenants (id, name, host, created_date) VALUES (?, ?, ?, ?)",
+        id,
+        name,
+        host,
+        now_date
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    if result.rows_affected() == 0 {
+        Err(ApiError::BadArgument {
+            argument_name: "id",
+        })
+    } else {
+        Ok(())
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_tenant(pool: &DbPool, id: &str) -> ApiResult<Option<Tenant>> {
+    let record = sqlx::query!(
+        "SELECT id, name, host, allowed_email_domains, denied_email_domains,
+        block_disposable_email_domains, lowercase_email_local_part,
+        canonicalize_email_providers, min_password_length, max_password_length,
+        password_character_classes, min_password_score, password_history_size,
+        password_max_age_days, mfa_required, email_code_subject, email_code_template, created_date
+        FROM tenants WHERE id=? LIMIT 1",
+        id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| Tenant {
+        id: r.id,
+        name: r.name,
+        host: r.host,
+        settings: TenantSettings {
+            allowed_email_domains: r
+                .allowed_email_domains
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            denied_email_domains: r
+                .denied_email_domains
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            block_disposable_email_domains: r.block_disposable_email_domains,
+            lowercase_email_local_part: r.lowercase_email_local_part,
+            canonicalize_email_providers: r.canonicalize_email_providers,
+            min_password_length: r.min_password_length,
+            max_password_length: r.max_password_length,
+            password_character_classes: r
+                .password_character_classes
+                .split_whitespace()
+                .filter_map(CharacterClass::parse)
+                .collect(),
+            min_password_score: r.min_password_score,
+            password_history_size: r.password_history_size,
+            password_max_age_days: r.password_max_age_days,
+            mfa_required: r.mfa_required,
+            email_code_subject: r.email_code_subject,
+            email_code_template: r.email_code_template,
+        },
+        created_date: parse_date(&r.created_date),
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_tenant_id_by_host(pool: &DbPool, host: &str) -> ApiResult<Option<String>> {
+    let record = sqlx::query!("SELECT id FROM tenants WHERE host=? LIMIT 1", host)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(record.map(|r| r.id))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_tenants(pool: &DbPool) -> ApiResult<Vec<Tenant>> {
+    let ids = sqlx::query!("SELECT id FROM tenants ORDER BY id")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    let mut tenants = Vec::with_capacity(ids.len());
+    for record in ids {
+        if let Some(tenant) = get_tenant(pool, &record.id).await? {
+            tenants.push(tenant);
+        }
+    }
+    Ok(tenants)
+}
+
+/// returns false if the tenant doesn't exist, fails if another tenant has the host
+#[tracing::instrument(skip_all)]
+pub async fn update_tenant(
+    pool: &DbPool,
+    id: &str,
+    name: &str,
+    host: Option<&str>,
+    settings: &TenantSettings,
+) -> ApiResult<bool> {
+    let allowed_email_domains = settings.allowed_email_domains.join(" ");
+    let denied_email_domains = settings.denied_email_domains.join(" ");
+    let password_character_classes = settings
+        .password_character_classes
+        .iter()
+        .map(CharacterClass::as_str)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let result = sqlx::query!(
+        "UPDATE tenants SET name=?, host=?, allowed_email_domains=?, denied_email_domains=?,
+        block_disposable_email_domains=?, lowercase_email_local_part=?,
+        canonicalize_email_providers=?, min_password_length=?,
+        max_password_length=?, password_character_classes=?, min_password_score=?,
+        password_history_size=?, password_max_age_days=?, mfa_required=?, email_code_subject=?,
+        email_code_template=? WHERE id=?",
+        name,
+        host,
+        allowed_email_domains,
+        denied_email_domains,
+        settings.block_disposable_email_domains,
+        settings.lowercase_email_local_part,
+        settings.canonicalize_email_providers,
+        settings.min_password_length,
+        settings.max_password_length,
+        password_character_classes,
+        settings.min_password_score,
+        settings.password_history_size,
+        settings.password_max_age_days,
+        settings.mfa_required,
+        settings.email_code_subject,
+        settings.email_code_template,
+        id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::helper::create_test_db;
+
+    #[actix_web::test]
+    async fn insert_update_and_get_tenants() {
+        let db = create_test_db().await;
+        let default = get_tenant(&db, DEFAULT_TENANT_ID).await.unwrap().unwrap();
+        assert_eq!(default.settings.min_password_length, 5);
+        assert_eq!(default.settings.min_password_score, 2);
+        assert!(default.settings.allowed_email_domains.is_empty());
+        assert!(default.settings.lowercase_email_local_part);
+        assert!(default.settings.block_disposable_email_domains);
+        assert!(!default.settings.canonicalize_email_providers);
+
+        insert_tenant(&db, "shop", "Shop", Some("shop.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(
+            insert_tenant(&db, "shop", "Shop again", None).await,
+            Err(ApiError::BadArgument {
+                argument_name: "id"
+            })
+        );
+        assert_eq!(
+            get_tenant_id_by_host(&db, "shop.example.com")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("shop")
+        );
+
+        let settings = TenantSettings {
+            allowed_email_domains: vec!["example.com".to_string()],
+            denied_email_domains: vec!["*.example.com".to_string()],
+            block_disposable_email_domains: false,
+            canonicalize_email_providers: true,
+            min_password_length: 12,
+            password_character_classes: vec![CharacterClass::Digit, CharacterClass::Symbol],
+            min_password_score: 3,
+            password_history_size: 5,
+            password_max_age_days: 90,
+            mfa_required: true,
+            ..default.settings
+        };
+        assert!(update_tenant(&db, "shop", "Shop", None, &settings)
+            .await
+            .unwrap());
+        assert!(!update_tenant(&db, "nope", "Nope", None, &settings)
+            .await
+            .unwrap());
+
+        let shop = get_tenant(&db, "shop").await.unwrap().unwrap();
+        assert_eq!(shop.settings, settings);
+        assert_eq!(shop.host, None);
+        assert_eq!(get_tenants(&db).await.unwrap().len(), 2);
+    }
+}
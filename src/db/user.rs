@@ -1,17 +1,139 @@
-use super::DbPool;
-use crate::error::{ApiError, ApiResult};
+use super::{
+    account_deletions::cancel_account_deletion, oauth::delete_refresh_tokens_of_user, parse_date,
+    sessions::delete_sessions_of_user, tenants::get_tenant, DbPool,
+};
+use crate::{
+    error::{ApiError, ApiResult},
+    utils::email::EmailAddress,
+};
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
 
+/// previous passwords kept per user, and so the most a tenant can forbid reusing
+pub const MAX_PASSWORD_HISTORY: usize = 24;
+
+/// only active accounts can log in or use their sessions, api keys and refresh tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccountStatus {
+    /// created but waiting for an admin to activate it
+    Pending,
+    Active,
+    /// blocked by an admin
+    Suspended,
+    /// blocked for security reasons, like a compromised password
+    Locked,
+    /// the user asked to delete the account, it's purged after the grace period
+    Deleted,
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Active => "active",
+            Self::Suspended => "suspended",
+            Self::Locked => "locked",
+            Self::Deleted => "deleted",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "pending" => Some(Self::Pending),
+            "active" => Some(Self::Active),
+            "suspended" => Some(Self::Suspended),
+            "locked" => Some(Self::Locked),
+            "deleted" => Some(Self::Deleted),
+            _ => None,
+        }
+    }
+
+    /// transitions admins may make, accounts only become deleted through self-service deletion
+    pub fn can_transition_to(&self, status: Self) -> bool {
+        matches!(
+            (self, status),
+            (Self::Pending, Self::Active | Self::Suspended)
+                | (Self::Active, Self::Suspended | Self::Locked)
+                | (Self::Suspended, Self::Active)
+                | (Self::Locked, Self::Active | Self::Suspended)
+                | (Self::Deleted, Self::Active)
+        )
+    }
+}
+
+/// unknown statuses are treated as suspended, so they never let anyone in
+fn parse_status(status: &str) -> AccountStatus {
+    AccountStatus::parse(status).unwrap_or(AccountStatus::Suspended)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct User {
+    pub id: i64,
+    pub tenant_id: String,
+    pub email_address: String,
+    pub name: String,
+    pub status: AccountStatus,
+    /// why the account got its status, NULL for accounts that never changed status
+    pub status_reason: Option<String>,
+    pub status_date: Option<DateTime<Utc>>,
+    pub password_reset_required: bool,
+    /// NULL only for accounts created before it was recorded
+    pub password_changed_at: Option<DateTime<Utc>>,
+}
+
+/// what users can change about themselves through `/me`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub display_name: Option<String>,
+    pub locale: Option<String>,
+    pub time_zone: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+/// filters of the admin user listing, `None` means no filtering
+#[derive(Debug, Default)]
+pub struct UserFilter {
+    /// matched against part of the email address or name
+    pub search: Option<String>,
+    pub status: Option<AccountStatus>,
+    pub role: Option<String>,
+}
+
+/// canonical form of the address under the rules of the tenant
+async fn canonical_email_address(
+    pool: &DbPool,
+    tenant_id: &str,
+    email_address: &str,
+) -> ApiResult<String> {
+    let tenant = get_tenant(pool, tenant_id)
+        .await?
+        .ok_or(ApiError::UnknownTenant)?;
+    let canonicalization = tenant.settings.email_canonicalization();
+    Ok(EmailAddress::parse(email_address)?.canonical(&canonicalization))
+}
+
+/// returns the id of the new user, email addresses only have to be unique within a tenant.
+/// addresses of the same mailbox, like ones that only differ in case, count as the same
+#[tracing::instrument(skip_all)]
 pub async fn insert_user(
     pool: &DbPool,
+    tenant_id: &str,
     name: &str,
     password: &str,
     email_address: &str,
-) -> ApiResult<()> {
+) -> ApiResult<i64> {
+    let canonical_email_address = canonical_email_address(pool, tenant_id, email_address).await?;
+    let now_date = Utc::now().to_rfc3339();
     let result = sqlx::query!(
-        "INSERT OR IGNORE INTO users (name, password, email_address) VALUES (?, ?, ?)",
+        "INSERT OR IGNORE INTO users (tenant_id, name, password, email_address,
+        canonical_email_address, password_changed_at) VALUES (?, ?, ?, ?, ?, ?)",
+        tenant_id,
         name,
         password,
-        email_address
+        email_address,
+        canonical_email_address,
+        now_date
     )
     .execute(pool)
     .await
@@ -20,23 +142,814 @@ pub async fn insert_user(
     if result.rows_affected() == 0 {
         Err(ApiError::RegisterDuplicate)
     } else {
-        Ok(())
+        Ok(result.last_insert_rowid())
     }
 }
 
+/// whether the password is the one of the user `get_user` finds by the address
+#[tracing::instrument(skip_all)]
 pub async fn does_user_exists(
     pool: &DbPool,
+    tenant_id: &str,
     email_address: &str,
     password: &str,
 ) -> ApiResult<bool> {
+    Ok(get_user_with_password(pool, tenant_id, email_address)
+        .await?
+        .is_some_and(|(_, hash)| hash == password))
+}
+
+/// finds the user by any address of the same mailbox, accounts left without a canonical address
+/// by the migration only by their exact address
+#[tracing::instrument(skip_all)]
+pub async fn get_user(
+    pool: &DbPool,
+    tenant_id: &str,
+    email_address: &str,
+) -> ApiResult<Option<User>> {
+    Ok(get_user_with_password(pool, tenant_id, email_address)
+        .await?
+        .map(|(user, _)| user))
+}
+
+/// like `get_user`, also returns the password hash of the same row so a login can't check the
+/// password of one account and get another
+#[tracing::instrument(skip_all)]
+pub async fn get_user_with_password(
+    pool: &DbPool,
+    tenant_id: &str,
+    email_address: &str,
+) -> ApiResult<Option<(User, String)>> {
+    let canonical_email_address = canonical_email_address(pool, tenant_id, email_address)
+        .await
+        .ok();
+    let record = sqlx::query!(
+        // the planner turns the OR into two lookups, which hides that the columns are NOT NULL
+        r#"SELECT id as "id!", tenant_id as "tenant_id!", email_address as "email_address!",
+        name as "name!", status as "status!", status_reason, status_date,
+        password_reset_required as "password_reset_required!: bool", password_changed_at,
+        password as "password!" FROM users
+        WHERE tenant_id=?1 AND (canonical_email_address=?2 OR email_address=?3)
+        ORDER BY email_address=?3 DESC LIMIT 1"#,
+        tenant_id,
+        canonical_email_address,
+        email_address
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| {
+        let user = User {
+            id: r.id,
+            tenant_id: r.tenant_id,
+            email_address: r.email_address,
+            name: r.name,
+            status: parse_status(&r.status),
+            status_reason: r.status_reason,
+            status_date: r.status_date.as_deref().map(parse_date),
+            password_reset_required: r.password_reset_required,
+            password_changed_at: r.password_changed_at.as_deref().map(parse_date),
+        };
+        (user, r.password)
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_user_by_id(pool: &DbPool, id: i64) -> ApiResult<Option<User>> {
+    let record = sqlx::query!(
+        r#"SELECT id as "id!", tenant_id, email_address, name, status, status_reason, status_date, password_reset_required,
+        password_changed_at FROM users
+        WHERE id=? LIMIT 1"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(record.map(|r| User {
+        id: r.id,
+        tenant_id: r.tenant_id,
+        email_address: r.email_address,
+        name: r.name,
+        status: parse_status(&r.status),
+        status_reason: r.status_reason,
+        status_date: r.status_date.as_deref().map(parse_date),
+        password_reset_required: r.password_reset_required,
+        password_changed_at: r.password_changed_at.as_deref().map(parse_date),
+    }))
+}
+
+fn like_pattern(search: &Option<String>) -> Option<String> {
+    search.as_ref().map(|search| {
+        let escaped = search
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        format!("%{escaped}%")
+    })
+}
+
+/// users of a tenant, ordered by email address
+#[tracing::instrument(skip_all)]
+pub async fn search_users(
+    pool: &DbPool,
+    tenant_id: &str,
+    filter: &UserFilter,
+    limit: i64,
+    offset: i64,
+) -> ApiResult<Vec<User>> {
+    let pattern = like_pattern(&filter.search);
+    let status = filter.status.map(|status| status.as_str());
+    let records = sqlx::query!(
+        r#"SELECT id as "id!", tenant_id, email_address, name, status, status_reason, status_date, password_reset_required,
+        password_changed_at FROM users
+        WHERE tenant_id = ?1
+        AND (?2 IS NULL OR email_address LIKE ?2 ESCAPE '\' OR name LIKE ?2 ESCAPE '\')
+        AND (?3 IS NULL OR status = ?3)
+        AND (?4 IS NULL OR EXISTS (SELECT 1 FROM user_roles
+            WHERE user_roles.user_id = users.id AND user_roles.role_name = ?4))
+        ORDER BY email_address LIMIT ?5 OFFSET ?6"#,
+        tenant_id,
+        pattern,
+        status,
+        filter.role,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| User {
+            id: r.id,
+            tenant_id: r.tenant_id,
+            email_address: r.email_address,
+            name: r.name,
+            status: parse_status(&r.status),
+            status_reason: r.status_reason,
+            status_date: r.status_date.as_deref().map(parse_date),
+            password_reset_required: r.password_reset_required,
+            password_changed_at: r.password_changed_at.as_deref().map(parse_date),
+        })
+        .collect())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn count_users(pool: &DbPool, tenant_id: &str, filter: &UserFilter) -> ApiResult<i64> {
+    let pattern = like_pattern(&filter.search);
+    let status = filter.status.map(|status| status.as_str());
+    let record = sqlx::query!(
+        r#"SELECT COUNT(*) as "count!: i64" FROM users
+        WHERE tenant_id = ?1
+        AND (?2 IS NULL OR email_address LIKE ?2 ESCAPE '\' OR name LIKE ?2 ESCAPE '\')
+        AND (?3 IS NULL OR status = ?3)
+        AND (?4 IS NULL OR EXISTS (SELECT 1 FROM user_roles
+            WHERE user_roles.user_id = users.id AND user_roles.role_name = ?4))"#,
+        tenant_id,
+        pattern,
+        status,
+        filter.role
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(record.count)
+}
+
+/// doesn't check the transition, returns false if the user doesn't exist
+#[tracing::instrument(skip_all)]
+pub async fn set_user_status(
+    pool: &DbPool,
+    id: i64,
+    status: AccountStatus,
+    reason: Option<&str>,
+) -> ApiResult<bool> {
+    let status = status.as_str();
+    let now_date = Utc::now().to_rfc3339();
     let result = sqlx::query!(
-        "SELECT name FROM users WHERE email_address=? AND password=? LIMIT 1",
-        email_address,
-        password
+        "UPDATE users SET status=?, status_reason=?, status_date=? WHERE id=?",
+        status,
+        reason,
+        now_date,
+        id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// moves the account to another status if `can_transition_to` allows it. restoring an account
+/// that is waiting to be purged keeps it, and accounts that can't be used are logged out
+#[tracing::instrument(skip_all)]
+pub async fn transition_user_status(
+    pool: &DbPool,
+    user: &User,
+    status: AccountStatus,
+    reason: Option<&str>,
+) -> ApiResult<()> {
+    if !user.status.can_transition_to(status) {
+        return Err(ApiError::BadArgument {
+            argument_name: "status",
+        });
+    }
+    if user.status == AccountStatus::Deleted {
+        cancel_account_deletion(pool, user.id).await?;
+    }
+    set_user_status(pool, user.id, status, reason).await?;
+    if status != AccountStatus::Active {
+        delete_sessions_of_user(pool, user.id).await?;
+        delete_refresh_tokens_of_user(pool, user.id).await?;
+    }
+    Ok(())
+}
+
+/// returns false if the user doesn't exist
+#[tracing::instrument(skip_all)]
+pub async fn set_password_reset_required(pool: &DbPool, id: i64) -> ApiResult<bool> {
+    let result = sqlx::query!(
+        "UPDATE users SET password_reset_required=TRUE WHERE id=?",
+        id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_user_profile(pool: &DbPool, id: i64) -> ApiResult<Option<Profile>> {
+    let record = sqlx::query!(
+        "SELECT name, display_name, locale, time_zone, avatar_url FROM users WHERE id=? LIMIT 1",
+        id
     )
     .fetch_optional(pool)
     .await
     .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
 
-    Ok(result.is_some())
+    Ok(record.map(|r| Profile {
+        name: r.name,
+        display_name: r.display_name,
+        locale: r.locale,
+        time_zone: r.time_zone,
+        avatar_url: r.avatar_url,
+    }))
+}
+
+/// returns false if the user doesn't exist
+#[tracing::instrument(skip_all)]
+pub async fn update_user_profile(pool: &DbPool, id: i64, profile: &Profile) -> ApiResult<bool> {
+    let result = sqlx::query!(
+        "UPDATE users SET name=?, display_name=?, locale=?, time_zone=?, avatar_url=? WHERE id=?",
+        profile.name,
+        profile.display_name,
+        profile.locale,
+        profile.time_zone,
+        profile.avatar_url,
+        id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// also clears a pending forced reset and moves the old password to the history, returns false
+/// if the user doesn't exist
+#[tracing::instrument(skip_all)]
+pub async fn update_user_password(pool: &DbPool, id: i64, password: &str) -> ApiResult<bool> {
+    let now_date = Utc::now().to_rfc3339();
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    sqlx::query!(
+        "INSERT INTO password_history (user_id, password, created_date)
+        SELECT id, password, ? FROM users WHERE id=?",
+        now_date,
+        id
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    let keep = MAX_PASSWORD_HISTORY as i64;
+    sqlx::query!(
+        "DELETE FROM password_history WHERE user_id=? AND id NOT IN
+        (SELECT id FROM password_history WHERE user_id=? ORDER BY id DESC LIMIT ?)",
+        id,
+        id,
+        keep
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    let result = sqlx::query!(
+        "UPDATE users SET password=?, password_reset_required=FALSE, password_changed_at=?
+        WHERE id=?",
+        password,
+        now_date,
+        id
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// whether the password is among the last `count` passwords of the user, the current one
+/// included
+#[tracing::instrument(skip_all)]
+pub async fn is_recent_password(
+    pool: &DbPool,
+    id: i64,
+    password: &str,
+    count: i64,
+) -> ApiResult<bool> {
+    if count <= 0 {
+        return Ok(false);
+    }
+    let previous_count = count - 1;
+    let record = sqlx::query!(
+        r#"SELECT (EXISTS (SELECT 1 FROM users WHERE id=?1 AND password=?2)
+        OR EXISTS (SELECT 1 FROM (SELECT password FROM password_history WHERE user_id=?1
+            ORDER BY id DESC LIMIT ?3) WHERE password=?2)) as "recent!: bool""#,
+        id,
+        password,
+        previous_count
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(record.recent)
+}
+
+/// everything else points at the user id, so only the user row and the pending email code of
+/// the old address are touched
+#[tracing::instrument(skip_all)]
+pub async fn change_user_email_address(
+    pool: &DbPool,
+    id: i64,
+    new_email_address: &str,
+) -> ApiResult<()> {
+    let user = get_user_by_id(pool, id)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+    let canonical_email_address =
+        canonical_email_address(pool, &user.tenant_id, new_email_address).await?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    sqlx::query!(
+        "DELETE FROM email_codes WHERE (tenant_id, email_address) IN
+        (SELECT tenant_id, email_address FROM users WHERE id=?)",
+        id
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    let result = sqlx::query!(
+        "UPDATE OR IGNORE users SET email_address=?, canonical_email_address=? WHERE id=?",
+        new_email_address,
+        canonical_email_address,
+        id
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::RegisterDuplicate);
+    }
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+/// recomputes the canonical addresses of a tenant after its rules changed. when addresses
+/// become the same, the oldest account keeps it and the others are left without one
+#[tracing::instrument(skip_all)]
+pub async fn update_canonical_email_addresses(pool: &DbPool, tenant_id: &str) -> ApiResult<()> {
+    let tenant = get_tenant(pool, tenant_id)
+        .await?
+        .ok_or(ApiError::UnknownTenant)?;
+    let canonicalization = tenant.settings.email_canonicalization();
+    let mut transaction = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    let records = sqlx::query!(
+        r#"SELECT id as "id!", email_address FROM users WHERE tenant_id=? ORDER BY id"#,
+        tenant_id
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    sqlx::query!(
+        "UPDATE users SET canonical_email_address=NULL WHERE tenant_id=?",
+        tenant_id
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    let mut taken = HashSet::new();
+    for record in records {
+        let Ok(email_address) = EmailAddress::parse(&record.email_address) else {
+            continue;
+        };
+        let canonical_email_address = email_address.canonical(&canonicalization);
+        if !taken.insert(canonical_email_address.clone()) {
+            continue;
+        }
+        sqlx::query!(
+            "UPDATE users SET canonical_email_address=? WHERE id=?",
+            canonical_email_address,
+            record.id
+        )
+        .execute(&mut transaction)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+/// everything that references the user is deleted too, returns false if the user doesn't exist
+#[tracing::instrument(skip_all)]
+pub async fn delete_user(pool: &DbPool, id: i64) -> ApiResult<bool> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    sqlx::query!(
+        "DELETE FROM email_codes WHERE (tenant_id, email_address) IN
+        (SELECT tenant_id, email_address FROM users WHERE id=?)",
+        id
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    let result = sqlx::query!("DELETE FROM users WHERE id=?", id)
+        .execute(&mut tx)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{
+            account_deletions::{get_account_deletion, schedule_account_deletion},
+            roles::{assign_role, get_user_roles},
+            sessions::get_session,
+            tenants::{get_tenant, insert_tenant, update_tenant, DEFAULT_TENANT_ID},
+        },
+        test::helper::{create_test_db, create_test_user_with_session, test_user_id},
+        utils::hash::sha256_hash,
+    };
+
+    #[actix_web::test]
+    async fn transition_user_status_checks_and_cleans_up() {
+        let db = create_test_db().await;
+        let token = create_test_user_with_session(&db, "arian@gmail.com").await;
+        let id = test_user_id(&db, "arian@gmail.com").await;
+        let user = get_user_by_id(&db, id).await.unwrap().unwrap();
+
+        assert_eq!(
+            transition_user_status(&db, &user, AccountStatus::Deleted, None).await,
+            Err(ApiError::BadArgument {
+                argument_name: "status"
+            })
+        );
+        assert!(get_session(&db, &sha256_hash(&token))
+            .await
+            .unwrap()
+            .is_some());
+
+        transition_user_status(&db, &user, AccountStatus::Suspended, Some("spam"))
+            .await
+            .unwrap();
+        let user = get_user_by_id(&db, id).await.unwrap().unwrap();
+        assert_eq!(user.status, AccountStatus::Suspended);
+        assert_eq!(user.status_reason.as_deref(), Some("spam"));
+        assert!(get_session(&db, &sha256_hash(&token))
+            .await
+            .unwrap()
+            .is_none());
+
+        // restoring a deleted account stops its purge
+        set_user_status(&db, id, AccountStatus::Deleted, None)
+            .await
+            .unwrap();
+        schedule_account_deletion(&db, id, "cancel", Utc::now() + chrono::Duration::days(1))
+            .await
+            .unwrap();
+        let user = get_user_by_id(&db, id).await.unwrap().unwrap();
+        transition_user_status(&db, &user, AccountStatus::Active, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_user_by_id(&db, id).await.unwrap().unwrap().status,
+            AccountStatus::Active
+        );
+        assert!(get_account_deletion(&db, id).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn search_users_with_filters() {
+        let db = create_test_db().await;
+        let mut ids = vec![];
+        for email_address in ["a@gmail.com", "b@gmail.com", "c_d@yahoo.com"] {
+            let id = insert_user(&db, DEFAULT_TENANT_ID, "arian", "password", email_address)
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        set_user_status(&db, ids[1], AccountStatus::Suspended, Some("spam"))
+            .await
+            .unwrap();
+        assign_role(&db, ids[2], "admin").await.unwrap();
+
+        let filter = UserFilter {
+            search: Some("gmail".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            count_users(&db, DEFAULT_TENANT_ID, &filter).await.unwrap(),
+            2
+        );
+        let users = search_users(&db, DEFAULT_TENANT_ID, &filter, 1, 1)
+            .await
+            .unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].email_address, "b@gmail.com");
+        assert_eq!(users[0].status, AccountStatus::Suspended);
+        assert_eq!(users[0].status_reason.as_deref(), Some("spam"));
+        assert!(users[0].status_date.is_some());
+
+        // like wildcards are matched literally
+        let filter = UserFilter {
+            search: Some("_".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            count_users(&db, DEFAULT_TENANT_ID, &filter).await.unwrap(),
+            1
+        );
+
+        let filter = UserFilter {
+            status: Some(AccountStatus::Active),
+            role: Some("admin".to_string()),
+            ..Default::default()
+        };
+        let users = search_users(&db, DEFAULT_TENANT_ID, &filter, 10, 0)
+            .await
+            .unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].email_address, "c_d@yahoo.com");
+    }
+
+    #[test]
+    fn account_status_transitions() {
+        use AccountStatus::*;
+        assert!(Pending.can_transition_to(Active));
+        assert!(Active.can_transition_to(Suspended));
+        assert!(Locked.can_transition_to(Active));
+        assert!(Deleted.can_transition_to(Active));
+        assert!(!Active.can_transition_to(Active));
+        assert!(!Active.can_transition_to(Pending));
+        assert!(!Active.can_transition_to(Deleted));
+        assert!(!Suspended.can_transition_to(Locked));
+        for status in [Pending, Active, Suspended, Locked, Deleted] {
+            assert_eq!(AccountStatus::parse(status.as_str()), Some(status));
+        }
+        assert_eq!(AccountStatus::parse("disabled"), None);
+    }
+
+    #[actix_web::test]
+    async fn email_addresses_are_unique_per_tenant() {
+        let db = create_test_db().await;
+        insert_tenant(&db, "shop", "Shop", None).await.unwrap();
+        let default_id = insert_user(&db, DEFAULT_TENANT_ID, "arian", "a", "arian@gmail.com")
+            .await
+            .unwrap();
+        let shop_id = insert_user(&db, "shop", "arian", "b", "arian@gmail.com")
+            .await
+            .unwrap();
+        assert_ne!(default_id, shop_id);
+        assert_eq!(
+            insert_user(&db, "shop", "arian", "c", "arian@gmail.com").await,
+            Err(ApiError::RegisterDuplicate)
+        );
+
+        assert!(does_user_exists(&db, "shop", "arian@gmail.com", "b")
+            .await
+            .unwrap());
+        assert!(!does_user_exists(&db, "shop", "arian@gmail.com", "a")
+            .await
+            .unwrap());
+        let user = get_user(&db, "shop", "arian@gmail.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.id, shop_id);
+        assert_eq!(
+            count_users(&db, "shop", &UserFilter::default())
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    #[actix_web::test]
+    async fn change_email_address_keeps_everything() {
+        let db = create_test_db().await;
+        let session_token = create_test_user_with_session(&db, "old@gmail.com").await;
+        let id = test_user_id(&db, "old@gmail.com").await;
+        assign_role(&db, id, "admin").await.unwrap();
+        insert_user(
+            &db,
+            DEFAULT_TENANT_ID,
+            "taken",
+            "password",
+            "taken@gmail.com",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            change_user_email_address(&db, id, "taken@gmail.com").await,
+            Err(ApiError::RegisterDuplicate)
+        );
+        change_user_email_address(&db, id, "new@gmail.com")
+            .await
+            .unwrap();
+
+        assert!(get_user(&db, DEFAULT_TENANT_ID, "old@gmail.com")
+            .await
+            .unwrap()
+            .is_none());
+        let user = get_user_by_id(&db, id).await.unwrap().unwrap();
+        assert_eq!(user.email_address, "new@gmail.com");
+        let session = get_session(&db, &sha256_hash(&session_token))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.user_id, id);
+        assert_eq!(get_user_roles(&db, id).await.unwrap(), vec!["admin"]);
+    }
+
+    #[actix_web::test]
+    async fn update_and_get_profile() {
+        let db = create_test_db().await;
+        let id = insert_user(
+            &db,
+            DEFAULT_TENANT_ID,
+            "arian",
+            "password",
+            "arian@gmail.com",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            get_user_profile(&db, id).await.unwrap(),
+            Some(Profile {
+                name: "arian".to_string(),
+                ..Default::default()
+            })
+        );
+
+        let profile = Profile {
+            name: "pouya".to_string(),
+            display_name: Some("Pouya A.".to_string()),
+            locale: Some("fa-IR".to_string()),
+            time_zone: Some("Asia/Tehran".to_string()),
+            avatar_url: None,
+        };
+        assert!(update_user_profile(&db, id, &profile).await.unwrap());
+        assert_eq!(
+            get_user_profile(&db, id).await.unwrap(),
+            Some(profile.clone())
+        );
+        assert!(!update_user_profile(&db, id + 1, &profile).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn addresses_of_the_same_mailbox_are_one_account() {
+        let db = create_test_db().await;
+        let id = insert_user(&db, DEFAULT_TENANT_ID, "arian", "a", "Ari.an+x@Gmail.com")
+            .await
+            .unwrap();
+        assert_eq!(
+            insert_user(&db, DEFAULT_TENANT_ID, "arian", "b", "ari.an+x@gmail.com").await,
+            Err(ApiError::RegisterDuplicate)
+        );
+        assert_eq!(
+            insert_user(&db, DEFAULT_TENANT_ID, "arian", "b", "not an address").await,
+            Err(ApiError::BadArgument {
+                argument_name: "email_address"
+            })
+        );
+        // the address is shown the way it was typed
+        let user = get_user(&db, DEFAULT_TENANT_ID, "ARI.AN+X@gmail.COM")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (user.id, user.email_address.as_str()),
+            (id, "Ari.an+x@Gmail.com")
+        );
+        assert!(
+            does_user_exists(&db, DEFAULT_TENANT_ID, "ari.an+x@gmail.com", "a")
+                .await
+                .unwrap()
+        );
+
+        // provider rules only apply once the tenant turns them on
+        let other_id = insert_user(&db, DEFAULT_TENANT_ID, "arian", "b", "arian@googlemail.com")
+            .await
+            .unwrap();
+        let mut tenant = get_tenant(&db, DEFAULT_TENANT_ID).await.unwrap().unwrap();
+        tenant.settings.canonicalize_email_providers = true;
+        update_tenant(&db, DEFAULT_TENANT_ID, &tenant.name, None, &tenant.settings)
+            .await
+            .unwrap();
+        update_canonical_email_addresses(&db, DEFAULT_TENANT_ID)
+            .await
+            .unwrap();
+
+        let user = get_user(&db, DEFAULT_TENANT_ID, "arian@gmail.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.id, id);
+        // the newer account lost its canonical address, but its own address still works
+        let user = get_user(&db, DEFAULT_TENANT_ID, "arian@googlemail.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.id, other_id);
+        assert_eq!(
+            insert_user(&db, DEFAULT_TENANT_ID, "arian", "c", "a.r.i.a.n@gmail.com").await,
+            Err(ApiError::RegisterDuplicate)
+        );
+    }
+
+    #[actix_web::test]
+    async fn password_history_keeps_the_last_passwords() {
+        let db = create_test_db().await;
+        let id = insert_user(&db, DEFAULT_TENANT_ID, "arian", "p0", "arian@gmail.com")
+            .await
+            .unwrap();
+        let created = get_user_by_id(&db, id).await.unwrap().unwrap();
+        assert!(created.password_changed_at.is_some());
+
+        for password in ["p1", "p2", "p3"] {
+            assert!(update_user_password(&db, id, password).await.unwrap());
+        }
+        let user = get_user_by_id(&db, id).await.unwrap().unwrap();
+        assert!(user.password_changed_at >= created.password_changed_at);
+
+        // the current password counts as the first one
+        assert!(!is_recent_password(&db, id, "p3", 0).await.unwrap());
+        assert!(is_recent_password(&db, id, "p3", 1).await.unwrap());
+        assert!(!is_recent_password(&db, id, "p2", 1).await.unwrap());
+        assert!(is_recent_password(&db, id, "p2", 2).await.unwrap());
+        assert!(!is_recent_password(&db, id, "p0", 3).await.unwrap());
+        assert!(is_recent_password(&db, id, "p0", 4).await.unwrap());
+
+        for index in 0..MAX_PASSWORD_HISTORY {
+            update_user_password(&db, id, &format!("q{index}"))
+                .await
+                .unwrap();
+        }
+        let count = MAX_PASSWORD_HISTORY as i64 + 10;
+        assert!(!is_recent_password(&db, id, "p2", count).await.unwrap());
+        assert!(is_recent_password(&db, id, "p3", count).await.unwrap());
+    }
 }
@@ -0,0 +1,460 @@
+use super::{parse_date, DbPool};
+use crate::error::{ApiError, ApiResult};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeliveryStatus {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+        }
+    }
+
+    fn parse(status: &str) -> Self {
+        match status {
+            "succeeded" => Self::Succeeded,
+            "failed" => Self::Failed,
+            _ => Self::Pending,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    pub secret: String,
+    /// empty means every event
+    pub events: Vec<String>,
+    pub created_by: String,
+    pub created_date: DateTime<Utc>,
+}
+
+pub struct NewWebhook<'a> {
+    pub url: &'a str,
+    pub secret: &'a str,
+    pub events: &'a [String],
+    pub created_by: &'a str,
+}
+
+#[derive(Debug)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event_type: String,
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: i64,
+    pub next_attempt_date: DateTime<Utc>,
+    pub created_date: DateTime<Utc>,
+}
+
+/// a delivery together with where and how to send it
+#[derive(Debug)]
+pub struct DueDelivery {
+    pub id: i64,
+    pub url: String,
+    pub secret: String,
+    pub event_type: String,
+    pub payload: String,
+    pub attempts: i64,
+}
+
+#[derive(Debug)]
+pub struct DeliveryAttempt {
+    pub attempted_date: DateTime<Utc>,
+    /// none when no response was received
+    pub status_code: Option<i64>,
+    pub error: Option<String>,
+}
+
+/// the outcome of sending a delivery once
+pub struct AttemptResult<'a> {
+    pub status_code: Option<i64>,
+    pub error: Option<&'a str>,
+    /// status of the delivery after this attempt
+    pub status: DeliveryStatus,
+    pub next_attempt_date: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_webhook(pool: &DbPool, webhook: &NewWebhook<'_>) -> ApiResult<i64> {
+    let now_date = Utc::now().to_rfc3339();
+    let events = webhook.events.join(" ");
+    let result = sqlx::query!(
+        "INSERT INTO webhooks (url, secret, events, created_by, created_date) VALUES (?, ?, ?, ?, ?)",
+        webhook.url,
+        webhook.secret,
+        events,
+        webhook.created_by,
+        now_date
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.last_insert_rowid())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_webhooks(pool: &DbPool) -> ApiResult<Vec<Webhook>> {
+    let records = sqlx::query!(
+        "SELECT id, url, secret, events, created_by, created_date FROM webhooks ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| Webhook {
+            id: r.id,
+            url: r.url,
+            secret: r.secret,
+            events: r.events.split_whitespace().map(str::to_string).collect(),
+            created_by: r.created_by,
+            created_date: parse_date(&r.created_date),
+        })
+        .collect())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn does_webhook_exist(pool: &DbPool, id: i64) -> ApiResult<bool> {
+    let record = sqlx::query!("SELECT id FROM webhooks WHERE id=?", id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(record.is_some())
+}
+
+/// also deletes the delivery log of the webhook
+#[tracing::instrument(skip_all)]
+pub async fn delete_webhook(pool: &DbPool, id: i64) -> ApiResult<bool> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    sqlx::query!(
+        "DELETE FROM webhook_delivery_attempts
+        WHERE delivery_id IN (SELECT id FROM webhook_deliveries WHERE webhook_id=?)",
+        id
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    sqlx::query!("DELETE FROM webhook_deliveries WHERE webhook_id=?", id)
+        .execute(&mut tx)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    let result = sqlx::query!("DELETE FROM webhooks WHERE id=?", id)
+        .execute(&mut tx)
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// queues a delivery for every webhook subscribed to the event, returns how many were queued
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_deliveries(pool: &DbPool, event_type: &str, payload: &str) -> ApiResult<u64> {
+    let now_date = Utc::now().to_rfc3339();
+    let pending = DeliveryStatus::Pending.as_str();
+    let result = sqlx::query!(
+        "INSERT INTO webhook_deliveries (webhook_id, event_type, payload, status, attempts, next_attempt_date, created_date)
+        SELECT id, ?1, ?2, ?3, 0, ?4, ?4 FROM webhooks
+        WHERE events = '' OR (' ' || events || ' ') LIKE ('% ' || ?1 || ' %')",
+        event_type,
+        payload,
+        pending,
+        now_date
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected())
+}
+
+/// replaces the address with null in every queued or delivered payload, returns how many
+/// payloads had it
+#[tracing::instrument(skip_all)]
+pub async fn scrub_address_from_deliveries(pool: &DbPool, email_address: &str) -> ApiResult<u64> {
+    // payloads are json, so the address is always written as this string
+    let quoted = serde_json::Value::from(email_address).to_string();
+    let result = sqlx::query!(
+        "UPDATE webhook_deliveries SET payload=replace(payload, ?1, 'null')
+        WHERE instr(payload, ?1) > 0",
+        quoted
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected())
+}
+
+/// pending deliveries whose next attempt is due, oldest first
+#[tracing::instrument(skip_all)]
+pub async fn get_due_deliveries(pool: &DbPool, limit: i64) -> ApiResult<Vec<DueDelivery>> {
+    let now_date = Utc::now().to_rfc3339();
+    let pending = DeliveryStatus::Pending.as_str();
+    let records = sqlx::query!(
+        r#"SELECT d.id as "id!", w.url as "url!", w.secret as "secret!", d.event_type as "event_type!",
+        d.payload as "payload!", d.attempts as "attempts!"
+        FROM webhook_deliveries d JOIN webhooks w ON w.id = d.webhook_id
+        WHERE d.status=? AND julianday(d.next_attempt_date) <= julianday(?)
+        ORDER BY d.id LIMIT ?"#,
+        pending,
+        now_date,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| DueDelivery {
+            id: r.id,
+            url: r.url,
+            secret: r.secret,
+            event_type: r.event_type,
+            payload: r.payload,
+            attempts: r.attempts,
+        })
+        .collect())
+}
+
+/// logs an attempt and moves the delivery to its new status
+#[tracing::instrument(skip_all)]
+pub async fn record_delivery_attempt(
+    pool: &DbPool,
+    delivery_id: i64,
+    result: &AttemptResult<'_>,
+) -> ApiResult<()> {
+    let now_date = Utc::now().to_rfc3339();
+    let status = result.status.as_str();
+    let next_attempt_date = result.next_attempt_date.to_rfc3339();
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    sqlx::query!(
+        "INSERT INTO webhook_delivery_attempts (delivery_id, attempted_date, status_code, error)
+        VALUES (?, ?, ?, ?)",
+        delivery_id,
+        now_date,
+        result.status_code,
+        result.error
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    sqlx::query!(
+        "UPDATE webhook_deliveries SET status=?, attempts=attempts + 1, next_attempt_date=? WHERE id=?",
+        status,
+        next_attempt_date,
+        delivery_id
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    tx.commit()
+        .await
+        .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(())
+}
+
+/// newest first
+#[tracing::instrument(skip_all)]
+pub async fn get_deliveries_of_webhook(
+    pool: &DbPool,
+    webhook_id: i64,
+    limit: i64,
+) -> ApiResult<Vec<WebhookDelivery>> {
+    let records = sqlx::query!(
+        "SELECT id, webhook_id, event_type, payload, status, attempts, next_attempt_date, created_date
+        FROM webhook_deliveries WHERE webhook_id=? ORDER BY id DESC LIMIT ?",
+        webhook_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| WebhookDelivery {
+            id: r.id,
+            webhook_id: r.webhook_id,
+            event_type: r.event_type,
+            payload: r.payload,
+            status: DeliveryStatus::parse(&r.status),
+            attempts: r.attempts,
+            next_attempt_date: parse_date(&r.next_attempt_date),
+            created_date: parse_date(&r.created_date),
+        })
+        .collect())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_delivery_attempts(
+    pool: &DbPool,
+    delivery_id: i64,
+) -> ApiResult<Vec<DeliveryAttempt>> {
+    let records = sqlx::query!(
+        "SELECT attempted_date, status_code, error FROM webhook_delivery_attempts
+        WHERE delivery_id=? ORDER BY id",
+        delivery_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| DeliveryAttempt {
+            attempted_date: parse_date(&r.attempted_date),
+            status_code: r.status_code,
+            error: r.error,
+        })
+        .collect())
+}
+
+/// queues a delivery again right away with a fresh retry budget, the attempt log is kept
+#[tracing::instrument(skip_all)]
+pub async fn redeliver(pool: &DbPool, webhook_id: i64, delivery_id: i64) -> ApiResult<bool> {
+    let now_date = Utc::now().to_rfc3339();
+    let pending = DeliveryStatus::Pending.as_str();
+    let result = sqlx::query!(
+        "UPDATE webhook_deliveries SET status=?, attempts=0, next_attempt_date=? WHERE id=? AND webhook_id=?",
+        pending,
+        now_date,
+        delivery_id,
+        webhook_id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ApiError::SqlError { msg: e.to_string() })?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::helper::create_test_db;
+
+    #[actix_web::test]
+    async fn deliveries_are_only_queued_for_subscribed_webhooks() {
+        let db = create_test_db().await;
+        let all = insert_webhook(
+            &db,
+            &NewWebhook {
+                url: "https://crm.example.com/hook",
+                secret: "secret",
+                events: &[],
+                created_by: "admin@gmail.com",
+            },
+        )
+        .await
+        .unwrap();
+        let logins = insert_webhook(
+            &db,
+            &NewWebhook {
+                url: "https://analytics.example.com/hook",
+                secret: "secret",
+                events: &["user.logged_in".to_string()],
+                created_by: "admin@gmail.com",
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            enqueue_deliveries(&db, "user.registered", "{}")
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            enqueue_deliveries(&db, "user.logged_in", "{}")
+                .await
+                .unwrap(),
+            2
+        );
+        // a prefix of a subscribed event doesn't match
+        assert_eq!(
+            enqueue_deliveries(&db, "user.logged", "{}").await.unwrap(),
+            1
+        );
+
+        assert_eq!(
+            get_deliveries_of_webhook(&db, all, 10).await.unwrap().len(),
+            3
+        );
+        let deliveries = get_deliveries_of_webhook(&db, logins, 10).await.unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].event_type, "user.logged_in");
+        assert_eq!(get_due_deliveries(&db, 10).await.unwrap().len(), 4);
+
+        assert!(delete_webhook(&db, all).await.unwrap());
+        assert!(!delete_webhook(&db, all).await.unwrap());
+        assert_eq!(get_due_deliveries(&db, 10).await.unwrap().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn attempts_are_logged_and_redelivery_resets_the_delivery() {
+        let db = create_test_db().await;
+        let webhook_id = insert_webhook(
+            &db,
+            &NewWebhook {
+                url: "https://crm.example.com/hook",
+                secret: "secret",
+                events: &[],
+                created_by: "admin@gmail.com",
+            },
+        )
+        .await
+        .unwrap();
+        enqueue_deliveries(&db, "user.registered", "{}")
+            .await
+            .unwrap();
+        let delivery_id = get_due_deliveries(&db, 10).await.unwrap()[0].id;
+
+        record_delivery_attempt(
+            &db,
+            delivery_id,
+            &AttemptResult {
+                status_code: Some(500),
+                error: None,
+                status: DeliveryStatus::Failed,
+                next_attempt_date: Utc::now(),
+            },
+        )
+        .await
+        .unwrap();
+        assert!(get_due_deliveries(&db, 10).await.unwrap().is_empty());
+        let delivery = &get_deliveries_of_webhook(&db, webhook_id, 10)
+            .await
+            .unwrap()[0];
+        assert_eq!(delivery.status, DeliveryStatus::Failed);
+        assert_eq!(delivery.attempts, 1);
+
+        assert!(!redeliver(&db, webhook_id + 1, delivery_id).await.unwrap());
+        assert!(redeliver(&db, webhook_id, delivery_id).await.unwrap());
+        let due = get_due_deliveries(&db, 10).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].attempts, 0);
+        assert_eq!(
+            get_delivery_attempts(&db, delivery_id).await.unwrap().len(),
+            1
+        );
+    }
+}
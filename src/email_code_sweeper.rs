@@ -0,0 +1,119 @@
+use crate::{
+    clock::Clock,
+    config::{Config, EmailCodePurpose},
+    db::{email_codes::delete_email_codes_of_purpose_sent_before, DbPool},
+    error::ApiResult,
+};
+use actix_web::web::Data;
+use chrono::{DateTime, Utc};
+
+/// deletes the codes that outlived the lifetime of their purpose, returns how many were deleted
+pub async fn delete_expired_email_codes(
+    pool: &DbPool,
+    config: &Config,
+    now: DateTime<Utc>,
+) -> ApiResult<u64> {
+    let mut deleted = 0;
+    for purpose in EmailCodePurpose::ALL {
+        let sent_before = now - config.email_code_lifetime(purpose);
+        deleted += delete_email_codes_of_purpose_sent_before(pool, purpose, sent_before).await?;
+    }
+    Ok(deleted)
+}
+
+/// deletes expired codes forever, meant to be spawned next to the http server
+pub async fn run_email_code_sweeper(
+    pool: DbPool,
+    config: Config,
+    clock: Data<dyn Clock + Send + Sync>,
+) {
+    let interval = config
+        .email_code_sweep_interval
+        .to_std()
+        .unwrap_or_default();
+    loop {
+        match delete_expired_email_codes(&pool, &config, clock.now()).await {
+            Ok(0) => {}
+            Ok(deleted) => tracing::info!("deleted {deleted} expired email codes"),
+            Err(e) => tracing::error!("deleting expired email codes failed: {e}"),
+        }
+        actix_web::rt::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::FakeClock,
+        db::{
+            email_codes::{get_last_sent_email_code, insert_or_update_email_code},
+            tenants::DEFAULT_TENANT_ID,
+        },
+        test::helper::create_test_db,
+    };
+    use chrono::Duration;
+
+    #[actix_web::test]
+    async fn codes_are_deleted_after_the_lifetime_of_their_purpose() {
+        let db = create_test_db().await;
+        let config = Config {
+            registration_email_code_lifetime: Duration::hours(1),
+            password_reset_email_code_lifetime: Duration::hours(2),
+            ..Config::default()
+        };
+        let clock = FakeClock::new(Utc::now());
+        let code_exists = |email_address, purpose| {
+            let db = db.clone();
+            async move {
+                get_last_sent_email_code(&db, DEFAULT_TENANT_ID, email_address, purpose)
+                    .await
+                    .unwrap()
+                    .is_some()
+            }
+        };
+        for purpose in EmailCodePurpose::ALL {
+            insert_or_update_email_code(
+                &db,
+                DEFAULT_TENANT_ID,
+                "old@gmail.com",
+                purpose,
+                123456,
+                clock.now(),
+            )
+            .await
+            .unwrap();
+        }
+        clock.advance(Duration::minutes(90));
+        insert_or_update_email_code(
+            &db,
+            DEFAULT_TENANT_ID,
+            "new@gmail.com",
+            EmailCodePurpose::Registration,
+            123456,
+            clock.now(),
+        )
+        .await
+        .unwrap();
+
+        // the old reset code can still reset a password
+        assert_eq!(
+            delete_expired_email_codes(&db, &config, clock.now())
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(!code_exists("old@gmail.com", EmailCodePurpose::Registration).await);
+        assert!(code_exists("old@gmail.com", EmailCodePurpose::PasswordReset).await);
+
+        clock.advance(Duration::minutes(31));
+        assert_eq!(
+            delete_expired_email_codes(&db, &config, clock.now())
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(!code_exists("old@gmail.com", EmailCodePurpose::PasswordReset).await);
+        assert!(code_exists("new@gmail.com", EmailCodePurpose::Registration).await);
+    }
+}
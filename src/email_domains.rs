@@ -0,0 +1,261 @@
+//! server wide lists of email domains, read from files that are reloaded when they change
+
+use crate::config::Config;
+use actix_web::web::Data;
+use std::{collections::HashSet, fs, io, path::PathBuf, sync::RwLock, time::SystemTime};
+
+/// used unless `disposable_email_domains_path` is set
+const BUNDLED_DISPOSABLE_EMAIL_DOMAINS: &str = include_str!("disposable_email_domains.txt");
+
+/// domain patterns: `example.com` matches only that domain, `*.example.com` only its
+/// subdomains. patterns and domains are compared in their lowercase ascii form
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DomainList {
+    domains: HashSet<String>,
+    /// the part after `*.` of the wildcard patterns
+    parents: HashSet<String>,
+}
+
+impl DomainList {
+    /// patterns that aren't valid are left out
+    pub fn new<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut list = DomainList::default();
+        for pattern in patterns {
+            let Some(normalized) = normalize_pattern(pattern) else {
+                continue;
+            };
+            match normalized.strip_prefix("*.") {
+                Some(parent) => list.parents.insert(parent.to_string()),
+                None => list.domains.insert(normalized),
+            };
+        }
+        list
+    }
+
+    /// one pattern per line, `#` starts a comment
+    pub fn parse(text: &str) -> Self {
+        Self::new(
+            text.lines()
+                .map(|line| line.split('#').next().unwrap_or_default().trim())
+                .filter(|line| !line.is_empty()),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty() && self.parents.is_empty()
+    }
+
+    /// `domain` has to be in its lowercase ascii form, like `EmailAddress::domain` returns it
+    pub fn matches(&self, domain: &str) -> bool {
+        if self.domains.contains(domain) {
+            return true;
+        }
+        let mut rest = domain;
+        while let Some((_, parent)) = rest.split_once('.') {
+            if self.parents.contains(parent) {
+                return true;
+            }
+            rest = parent;
+        }
+        false
+    }
+}
+
+/// the lowercase ascii form of a pattern, `None` if it isn't a domain with an optional `*.`
+pub fn normalize_pattern(pattern: &str) -> Option<String> {
+    let (wildcard, domain) = match pattern.strip_prefix("*.") {
+        Some(domain) => ("*.", domain),
+        None => ("", pattern),
+    };
+    let domain = idna::domain_to_ascii(domain).ok()?;
+    let is_valid = domain.contains('.')
+        && domain.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && label
+                    .chars()
+                    .all(|char| char.is_ascii_alphanumeric() || char == '-')
+        });
+    is_valid.then(|| format!("{wildcard}{domain}"))
+}
+
+struct LoadedList {
+    domains: DomainList,
+    /// modification time of the file the list was read from
+    modified: Option<SystemTime>,
+}
+
+/// a list read from a file, or a fixed one if there is no file
+struct ReloadableList {
+    path: Option<PathBuf>,
+    loaded: RwLock<LoadedList>,
+}
+
+impl ReloadableList {
+    fn fixed(domains: DomainList) -> Self {
+        ReloadableList {
+            path: None,
+            loaded: RwLock::new(LoadedList {
+                domains,
+                modified: None,
+            }),
+        }
+    }
+
+    fn open(path: PathBuf) -> io::Result<Self> {
+        let list = ReloadableList {
+            path: Some(path),
+            loaded: RwLock::new(LoadedList {
+                domains: DomainList::default(),
+                modified: None,
+            }),
+        };
+        list.reload_if_changed()?;
+        Ok(list)
+    }
+
+    /// returns whether the file changed, the old list is kept if it can't be read
+    fn reload_if_changed(&self) -> io::Result<bool> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        let modified = fs::metadata(path)?.modified()?;
+        if self.loaded.read().unwrap().modified == Some(modified) {
+            return Ok(false);
+        }
+        let domains = DomainList::parse(&fs::read_to_string(path)?);
+        *self.loaded.write().unwrap() = LoadedList {
+            domains,
+            modified: Some(modified),
+        };
+        Ok(true)
+    }
+
+    fn matches(&self, domain: &str) -> bool {
+        self.loaded.read().unwrap().domains.matches(domain)
+    }
+}
+
+/// the bundled disposable domains and no denied ones, unless files are configured
+pub struct EmailDomainLists {
+    disposable: ReloadableList,
+    denied: ReloadableList,
+}
+
+impl Default for EmailDomainLists {
+    fn default() -> Self {
+        EmailDomainLists {
+            disposable: ReloadableList::fixed(DomainList::parse(BUNDLED_DISPOSABLE_EMAIL_DOMAINS)),
+            denied: ReloadableList::fixed(DomainList::default()),
+        }
+    }
+}
+
+impl EmailDomainLists {
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        let default = Self::default();
+        Ok(EmailDomainLists {
+            disposable: match &config.disposable_email_domains_path {
+                Some(path) => ReloadableList::open(path.into())?,
+                None => default.disposable,
+            },
+            denied: match &config.denied_email_domains_path {
+                Some(path) => ReloadableList::open(path.into())?,
+                None => default.denied,
+            },
+        })
+    }
+
+    /// returns whether any of the files changed
+    pub fn reload_if_changed(&self) -> io::Result<bool> {
+        let disposable_changed = self.disposable.reload_if_changed()?;
+        let denied_changed = self.denied.reload_if_changed()?;
+        Ok(disposable_changed || denied_changed)
+    }
+
+    pub fn is_disposable(&self, domain: &str) -> bool {
+        self.disposable.matches(domain)
+    }
+
+    pub fn is_denied(&self, domain: &str) -> bool {
+        self.denied.matches(domain)
+    }
+}
+
+/// picks up changes of the list files forever, meant to be spawned next to the http server
+pub async fn run_email_domain_lists_reloader(lists: Data<EmailDomainLists>, config: Config) {
+    let interval = config
+        .email_domain_lists_reload_interval
+        .to_std()
+        .unwrap_or_default();
+    loop {
+        actix_web::rt::time::sleep(interval).await;
+        match lists.reload_if_changed() {
+            Ok(true) => tracing::info!("reloaded the email domain lists"),
+            Ok(false) => {}
+            Err(e) => tracing::error!("reloading the email domain lists failed: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread::sleep, time::Duration};
+
+    #[test]
+    fn patterns_match_domains_and_subdomains() {
+        let list = DomainList::parse(
+            "# comment\n\
+            Example.COM\n\
+            *.mail.example.org  # only subdomains\n\
+            *.bücher.de\n\
+            not a domain\n",
+        );
+        for (domain, expected) in [
+            ("example.com", true),
+            ("sub.example.com", false),
+            ("mail.example.org", false),
+            ("a.mail.example.org", true),
+            ("a.b.mail.example.org", true),
+            ("xn--bcher-kva.de", false),
+            ("shop.xn--bcher-kva.de", true),
+            ("example.org", false),
+        ] {
+            assert_eq!(list.matches(domain), expected, "{domain}");
+        }
+        assert_eq!(list.domains.len() + list.parents.len(), 3);
+
+        let lists = EmailDomainLists::default();
+        assert!(lists.is_disposable("mailinator.com"));
+        assert!(lists.is_disposable("abc.mailinator.com"));
+        assert!(!lists.is_disposable("gmail.com"));
+        assert!(!lists.is_denied("mailinator.com"));
+    }
+
+    #[test]
+    fn list_files_are_reloaded_when_they_change() {
+        let path = std::env::temp_dir().join(format!("denied_{}.txt", std::process::id()));
+        fs::write(&path, "spam.com\n").unwrap();
+        let config = Config {
+            denied_email_domains_path: Some(path.to_string_lossy().to_string()),
+            ..Config::default()
+        };
+        let lists = EmailDomainLists::from_config(&config).unwrap();
+        assert!(lists.is_denied("spam.com"));
+        assert!(!lists.reload_if_changed().unwrap());
+
+        // modification times can be as coarse as a second
+        sleep(Duration::from_millis(1100));
+        fs::write(&path, "*.spam.com\n").unwrap();
+        assert!(lists.reload_if_changed().unwrap());
+        assert!(!lists.is_denied("spam.com"));
+        assert!(lists.is_denied("www.spam.com"));
+
+        // a list that can't be read keeps the last one
+        fs::remove_file(&path).unwrap();
+        assert!(lists.reload_if_changed().is_err());
+        assert!(lists.is_denied("www.spam.com"));
+        // the bundled list is still used for disposable domains
+        assert!(lists.is_disposable("yopmail.com"));
+    }
+}
@@ -1,4 +1,8 @@
-use crate::error::{ApiError, ApiResult};
+use crate::{
+    error::{ApiError, ApiResult},
+    metrics::record_email,
+    telemetry::redact,
+};
 use async_trait::async_trait;
 use lettre::{
     message::header::ContentType, message::Mailbox, transport::smtp::authentication::Credentials,
@@ -18,7 +22,7 @@ pub trait EmailSender {
     async fn send_email(&self, message: Message) -> ApiResult<()>;
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct RealEmailSender {}
 
 impl RealEmailSender {
@@ -38,6 +42,8 @@ lazy_static! {
 
 #[async_trait]
 impl EmailSender for RealEmailSender {
+    // the message isn't recorded, it holds the address and often a code
+    #[tracing::instrument(skip_all)]
     async fn send_email(&self, message: Message) -> ApiResult<()> {
         let email = lettre::Message::builder()
             .from(EMAIL_ADDRESS.clone())
@@ -63,6 +69,29 @@ impl EmailSender for RealEmailSender {
     }
 }
 
+/// counts the emails `inner` sent and failed to send in the metrics
+pub struct CountingEmailSender<S> {
+    inner: S,
+}
+
+impl<S> CountingEmailSender<S> {
+    pub fn new(inner: S) -> Self {
+        CountingEmailSender { inner }
+    }
+}
+
+#[async_trait]
+impl<S: EmailSender + Send + Sync> EmailSender for CountingEmailSender<S> {
+    async fn send_email(&self, message: Message) -> ApiResult<()> {
+        let result = self.inner.send_email(message).await;
+        record_email(result.is_ok());
+        if let Err(e) = &result {
+            tracing::warn!("sending an email failed: {}", redact(&e.to_string()));
+        }
+        result
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,4 +1,9 @@
-use actix_web::{http::StatusCode, ResponseError};
+use crate::{telemetry::current_request_id, utils::password_policy::PasswordViolation};
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    HttpResponse, ResponseError,
+};
+use serde_json::{json, Value};
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -15,6 +20,12 @@ pub enum ApiError {
     #[error("wrong email code")]
     WrongEmailCode,
 
+    #[error("invitation is invalid or expired")]
+    InvalidInvitation,
+
+    #[error("email change link is invalid or expired")]
+    InvalidEmailChange,
+
     #[error("user with same phone number already exists")]
     RegisterDuplicate,
 
@@ -24,8 +35,57 @@ pub enum ApiError {
     #[error("argument '{argument_name}' is incorrect")]
     BadArgument { argument_name: &'static str },
 
+    #[error("password doesn't meet the password policy")]
+    WeakPassword { violations: Vec<PasswordViolation> },
+
     #[error("wrong credentials")]
     WrongCredentials,
+
+    #[error("email domain is not allowed")]
+    EmailDomainNotAllowed,
+
+    #[error("disposable email addresses are not allowed")]
+    DisposableEmailAddress,
+
+    #[error("unknown tenant")]
+    UnknownTenant,
+
+    #[error("account isn't activated yet")]
+    AccountPending,
+
+    #[error("account is suspended")]
+    AccountSuspended,
+
+    #[error("account is locked")]
+    AccountLocked,
+
+    #[error("account is scheduled for deletion")]
+    AccountDeletionScheduled,
+
+    #[error("account deletion link is invalid or expired")]
+    InvalidAccountDeletion,
+
+    #[error("password has to be reset with an email code")]
+    PasswordResetRequired,
+
+    #[error("password has expired and has to be changed")]
+    PasswordExpired,
+
+    #[error("authentication required")]
+    Unauthorized,
+
+    #[error("not allowed")]
+    Forbidden,
+
+    #[error("token error: {reason}")]
+    TokenError { reason: String },
+
+    /// error defined by the oauth 2.0 spec, `error` is one of the codes from rfc 6749 section 5.2
+    #[error("{error}: {description}")]
+    OAuth {
+        error: &'static str,
+        description: &'static str,
+    },
 }
 
 pub type ApiResult<T> = Result<T, ApiError>;
@@ -33,8 +93,46 @@ pub type ApiResult<T> = Result<T, ApiError>;
 impl ResponseError for ApiError {
     fn status_code(&self) -> StatusCode {
         match *self {
-            Self::SqlError { msg: _ } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::SqlError { msg: _ } | Self::TokenError { reason: _ } => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            Self::Unauthorized
+            | Self::OAuth {
+                error: "invalid_client",
+                ..
+            } => StatusCode::UNAUTHORIZED,
+            Self::Forbidden | Self::PasswordExpired => StatusCode::FORBIDDEN,
+            Self::UnknownTenant => StatusCode::NOT_FOUND,
             _ => StatusCode::BAD_REQUEST,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        // the request id lets support find the logs of a failed request
+        let request_id = current_request_id();
+        let with_request_id = |mut body: Value| {
+            if let Some(request_id) = &request_id {
+                body["request_id"] = json!(request_id);
+            }
+            body
+        };
+        match self {
+            // oauth clients expect a json body, see rfc 6749 section 5.2
+            Self::OAuth { error, description } => HttpResponse::build(self.status_code())
+                .insert_header(("Cache-Control", "no-store"))
+                .json(with_request_id(
+                    json!({ "error": error, "error_description": description }),
+                )),
+            // every violation is listed, so forms can show them all at once
+            Self::WeakPassword { violations } => HttpResponse::build(self.status_code()).json(
+                with_request_id(json!({ "error": self.to_string(), "violations": violations })),
+            ),
+            _ => HttpResponse::build(self.status_code())
+                .insert_header(ContentType::plaintext())
+                .body(match request_id {
+                    Some(request_id) => format!("{self}\nrequest id: {request_id}"),
+                    None => self.to_string(),
+                }),
+        }
+    }
 }
@@ -0,0 +1,302 @@
+use crate::{
+    config::Config,
+    db::{
+        signing_keys::{delete_signing_key, get_signing_keys, insert_signing_key, SigningKey},
+        DbPool,
+    },
+    error::{ApiError, ApiResult},
+    utils::random::generate_random_token,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use ring::{
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AccessTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    /// client id of the client the token was issued to
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    /// space separated list of granted scopes
+    pub scope: String,
+    /// roles of the user, so resource servers can authorize without asking us
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    /// tenant of the user, service accounts don't belong to one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+/// openid connect id token, see openid connect core section 2
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub user_claims: UserClaims,
+}
+
+/// audience of invitation tokens, so they can't be mistaken for access tokens
+pub const INVITATION_AUDIENCE: &str = "invitation";
+
+/// token in the link that invited users follow, `sub` is the id of the invitation
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct InvitationClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    /// changes when the invitation is resent, so only the newest email works
+    pub jti: String,
+    pub tenant: String,
+}
+
+/// standard claims about the user, which ones are filled depends on the granted scopes
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct UserClaims {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// public part of a signing key in the json web key format, see rfc 7517 and rfc 7518 section 6.2
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub x: String,
+    pub y: String,
+    pub kid: String,
+    #[serde(rename = "use")]
+    pub key_use: String,
+    pub alg: String,
+}
+
+pub fn generate_signing_key() -> ApiResult<SigningKey> {
+    let private_key =
+        EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+            .map_err(|e| ApiError::TokenError {
+                reason: e.to_string(),
+            })?;
+
+    Ok(SigningKey {
+        kid: generate_random_token(),
+        private_key: private_key.as_ref().to_vec(),
+        created_date: Utc::now(),
+    })
+}
+
+/// uncompressed P-256 public key (`0x04 || x || y`) of the signing key
+pub fn public_key(key: &SigningKey) -> ApiResult<Vec<u8>> {
+    let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &key.private_key)
+        .map_err(|e| ApiError::TokenError {
+            reason: e.to_string(),
+        })?;
+    Ok(key_pair.public_key().as_ref().to_vec())
+}
+
+pub fn jwk(key: &SigningKey) -> ApiResult<Jwk> {
+    let public_key = public_key(key)?;
+    // skip the leading 0x04 that marks the point as uncompressed
+    let (x, y) = public_key[1..].split_at(32);
+    Ok(Jwk {
+        kty: "EC".to_string(),
+        crv: "P-256".to_string(),
+        x: URL_SAFE_NO_PAD.encode(x),
+        y: URL_SAFE_NO_PAD.encode(y),
+        kid: key.kid.clone(),
+        key_use: "sig".to_string(),
+        alg: "ES256".to_string(),
+    })
+}
+
+/// returns the key that new tokens should be signed with, generates a new one
+/// when the newest key is older than the rotation period
+pub async fn current_signing_key(pool: &DbPool, config: &Config) -> ApiResult<SigningKey> {
+    let keys = get_signing_keys(pool).await?;
+    match keys.into_iter().next() {
+        Some(key) if Utc::now() - key.created_date < config.signing_key_rotation_period => Ok(key),
+        _ => {
+            let key = generate_signing_key()?;
+            insert_signing_key(pool, &key).await?;
+            Ok(key)
+        }
+    }
+}
+
+/// returns the keys that tokens may still be signed with, retired keys are kept
+/// around until every token they signed has expired and are deleted after that
+pub async fn verification_keys(pool: &DbPool, config: &Config) -> ApiResult<Vec<SigningKey>> {
+    let longest_token_lifetime = config.access_token_lifetime.max(config.invitation_lifetime);
+    let overlap = config.signing_key_rotation_period + longest_token_lifetime;
+    let now_date = Utc::now();
+
+    let mut keys = Vec::new();
+    for (index, key) in get_signing_keys(pool).await?.into_iter().enumerate() {
+        if index == 0 || now_date - key.created_date < overlap {
+            keys.push(key);
+        } else {
+            delete_signing_key(pool, &key.kid).await?;
+        }
+    }
+    Ok(keys)
+}
+
+pub async fn encode_token<T: Serialize>(
+    pool: &DbPool,
+    config: &Config,
+    claims: &T,
+) -> ApiResult<String> {
+    let key = current_signing_key(pool, config).await?;
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = Some(key.kid);
+
+    jsonwebtoken::encode(&header, claims, &EncodingKey::from_ec_der(&key.private_key)).map_err(
+        |e| ApiError::TokenError {
+            reason: e.to_string(),
+        },
+    )
+}
+
+/// verifies the signature, expiry, issuer and optionally the audience of a token
+pub async fn decode_token<T: DeserializeOwned>(
+    pool: &DbPool,
+    config: &Config,
+    token: &str,
+    audience: Option<&str>,
+) -> ApiResult<T> {
+    let header = jsonwebtoken::decode_header(token).map_err(|_| ApiError::Unauthorized)?;
+    let kid = header.kid.ok_or(ApiError::Unauthorized)?;
+    let key = verification_keys(pool, config)
+        .await?
+        .into_iter()
+        .find(|key| key.kid == kid)
+        .ok_or(ApiError::Unauthorized)?;
+
+    let mut validation = Validation::new(Algorithm::ES256);
+    validation.set_issuer(&[&config.issuer]);
+    if let Some(audience) = audience {
+        validation.set_audience(&[audience]);
+    }
+
+    let public_key = public_key(&key)?;
+    jsonwebtoken::decode(token, &DecodingKey::from_ec_der(&public_key), &validation)
+        .map(|data| data.claims)
+        .map_err(|_| ApiError::Unauthorized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::helper::create_test_db;
+    use chrono::Duration;
+
+    fn test_claims(config: &Config) -> AccessTokenClaims {
+        let now_date = Utc::now();
+        AccessTokenClaims {
+            iss: config.issuer.clone(),
+            sub: "arian@gmail.com".to_string(),
+            aud: "spa".to_string(),
+            exp: (now_date + config.access_token_lifetime).timestamp(),
+            iat: now_date.timestamp(),
+            scope: "openid".to_string(),
+            roles: vec![],
+            tenant: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn encode_and_decode_token() {
+        let db = create_test_db().await;
+        let config = Config::default();
+        let claims = test_claims(&config);
+
+        let token = encode_token(&db, &config, &claims).await.unwrap();
+        let decoded: AccessTokenClaims = decode_token(&db, &config, &token, Some("spa"))
+            .await
+            .unwrap();
+        assert_eq!(decoded, claims);
+
+        let wrong_audience: ApiResult<AccessTokenClaims> =
+            decode_token(&db, &config, &token, Some("another_client")).await;
+        assert_eq!(wrong_audience, Err(ApiError::Unauthorized));
+    }
+
+    #[actix_web::test]
+    async fn jwk_can_verify_token() {
+        let db = create_test_db().await;
+        let config = Config::default();
+        let token = encode_token(&db, &config, &test_claims(&config))
+            .await
+            .unwrap();
+
+        // verify only with what a client would get from the jwks endpoint
+        let key = current_signing_key(&db, &config).await.unwrap();
+        let jwk = jwk(&key).unwrap();
+        let decoding_key = DecodingKey::from_ec_components(&jwk.x, &jwk.y).unwrap();
+        let mut validation = Validation::new(Algorithm::ES256);
+        validation.set_audience(&["spa"]);
+        assert!(
+            jsonwebtoken::decode::<AccessTokenClaims>(&token, &decoding_key, &validation).is_ok()
+        );
+    }
+
+    #[actix_web::test]
+    async fn signing_key_gets_rotated() {
+        let db = create_test_db().await;
+        let config = Config::default();
+
+        let mut old_key = generate_signing_key().unwrap();
+        old_key.created_date = Utc::now() - config.signing_key_rotation_period;
+        insert_signing_key(&db, &old_key).await.unwrap();
+
+        let new_key = current_signing_key(&db, &config).await.unwrap();
+        assert_ne!(new_key.kid, old_key.kid);
+        assert_eq!(current_signing_key(&db, &config).await.unwrap(), new_key);
+
+        // old key is still in its overlap window
+        let kids: Vec<String> = verification_keys(&db, &config)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|key| key.kid)
+            .collect();
+        assert_eq!(kids, vec![new_key.kid.clone(), old_key.kid.clone()]);
+    }
+
+    #[actix_web::test]
+    async fn expired_signing_key_gets_deleted() {
+        let db = create_test_db().await;
+        let config = Config::default();
+
+        let mut old_key = generate_signing_key().unwrap();
+        old_key.created_date = Utc::now()
+            - config.signing_key_rotation_period
+            - config.access_token_lifetime.max(config.invitation_lifetime)
+            - Duration::seconds(1);
+        insert_signing_key(&db, &old_key).await.unwrap();
+        let new_key = current_signing_key(&db, &config).await.unwrap();
+
+        assert_eq!(
+            verification_keys(&db, &config).await.unwrap(),
+            vec![new_key]
+        );
+        assert_eq!(get_signing_keys(&db).await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,26 @@
+#[macro_use]
+extern crate lazy_static;
+
+pub mod account_deletion;
+pub mod admin_commands;
+pub mod api;
+pub mod audit;
+pub mod auth;
+pub mod breached_passwords;
+pub mod clock;
+pub mod config;
+pub mod db;
+pub mod email_code_sweeper;
+pub mod email_domains;
+pub mod email_sender;
+pub mod error;
+pub mod jwt;
+pub mod metrics;
+pub mod rng;
+pub mod telemetry;
+pub mod tenant;
+pub mod utils;
+pub mod webhooks;
+
+#[cfg(test)]
+mod test;
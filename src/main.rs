@@ -1,45 +1,100 @@
-#[macro_use]
-extern crate lazy_static;
-
-mod api;
-mod db;
-mod email_sender;
-mod error;
-mod utils;
-
-#[cfg(test)]
-mod test;
-
-use actix_web::{middleware::Logger, web::Data, App, HttpServer};
+use actix_web::{
+    web::{self, Data},
+    App, HttpServer,
+};
 use anyhow::Result;
+use auth_system::{
+    account_deletion::run_account_deletion_worker,
+    api,
+    breached_passwords::BreachedPasswords,
+    clock::{Clock, SystemClock},
+    config::Config,
+    db,
+    email_code_sweeper::run_email_code_sweeper,
+    email_domains::{run_email_domain_lists_reloader, EmailDomainLists},
+    email_sender::{CountingEmailSender, EmailSender, RealEmailSender},
+    metrics,
+    rng::{RngProvider, ThreadRngProvider},
+    telemetry,
+    webhooks::run_webhook_worker,
+};
 use dotenv::dotenv;
-use email_sender::{EmailSender, RealEmailSender};
 use std::env;
 use std::sync::Arc;
 
 #[actix_web::main]
 async fn main() -> Result<()> {
     dotenv().ok();
+    let config = Config::from_env();
+    telemetry::init(&config)?;
 
     let db_url = env::var("DATABASE_URL").expect("environment variable DATABASE_URL is not set!");
     let pool = db::establish_connection(&db_url).await?;
     db::setup(&pool).await?;
 
-    let email_provider: Arc<dyn EmailSender + Send + Sync> = Arc::new(RealEmailSender::new());
+    let email_provider: Arc<dyn EmailSender + Send + Sync> =
+        Arc::new(CountingEmailSender::new(RealEmailSender::new()));
+    let clock: Arc<dyn Clock + Send + Sync> = Arc::new(SystemClock::new());
+    let rng: Arc<dyn RngProvider + Send + Sync> = Arc::new(ThreadRngProvider::new());
+    let breached_passwords = Data::new(BreachedPasswords::from_config(&config)?);
+    let email_domain_lists = Data::new(EmailDomainLists::from_config(&config)?);
 
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    actix_web::rt::spawn(run_webhook_worker(pool.clone(), config.clone()));
+    actix_web::rt::spawn(run_account_deletion_worker(
+        pool.clone(),
+        config.clone(),
+        Data::from(clock.clone()),
+    ));
+    actix_web::rt::spawn(run_email_code_sweeper(
+        pool.clone(),
+        config.clone(),
+        Data::from(clock.clone()),
+    ));
+    actix_web::rt::spawn(run_email_domain_lists_reloader(
+        email_domain_lists.clone(),
+        config.clone(),
+    ));
+    let serve_metrics_with_api = match &config.metrics_address {
+        Some(address) => {
+            let pool = pool.clone();
+            let metrics_server = HttpServer::new(move || {
+                App::new()
+                    .app_data(Data::new(pool.clone()))
+                    .service(metrics::metrics)
+            })
+            .workers(1)
+            .bind(address)?
+            .run();
+            actix_web::rt::spawn(metrics_server);
+            false
+        }
+        None => true,
+    };
     HttpServer::new(move || {
         App::new()
-            .wrap(Logger::default())
+            .wrap_fn(metrics::track_request)
+            .wrap_fn(telemetry::trace_request)
             .app_data(Data::new(pool.clone()))
             .app_data(Data::from(email_provider.clone()))
-            .service(api::register::register)
-            .service(api::send_email_code::send_email_code)
-            .service(api::login::login)
+            .app_data(Data::from(clock.clone()))
+            .app_data(Data::from(rng.clone()))
+            .app_data(Data::new(config.clone()))
+            .app_data(breached_passwords.clone())
+            .app_data(email_domain_lists.clone())
+            // every tenant can also be reached under its own path, for clients that can't
+            // set headers and hosts that serve several tenants
+            .service(web::scope("/t/{tenant}").configure(api::configure))
+            .configure(api::configure)
+            .configure(|cfg| {
+                if serve_metrics_with_api {
+                    cfg.service(metrics::metrics);
+                }
+            })
     })
     .bind(("127.0.0.1", 8000))?
     .run()
     .await?;
 
+    telemetry::shutdown();
     Ok(())
 }
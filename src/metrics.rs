@@ -0,0 +1,303 @@
+//! prometheus metrics of the server, served in the text format by `metrics`
+
+use crate::{config::EmailCodePurpose, db::DbPool, error::ApiError};
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse},
+    get,
+    http::header::ContentType,
+    web::Data,
+    HttpResponse,
+};
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder,
+};
+use std::{future::Future, time::Duration, time::Instant};
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+lazy_static! {
+    /// every metric of the server, apart from the process wide default registry
+    pub static ref REGISTRY: Registry = Registry::new_custom(Some("auth".to_string()), None)
+        .expect("the metrics prefix is valid");
+    static ref HTTP_REQUESTS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("http_requests_total", "handled http requests"),
+        &["method", "route", "status"],
+    ));
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new(
+            "http_request_duration_seconds",
+            "time from receiving a request to having its response",
+        ),
+        &["method", "route", "status"],
+    ));
+    static ref LOGINS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("logins_total", "login attempts, `reason` says why one failed"),
+        &["outcome", "reason"],
+    ));
+    static ref EMAILS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("emails_total", "emails handed to the email sender"),
+        &["outcome"],
+    ));
+    static ref EMAIL_CODE_VERIFICATIONS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("email_code_verifications_total", "checks of codes users entered"),
+        &["purpose", "outcome"],
+    ));
+    static ref DB_POOL_CONNECTIONS: IntGauge = register(IntGauge::new(
+        "db_pool_connections",
+        "open connections of the database pool",
+    ));
+    static ref DB_POOL_IDLE_CONNECTIONS: IntGauge = register(IntGauge::new(
+        "db_pool_idle_connections",
+        "open connections of the database pool that aren't in use",
+    ));
+    static ref DB_QUERY_DURATION: Histogram = register(Histogram::with_opts(
+        HistogramOpts::new("db_query_duration_seconds", "time the database took for a query")
+            .buckets(exponential_buckets(0.0001, 4.0, 9).expect("the buckets are valid")),
+    ));
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<T>) -> T {
+    let metric = metric.expect("the metric options are valid");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("every metric is registered once");
+    metric
+}
+
+/// counts and times a request, for `App::wrap_fn`. requests are grouped by the pattern of the
+/// route they matched, so that ids in paths don't make up new series
+pub fn track_request<S, B>(
+    req: ServiceRequest,
+    service: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let start = Instant::now();
+    let response = service.call(req);
+    async move {
+        let response = response.await?;
+        let request = response.request();
+        let route = request.match_pattern();
+        let status = response.status();
+        let labels = [
+            request.method().as_str(),
+            route.as_deref().unwrap_or("unmatched"),
+            status.as_str(),
+        ];
+        HTTP_REQUESTS.with_label_values(&labels).inc();
+        HTTP_REQUEST_DURATION
+            .with_label_values(&labels)
+            .observe(start.elapsed().as_secs_f64());
+        Ok(response)
+    }
+}
+
+/// `reason` is empty for successful logins
+pub fn record_login<T>(result: &Result<T, ApiError>) {
+    let (outcome, reason) = match result {
+        Ok(_) => ("success", ""),
+        Err(error) => ("failure", login_failure_reason(error)),
+    };
+    LOGINS.with_label_values(&[outcome, reason]).inc();
+}
+
+fn login_failure_reason(error: &ApiError) -> &'static str {
+    match error {
+        ApiError::WrongCredentials => "wrong_credentials",
+        ApiError::AccountPending => "account_pending",
+        ApiError::AccountSuspended => "account_suspended",
+        ApiError::AccountLocked => "account_locked",
+        ApiError::AccountDeletionScheduled => "account_deletion_scheduled",
+        ApiError::PasswordResetRequired => "password_reset_required",
+        ApiError::BadArgument { .. } => "bad_argument",
+        _ => "other",
+    }
+}
+
+pub fn record_email(sent: bool) {
+    let outcome = if sent { "sent" } else { "failed" };
+    EMAILS.with_label_values(&[outcome]).inc();
+}
+
+pub fn record_email_code_verification<T>(purpose: EmailCodePurpose, result: &Result<T, ApiError>) {
+    let outcome = match result {
+        Ok(_) => "success",
+        Err(ApiError::WrongEmailCode) => "wrong",
+        Err(ApiError::ExpiredEmailCode) => "expired",
+        Err(_) => "error",
+    };
+    EMAIL_CODE_VERIFICATIONS
+        .with_label_values(&[purpose.as_str(), outcome])
+        .inc();
+}
+
+/// sqlx reports how long every statement took in the log records of the `sqlx::query` target,
+/// like `SELECT …; rows affected: 0, rows returned: 1, elapsed: 1.234ms`
+fn query_duration(message: &str) -> Option<Duration> {
+    let elapsed = message.split("elapsed: ").nth(1)?;
+    let elapsed = elapsed.split_whitespace().next()?;
+    let unit_start = elapsed.find(|char: char| !char.is_ascii_digit() && char != '.')?;
+    let (value, unit) = elapsed.split_at(unit_start);
+    let value: f64 = value.parse().ok()?;
+    let seconds = match unit {
+        "s" => value,
+        "ms" => value / 1e3,
+        "µs" => value / 1e6,
+        "ns" => value / 1e9,
+        _ => return None,
+    };
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// takes the query durations out of the events of sqlx, meant to be filtered to the
+/// `sqlx::query` target. sqlx logs through the `log` crate, so the events come from the bridge
+/// of `tracing_subscriber`
+pub struct QueryMetricsLayer;
+
+impl<S: Subscriber> Layer<S> for QueryMetricsLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        if let Some(duration) = query_duration(&message.0) {
+            DB_QUERY_DURATION.observe(duration.as_secs_f64());
+        }
+    }
+}
+
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{value:?}");
+        }
+    }
+}
+
+/// every metric in the prometheus text format
+#[get("/metrics")]
+pub async fn metrics(pool: Data<DbPool>) -> HttpResponse {
+    DB_POOL_CONNECTIONS.set(pool.size().into());
+    DB_POOL_IDLE_CONNECTIONS.set(pool.num_idle() as i64);
+
+    let mut body = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut body) {
+        tracing::error!("encoding the metrics failed: {e}");
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType(
+            TextEncoder::new()
+                .format_type()
+                .parse()
+                .expect("the prometheus content type is valid"),
+        ))
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::helper::create_test_db;
+    use actix_web::{
+        get,
+        test::{self, TestRequest},
+        App,
+    };
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[get("/items/{id}")]
+    async fn item() -> &'static str {
+        ""
+    }
+
+    fn sample(body: &str, series: &str) -> f64 {
+        body.lines()
+            .find_map(|line| line.strip_prefix(series))
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn query_durations_are_read_from_sqlx_logs() {
+        for (message, expected) in [
+            (
+                "SELECT 1; rows affected: 0, rows returned: 1, elapsed: 1.500ms",
+                Some(0.0015),
+            ),
+            ("UPDATE …; rows affected: 1, rows returned: 0, elapsed: 250.000µs\n\nUPDATE users SET name = ?", Some(0.00025)),
+            ("SELECT 1; elapsed: 2.000s", Some(2.0)),
+            ("no timing here", None),
+        ] {
+            let duration = query_duration(message).map(|duration| duration.as_secs_f64());
+            match (duration, expected) {
+                (Some(duration), Some(expected)) => {
+                    assert!((duration - expected).abs() < 1e-9, "{message}")
+                }
+                _ => assert_eq!(duration, expected, "{message}"),
+            }
+        }
+    }
+
+    #[test]
+    fn query_durations_are_observed_from_sqlx_events() {
+        let before = DB_QUERY_DURATION.get_sample_count();
+        let subscriber = tracing_subscriber::registry().with(QueryMetricsLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "sqlx::query", "SELECT 1; rows returned: 1, elapsed: 1.000ms");
+        });
+        // other tests run queries at the same time
+        assert!(DB_QUERY_DURATION.get_sample_count() > before);
+    }
+
+    #[actix_web::test]
+    async fn requests_are_counted_per_route() {
+        let db = create_test_db().await;
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(track_request)
+                .app_data(Data::new(db))
+                .service(item)
+                .service(metrics),
+        )
+        .await;
+        let series = r#"auth_http_requests_total{method="GET",route="/items/{id}",status="200"}"#;
+        let body =
+            test::call_and_read_body(&app, TestRequest::get().uri("/metrics").to_request()).await;
+        let before = sample(std::str::from_utf8(&body).unwrap(), series);
+
+        for id in 1..=3 {
+            let req = TestRequest::get().uri(&format!("/items/{id}")).to_request();
+            test::call_service(&app, req).await;
+        }
+        record_login::<()>(&Err(ApiError::WrongCredentials));
+
+        let resp = test::call_service(&app, TestRequest::get().uri("/metrics").to_request()).await;
+        assert!(resp
+            .headers()
+            .get("content-type")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let body = test::read_body(resp).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        // other tests run at the same time and share the registry
+        assert!(sample(body, series) >= before + 3.0);
+        assert!(
+            sample(
+                body,
+                r#"auth_logins_total{outcome="failure",reason="wrong_credentials"}"#
+            ) >= 1.0
+        );
+        assert!(body.contains("auth_http_request_duration_seconds_bucket"));
+        assert!(body.contains("auth_db_pool_connections "));
+    }
+}
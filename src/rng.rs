@@ -0,0 +1,49 @@
+//! the source of the codes users type in, behind a trait so that tests can predict them
+
+use rand::RngCore;
+
+pub trait RngProvider {
+    fn rng(&self) -> Box<dyn RngCore + '_>;
+}
+
+#[derive(Clone, Default)]
+pub struct ThreadRngProvider {}
+
+impl ThreadRngProvider {
+    pub fn new() -> Self {
+        ThreadRngProvider {}
+    }
+}
+
+impl RngProvider for ThreadRngProvider {
+    fn rng(&self) -> Box<dyn RngCore + '_> {
+        Box::new(rand::thread_rng())
+    }
+}
+
+/// every call gets the next generator of a fixed sequence, so two providers with the same
+/// seed hand out the same numbers
+#[cfg(test)]
+pub struct SeededRngProvider {
+    seed: u64,
+    calls: std::sync::atomic::AtomicU64,
+}
+
+#[cfg(test)]
+impl SeededRngProvider {
+    pub fn new(seed: u64) -> Self {
+        SeededRngProvider {
+            seed,
+            calls: std::sync::atomic::AtomicU64::new(0),
+        }
+    }
+}
+
+#[cfg(test)]
+impl RngProvider for SeededRngProvider {
+    fn rng(&self) -> Box<dyn RngCore + '_> {
+        use rand::SeedableRng;
+        let call = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Box::new(rand::rngs::StdRng::seed_from_u64(self.seed + call))
+    }
+}
@@ -0,0 +1,456 @@
+//! logs and traces of the server. every request gets a span with its id, log lines are
+//! written as text or json with personal data masked, and spans can be exported to an
+//! OTLP collector
+
+use crate::{
+    config::{Config, LogFormat},
+    metrics::QueryMetricsLayer,
+    utils::random::generate_random_alphanumeric,
+};
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+};
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::{Status, TraceError, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    export::trace::{ExportResult, SpanData, SpanExporter},
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{self, BatchConfig, BatchSpanProcessor, EvictedQueue, Tracer, TracerProvider},
+    Resource,
+};
+use regex::{Captures, Regex};
+use std::{
+    borrow::Cow,
+    future::Future,
+    io::{self, Write},
+    pin::Pin,
+    time::Instant,
+};
+use tracing::{field, Instrument, Level};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    filter::Targets, fmt::MakeWriter, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
+    Layer,
+};
+
+/// header a request id is taken from and returned in
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+lazy_static! {
+    static ref EMAIL_ADDRESS: Regex =
+        Regex::new(r#"[^\s@"'<>(),;:\[\]]+@[^\s@"'<>(),;:\[\]]+"#).unwrap();
+    /// six digits after a `code` key or word, other numbers like ids and timings are kept
+    static ref EMAIL_CODE: Regex = Regex::new(r#"(?i)(code("?)\s*[:=]?\s*)("?)\d{6}\b"#).unwrap();
+}
+
+/// sets up logging for the whole process, `RUST_LOG` filters it like it did for env_logger.
+/// records of the `log` crate, like the ones of sqlx and actix, are passed on as well
+pub fn init(config: &Config) -> anyhow::Result<()> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let fmt = tracing_subscriber::fmt::layer().with_writer(RedactingWriter(io::stdout));
+    let fmt = match config.log_format {
+        LogFormat::Text => fmt.boxed(),
+        LogFormat::Json => fmt.json().boxed(),
+    };
+    // only our own spans leave the process, the ones of libraries may hold sql or addresses
+    let otlp = match &config.otlp_endpoint {
+        Some(endpoint) => Some(
+            tracing_opentelemetry::layer()
+                .with_tracer(otlp_tracer(endpoint, BatchConfig::default())?)
+                .with_filter(Targets::new().with_target("auth_system", Level::INFO)),
+        ),
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(fmt.with_filter(env_filter()))
+        .with(otlp)
+        .with(QueryMetricsLayer.with_filter(Targets::new().with_target("sqlx::query", Level::INFO)))
+        .try_init()?;
+    Ok(())
+}
+
+fn env_filter() -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
+}
+
+/// exports spans to the OTLP/HTTP collector at `endpoint` from a thread of its own, with
+/// their fields redacted like log lines
+pub fn otlp_tracer(endpoint: &str, batch_config: BatchConfig) -> Result<Tracer, TraceError> {
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(endpoint)
+        .build_span_exporter()?;
+    let processor =
+        BatchSpanProcessor::builder(RedactingExporter(exporter), runtime::TokioCurrentThread)
+            .with_batch_config(batch_config)
+            .build();
+    let provider = TracerProvider::builder()
+        .with_span_processor(processor)
+        .with_config(trace::config().with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            "auth_system",
+        )])))
+        .build();
+    let tracer = provider.tracer("auth_system");
+    global::set_tracer_provider(provider);
+    Ok(tracer)
+}
+
+/// redacts the spans before they leave the process, `RedactingWriter` only sees log lines
+#[derive(Debug)]
+struct RedactingExporter<E>(E);
+
+impl<E: SpanExporter> SpanExporter for RedactingExporter<E> {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<ExportResult> {
+        self.0.export(batch.into_iter().map(redact_span).collect())
+    }
+
+    fn shutdown(&mut self) {
+        self.0.shutdown();
+    }
+
+    fn force_flush(&mut self) -> BoxFuture<ExportResult> {
+        self.0.force_flush()
+    }
+}
+
+fn redact_span(mut span: SpanData) -> SpanData {
+    span.attributes = span.attributes.into_iter().map(redact_attribute).collect();
+    // the queue can't be changed in place, only the count of dropped events is lost
+    let mut events = EvictedQueue::new(span.events.len() as u32);
+    events.extend(span.events.into_iter().map(|mut event| {
+        event.name = redact(&event.name).into_owned().into();
+        event.attributes = event.attributes.into_iter().map(redact_attribute).collect();
+        event
+    }));
+    span.events = events;
+    if let Status::Error { description } = &span.status {
+        span.status = Status::error(redact(description).into_owned());
+    }
+    span
+}
+
+/// redacted with its key, so that codes are found by the name of their field
+fn redact_attribute(attribute: KeyValue) -> KeyValue {
+    let prefix = format!("{}=", attribute.key);
+    let text = format!("{prefix}{}", attribute.value);
+    match redact(&text) {
+        Cow::Borrowed(_) => attribute,
+        Cow::Owned(redacted) => KeyValue::new(
+            attribute.key,
+            redacted
+                .strip_prefix(&prefix)
+                .unwrap_or("[redacted]")
+                .to_string(),
+        ),
+    }
+}
+
+/// exports the spans that are still buffered, blocks until the collector got them
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// masks email addresses and six digit email codes
+pub fn redact(text: &str) -> Cow<'_, str> {
+    match EMAIL_ADDRESS.replace_all(text, "[email]") {
+        Cow::Borrowed(text) => EMAIL_CODE.replace_all(text, redact_code),
+        Cow::Owned(text) => Cow::Owned(EMAIL_CODE.replace_all(&text, redact_code).into_owned()),
+    }
+}
+
+fn redact_code(captures: &Captures) -> String {
+    // a number right after a json key is quoted, so that the line stays valid json
+    let quoted = !captures[2].is_empty() && captures[3].is_empty();
+    let quote = if quoted { "\"" } else { "" };
+    format!("{}{}{quote}[code]{quote}", &captures[1], &captures[3])
+}
+
+/// redacts whatever the log lines are written to. every line is written at once, so a
+/// match is never split between two writes
+pub struct RedactingWriter<M>(pub M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for RedactingWriter<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        self.0.write_all(redact(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// id of the request that is being handled, if it went through `trace_request`
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// ids of proxies and clients are kept as long as they can't mess up log lines
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || "-_.".contains(char))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// runs a request in a span with its id, for `App::wrap_fn`. the id is taken from the
+/// `X-Request-ID` header or generated, and returned in the same header. a `traceparent`
+/// header makes the span part of the trace of the caller
+pub fn trace_request<S, B>(
+    req: ServiceRequest,
+    service: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_owned)
+        .unwrap_or_else(|| generate_random_alphanumeric(20));
+    // the path isn't logged, it can hold email addresses
+    let span = tracing::info_span!(
+        "http_request",
+        request_id = %request_id,
+        method = %req.method(),
+        route = field::Empty,
+        status = field::Empty,
+    );
+    span.set_parent(global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    }));
+
+    let start = Instant::now();
+    let response =
+        span.in_scope(|| REQUEST_ID.sync_scope(request_id.clone(), || service.call(req)));
+    let header = HeaderValue::from_str(&request_id).expect("request ids are valid header values");
+    let handle = async move {
+        let mut response = response.await?;
+        let route = response.request().match_pattern();
+        let status = response.status();
+        let span = tracing::Span::current();
+        span.record("route", route.as_deref().unwrap_or("unmatched"));
+        span.record("status", status.as_u16());
+        tracing::info!(
+            latency_ms = start.elapsed().as_millis() as u64,
+            "{} {}",
+            status.as_u16(),
+            route.as_deref().unwrap_or("unmatched"),
+        );
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(REQUEST_ID_HEADER), header);
+        Ok(response)
+    };
+    REQUEST_ID.scope(request_id, handle.instrument(span))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ApiError;
+    use actix_web::{
+        get,
+        test::{self, TestRequest},
+        web::{self, Bytes},
+        App, HttpResponse, HttpServer,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[get("/fail")]
+    async fn fail() -> Result<HttpResponse, ApiError> {
+        Err(ApiError::WrongCredentials)
+    }
+
+    #[get("/weak")]
+    async fn weak() -> Result<HttpResponse, ApiError> {
+        Err(ApiError::WeakPassword { violations: vec![] })
+    }
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl<'a> MakeWriter<'a> for Output {
+        type Writer = Output;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn addresses_and_codes_are_redacted() {
+        assert_eq!(
+            redact("sent code 123456 to arian@gmail.com, not to <b.c+d@müller.de>"),
+            "sent code [code] to [email], not to <[email]>"
+        );
+        assert_eq!(
+            redact(r#"email_code=123456 {"email_code":123456,"code":"123456"}"#),
+            r#"email_code=[code] {"email_code":"[code]","code":"[code]"}"#
+        );
+        assert_eq!(
+            redact("elapsed: 1.234ms, id 123456, user 12345678, at 2023-10-14T08:30:21"),
+            "elapsed: 1.234ms, id 123456, user 12345678, at 2023-10-14T08:30:21"
+        );
+
+        let output = Output::default();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_writer(RedactingWriter(output.clone()))
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(
+                user = "arian@gmail.com",
+                email_code = 123456,
+                "code {} is wrong",
+                654321
+            );
+        });
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(line["fields"]["user"], "[email]");
+        assert_eq!(line["fields"]["email_code"], "[code]");
+        assert_eq!(line["fields"]["message"], "code [code] is wrong");
+    }
+
+    #[actix_web::test]
+    async fn request_ids_are_returned_in_headers_and_errors() {
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(trace_request)
+                .service(fail)
+                .service(weak),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/fail")
+            .insert_header((REQUEST_ID_HEADER, "from-the-proxy.1"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "from-the-proxy.1"
+        );
+        let body = test::read_body(resp).await;
+        assert_eq!(body, "wrong credentials\nrequest id: from-the-proxy.1");
+
+        let req = TestRequest::get()
+            .uri("/weak")
+            .insert_header((REQUEST_ID_HEADER, "no spaces"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let request_id = resp.headers().get(REQUEST_ID_HEADER).unwrap().clone();
+        assert_ne!(request_id, "no spaces");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["request_id"], request_id.to_str().unwrap());
+
+        // without the middleware errors stay as they were
+        let app = test::init_service(App::new().service(fail)).await;
+        let req = TestRequest::get().uri("/fail").to_request();
+        assert_eq!(
+            test::call_and_read_body(&app, req).await,
+            "wrong credentials"
+        );
+    }
+
+    #[actix_web::test]
+    async fn spans_are_exported_to_the_collector() {
+        let received = Arc::new(Mutex::new(Vec::<Bytes>::new()));
+        let received_clone = received.clone();
+        let server = HttpServer::new(move || {
+            let received = received_clone.clone();
+            App::new().route(
+                "/v1/traces",
+                web::post().to(move |body: Bytes| {
+                    received.lock().unwrap().push(body);
+                    async { HttpResponse::Ok().finish() }
+                }),
+            )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let endpoint = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        let tracer = otlp_tracer(&endpoint, BatchConfig::default()).unwrap();
+        let provider = tracer.provider().unwrap();
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("exported_span", user = "arian@gmail.com").in_scope(|| {
+                tracing::info!(email_code = 123456, "inside, sent to pouya@gmail.com")
+            });
+        });
+        // the exporter posts to the collector, which runs on this thread
+        actix_web::rt::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap();
+
+        let received = received.lock().unwrap();
+        let contains = |needle: &[u8]| {
+            received
+                .iter()
+                .any(|body| body.windows(needle.len()).any(|window| window == needle))
+        };
+        assert!(contains(b"exported_span"));
+        assert!(contains(b"auth_system"));
+        assert!(contains(b"inside, sent to [email]"));
+        assert!(contains(b"[code]"));
+        assert!(!contains(b"arian@gmail.com"));
+        assert!(!contains(b"pouya@gmail.com"));
+        assert!(!contains(b"123456"));
+    }
+}
@@ -0,0 +1,337 @@
+use crate::{
+    breached_passwords::BreachedPasswords,
+    db::{
+        tenants::{get_tenant, get_tenant_id_by_host, Tenant, TenantSettings, DEFAULT_TENANT_ID},
+        user::is_recent_password,
+        DbPool,
+    },
+    email_domains::{DomainList, EmailDomainLists},
+    error::{ApiError, ApiResult},
+    utils::{
+        email::{EmailAddress, EmailCanonicalization},
+        password_policy::{PasswordPolicy, PasswordViolation},
+    },
+};
+use actix_web::{dev::Payload, web::Data, FromRequest, HttpMessage, HttpRequest};
+use chrono::{DateTime, Duration, Utc};
+use std::{future::Future, pin::Pin};
+
+pub const TENANT_HEADER: &str = "X-Tenant";
+/// routes are also mounted under `/t/{tenant}`, for clients that can't set headers
+pub const TENANT_PATH_PARAMETER: &str = "tenant";
+
+/// the tenant a request explicitly asks for, the path wins over the header
+fn requested_tenant_id(req: &HttpRequest) -> Option<String> {
+    req.match_info()
+        .get(TENANT_PATH_PARAMETER)
+        .map(str::to_string)
+        .or_else(|| {
+            req.headers()
+                .get(TENANT_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        })
+}
+
+/// host of the request without the port
+fn request_host(req: &HttpRequest) -> String {
+    let connection_info = req.connection_info();
+    let host = connection_info.host();
+    host.rsplit_once(':')
+        .filter(|(_, port)| port.chars().all(|char| char.is_ascii_digit()))
+        .map_or(host, |(host, _)| host)
+        .to_string()
+}
+
+/// the tenant of a request comes from the path, the `X-Tenant` header or the host, in that
+/// order, and is the default tenant if none of them names one. naming a tenant that doesn't
+/// exist is an error instead of a silent fallback
+pub async fn resolve_tenant(req: HttpRequest) -> ApiResult<Tenant> {
+    let cached = req.extensions().get::<Tenant>().cloned();
+    if let Some(tenant) = cached {
+        return Ok(tenant);
+    }
+    let pool = req
+        .app_data::<Data<DbPool>>()
+        .cloned()
+        .ok_or(ApiError::UnknownTenant)?;
+
+    let tenant_id = match requested_tenant_id(&req) {
+        Some(tenant_id) => tenant_id,
+        None => get_tenant_id_by_host(&pool, &request_host(&req))
+            .await?
+            .unwrap_or_else(|| DEFAULT_TENANT_ID.to_string()),
+    };
+    let tenant = get_tenant(&pool, &tenant_id)
+        .await?
+        .ok_or(ApiError::UnknownTenant)?;
+    req.extensions_mut().insert(tenant.clone());
+    Ok(tenant)
+}
+
+impl FromRequest for Tenant {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = ApiResult<Self>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        Box::pin(resolve_tenant(req.clone()))
+    }
+}
+
+impl TenantSettings {
+    /// denied domains win over allowed ones, and explicitly allowed domains are never refused
+    /// as disposable
+    pub fn check_email_address(
+        &self,
+        email_address: &str,
+        email_domain_lists: &EmailDomainLists,
+    ) -> ApiResult<()> {
+        let email_address = EmailAddress::parse(email_address)?;
+        let domain = email_address.domain();
+        let denied = DomainList::new(self.denied_email_domains.iter().map(String::as_str));
+        if denied.matches(domain) || email_domain_lists.is_denied(domain) {
+            return Err(ApiError::EmailDomainNotAllowed);
+        }
+        let allowed = DomainList::new(self.allowed_email_domains.iter().map(String::as_str));
+        if !allowed.is_empty() {
+            return allowed
+                .matches(domain)
+                .then_some(())
+                .ok_or(ApiError::EmailDomainNotAllowed);
+        }
+        if self.block_disposable_email_domains && email_domain_lists.is_disposable(domain) {
+            return Err(ApiError::DisposableEmailAddress);
+        }
+        Ok(())
+    }
+
+    pub fn email_canonicalization(&self) -> EmailCanonicalization {
+        EmailCanonicalization {
+            lowercase_local_part: self.lowercase_email_local_part,
+            provider_rules: self.canonicalize_email_providers,
+        }
+    }
+
+    pub fn password_policy(&self) -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: self.min_password_length as usize,
+            max_length: self.max_password_length as usize,
+            required_classes: self.password_character_classes.clone(),
+            min_score: self.min_password_score as u8,
+        }
+    }
+
+    /// on top of `validate_password`, which every tenant gets. `personal_info` is what the
+    /// password shouldn't contain, like the name and email address of the user
+    pub fn check_password(
+        &self,
+        password: &str,
+        personal_info: &[&str],
+        breached_passwords: &BreachedPasswords,
+    ) -> ApiResult<()> {
+        let mut violations = self.password_policy().check(password, personal_info);
+        if let Some(count) = breached_passwords.breach_count(password) {
+            violations.push(PasswordViolation::Breached { count });
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::WeakPassword { violations })
+        }
+    }
+
+    /// rejects the password if it's one of the user's last `password_history_size` passwords,
+    /// `hashed_password` is hashed like the stored passwords
+    pub async fn check_password_reuse(
+        &self,
+        pool: &DbPool,
+        user_id: i64,
+        hashed_password: &str,
+    ) -> ApiResult<()> {
+        if is_recent_password(pool, user_id, hashed_password, self.password_history_size).await? {
+            return Err(ApiError::WeakPassword {
+                violations: vec![PasswordViolation::Reused {
+                    history_size: self.password_history_size as usize,
+                }],
+            });
+        }
+        Ok(())
+    }
+
+    /// passwords of accounts created before the change date was recorded never expire
+    pub fn is_password_expired(
+        &self,
+        password_changed_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        self.password_max_age_days > 0
+            && password_changed_at.is_some_and(|changed_at| {
+                changed_at + Duration::days(self.password_max_age_days) <= now
+            })
+    }
+
+    /// subject and body of the email that carries an email code
+    pub fn email_code_message(&self, code: u32) -> (String, String) {
+        (
+            self.email_code_subject.clone(),
+            self.email_code_template
+                .replace("{code}", &code.to_string()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::tenants::insert_tenant, test::helper::create_test_db};
+    use actix_web::{
+        get,
+        http::{header::HOST, StatusCode},
+        test::{self, TestRequest},
+        web, App,
+    };
+
+    #[get("/tenant_id")]
+    async fn tenant_id(tenant: Tenant) -> String {
+        tenant.id
+    }
+
+    #[actix_web::test]
+    async fn tenant_is_resolved_from_path_header_or_host() {
+        let db = create_test_db().await;
+        insert_tenant(&db, "shop", "Shop", Some("shop.example.com"))
+            .await
+            .unwrap();
+        insert_tenant(&db, "blog", "Blog", None).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .service(web::scope("/t/{tenant}").service(tenant_id))
+                .service(tenant_id),
+        )
+        .await;
+
+        for (req, expected) in [
+            (TestRequest::get().uri("/tenant_id"), "default"),
+            (TestRequest::get().uri("/t/blog/tenant_id"), "blog"),
+            (
+                TestRequest::get()
+                    .uri("/tenant_id")
+                    .insert_header((TENANT_HEADER, "blog")),
+                "blog",
+            ),
+            (
+                TestRequest::get()
+                    .uri("/tenant_id")
+                    .insert_header((HOST, "shop.example.com:8000")),
+                "shop",
+            ),
+            // an explicit tenant wins over the host
+            (
+                TestRequest::get()
+                    .uri("/t/blog/tenant_id")
+                    .insert_header((HOST, "shop.example.com")),
+                "blog",
+            ),
+        ] {
+            let body = test::call_and_read_body(&app, req.to_request()).await;
+            assert_eq!(body, expected);
+        }
+
+        let req = TestRequest::get()
+            .uri("/tenant_id")
+            .insert_header((TENANT_HEADER, "nope"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn settings_checks() {
+        let settings = TenantSettings {
+            allowed_email_domains: vec!["example.com".to_string()],
+            denied_email_domains: vec![],
+            block_disposable_email_domains: true,
+            lowercase_email_local_part: true,
+            canonicalize_email_providers: false,
+            min_password_length: 8,
+            max_password_length: 64,
+            password_character_classes: vec![],
+            min_password_score: 2,
+            password_history_size: 0,
+            password_max_age_days: 90,
+            mfa_required: false,
+            email_code_subject: "Your code".to_string(),
+            email_code_template: "code: {code}".to_string(),
+        };
+        let lists = EmailDomainLists::default();
+        assert!(settings
+            .check_email_address("arian@Example.com", &lists)
+            .is_ok());
+        assert_eq!(
+            settings.check_email_address("arian@gmail.com", &lists),
+            Err(ApiError::EmailDomainNotAllowed)
+        );
+        let settings = TenantSettings {
+            allowed_email_domains: vec![],
+            denied_email_domains: vec!["*.gmail.com".to_string(), "hotmail.com".to_string()],
+            ..settings
+        };
+        for (email_address, expected) in [
+            ("arian@gmail.com", Ok(())),
+            ("arian@eu.gmail.com", Err(ApiError::EmailDomainNotAllowed)),
+            ("arian@HOTMAIL.com", Err(ApiError::EmailDomainNotAllowed)),
+            ("arian@yopmail.com", Err(ApiError::DisposableEmailAddress)),
+        ] {
+            assert_eq!(
+                settings.check_email_address(email_address, &lists),
+                expected,
+                "{email_address}"
+            );
+        }
+        // allowing a disposable domain explicitly lets it through
+        let allowing_disposable = TenantSettings {
+            allowed_email_domains: vec!["yopmail.com".to_string()],
+            ..settings.clone()
+        };
+        assert!(allowing_disposable
+            .check_email_address("arian@yopmail.com", &lists)
+            .is_ok());
+        let not_blocking = TenantSettings {
+            block_disposable_email_domains: false,
+            ..settings.clone()
+        };
+        assert!(not_blocking
+            .check_email_address("arian@yopmail.com", &lists)
+            .is_ok());
+        assert_eq!(
+            settings.check_password("short", &[], &BreachedPasswords::default()),
+            Err(ApiError::WeakPassword {
+                violations: vec![PasswordViolation::TooShort { min_length: 8 }]
+            })
+        );
+        assert!(settings
+            .check_password("long enough", &[], &BreachedPasswords::default())
+            .is_ok());
+        assert!(settings
+            .check_password(
+                "arian-long-enough",
+                &["arian@gmail.com"],
+                &BreachedPasswords::default()
+            )
+            .is_err());
+        let now = Utc::now();
+        assert!(!settings.is_password_expired(Some(now - Duration::days(89)), now));
+        assert!(settings.is_password_expired(Some(now - Duration::days(90)), now));
+        assert!(!settings.is_password_expired(None, now));
+        let never_expire = TenantSettings {
+            password_max_age_days: 0,
+            ..settings.clone()
+        };
+        assert!(!never_expire.is_password_expired(Some(now - Duration::days(1000)), now));
+        assert_eq!(
+            settings.email_code_message(123456),
+            ("Your code".to_string(), "code: 123456".to_string())
+        );
+    }
+}
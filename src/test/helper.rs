@@ -1,10 +1,28 @@
 use std::fs::File;
 
 use crate::{
-    db::{establish_connection, setup, DbPool},
-    utils::random::generate_random_six_digit_code,
+    breached_passwords::BreachedPasswords,
+    clock::{Clock, SystemClock},
+    db::{
+        establish_connection,
+        oauth::{insert_oauth_client, OAuthClient},
+        sessions::insert_session,
+        setup,
+        tenants::DEFAULT_TENANT_ID,
+        user::{get_user, insert_user},
+        DbPool,
+    },
+    rng::{RngProvider, ThreadRngProvider},
+    utils::{
+        hash::sha256_hash,
+        random::{generate_random_six_digit_code, generate_random_token},
+    },
 };
+use actix_web::web::Data;
 use anyhow::Result;
+use chrono::{Duration, Utc};
+use sha1::{Digest, Sha1};
+use std::{path::Path, sync::Arc};
 
 pub async fn create_test_db() -> DbPool {
     let db_file = format!("/tmp/testdb_{}", generate_random_six_digit_code());
@@ -18,9 +36,101 @@ pub async fn create_test_db() -> DbPool {
 }
 
 async fn reset_db(pool: &DbPool) -> Result<()> {
-    sqlx::query!("DROP TABLE IF EXISTS users; DROP TABLE IF EXISTS email_codes")
-        .execute(pool)
-        .await?;
+    sqlx::query!(
+        "DROP TABLE IF EXISTS users; DROP TABLE IF EXISTS email_codes; DROP TABLE IF EXISTS sessions;
+        DROP TABLE IF EXISTS oauth_clients; DROP TABLE IF EXISTS oauth_authorization_codes;
+        DROP TABLE IF EXISTS oauth_refresh_tokens; DROP TABLE IF EXISTS signing_keys;
+        DROP TABLE IF EXISTS service_accounts; DROP TABLE IF EXISTS service_account_secrets;
+        DROP TABLE IF EXISTS api_keys; DROP TABLE IF EXISTS device_codes;
+        DROP TABLE IF EXISTS roles; DROP TABLE IF EXISTS permissions;
+        DROP TABLE IF EXISTS role_permissions; DROP TABLE IF EXISTS user_roles;
+        DROP TABLE IF EXISTS audit_log; DROP TABLE IF EXISTS webhooks;
+        DROP TABLE IF EXISTS webhook_deliveries; DROP TABLE IF EXISTS webhook_delivery_attempts;
+        DROP TABLE IF EXISTS invitations; DROP TABLE IF EXISTS email_changes;
+        DROP TABLE IF EXISTS account_deletions;
+        DROP TABLE IF EXISTS tenants"
+    )
+    .execute(pool)
+    .await?;
 
     Ok(())
 }
+
+/// inserts a user of the default tenant with password `some_hard_password` and returns a
+/// valid session token for it
+pub async fn create_test_user_with_session(pool: &DbPool, email_address: &str) -> String {
+    let user_id = insert_user(
+        pool,
+        DEFAULT_TENANT_ID,
+        "arian",
+        &sha256_hash("some_hard_password"),
+        email_address,
+    )
+    .await
+    .unwrap();
+
+    let session_token = generate_random_token();
+    insert_session(
+        pool,
+        &sha256_hash(&session_token),
+        user_id,
+        Utc::now() + Duration::days(1),
+    )
+    .await
+    .unwrap();
+    session_token
+}
+
+/// id of a user of the default tenant
+pub async fn test_user_id(pool: &DbPool, email_address: &str) -> i64 {
+    get_user(pool, DEFAULT_TENANT_ID, email_address)
+        .await
+        .unwrap()
+        .unwrap()
+        .id
+}
+
+/// inserts an oauth client with id `test_client`, redirect uri `https://app.example.com/callback`
+/// and scopes `openid email profile`
+pub async fn create_test_oauth_client(pool: &DbPool, client_secret: Option<&str>) {
+    let client = OAuthClient {
+        client_id: "test_client".to_string(),
+        client_secret_hash: client_secret.map(sha256_hash),
+        name: "test client".to_string(),
+        redirect_uris: vec!["https://app.example.com/callback".to_string()],
+        scopes: vec![
+            "openid".to_string(),
+            "email".to_string(),
+            "profile".to_string(),
+        ],
+        owner_email_address: "owner@gmail.com".to_string(),
+    };
+    insert_oauth_client(pool, &client).await.unwrap();
+}
+
+/// a breach list that contains exactly these passwords
+pub fn create_test_breached_passwords(passwords: &[&str]) -> BreachedPasswords {
+    let mut lines: Vec<String> = passwords
+        .iter()
+        .map(|password| format!("{:X}:10", Sha1::digest(password.as_bytes())))
+        .collect();
+    lines.sort();
+    let path = format!(
+        "/tmp/breached_passwords_{}",
+        generate_random_six_digit_code()
+    );
+    std::fs::write(&path, lines.join("\n")).unwrap();
+    BreachedPasswords::open(Path::new(&path), 1).unwrap()
+}
+
+/// the real clock, as handlers take it from app data
+pub fn create_test_clock() -> Data<dyn Clock + Send + Sync> {
+    let clock: Arc<dyn Clock + Send + Sync> = Arc::new(SystemClock::new());
+    Data::from(clock)
+}
+
+/// the real random numbers, as handlers take them from app data
+pub fn create_test_rng() -> Data<dyn RngProvider + Send + Sync> {
+    let rng: Arc<dyn RngProvider + Send + Sync> = Arc::new(ThreadRngProvider::new());
+    Data::from(rng)
+}
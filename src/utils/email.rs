@@ -0,0 +1,235 @@
+use crate::error::{ApiError, ApiResult};
+
+/// limits of rfc 5321 section 4.5.3.1, in octets
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+const MAX_DOMAIN_LENGTH: usize = 253;
+const MAX_ADDRESS_LENGTH: usize = 254;
+
+/// providers that ignore dots in the local part
+const DOT_INSENSITIVE_DOMAINS: &[&str] = &["gmail.com", "googlemail.com"];
+/// providers that deliver `user+tag@` to `user@`
+const PLUS_TAG_DOMAINS: &[&str] = &[
+    "gmail.com",
+    "googlemail.com",
+    "outlook.com",
+    "hotmail.com",
+    "live.com",
+    "icloud.com",
+    "me.com",
+    "fastmail.com",
+    "proton.me",
+    "protonmail.com",
+];
+
+/// how much of an address is ignored when telling whether two addresses are the same mailbox,
+/// the domain is always case-insensitive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmailCanonicalization {
+    /// rfc 5321 leaves the case of the local part to the receiving server, but hardly any
+    /// server cares about it
+    pub lowercase_local_part: bool,
+    /// dots and `+tags` of the providers known to ignore them
+    pub provider_rules: bool,
+}
+
+/// a mailbox of rfc 5321 with the utf-8 local parts of rfc 6531. the domain is kept in its
+/// ascii form, internationalized domains are converted to punycode
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailAddress {
+    local_part: String,
+    domain: String,
+}
+
+fn bad_address() -> ApiError {
+    ApiError::BadArgument {
+        argument_name: "email_address",
+    }
+}
+
+/// `atext` of rfc 5322, extended with every non-ascii character but controls by rfc 6531
+fn is_atext(char: char) -> bool {
+    char.is_ascii_alphanumeric()
+        || "!#$%&'*+-/=?^_`{|}~".contains(char)
+        || (!char.is_ascii() && !char.is_control())
+}
+
+fn is_dot_atom(local_part: &str) -> bool {
+    local_part
+        .split('.')
+        .all(|atom| !atom.is_empty() && atom.chars().all(is_atext))
+}
+
+/// `quoted-string` of rfc 5321, the quotes included
+fn is_quoted_string(local_part: &str) -> bool {
+    let Some(content) = local_part
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    else {
+        return false;
+    };
+    let mut chars = content.chars();
+    while let Some(char) = chars.next() {
+        let is_valid = match char {
+            '\\' => chars
+                .next()
+                .is_some_and(|quoted| (' '..='~').contains(&quoted)),
+            '"' => false,
+            _ => (' '..='~').contains(&char) || (!char.is_ascii() && !char.is_control()),
+        };
+        if !is_valid {
+            return false;
+        }
+    }
+    true
+}
+
+/// letters, digits and hyphens, not at the start or end of a label
+fn is_valid_label(label: &str) -> bool {
+    (1..=63).contains(&label.len())
+        && label
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '-')
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+}
+
+impl EmailAddress {
+    /// address literals like `user@[127.0.0.1]` are refused, they're no use for accounts
+    pub fn parse(email_address: &str) -> ApiResult<Self> {
+        let (local_part, domain) = email_address.rsplit_once('@').ok_or_else(bad_address)?;
+        let is_valid_local_part = local_part.len() <= MAX_LOCAL_PART_LENGTH
+            && (is_dot_atom(local_part) || is_quoted_string(local_part));
+        if !is_valid_local_part {
+            return Err(bad_address());
+        }
+
+        let domain = idna::domain_to_ascii(domain).map_err(|_| bad_address())?;
+        let labels: Vec<&str> = domain.split('.').collect();
+        let is_valid_domain = domain.len() <= MAX_DOMAIN_LENGTH
+            && labels.len() >= 2
+            && labels.iter().all(|label| is_valid_label(label));
+        if !is_valid_domain || local_part.len() + 1 + domain.len() > MAX_ADDRESS_LENGTH {
+            return Err(bad_address());
+        }
+
+        Ok(EmailAddress {
+            local_part: local_part.to_string(),
+            domain,
+        })
+    }
+
+    /// the domain in its lowercase ascii form
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// the form two addresses of the same mailbox share
+    pub fn canonical(&self, canonicalization: &EmailCanonicalization) -> String {
+        let mut local_part = self.local_part.clone();
+        let mut domain = self.domain.as_str();
+        if canonicalization.lowercase_local_part {
+            local_part = local_part.to_lowercase();
+        }
+        if canonicalization.provider_rules {
+            if PLUS_TAG_DOMAINS.contains(&domain) {
+                if let Some((user, _)) = local_part.split_once('+') {
+                    local_part = user.to_string();
+                }
+            }
+            if DOT_INSENSITIVE_DOMAINS.contains(&domain) {
+                // both domains are the same mailboxes, and their local parts ignore case too
+                local_part = local_part.replace('.', "").to_lowercase();
+                domain = "gmail.com";
+            }
+        }
+        format!("{local_part}@{domain}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_should_follow_the_rfcs() {
+        for email_address in [
+            "arian@gmail.com",
+            "arian.pouya+news@mail.example.co.uk",
+            "o'neil@example.com",
+            "\"arian pouya\"@example.com",
+            "\"a@b\"@example.com",
+            "آرین@example.com",
+            "arian@bücher.de",
+        ] {
+            assert!(
+                EmailAddress::parse(email_address).is_ok(),
+                "{email_address}"
+            );
+        }
+        for email_address in [
+            "arian",
+            "@gmail.com",
+            "arian@",
+            "arian@localhost",
+            ".arian@gmail.com",
+            "arian.@gmail.com",
+            "ari..an@gmail.com",
+            "ari an@gmail.com",
+            "arian@-gmail.com",
+            "arian@gmail_.com",
+            "arian@[127.0.0.1]",
+            "\"unterminated@gmail.com",
+            "ari\nan@gmail.com",
+        ] {
+            assert!(
+                EmailAddress::parse(email_address).is_err(),
+                "{email_address}"
+            );
+        }
+        let long_local_part = format!("{}@gmail.com", "a".repeat(MAX_LOCAL_PART_LENGTH + 1));
+        assert!(EmailAddress::parse(&long_local_part).is_err());
+
+        assert_eq!(
+            EmailAddress::parse("arian@Bücher.DE").unwrap().domain(),
+            "xn--bcher-kva.de"
+        );
+    }
+
+    #[test]
+    fn canonical_forms() {
+        let plain = EmailCanonicalization {
+            lowercase_local_part: false,
+            provider_rules: false,
+        };
+        let lowercase = EmailCanonicalization {
+            lowercase_local_part: true,
+            ..plain
+        };
+        let providers = EmailCanonicalization {
+            provider_rules: true,
+            ..lowercase
+        };
+        for (email_address, canonicalization, expected) in [
+            ("Arian@Gmail.COM", plain, "Arian@gmail.com"),
+            ("Arian@Gmail.COM", lowercase, "arian@gmail.com"),
+            (
+                "A.Rian+news@googlemail.com",
+                lowercase,
+                "a.rian+news@googlemail.com",
+            ),
+            ("A.Rian+news@googlemail.com", providers, "arian@gmail.com"),
+            ("a.rian+news@outlook.com", providers, "a.rian@outlook.com"),
+            (
+                "a.rian+news@example.com",
+                providers,
+                "a.rian+news@example.com",
+            ),
+            ("arian@bücher.de", providers, "arian@xn--bcher-kva.de"),
+        ] {
+            let canonical = EmailAddress::parse(email_address)
+                .unwrap()
+                .canonical(&canonicalization);
+            assert_eq!(canonical, expected, "{email_address}");
+        }
+    }
+}
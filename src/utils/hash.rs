@@ -1,3 +1,5 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::hmac;
 use sha2::{Digest, Sha256};
 
 pub fn sha256_hash(input: &str) -> String {
@@ -7,6 +9,21 @@ pub fn sha256_hash(input: &str) -> String {
     format!("{:x}", result)
 }
 
+/// base64url encoded sha256 without padding, as pkce `S256` challenges use
+pub fn sha256_base64url(input: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(input))
+}
+
+/// hex encoded hmac-sha256, used to sign webhook deliveries
+pub fn hmac_sha256_hex(key: &str, input: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+    hmac::sign(&key, input.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -26,4 +43,22 @@ mod tests {
             "60fcd6b50b3d0d0bbf8d13ed5ff7e4b1844a1239fec1a94b0fe189222670e832"
         );
     }
+
+    #[test]
+    fn sha256_base64url_should_work() {
+        // example from rfc 7636 appendix B
+        assert_eq!(
+            sha256_base64url("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn hmac_sha256_hex_should_work() {
+        // test case 2 from rfc 4231
+        assert_eq!(
+            hmac_sha256_hex("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
 }
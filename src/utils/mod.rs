@@ -1,3 +1,5 @@
+pub mod email;
 pub mod hash;
+pub mod password_policy;
 pub mod random;
 pub mod validators;
@@ -0,0 +1,360 @@
+use serde::{Deserialize, Serialize};
+
+/// most common passwords and password words, most common first
+const COMMON_PASSWORDS: &[&str] = &[
+    "password", "123456", "qwerty", "111111", "abc123", "letmein", "welcome", "monkey", "dragon",
+    "iloveyou", "admin", "login", "master", "sunshine", "princess", "football", "baseball",
+    "shadow", "superman", "trustno", "starwars", "whatever", "freedom", "secret", "hello",
+    "charlie", "michael", "jordan", "soccer", "hockey", "killer", "batman", "computer", "internet",
+    "summer", "winter", "flower", "cheese", "pepper", "ginger", "orange", "banana", "purple",
+    "silver", "golden", "lovely", "hunter", "ranger", "buster", "tigger", "guest", "changeme",
+    "default", "access", "love", "pass", "test", "user", "root", "god",
+];
+
+const KEYBOARD_ROWS: &[&str] = &["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CharacterClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol,
+}
+
+impl CharacterClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lowercase => "lowercase",
+            Self::Uppercase => "uppercase",
+            Self::Digit => "digit",
+            Self::Symbol => "symbol",
+        }
+    }
+
+    pub fn parse(class: &str) -> Option<Self> {
+        match class {
+            "lowercase" => Some(Self::Lowercase),
+            "uppercase" => Some(Self::Uppercase),
+            "digit" => Some(Self::Digit),
+            "symbol" => Some(Self::Symbol),
+            _ => None,
+        }
+    }
+
+    /// letters of other scripts count as lowercase unless they have a case
+    fn of(char: char) -> Self {
+        if char.is_uppercase() {
+            Self::Uppercase
+        } else if char.is_alphabetic() {
+            Self::Lowercase
+        } else if char.is_numeric() {
+            Self::Digit
+        } else {
+            Self::Symbol
+        }
+    }
+}
+
+/// one rule a password breaks, all of them are returned so users can fix them at once
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum PasswordViolation {
+    TooShort {
+        min_length: usize,
+    },
+    TooLong {
+        max_length: usize,
+    },
+    MissingCharacterClass {
+        class: CharacterClass,
+    },
+    /// `score` is the estimated strength from 0 (trivial) to 4 (very strong)
+    TooWeak {
+        score: u8,
+        min_score: u8,
+    },
+    /// the password contains the name or email address of the user
+    ContainsPersonalInfo,
+    /// the password is in the list of breached passwords, `count` is how often it was seen
+    Breached {
+        count: u64,
+    },
+    /// the password is one of the last `history_size` passwords of the user
+    Reused {
+        history_size: usize,
+    },
+}
+
+/// lengths are counted in unicode characters, not bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub required_classes: Vec<CharacterClass>,
+    pub min_score: u8,
+}
+
+impl PasswordPolicy {
+    /// `personal_info` is what the password shouldn't contain, like the name and email address
+    pub fn check(&self, password: &str, personal_info: &[&str]) -> Vec<PasswordViolation> {
+        let mut violations = vec![];
+        let length = password.chars().count();
+        if length < self.min_length {
+            violations.push(PasswordViolation::TooShort {
+                min_length: self.min_length,
+            });
+        }
+        if length > self.max_length {
+            violations.push(PasswordViolation::TooLong {
+                max_length: self.max_length,
+            });
+        }
+        for class in &self.required_classes {
+            if !password
+                .chars()
+                .any(|char| CharacterClass::of(char) == *class)
+            {
+                violations.push(PasswordViolation::MissingCharacterClass { class: *class });
+            }
+        }
+
+        let personal_info = personal_words(personal_info);
+        let lowercase_password = password.to_lowercase();
+        if personal_info
+            .iter()
+            .any(|word| lowercase_password.contains(word.as_str()))
+        {
+            violations.push(PasswordViolation::ContainsPersonalInfo);
+        }
+        let score = estimate_score(password, &personal_info);
+        if score < self.min_score {
+            violations.push(PasswordViolation::TooWeak {
+                score,
+                min_score: self.min_score,
+            });
+        }
+        violations
+    }
+}
+
+/// lowercased inputs and the parts of email addresses, shorter ones would match by chance
+fn personal_words(personal_info: &[&str]) -> Vec<String> {
+    let mut words = vec![];
+    for info in personal_info {
+        let info = info.to_lowercase();
+        if let Some((local_part, _)) = info.split_once('@') {
+            words.push(local_part.to_string());
+        }
+        words.push(info);
+    }
+    words.retain(|word| word.chars().count() >= 3);
+    words
+}
+
+/// undoes common character substitutions, so `p@ssw0rd` is still found in the dictionary
+fn unleet(char: char) -> char {
+    match char {
+        '0' => 'o',
+        '1' | '!' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        _ => char,
+    }
+}
+
+/// how many characters a brute force over the classes used in the password has to try
+fn cardinality(chars: &[char]) -> f64 {
+    let mut cardinality = 0.0;
+    for (class, size) in [
+        (CharacterClass::Lowercase, 26.0),
+        (CharacterClass::Uppercase, 26.0),
+        (CharacterClass::Digit, 10.0),
+        (CharacterClass::Symbol, 33.0),
+    ] {
+        if chars.iter().any(|char| CharacterClass::of(*char) == class) {
+            cardinality += size;
+        }
+    }
+    if chars.iter().any(|char| !char.is_ascii()) {
+        cardinality += 100.0;
+    }
+    f64::max(cardinality, 1.0)
+}
+
+fn starts_with(chars: &[char], word: &str) -> bool {
+    let word: Vec<char> = word.chars().collect();
+    chars.len() >= word.len() && chars[..word.len()] == word[..]
+}
+
+/// length of the run of the same character at the start
+fn repeat_length(chars: &[char]) -> usize {
+    chars.iter().take_while(|char| **char == chars[0]).count()
+}
+
+/// length of the run like `abc`, `987` or `asdf` at the start, `adjacent` tells if two
+/// characters follow each other in the same direction
+fn run_length(chars: &[char], adjacent: impl Fn(char, char) -> Option<i64>) -> usize {
+    if chars.len() < 2 {
+        return chars.len();
+    }
+    let Some(direction) = adjacent(chars[0], chars[1]) else {
+        return 1;
+    };
+    1 + chars
+        .windows(2)
+        .take_while(|pair| adjacent(pair[0], pair[1]) == Some(direction))
+        .count()
+}
+
+fn alphabet_step(a: char, b: char) -> Option<i64> {
+    let same_kind = (a.is_ascii_lowercase() && b.is_ascii_lowercase())
+        || (a.is_ascii_digit() && b.is_ascii_digit());
+    let step = b as i64 - a as i64;
+    (same_kind && step.abs() == 1).then_some(step)
+}
+
+fn keyboard_step(a: char, b: char) -> Option<i64> {
+    KEYBOARD_ROWS.iter().find_map(|row| {
+        let step = row.find(b)? as i64 - row.find(a)? as i64;
+        (step.abs() == 1).then_some(step)
+    })
+}
+
+/// bits needed to guess the longest pattern at the start, `None` if there is none worth using
+fn pattern_bits(
+    lowercase: &[char],
+    unleeted: &[char],
+    personal_info: &[String],
+    cardinality: f64,
+) -> Option<(usize, f64)> {
+    let mut candidates = vec![];
+    for (rank, word) in COMMON_PASSWORDS.iter().enumerate() {
+        if starts_with(unleeted, word) {
+            // one more bit for capitalization or substitutions
+            candidates.push((word.chars().count(), (rank as f64 + 1.0).log2() + 1.0));
+        }
+    }
+    for word in personal_info {
+        if starts_with(lowercase, word) {
+            candidates.push((word.chars().count(), 1.0));
+        }
+    }
+    let length = repeat_length(lowercase);
+    candidates.push((length, cardinality.log2() + (length as f64).log2()));
+    let length = run_length(lowercase, alphabet_step);
+    candidates.push((length, 26f64.log2() + (length as f64).log2() + 1.0));
+    let length = run_length(lowercase, keyboard_step);
+    candidates.push((length, 47f64.log2() + (length as f64).log2() + 1.0));
+
+    candidates
+        .into_iter()
+        .filter(|(length, _)| *length >= 3)
+        .max_by(|a, b| a.0.cmp(&b.0).then(b.1.total_cmp(&a.1)))
+}
+
+/// zxcvbn-style estimate from 0 to 4 of how hard the password is to guess. dictionary words,
+/// repeats, sequences, keyboard runs and personal info only cost a few guesses, everything else
+/// costs a brute force over the character classes in use
+pub fn estimate_score(password: &str, personal_info: &[String]) -> u8 {
+    let chars: Vec<char> = password.chars().collect();
+    let lowercase: Vec<char> = chars
+        .iter()
+        .map(|char| char.to_lowercase().next().unwrap_or(*char))
+        .collect();
+    let unleeted: Vec<char> = lowercase.iter().map(|char| unleet(*char)).collect();
+    let cardinality = cardinality(&chars);
+
+    let mut bits = 0.0;
+    let mut index = 0;
+    while index < chars.len() {
+        match pattern_bits(
+            &lowercase[index..],
+            &unleeted[index..],
+            personal_info,
+            cardinality,
+        ) {
+            Some((length, pattern_bits)) => {
+                bits += pattern_bits;
+                index += length;
+            }
+            None => {
+                bits += cardinality.log2();
+                index += 1;
+            }
+        }
+    }
+
+    // the guess thresholds of zxcvbn: 10^3, 10^6, 10^8 and 10^10
+    let guesses_log10 = bits * 2f64.log10();
+    match guesses_log10 {
+        x if x < 3.0 => 0,
+        x if x < 6.0 => 1,
+        x if x < 8.0 => 2,
+        x if x < 10.0 => 3,
+        _ => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weak_passwords_get_low_scores() {
+        for password in [
+            "password",
+            "P@ssw0rd",
+            "qwertyuiop",
+            "aaaaaaaaaaaa",
+            "abcdefgh",
+        ] {
+            assert!(estimate_score(password, &[]) <= 1, "{password}");
+        }
+        assert!(estimate_score("arian1234", &["arian".to_string()]) <= 1);
+        for password in [
+            "some_hard_password",
+            "correct horse battery staple",
+            "tR4x!mQ9$",
+        ] {
+            assert!(estimate_score(password, &[]) >= 3, "{password}");
+        }
+    }
+
+    #[test]
+    fn every_violation_is_returned() {
+        let policy = PasswordPolicy {
+            min_length: 8,
+            max_length: 16,
+            required_classes: vec![CharacterClass::Uppercase, CharacterClass::Digit],
+            min_score: 3,
+        };
+        assert_eq!(
+            policy.check("arian", &["Arian", "arian@gmail.com"]),
+            vec![
+                PasswordViolation::TooShort { min_length: 8 },
+                PasswordViolation::MissingCharacterClass {
+                    class: CharacterClass::Uppercase
+                },
+                PasswordViolation::MissingCharacterClass {
+                    class: CharacterClass::Digit
+                },
+                PasswordViolation::ContainsPersonalInfo,
+                PasswordViolation::TooWeak {
+                    score: 0,
+                    min_score: 3
+                },
+            ]
+        );
+        assert!(policy.check("Pouya-7-Kites", &["arian"]).is_empty());
+
+        // lengths are counted in characters
+        assert_eq!(
+            policy.check("Ünïcödé-Pässwörd-1", &[]),
+            vec![PasswordViolation::TooLong { max_length: 16 }]
+        );
+        assert!(policy.check("Ünïcödé-Pä-1", &[]).is_empty());
+    }
+}
@@ -1,11 +1,58 @@
-use rand::Rng;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{distributions::Alphanumeric, Rng, RngCore};
+
+/// codes that users type in from an email, without a leading zero so they always have six digits
+pub fn generate_six_digit_code(rng: &mut dyn RngCore) -> u32 {
+    rng.gen_range(100_000..1_000_000)
+}
 
 pub fn generate_random_six_digit_code() -> u32 {
+    generate_six_digit_code(&mut rand::thread_rng())
+}
+
+/// url safe token with 256 bits of randomness, used for sessions, codes and client ids
+pub fn generate_random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn generate_random_alphanumeric(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+/// code that users type on another device, like `BCDF-GHJK`, consonants only so it
+/// can't spell words and has no lookalike characters, see rfc 8628 section 6.1
+pub fn generate_user_code() -> String {
+    const ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
     let mut rng = rand::thread_rng();
-    (0..=6)
-        .map(|_| rng.gen_range(1..10).to_string())
-        .collect::<Vec<String>>()
-        .join("")
-        .parse::<u32>()
-        .unwrap()
+    let mut code: String = (0..8)
+        .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+        .collect();
+    code.insert(4, '-');
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::{RngProvider, SeededRngProvider};
+
+    #[test]
+    fn six_digit_codes_are_predictable_with_a_seed() {
+        let rng = SeededRngProvider::new(7);
+        let codes: Vec<u32> = (0..100)
+            .map(|_| generate_six_digit_code(&mut *rng.rng()))
+            .collect();
+        assert!(codes.iter().all(|code| (100_000..1_000_000).contains(code)));
+        // a new generator for every call, not the same code over and over
+        assert!(codes.windows(2).any(|pair| pair[0] != pair[1]));
+
+        let same_seed = SeededRngProvider::new(7);
+        assert_eq!(generate_six_digit_code(&mut *same_seed.rng()), codes[0]);
+    }
 }
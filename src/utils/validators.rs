@@ -1,24 +1,32 @@
-use crate::error::{ApiError, ApiResult};
+use crate::{
+    error::{ApiError, ApiResult},
+    utils::email::EmailAddress,
+};
 use regex::Regex;
+use url::Url;
 
 lazy_static! {
-    static ref VALID_EMAIL_REGEX: Regex = {
-        Regex::new(r"^[a-zA-Z0-9_.+-]+@[a-zA-Z0-9-]+\.[a-zA-Z0-9-.]+$")
-            .expect("email validator regex is wrong")
+    // bcp 47 language tags like `en`, `en-US` or `zh-Hant-TW`
+    static ref VALID_LOCALE_REGEX: Regex = {
+        Regex::new(r"^[a-zA-Z]{2,3}(-[a-zA-Z0-9]{2,8}){0,3}$").expect("locale validator regex is wrong")
+    };
+    // iana names like `UTC`, `Europe/Berlin` or `America/Argentina/Buenos_Aires`
+    static ref VALID_TIME_ZONE_REGEX: Regex = {
+        Regex::new(r"^(UTC|[A-Z][A-Za-z_]+(/[A-Za-z0-9_+-]+){1,2})$")
+            .expect("time zone validator regex is wrong")
     };
 }
 
+/// see `EmailAddress::parse` for what's accepted
 pub fn validate_email_address(email_address: &str) -> ApiResult<()> {
-    VALID_EMAIL_REGEX
-        .is_match(email_address)
-        .then_some(())
-        .ok_or(ApiError::BadArgument {
-            argument_name: "email_address",
-        })
+    EmailAddress::parse(email_address).map(|_| ())
 }
 
+/// no password policy allows longer passwords than this
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+
 pub fn validate_name(name: &str) -> ApiResult<()> {
-    let has_valid_length = name.len() > 2 || name.len() <= 16;
+    let has_valid_length = (3..=16).contains(&name.len());
     let has_valid_characters = name.chars().all(|char| char.is_ascii_alphanumeric());
     if has_valid_characters && has_valid_length {
         Ok(())
@@ -29,8 +37,10 @@ pub fn validate_name(name: &str) -> ApiResult<()> {
     }
 }
 
+/// bounds every password has to be in, new passwords are checked against the password policy of
+/// the tenant with `TenantSettings::check_password` too
 pub fn validate_password(password: &str) -> ApiResult<()> {
-    let has_valid_length = password.len() >= 5 || password.len() <= 64;
+    let has_valid_length = (1..=MAX_PASSWORD_LENGTH).contains(&password.chars().count());
     if has_valid_length {
         Ok(())
     } else {
@@ -39,3 +49,75 @@ pub fn validate_password(password: &str) -> ApiResult<()> {
         })
     }
 }
+
+pub fn validate_display_name(display_name: &str) -> ApiResult<()> {
+    let is_valid = (1..=64).contains(&display_name.chars().count())
+        && display_name.trim() == display_name
+        && !display_name.chars().any(char::is_control);
+    is_valid.then_some(()).ok_or(ApiError::BadArgument {
+        argument_name: "display_name",
+    })
+}
+
+/// reasons are shown to other admins, not to the user
+pub fn validate_status_reason(reason: &str) -> ApiResult<()> {
+    let is_valid =
+        (1..=255).contains(&reason.chars().count()) && !reason.chars().any(char::is_control);
+    is_valid.then_some(()).ok_or(ApiError::BadArgument {
+        argument_name: "reason",
+    })
+}
+
+pub fn validate_locale(locale: &str) -> ApiResult<()> {
+    VALID_LOCALE_REGEX
+        .is_match(locale)
+        .then_some(())
+        .ok_or(ApiError::BadArgument {
+            argument_name: "locale",
+        })
+}
+
+pub fn validate_time_zone(time_zone: &str) -> ApiResult<()> {
+    (time_zone.len() <= 64 && VALID_TIME_ZONE_REGEX.is_match(time_zone))
+        .then_some(())
+        .ok_or(ApiError::BadArgument {
+            argument_name: "time_zone",
+        })
+}
+
+/// avatars are shown by other clients, so only absolute http(s) urls are accepted
+pub fn validate_avatar_url(avatar_url: &str) -> ApiResult<()> {
+    let is_valid = avatar_url.len() <= 2048
+        && Url::parse(avatar_url)
+            .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host());
+    is_valid.then_some(()).ok_or(ApiError::BadArgument {
+        argument_name: "avatar_url",
+    })
+}
+
+pub fn validate_scope(scope: &str) -> ApiResult<()> {
+    // rfc 6749 section 3.3, printable ascii without space, '"' and '\'
+    let is_valid = !scope.is_empty()
+        && scope
+            .chars()
+            .all(|char| char.is_ascii_graphic() && char != '"' && char != '\\');
+    is_valid.then_some(()).ok_or(ApiError::BadArgument {
+        argument_name: "scopes",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_bounds_are_enforced() {
+        assert!(validate_name("ab").is_err());
+        assert!(validate_name("arian").is_ok());
+        assert!(validate_name(&"a".repeat(17)).is_err());
+
+        assert!(validate_password("").is_err());
+        assert!(validate_password(&"ü".repeat(MAX_PASSWORD_LENGTH)).is_ok());
+        assert!(validate_password(&"a".repeat(MAX_PASSWORD_LENGTH + 1)).is_err());
+    }
+}
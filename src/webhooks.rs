@@ -0,0 +1,146 @@
+use crate::{
+    config::Config,
+    db::{
+        webhooks::{
+            enqueue_deliveries, get_due_deliveries, record_delivery_attempt, AttemptResult,
+            DeliveryStatus, DueDelivery,
+        },
+        DbPool,
+    },
+    error::ApiResult,
+    utils::hash::hmac_sha256_hex,
+};
+use chrono::Utc;
+use serde_json::{json, Value};
+
+pub const USER_REGISTERED: &str = "user.registered";
+pub const USER_LOGGED_IN: &str = "user.logged_in";
+pub const PASSWORD_CHANGED: &str = "password.changed";
+pub const PROFILE_UPDATED: &str = "user.profile_updated";
+pub const EMAIL_CHANGED: &str = "user.email_changed";
+pub const USER_DELETED: &str = "user.deleted";
+/// events a webhook can subscribe to
+pub const EVENT_TYPES: [&str; 6] = [
+    USER_REGISTERED,
+    USER_LOGGED_IN,
+    PASSWORD_CHANGED,
+    PROFILE_UPDATED,
+    EMAIL_CHANGED,
+    USER_DELETED,
+];
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+/// how many deliveries the worker sends per round
+const DELIVERY_BATCH_SIZE: i64 = 50;
+
+/// queues `event_type` for every subscribed webhook, the worker sends it later
+pub async fn emit_event(pool: &DbPool, event_type: &str, data: Value) -> ApiResult<()> {
+    let payload = json!({
+        "type": event_type,
+        "created_date": Utc::now(),
+        "data": data,
+    });
+    enqueue_deliveries(pool, event_type, &payload.to_string()).await?;
+    Ok(())
+}
+
+/// value of the signature header, receivers recompute it over `{timestamp}.{body}`
+pub fn sign_payload(secret: &str, timestamp: i64, payload: &str) -> String {
+    format!(
+        "sha256={}",
+        hmac_sha256_hex(secret, &format!("{timestamp}.{payload}"))
+    )
+}
+
+async fn send_delivery(
+    client: &reqwest::Client,
+    delivery: &DueDelivery,
+) -> Result<(), (Option<i64>, String)> {
+    let timestamp = Utc::now().timestamp();
+    let response = client
+        .post(&delivery.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(
+            SIGNATURE_HEADER,
+            sign_payload(&delivery.secret, timestamp, &delivery.payload),
+        )
+        .header(TIMESTAMP_HEADER, timestamp)
+        .header(EVENT_HEADER, &delivery.event_type)
+        .header(DELIVERY_HEADER, delivery.id)
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map_err(|e| (None, e.to_string()))?;
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err((
+            Some(status.as_u16().into()),
+            format!("receiver answered {status}"),
+        ))
+    }
+}
+
+/// sends every due delivery once, failed ones are retried with exponential backoff until
+/// `webhook_max_attempts` is reached. returns how many deliveries were attempted
+pub async fn deliver_due_webhooks(
+    pool: &DbPool,
+    config: &Config,
+    client: &reqwest::Client,
+) -> ApiResult<usize> {
+    let deliveries = get_due_deliveries(pool, DELIVERY_BATCH_SIZE).await?;
+    for delivery in &deliveries {
+        let attempts = delivery.attempts + 1;
+        let now = Utc::now();
+        let sent = send_delivery(client, delivery).await;
+        let result = match &sent {
+            Ok(()) => AttemptResult {
+                status_code: None,
+                error: None,
+                status: DeliveryStatus::Succeeded,
+                next_attempt_date: now,
+            },
+            Err((status_code, error)) => AttemptResult {
+                status_code: *status_code,
+                error: Some(error),
+                status: if attempts >= config.webhook_max_attempts {
+                    DeliveryStatus::Failed
+                } else {
+                    DeliveryStatus::Pending
+                },
+                // 2^16 times the base delay is already far longer than anyone waits
+                next_attempt_date: now
+                    + config.webhook_retry_delay * 2i32.pow(delivery.attempts.min(16) as u32),
+            },
+        };
+        record_delivery_attempt(pool, delivery.id, &result).await?;
+    }
+    Ok(deliveries.len())
+}
+
+/// delivers queued webhooks forever, meant to be spawned next to the http server
+pub async fn run_webhook_worker(pool: DbPool, config: Config) {
+    let client = match reqwest::Client::builder()
+        .timeout(config.webhook_timeout.to_std().unwrap_or_default())
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!("can't start the webhook worker: {e}");
+            return;
+        }
+    };
+    let interval = config.webhook_poll_interval.to_std().unwrap_or_default();
+    loop {
+        if let Err(e) = deliver_due_webhooks(&pool, &config, &client).await {
+            tracing::error!("delivering webhooks failed: {e}");
+        }
+        actix_web::rt::time::sleep(interval).await;
+    }
+}